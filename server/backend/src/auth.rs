@@ -0,0 +1,173 @@
+// Application accounts and sessions. Passwords are only held long enough to hash or verify them,
+// a logged in client is identified by a random session token stored in its identity cookie.
+// Sessions are stored in the database, and cached in AkriveiaState once they have been seen.
+
+use actix_identity::Identity;
+use argon2::{ self, Config, };
+use chrono::Duration;
+use common::*;
+use crate::AKData;
+use crate::ak_error::AkError;
+use crate::hex;
+use crate::models::session;
+use futures::{ future::err, future::ok, Future, future::Either, };
+use rand::RngCore;
+use rand::rngs::OsRng;
+
+pub const MIN_PASSWORD_LENGTH: usize = 8;
+const SALT_LENGTH: usize = 16;
+const TOKEN_LENGTH: usize = 32;
+// bytes of a generated password, written out as twice as many hex characters
+const GENERATED_PASSWORD_LENGTH: usize = 8;
+
+#[derive(Debug, Clone)]
+pub struct Session {
+    pub account_id: i32,
+    pub expires: DateTime<Utc>,
+    pub name: String,
+    pub role: AccountRole,
+}
+
+impl Session {
+    pub fn new(account: &Account, expires: DateTime<Utc>) -> Session {
+        Session {
+            account_id: account.id,
+            expires,
+            name: account.name.clone(),
+            role: account.role,
+        }
+    }
+
+    pub fn info(&self) -> SessionInfo {
+        SessionInfo {
+            expires: self.expires,
+            name: self.name.clone(),
+            role: self.role,
+        }
+    }
+}
+
+pub fn session_expiry(now: DateTime<Utc>, hours: i64) -> DateTime<Utc> {
+    now + Duration::hours(hours)
+}
+
+pub fn hash_password(pw: &str) -> Result<String, AkError> {
+    let mut salt = [0u8; SALT_LENGTH];
+    OsRng.fill_bytes(&mut salt);
+    argon2::hash_encoded(pw.as_bytes(), &salt, &Config::default())
+        .map_err(|_e| AkError::internal())
+}
+
+pub fn verify_password(hash: &str, pw: &str) -> bool {
+    argon2::verify_encoded(hash, pw.as_bytes()).unwrap_or(false)
+}
+
+pub fn validate_password(pw: &str) -> Result<(), AkError> {
+    if pw.chars().count() < MIN_PASSWORD_LENGTH {
+        Err(AkError::validation(&format!("passwords must be at least {} characters", MIN_PASSWORD_LENGTH)))
+    } else {
+        Ok(())
+    }
+}
+
+// a random password for an account created without one, it passes validate_password
+pub fn new_password() -> String {
+    let mut bytes = [0u8; GENERATED_PASSWORD_LENGTH];
+    OsRng.fill_bytes(&mut bytes);
+    hex::encode_hex(&bytes)
+}
+
+pub fn new_token() -> String {
+    let mut bytes = [0u8; TOKEN_LENGTH];
+    OsRng.fill_bytes(&mut bytes);
+    hex::encode_hex(&bytes)
+}
+
+// removes every cached session of an account, so that changes to the account are seen on the
+// next request.
+pub fn forget_account(state: &AKData, account_id: i32) {
+    let mut s = state.lock().unwrap();
+    s.sessions.retain(|_token, session| session.account_id != account_id);
+}
+
+pub fn forget_all(state: &AKData) {
+    state.lock().unwrap().sessions.clear();
+}
+
+pub fn authenticate(id: &Identity, state: &AKData) -> impl Future<Item=Session, Error=AkError> {
+    let token = match id.identity() {
+        Some(token) => token,
+        None => return Either::A(err(AkError::unauthorized())),
+    };
+
+    let now = Utc::now();
+    let (cached, pool) = {
+        let s = state.lock().unwrap();
+        (s.sessions.get(&token).cloned(), s.system_pool.clone())
+    };
+
+    match cached {
+        Some(session) if session.expires > now => Either::A(ok(session)),
+        _ => {
+            let state = state.clone();
+            Either::B(pool.get()
+                .and_then(move |client| {
+                    session::select_session(client, token.clone(), now)
+                        .map(move |(_client, opt_session)| (token, opt_session))
+                })
+                .and_then(move |(token, opt_session)| {
+                    let mut s = state.lock().unwrap();
+                    match opt_session {
+                        Some((account, expires)) => {
+                            let session = Session::new(&account, expires);
+                            s.sessions.insert(token, session.clone());
+                            ok(session)
+                        },
+                        None => {
+                            s.sessions.remove(&token);
+                            err(AkError::unauthorized())
+                        },
+                    }
+                })
+            )
+        },
+    }
+}
+
+pub fn require_admin(id: &Identity, state: &AKData) -> impl Future<Item=Session, Error=AkError> {
+    authenticate(id, state)
+        .and_then(|session| {
+            if session.role == AccountRole::Admin {
+                ok(session)
+            } else {
+                err(AkError::unauthorized())
+            }
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verify() {
+        let hash = hash_password("correct horse").unwrap();
+        assert!(verify_password(&hash, "correct horse"));
+        assert!(!verify_password(&hash, "battery staple"));
+        assert!(!verify_password("not a hash", "correct horse"));
+    }
+
+    #[test]
+    fn unique_tokens() {
+        let a = new_token();
+        assert_eq!(a.len(), TOKEN_LENGTH * 2);
+        assert!(a != new_token());
+    }
+
+    #[test]
+    fn generated_passwords() {
+        let pw = new_password();
+        assert!(validate_password(&pw).is_ok());
+        assert!(pw != new_password());
+    }
+}
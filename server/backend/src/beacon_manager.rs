@@ -10,23 +10,28 @@
 use actix::prelude::*;
 use actix_web::Result;
 use crate::beacon_udp::*;
+use crate::capture::Capture;
+use crate::config::BeaconConfig;
 use crate::dummy_udp::*;
+use crate::replay_udp::ReplayUDP;
 use crate::data_processor::*;
-use crate::db_utils;
-use crate::models::network_interface;
 use crate::models::beacon;
-use std::time::Duration;
-use std::collections::{ BTreeSet, BTreeMap, };
+use crate::push::{ Publish, PushBroadcaster, };
+use crate::repository::Repositories;
+use common::emergency_scope::{ self, ScopeChange, };
+use futures::future::Either;
+use std::time::{ Duration, Instant, };
+use std::collections::{ BTreeSet, BTreeMap, HashMap, VecDeque, };
 use common::*;
 use std::net::{ IpAddr, Ipv4Addr, };
-use chrono::{ DateTime, Duration as cDuration, };
+use chrono::Duration as cDuration;
 use crate::ak_error::AkError;
 
 // Problem: Requests to beacons do not create a request object, and so
 // we need to manually monitor when beacons do not respond within a given time frame.
-// The manager itself has a BeaconState, indicating what the desired beacon's state should be
-// for all beacons. This allows us to know what state to tell the beacons to switch to after they
-// reboot.
+// The manager itself keeps the emergency status, indicating what the desired beacon's state should
+// be for the beacons on each map, an emergency may cover the whole site or only some maps. This
+// allows us to know what state to tell the beacons to switch to after they reboot.
 // 1. The manager must ping all beacons in the same state as the manager.
     // The manager will always be either idle or active.
     // ie when a beacon is idle and the manager is idle as well.
@@ -45,36 +50,49 @@ use crate::ak_error::AkError;
 // sln:
 // 1. ping beacons every x seconds, where the x value depends on the state. This is a broadcast
     // request.
-// 2. on command, send requests to change the state of the beacons. this is a broadcast.
+// 2. on command, send requests to change the state of the beacons. this is a broadcast, unless the
+    // emergency only covers some maps.
 // 3. after y seconds of a request, scan the beacons map to determine if their timestamps have not
     // been updated. (the timestamp should change when a beacon replies). if the timestamp has not
     // been updated, then add them to the stale map, which maps a mac address to a retry count.
 // 4. note that a ping request may happen at the same time as a state change request, this is fine
     // as either will update their timestamp.
 // 5. as long as the stale map has elements, repeat the retry callback.
+// Every command carries a request id, which beacons echo back in their acknowledgement. Pending
+// requests are kept until every beacon they were sent to has acknowledged them, so a reply
+// completes the exact request it answers. Legacy beacons cannot echo the id, their replies
+// complete the oldest pending request of the same kind.
 
-const USE_DUMMY_BEACONS: bool = true;
-const USE_UDP_BEACONS: bool = false;
-const PING_INTERVAL: Duration = Duration::from_millis(100000);
-const EMERGENCY_PING_INTERVAL: Duration = Duration::from_millis(10000);
-const RESPONSE_THRESHOLD: Duration = Duration::from_millis(2000);
-const RETRIES_THRESHOLD: u32 = 4;
+const BEACON_PUSH_INTERVAL: Duration = Duration::from_millis(1000);
+// alerts kept for the diagnostics page
+const MAX_ALERTS: usize = 100;
+
+pub type RequestId = u16;
 
 #[derive(Debug)]
 struct Retries {
-    pub expected_response: DateTime<Utc>,
+    // whether the beacon has acknowledged a request since the last one was sent to it
+    pub answered: bool,
     pub retries: u32,
 }
 
 impl Retries {
     fn new() -> Retries {
         Retries {
-            expected_response: Utc::now() - cDuration::from_std(RESPONSE_THRESHOLD).unwrap(),
+            answered: false,
             retries:  0,
         }
     }
 }
 
+#[derive(Debug)]
+struct PendingRequest {
+    kind: BeaconCommandKind,
+    sent: Instant,
+    // beacons that have not acknowledged the request yet
+    waiting: BTreeSet<MacAddress8>,
+}
+
 #[derive(Debug)]
 struct BeaconStatus {
     pub realtime: RealtimeBeacon,
@@ -82,20 +100,160 @@ struct BeaconStatus {
 }
 
 pub struct BeaconManager {
-    state: BeaconState,
+    // the maps in an emergency, who changed it and when. persisted so that a restart does not
+    // end an emergency
+    emergency: EmergencyStatus,
+    config: BeaconConfig,
     data_processor: Addr<DataProcessor>,
     diagnostic_data: common::DiagnosticData,
     udp_connections: Vec<Addr<BeaconUDP>>,
-    dummy_udp_connections: Vec<Addr<DummyUDP>>,
+    // simulated tags, or a capture being replayed
+    dummy_udp_connections: Vec<Recipient<BeaconCommand>>,
+    // given to the udp connections, so they can record what they receive
+    capture: Addr<Capture>,
     pinger: Option<SpawnHandle>,
     request_health: Option<SpawnHandle>,
     beacons: BTreeMap<MacAddress8, BeaconStatus>,
-    unknown_macs: BTreeSet<MacAddress8>,
+    pending: BTreeMap<RequestId, PendingRequest>,
+    next_request: RequestId,
+    latency: BTreeMap<BeaconCommandKind, CommandLatency>,
+    // beacons that have messaged the manager without being registered
+    discovered: BTreeMap<MacAddress8, DiscoveredBeacon>,
+    // beacons whose messages are ignored
+    blocked: BTreeSet<MacAddress8>,
+    alerts: VecDeque<BeaconAlert>,
+    push: Addr<PushBroadcaster>,
+    repositories: Repositories,
 }
 
 impl BeaconManager {
     pub fn is_emergency(&self) -> bool {
-        return self.state == BeaconState::Active;
+        return self.emergency.active;
+    }
+
+    fn publish_beacon(&self, mac: &MacAddress8) {
+        if let Some(beacon) = self.beacons.get(mac) {
+            self.push.do_send(Publish(PushMessage::BeaconChanged(beacon.realtime.clone())));
+        }
+    }
+
+    // changes what the emergency covers, account is None when the change was not made by a
+    // logged in account.
+    fn set_scope(&mut self, context: &mut Context<Self>, change: ScopeChange, account: Option<String>, reason: Option<String>) {
+        let was_active = self.emergency.active;
+        let was_map_ids = self.emergency.map_ids.clone();
+        let changed_by = account.clone();
+        let now = Utc::now();
+        match change {
+            ScopeChange::Active(map_ids) => {
+                if was_active && self.emergency.map_ids == map_ids {
+                    return;
+                }
+                if was_active {
+                    self.emergency.map_ids = map_ids;
+                } else {
+                    self.emergency = EmergencyStatus {
+                        active: true,
+                        started_at: Some(now),
+                        started_by: account,
+                        ended_at: None,
+                        ended_by: None,
+                        map_ids,
+                    };
+                    self.diagnostic_data = common::DiagnosticData::new();
+                    let interval = self.config.emergency_ping_interval();
+                    self.ping_health(context, interval);
+                }
+            },
+            ScopeChange::Ended => {
+                if !was_active {
+                    return;
+                }
+                self.emergency.active = false;
+                self.emergency.ended_at = Some(now);
+                self.emergency.ended_by = account;
+                self.diagnostic_data = common::DiagnosticData::new();
+                let interval = self.config.ping_interval();
+                self.ping_health(context, interval);
+            },
+        }
+        self.push.do_send(Publish(PushMessage::Emergency(self.emergency.clone())));
+        self.data_processor.do_send(DPMessage::SetEmergency(self.emergency.clone()));
+        self.save_emergency(context, was_active, was_map_ids, changed_by, reason);
+        self.reconcile();
+    }
+
+    // tells every beacon to switch to the state of its map
+    fn reconcile(&mut self) {
+        match (self.emergency.active, self.emergency.map_ids.is_some()) {
+            (false, _) => self.request(BeaconCommandKind::EndEmergency, None, BeaconCommand::EndEmergency),
+            (true, false) => self.request(BeaconCommandKind::StartEmergency, None, BeaconCommand::StartEmergency),
+            (true, true) => {
+                let macs: Vec<MacAddress8> = self.beacons.keys().cloned().collect();
+                for mac in macs {
+                    self.reconcile_beacon(mac);
+                }
+            },
+        }
+    }
+
+    // tells one beacon to switch to the state of its map
+    fn reconcile_beacon(&mut self, mac: MacAddress8) {
+        let state = match self.beacons.get(&mac) {
+            Some(b) => emergency_scope::beacon_state(&self.emergency, b.realtime.map_id),
+            None => return,
+        };
+        match state {
+            BeaconState::Active => self.request(BeaconCommandKind::StartEmergency, Some(mac), BeaconCommand::StartEmergency),
+            _ => self.request(BeaconCommandKind::EndEmergency, Some(mac), BeaconCommand::EndEmergency),
+        }
+    }
+
+    fn schedule_health(&mut self, context: &mut Context<Self>) {
+        if self.request_health.is_none() {
+            self.request_health = Some(context.run_later(self.config.response_threshold(), |actor, context| {
+                actor.check_health(context);
+            }));
+        }
+    }
+
+    // stores the current emergency state, and starts, rescopes or ends its entry in the emergency log
+    fn save_emergency(&self, context: &mut Context<Self>, was_active: bool, was_map_ids: Option<Vec<i32>>, account: Option<String>, reason: Option<String>) {
+        let status = self.emergency.clone();
+        let system = self.repositories.system.clone();
+        let fut = system.update_emergency(status.clone())
+            .and_then(move |_| {
+                if status.active && !was_active {
+                    let mut event = EmergencyEvent::new();
+                    event.started_at = status.started_at.unwrap_or(event.started_at);
+                    event.started_by = status.started_by;
+                    event.start_reason = reason;
+                    event.map_ids = status.map_ids;
+                    Either::A(system.insert_emergency_event(event))
+                } else if status.active {
+                    // part of the emergency was started or ended, the log keeps who did it and why
+                    let (started, map_ids) = emergency_scope::rescoped(&was_map_ids, &status.map_ids);
+                    let mut change = EmergencyScopeChange::new();
+                    change.account = account;
+                    change.reason = reason;
+                    change.started = started;
+                    change.map_ids = map_ids;
+                    let log = system.clone();
+                    Either::B(Either::A(system.update_emergency_events_scope(status.map_ids)
+                        .and_then(move |_count| log.insert_emergency_scope_change(change))
+                        .map(|_count| {})
+                    ))
+                } else {
+                    let ended_at = status.ended_at.unwrap_or(Utc::now());
+                    Either::B(Either::B(system.end_emergency_events(ended_at, status.ended_by, reason)
+                        .map(|_count| {})
+                    ))
+                }
+            })
+            .map_err(|e| {
+                println!("failed to save emergency state: {:?}", e);
+            });
+        context.spawn(fut.into_actor(self));
     }
 }
 
@@ -103,13 +261,14 @@ impl Actor for BeaconManager {
     type Context = Context<Self>;
 }
 
+// acknowledgements carry the id of the request they answer, when the beacon sent one
 pub enum BMResponse {
-    Start(IpAddr, MacAddress8),
-    End(IpAddr, MacAddress8),
-    Ping(IpAddr, MacAddress8),
-    Reboot(IpAddr, MacAddress8),
+    Start(IpAddr, MacAddress8, Option<RequestId>),
+    End(IpAddr, MacAddress8, Option<RequestId>),
+    Ping(IpAddr, MacAddress8, Option<RequestId>),
+    Reboot(IpAddr, MacAddress8, Option<RequestId>),
     TagData(IpAddr, TagData),
-    SetIp(IpAddr, MacAddress8),
+    SetIp(IpAddr, MacAddress8, Option<RequestId>),
 }
 
 #[derive(Debug, Clone)]
@@ -129,11 +288,11 @@ impl Message for BMCommand {
 
 #[derive(Clone, Copy)]
 pub enum BeaconCommand {
-    EndEmergency(Option<IpAddr>),
-    StartEmergency(Option<IpAddr>),
-    Ping(Option<IpAddr>),
-    Reboot(Option<IpAddr>),
-    SetIp(Ipv4Addr),
+    EndEmergency(RequestId, Option<IpAddr>),
+    StartEmergency(RequestId, Option<IpAddr>),
+    Ping(RequestId, Option<IpAddr>),
+    Reboot(RequestId, Option<IpAddr>),
+    SetIp(RequestId, Ipv4Addr),
 }
 
 impl Message for BeaconCommand {
@@ -144,36 +303,97 @@ impl Message for BMResponse {
     type Result = Result<(), ()>;
 }
 
+impl BMResponse {
+    // the address and mac of the beacon that sent the response
+    fn source(&self) -> (IpAddr, MacAddress8) {
+        match self {
+            BMResponse::Start(ip, mac, _)
+                | BMResponse::End(ip, mac, _)
+                | BMResponse::Ping(ip, mac, _)
+                | BMResponse::Reboot(ip, mac, _)
+                | BMResponse::SetIp(ip, mac, _) => (*ip, *mac),
+            BMResponse::TagData(ip, tag_data) => (*ip, tag_data.beacon_mac),
+        }
+    }
+}
+
+// a message from a beacon was rejected by a connection
+pub struct InBeaconAlert(pub BeaconAlert);
+
+impl Message for InBeaconAlert {
+    type Result = ();
+}
+
+// a message from a beacon without a key was rejected, it is only recorded as discovered
+pub struct InDiscoveredBeacon(pub MacAddress8, pub IpAddr);
+
+impl Message for InDiscoveredBeacon {
+    type Result = ();
+}
+
+// the signing key of a beacon was provisioned, rotated, or removed when None
+#[derive(Clone)]
+pub struct SetBeaconKey(pub MacAddress8, pub Option<beacon::BeaconKey>);
+
+impl Message for SetBeaconKey {
+    type Result = ();
+}
+
 impl BeaconManager {
-    pub fn new(dp: Addr<DataProcessor>) -> Addr<BeaconManager> {
+    pub fn new(dp: Addr<DataProcessor>, push: Addr<PushBroadcaster>, capture: Addr<Capture>, repositories: Repositories, config: BeaconConfig) -> Addr<BeaconManager> {
         BeaconManager::create(move |context| {
             let mut manager = BeaconManager {
-                state: BeaconState::Idle, // TODO get from db
+                emergency: EmergencyStatus::new(),
+                config,
                 data_processor: dp,
                 diagnostic_data: common::DiagnosticData::new(),
                 udp_connections: Vec::new(),
                 dummy_udp_connections: Vec::new(),
+                capture,
                 pinger: None,
                 request_health: Default::default(),
-                unknown_macs: BTreeSet::new(),
+                discovered: BTreeMap::new(),
+                blocked: BTreeSet::new(),
+                alerts: VecDeque::new(),
                 beacons: BTreeMap::new(),
+                pending: BTreeMap::new(),
+                next_request: 0,
+                latency: BTreeMap::new(),
+                push,
+                repositories,
             };
-            manager.ping_health(context, PING_INTERVAL);
+            let interval = manager.config.ping_interval();
+            manager.ping_health(context, interval);
 
-            let fut = db_utils::default_connect()
-                .map_err(AkError::from)
-                .and_then(|client| {
-                    beacon::select_beacons(client)
-                })
+            let fut = manager.repositories.beacons.beacons()
+                .join3(
+                    manager.repositories.beacons.blocked_beacons(),
+                    manager.repositories.system.emergency()
+                )
                 .into_actor(&manager)
-                .map(|(_client, beacons), actor, context| {
+                .map(|(beacons, blocked, status), actor, context| {
                     beacons.into_iter().for_each(|b| {
                         actor.beacons.insert(b.mac_address.clone(), BeaconStatus {
                             realtime: RealtimeBeacon::from(b),
                             retries: None,
                         });
                     });
-                    context.notify(BMCommand::Ping(None));
+                    actor.blocked = blocked.into_iter().collect();
+                    if status.active {
+                        // the emergency was still going when the backend stopped, resume it
+                        // without touching who started it.
+                        println!("resuming emergency started at {:?} by {:?}", status.started_at, status.started_by);
+                        actor.emergency = status;
+                        let interval = actor.config.emergency_ping_interval();
+                        actor.ping_health(context, interval);
+                        actor.push.do_send(Publish(PushMessage::Emergency(actor.emergency.clone())));
+                        actor.data_processor.do_send(DPMessage::SetEmergency(actor.emergency.clone()));
+                        actor.reconcile();
+                        actor.schedule_health(context);
+                    } else {
+                        actor.emergency = status;
+                        context.notify(BMCommand::Ping(None));
+                    }
                 })
                 .map_err(|_err, _actor, _context| { });
             context.spawn(fut);
@@ -182,41 +402,52 @@ impl BeaconManager {
         })
     }
 
-    fn find_beacon(&mut self, context: &mut Context<Self>, mac: MacAddress8) {
+    fn find_beacon(&mut self, context: &mut Context<Self>, mac: MacAddress8, ip: IpAddr) {
         let dup = mac.clone();
-        let fut = db_utils::default_connect()
-            .map_err(AkError::from)
-            .and_then(move |client| {
-                beacon::select_beacon_by_mac(client, dup)
-            })
+        let fut = self.repositories.beacons.beacon_by_mac(dup)
             .into_actor(self)
-            .map(move |(_client, beacon), actor, context| {
+            .map(move |beacon, actor, context| {
                 if let Some(b) = beacon {
+                    actor.discovered.remove(&mac);
                     actor.beacons.insert(b.mac_address.clone(), BeaconStatus {
                         realtime: RealtimeBeacon::from(b),
                         retries: None,
                     });
                     context.notify(BMCommand::Ping(Some(mac)));
                 } else {
-                    actor.unknown_macs.insert(mac);
+                    actor.discover(mac, ip);
                 }
             })
             .map_err(move |_err, actor, _context| {
-                actor.unknown_macs.insert(mac);
+                actor.discover(mac, ip);
             });
         context.spawn(fut);
     }
 
+    // records a message from a beacon that is not registered
+    fn discover(&mut self, mac: MacAddress8, ip: IpAddr) {
+        let now = Utc::now();
+        let discovered = self.discovered.entry(mac).or_insert_with(|| DiscoveredBeacon::new(mac));
+        discovered.ip = Some(ip);
+        discovered.first_seen.get_or_insert(now);
+        discovered.last_seen = Some(now);
+        discovered.messages += 1;
+    }
+
     // this callback is executed only after a request is sent to verify that beacons have responded
     fn check_health(&mut self, context: &mut Context<Self>) {
-        let manager_state = self.state;
+        let emergency = self.emergency.clone();
+        let retries_threshold = self.config.retries_threshold;
         let mut any_retries = false;
-        self.beacons.iter_mut().for_each(|(_mac, status)| {
+        let mut changed = Vec::new();
+        self.beacons.iter_mut().for_each(|(mac, status)| {
+            // the state this beacon's map should be in
+            let manager_state = emergency_scope::beacon_state(&emergency, status.realtime.map_id);
             // determine if further action is necessary before the next ping
             let set_none = if let Some(retries) = &mut status.retries {
                 retries.retries += 1;
                 // this beacon has had a request sent to it recently
-                if status.realtime.last_active > retries.expected_response {
+                if retries.answered {
                     if manager_state == status.realtime.state {
                         true
                     } else {
@@ -231,14 +462,16 @@ impl BeaconManager {
                 } else {
                     any_retries = true;
                     // this beacon has not responded yet, try again
-                    if retries.retries > RETRIES_THRESHOLD {
+                    if retries.retries > retries_threshold {
                         if status.realtime.state == BeaconState::Rebooting {
                             // beacon failed to reply and failed to reboot, set to unknown
                             status.realtime.state = BeaconState::Unknown;
+                            changed.push(*mac);
                             true
                         } else {
                             // beacon failed to reply, try to reboot it
                             status.realtime.state = BeaconState::Rebooting;
+                            changed.push(*mac);
                             retries.retries = 0;
                             context.notify(BMCommand::Reboot(Some(status.realtime.mac_address)));
                             false
@@ -270,8 +503,13 @@ impl BeaconManager {
             }
         });
 
+        for mac in changed {
+            self.publish_beacon(&mac);
+        }
+        self.expire_requests();
+
         if any_retries {
-            self.request_health = Some(context.run_later(RESPONSE_THRESHOLD, |actor, context| {
+            self.request_health = Some(context.run_later(self.config.response_threshold(), |actor, context| {
                 actor.check_health(context);
             }));
         } else {
@@ -287,12 +525,8 @@ impl BeaconManager {
         self.pinger = Some(ctx.run_interval(dur, |actor, context| {
             actor.beacons.iter().for_each(|(_mac, beacon)| {
                 let realtime = beacon.realtime.clone();
-                let fut = db_utils::default_connect()
-                    .map_err(AkError::from)
-                    .and_then(|client| {
-                        beacon::update_beacon_from_realtime(client, realtime)
-                    })
-                    .map(|(_client, _beacon)| { })
+                let fut = actor.repositories.beacons.update_beacon_from_realtime(realtime)
+                    .map(|_beacon| { })
                     .map_err(|_err| { });
                 context.spawn(fut.into_actor(actor));
             });
@@ -302,24 +536,21 @@ impl BeaconManager {
     }
 
     fn find_beacons(&mut self, context: &mut Context<Self>) {
-        if USE_DUMMY_BEACONS { self.find_beacons_dummy(context); }
-        if USE_UDP_BEACONS { self.find_beacons_udp(context); }
+        if self.config.use_dummy { self.find_beacons_dummy(context); }
+        if self.config.use_udp { self.find_beacons_udp(context); }
     }
 
     fn find_beacons_udp(&mut self, context: &mut Context<Self>) {
-        let fut = db_utils::default_connect()
-            .and_then(|client| {
-                network_interface::select_network_interfaces(client)
-                    .map(|(_client, ifaces)| {
-                        ifaces
-                    })
-            })
+        let fut = self.repositories.system.network_interfaces()
+            .join(self.repositories.beacons.beacon_keys())
             .into_actor(self)
-            .and_then(|ifaces, actor, context| {
+            .and_then(|(ifaces, keys), actor, context| {
+                let keys: HashMap<MacAddress8, beacon::BeaconKey> = keys.into_iter().collect();
                 for iface in ifaces {
                     match iface.beacon_port {
                         Some(port) => {
-                            actor.udp_connections.push(BeaconUDP::new(context.address(), iface.ip.clone(), port as u16));
+                            let connection = BeaconUDP::new(context.address(), actor.capture.clone(), actor.repositories.clone(), iface.ip.clone(), port as u16, keys.clone(), actor.config.allow_unsigned);
+                            actor.udp_connections.push(connection);
                         },
                         None => {},
                     }
@@ -333,7 +564,12 @@ impl BeaconManager {
     }
 
     fn find_beacons_dummy(&mut self, context: &mut Context<Self>) {
-        self.dummy_udp_connections.push(DummyUDP::new(context.address()));
+        // a capture takes the place of the simulated tags
+        let connection = match self.config.replay.file {
+            Some(_) => ReplayUDP::new(context.address(), self.config.replay.clone()).recipient(),
+            None => DummyUDP::new(context.address(), self.repositories.clone(), self.config.simulator.clone()).recipient(),
+        };
+        self.dummy_udp_connections.push(connection);
     }
 
     fn mass_send(&self, msg: BeaconCommand) {
@@ -342,7 +578,116 @@ impl BeaconManager {
         }
 
         for connection in &self.dummy_udp_connections {
-            connection.do_send(msg);
+            let _ = connection.do_send(msg);
+        }
+    }
+
+    // sends a command to a single beacon, or to every beacon when no mac is given, and waits for
+    // each of those beacons to acknowledge it.
+    fn request<F>(&mut self, kind: BeaconCommandKind, opt_mac: Option<MacAddress8>, command: F)
+        where F: FnOnce(RequestId, Option<IpAddr>) -> BeaconCommand
+    {
+        let (opt_ip, waiting) = match opt_mac {
+            Some(mac) => {
+                match self.beacons.get(&mac) {
+                    Some(beacon) => (Some(beacon.realtime.ip), vec![mac]),
+                    None => return,
+                }
+            },
+            None => (None, self.beacons.keys().cloned().collect()),
+        };
+
+        for mac in &waiting {
+            if let Some(beacon) = self.beacons.get_mut(mac) {
+                beacon.retries.get_or_insert_with(Retries::new).answered = false;
+            }
+        }
+
+        // id 0 is left for messages that do not answer a request
+        self.next_request = self.next_request.wrapping_add(1).max(1);
+        let id = self.next_request;
+        self.pending.insert(id, PendingRequest {
+            kind,
+            sent: Instant::now(),
+            waiting: waiting.into_iter().collect(),
+        });
+        self.mass_send(command(id, opt_ip));
+    }
+
+    // completes the request a beacon acknowledged, returns false if there was no such request
+    fn complete(&mut self, kind: BeaconCommandKind, mac: MacAddress8, opt_id: Option<RequestId>) -> bool {
+        let opt_id = match opt_id {
+            Some(id) => Some(id),
+            // legacy beacons do not send the id, assume the reply is for the oldest request
+            None => {
+                self.pending.iter()
+                    .filter(|(_id, request)| request.kind == kind && request.waiting.contains(&mac))
+                    .min_by_key(|(_id, request)| request.sent)
+                    .map(|(id, _request)| *id)
+            },
+        };
+
+        let id = match opt_id {
+            Some(id) => id,
+            None => return false,
+        };
+        let (sent, done) = match self.pending.get_mut(&id) {
+            Some(request) if request.kind == kind => {
+                if !request.waiting.remove(&mac) {
+                    return false;
+                }
+                (request.sent, request.waiting.is_empty())
+            },
+            _ => return false,
+        };
+        if done {
+            self.pending.remove(&id);
+        }
+
+        let elapsed = sent.elapsed();
+        let ms = elapsed.as_secs() as f64 * 1000.0 + elapsed.subsec_micros() as f64 / 1000.0;
+        self.latency.entry(kind).or_insert_with(|| CommandLatency::new(kind)).record(ms);
+
+        if let Some(retries) = self.beacons.get_mut(&mac).and_then(|beacon| beacon.retries.as_mut()) {
+            retries.answered = true;
+        }
+        true
+    }
+
+    // forgets requests that have had time for every retry, counting the beacons that never replied
+    fn expire_requests(&mut self) {
+        let max_age = self.config.response_threshold() * (self.config.retries_threshold + 1);
+        let expired: Vec<RequestId> = self.pending.iter()
+            .filter(|(_id, request)| request.sent.elapsed() > max_age)
+            .map(|(id, _request)| *id)
+            .collect();
+
+        for id in expired {
+            if let Some(request) = self.pending.remove(&id) {
+                let kind = request.kind;
+                self.latency.entry(kind).or_insert_with(|| CommandLatency::new(kind)).timeouts += request.waiting.len() as u64;
+            }
+        }
+    }
+
+    // updates a beacon that acknowledged a request, returns true if the beacon is known
+    fn acknowledge<F>(&mut self, context: &mut Context<Self>, kind: BeaconCommandKind, ip: IpAddr, mac: MacAddress8, opt_id: Option<RequestId>, update: F) -> bool
+        where F: FnOnce(&mut RealtimeBeacon)
+    {
+        match self.beacons.get_mut(&mac) {
+            Some(beacon) => {
+                update(&mut beacon.realtime);
+                beacon.realtime.last_active = Utc::now();
+                beacon.realtime.ip = ip;
+                if !self.complete(kind, mac, opt_id) {
+                    println!("beacon {} sent an unexpected {} acknowledgement", mac.to_hex_string(), kind);
+                }
+                true
+            },
+            None => {
+                self.find_beacon(context, mac, ip);
+                false
+            }
         }
     }
 }
@@ -356,109 +701,32 @@ impl Handler<BMCommand> for BeaconManager {
             BMCommand::ScanBeacons => {
                 self.find_beacons(context);
             },
-            BMCommand::StartEmergency(opt_mac) => {
-                if self.state != BeaconState::Active {
-                    self.state = BeaconState::Active;
-                    self.diagnostic_data = common::DiagnosticData::new();
-                    self.ping_health(context, EMERGENCY_PING_INTERVAL);
-                }
-
-                if let Some(mac) = opt_mac {
-                    let opt_beacon = self.beacons.get_mut(&mac);
-                    if let Some(beacon) = opt_beacon {
-                        let ip = beacon.realtime.ip;
-                        if beacon.retries.is_none() {
-                            beacon.retries = Some(Retries::new());
-                        }
-                        self.mass_send(BeaconCommand::StartEmergency(Some(ip)));
-                    }
-                } else {
-                    self.mass_send(BeaconCommand::StartEmergency(None));
-                    self.beacons.iter_mut().for_each(|(_mac, beacon)| {
-                        if beacon.retries.is_none() {
-                            beacon.retries = Some(Retries::new());
-                        }
-                    });
-                }
-            }
-            BMCommand::EndEmergency(opt_mac) => {
-                if self.state != BeaconState::Idle {
-                    self.state = BeaconState::Idle;
-                    self.diagnostic_data = common::DiagnosticData::new();
-                    self.ping_health(context, PING_INTERVAL);
-                }
-
-                if let Some(mac) = opt_mac {
-                    let opt_beacon = self.beacons.get_mut(&mac);
-                    if let Some(beacon) = opt_beacon {
-                        let ip = beacon.realtime.ip;
-                        if beacon.retries.is_none() {
-                            beacon.retries = Some(Retries::new());
-                        }
-                        self.mass_send(BeaconCommand::EndEmergency(Some(ip)));
-                    }
-                } else {
-                    self.mass_send(BeaconCommand::EndEmergency(None));
-                    self.beacons.iter_mut().for_each(|(_mac, beacon)| {
-                        if beacon.retries.is_none() {
-                            beacon.retries = Some(Retries::new());
-                        }
-                    });
-                }
+            // without a mac the whole site is switched, otherwise only the beacon is told to
+            // switch, which is how beacons are brought back in line with their map.
+            BMCommand::StartEmergency(Some(mac)) => {
+                self.request(BeaconCommandKind::StartEmergency, Some(mac), BeaconCommand::StartEmergency);
+            },
+            BMCommand::StartEmergency(None) => {
+                self.set_scope(context, ScopeChange::Active(None), None, None);
+            },
+            BMCommand::EndEmergency(Some(mac)) => {
+                self.request(BeaconCommandKind::EndEmergency, Some(mac), BeaconCommand::EndEmergency);
+            },
+            BMCommand::EndEmergency(None) => {
+                self.set_scope(context, ScopeChange::Ended, None, None);
             },
             BMCommand::Ping(opt_mac) => {
-                if let Some(mac) = opt_mac {
-                    let opt_beacon = self.beacons.get_mut(&mac);
-                    if let Some(beacon) = opt_beacon {
-                        let ip = beacon.realtime.ip;
-                        if beacon.retries.is_none() {
-                            beacon.retries = Some(Retries::new());
-                        }
-                        self.mass_send(BeaconCommand::Ping(Some(ip)));
-                    }
-                } else {
-                    self.mass_send(BeaconCommand::Ping(None));
-                    self.beacons.iter_mut().for_each(|(_mac, beacon)| {
-                        if beacon.retries.is_none() {
-                            beacon.retries = Some(Retries::new());
-                        }
-                    });
-                }
+                self.request(BeaconCommandKind::Ping, opt_mac, BeaconCommand::Ping);
             },
             BMCommand::Reboot(opt_mac) => {
-                if let Some(mac) = opt_mac {
-                    let opt_beacon = self.beacons.get_mut(&mac);
-                    if let Some(beacon) = opt_beacon {
-                        let ip = beacon.realtime.ip;
-                        if beacon.retries.is_none() {
-                            beacon.retries = Some(Retries::new());
-                        }
-                        self.mass_send(BeaconCommand::Reboot(Some(ip)));
-                    }
-                } else {
-                    self.mass_send(BeaconCommand::Reboot(None));
-                    self.beacons.iter_mut().for_each(|(_mac, beacon)| {
-                        if beacon.retries.is_none() {
-                            beacon.retries = Some(Retries::new());
-                        }
-                    });
-                }
+                self.request(BeaconCommandKind::Reboot, opt_mac, BeaconCommand::Reboot);
             },
             BMCommand::SetIp(ip) => {
-                self.mass_send(BeaconCommand::SetIp(ip));
-                self.beacons.iter_mut().for_each(|(_mac, beacon)| {
-                    if beacon.retries.is_none() {
-                        beacon.retries = Some(Retries::new());
-                    }
-                });
+                self.request(BeaconCommandKind::SetIp, None, |id, _opt_ip| BeaconCommand::SetIp(id, ip));
             },
         }
 
-        if self.request_health.is_none() {
-            self.request_health = Some(context.run_later(RESPONSE_THRESHOLD, |actor, context| {
-                actor.check_health(context);
-            }));
-        }
+        self.schedule_health(context);
         Ok(self.is_emergency())
     }
 }
@@ -467,86 +735,266 @@ impl Handler<BMResponse> for BeaconManager {
     type Result = Result<(), ()>;
 
     fn handle(&mut self, msg: BMResponse, context: &mut Context<Self>) -> Self::Result {
+        let (source_ip, source_mac) = msg.source();
+        if self.blocked.contains(&source_mac) {
+            self.discover(source_mac, source_ip);
+            return Ok(());
+        }
+
+        let mut changed = None;
         match msg {
-            BMResponse::Start(ip, mac) => {
-                match self.beacons.get_mut(&mac) {
-                    Some(beacon) => {
-                        beacon.realtime.state = BeaconState::Active;
-                        beacon.realtime.last_active = Utc::now();
-                        beacon.realtime.ip = ip;
-                    },
-                    None => {
-                        self.find_beacon(context, mac);
-                    }
+            BMResponse::Start(ip, mac, opt_id) => {
+                if self.acknowledge(context, BeaconCommandKind::StartEmergency, ip, mac, opt_id, |b| b.state = BeaconState::Active) {
+                    changed = Some(mac);
                 }
             }
-            BMResponse::End(ip, mac) => {
-                match self.beacons.get_mut(&mac) {
-                    Some(beacon) => {
-                        beacon.realtime.state = BeaconState::Idle;
-                        beacon.realtime.last_active = Utc::now();
-                        beacon.realtime.ip = ip;
-                    },
-                    None => {
-                        self.find_beacon(context, mac);
-                    }
+            BMResponse::End(ip, mac, opt_id) => {
+                if self.acknowledge(context, BeaconCommandKind::EndEmergency, ip, mac, opt_id, |b| b.state = BeaconState::Idle) {
+                    changed = Some(mac);
                 }
             },
-            BMResponse::Ping(ip, mac) => {
-                match self.beacons.get_mut(&mac) {
-                    Some(beacon) => {
-                        beacon.realtime.last_active = Utc::now();
-                        beacon.realtime.ip = ip;
-                    },
-                    None => {
-                        self.find_beacon(context, mac);
-                    }
+            BMResponse::Ping(ip, mac, opt_id) => {
+                if self.acknowledge(context, BeaconCommandKind::Ping, ip, mac, opt_id, |_b| {}) {
+                    changed = Some(mac);
                 }
             },
-            BMResponse::Reboot(ip, mac) => {
-                match self.beacons.get_mut(&mac) {
-                    Some(beacon) => {
-                        beacon.realtime.state = BeaconState::Rebooting;
-                        beacon.realtime.last_active = Utc::now();
-                        beacon.realtime.ip = ip;
-                    },
-                    None => {
-                        self.find_beacon(context, mac);
-                    }
+            BMResponse::Reboot(ip, mac, opt_id) => {
+                if self.acknowledge(context, BeaconCommandKind::Reboot, ip, mac, opt_id, |b| b.state = BeaconState::Rebooting) {
+                    changed = Some(mac);
                 }
             },
-            BMResponse::SetIp(ip, mac) => {
-                match self.beacons.get_mut(&mac) {
-                    Some(beacon) => {
-                        beacon.realtime.last_active = Utc::now();
-                        beacon.realtime.ip = ip;
-                    },
-                    None => {
-                        self.find_beacon(context, mac);
-                    }
+            BMResponse::SetIp(ip, mac, opt_id) => {
+                if self.acknowledge(context, BeaconCommandKind::SetIp, ip, mac, opt_id, |_b| {}) {
+                    changed = Some(mac);
                 }
             },
             BMResponse::TagData(ip, tag_data) => {
                 match self.beacons.get_mut(&tag_data.beacon_mac) {
                     Some(beacon) => {
-                        if tag_data.tag_distance < 50.0 { // any distance over 50meter is garbage data
+                        if tag_data.tag_distance < self.config.max_distance { // any further distance is garbage data
+                            let now = Utc::now();
+                            // ranges arrive constantly, only push the beacon when it has gone quiet
+                            if now - beacon.realtime.last_active > cDuration::from_std(BEACON_PUSH_INTERVAL).unwrap() {
+                                changed = Some(tag_data.beacon_mac);
+                            }
                             self.diagnostic_data.tag_data.push(tag_data.clone());
+                            self.push.do_send(Publish(PushMessage::Range(tag_data.clone())));
                             self.data_processor.do_send(InLocationData(tag_data));
                             beacon.realtime.ip = ip;
-                            beacon.realtime.last_active = Utc::now();
+                            beacon.realtime.last_active = now;
                         }
                     },
                     None => {
-                        self.unknown_macs.insert(tag_data.beacon_mac);
+                        self.discover(tag_data.beacon_mac, ip);
                     }
                 }
             },
 
         }
+
+        if let Some(mac) = changed {
+            self.publish_beacon(&mac);
+        }
         Ok(())
     }
 }
 
+impl Handler<InBeaconAlert> for BeaconManager {
+    type Result = ();
+
+    fn handle(&mut self, msg: InBeaconAlert, _context: &mut Context<Self>) -> Self::Result {
+        let InBeaconAlert(alert) = msg;
+        if self.alerts.len() >= MAX_ALERTS {
+            self.alerts.pop_front();
+        }
+        self.alerts.push_back(alert.clone());
+        self.push.do_send(Publish(PushMessage::BeaconAlert(alert)));
+    }
+}
+
+impl Handler<InDiscoveredBeacon> for BeaconManager {
+    type Result = ();
+
+    fn handle(&mut self, msg: InDiscoveredBeacon, _context: &mut Context<Self>) -> Self::Result {
+        let InDiscoveredBeacon(mac, ip) = msg;
+        // a registered beacon that has not been given a key yet is not discovered again
+        if !self.beacons.contains_key(&mac) {
+            self.discover(mac, ip);
+        }
+    }
+}
+
+impl Handler<SetBeaconKey> for BeaconManager {
+    type Result = ();
+
+    fn handle(&mut self, msg: SetBeaconKey, _context: &mut Context<Self>) -> Self::Result {
+        for connection in &self.udp_connections {
+            connection.do_send(msg.clone());
+        }
+    }
+}
+
+// starts or ends the emergency on behalf of an account, for the given maps or the whole site
+// when map_ids is None
+pub struct SetEmergency {
+    pub active: bool,
+    pub account: String,
+    pub reason: Option<String>,
+    pub map_ids: Option<Vec<i32>>,
+}
+
+impl Message for SetEmergency {
+    type Result = Result<bool, AkError>;
+}
+
+impl Handler<SetEmergency> for BeaconManager {
+    type Result = Result<bool, AkError>;
+
+    fn handle(&mut self, msg: SetEmergency, context: &mut Context<Self>) -> Self::Result {
+        let change = if msg.active {
+            emergency_scope::start(&self.emergency, msg.map_ids)
+        } else {
+            emergency_scope::end(&self.emergency, msg.map_ids)
+        };
+        let change = change.map_err(AkError::validation)?;
+        self.set_scope(context, change, Some(msg.account), msg.reason);
+        self.schedule_health(context);
+        Ok(self.is_emergency())
+    }
+}
+
+pub struct OutEmergencyStatus;
+
+impl Message for OutEmergencyStatus {
+    type Result = Result<EmergencyStatus, AkError>;
+}
+
+impl Handler<OutEmergencyStatus> for BeaconManager {
+    type Result = Result<EmergencyStatus, AkError>;
+
+    fn handle(&mut self, _msg: OutEmergencyStatus, _context: &mut Context<Self>) -> Self::Result {
+        Ok(self.emergency.clone())
+    }
+}
+
+pub struct OutDiscoveredBeacons;
+
+impl Message for OutDiscoveredBeacons {
+    type Result = Result<Vec<DiscoveredBeacon>, AkError>;
+}
+
+impl Handler<OutDiscoveredBeacons> for BeaconManager {
+    type Result = Result<Vec<DiscoveredBeacon>, AkError>;
+
+    fn handle(&mut self, _msg: OutDiscoveredBeacons, _context: &mut Context<Self>) -> Self::Result {
+        let mut beacons: Vec<DiscoveredBeacon> = self.discovered.values()
+            .map(|discovered| {
+                let mut discovered = discovered.clone();
+                discovered.blocked = self.blocked.contains(&discovered.mac_address);
+                discovered
+            })
+            .collect();
+        // blocked beacons are listed even when they are quiet, so they can be unblocked
+        beacons.extend(self.blocked.iter()
+            .filter(|mac| !self.discovered.contains_key(mac))
+            .map(|mac| {
+                let mut discovered = DiscoveredBeacon::new(*mac);
+                discovered.blocked = true;
+                discovered
+            }));
+        Ok(beacons)
+    }
+}
+
+// a discovered beacon was registered, adopting a blocked beacon unblocks it
+pub struct AdoptBeacon(pub Beacon);
+
+impl Message for AdoptBeacon {
+    type Result = ();
+}
+
+impl Handler<AdoptBeacon> for BeaconManager {
+    type Result = ();
+
+    fn handle(&mut self, msg: AdoptBeacon, context: &mut Context<Self>) -> Self::Result {
+        let AdoptBeacon(beacon) = msg;
+        let mac = beacon.mac_address;
+        self.discovered.remove(&mac);
+        self.blocked.remove(&mac);
+        self.beacons.insert(mac, BeaconStatus {
+            realtime: RealtimeBeacon::from(beacon),
+            retries: None,
+        });
+        self.publish_beacon(&mac);
+        context.notify(BMCommand::Ping(Some(mac)));
+    }
+}
+
+// sent when a beacon is edited, the manager keeps its own copy of where each beacon is
+pub struct UpdateBeacon(pub Beacon);
+
+impl Message for UpdateBeacon {
+    type Result = ();
+}
+
+impl Handler<UpdateBeacon> for BeaconManager {
+    type Result = ();
+
+    fn handle(&mut self, msg: UpdateBeacon, _context: &mut Context<Self>) -> Self::Result {
+        let UpdateBeacon(beacon) = msg;
+        let mac = beacon.mac_address;
+        let moved = match self.beacons.get_mut(&mac) {
+            Some(status) => {
+                // the ip and state are only known to the manager, the edit may carry stale values
+                let moved = status.realtime.map_id != beacon.map_id;
+                status.realtime.id = beacon.id;
+                status.realtime.map_id = beacon.map_id;
+                moved
+            },
+            None => return,
+        };
+        self.publish_beacon(&mac);
+        if moved && self.emergency.active && self.emergency.map_ids.is_some() {
+            self.reconcile_beacon(mac);
+        }
+    }
+}
+
+// sent when a beacon is deleted, by its id
+pub struct RemoveBeacon(pub i32);
+
+impl Message for RemoveBeacon {
+    type Result = ();
+}
+
+impl Handler<RemoveBeacon> for BeaconManager {
+    type Result = ();
+
+    fn handle(&mut self, msg: RemoveBeacon, _context: &mut Context<Self>) -> Self::Result {
+        let RemoveBeacon(id) = msg;
+        self.beacons.retain(|_mac, status| status.realtime.id != id);
+    }
+}
+
+pub struct BlockBeacon(pub MacAddress8, pub bool);
+
+impl Message for BlockBeacon {
+    type Result = ();
+}
+
+impl Handler<BlockBeacon> for BeaconManager {
+    type Result = ();
+
+    fn handle(&mut self, msg: BlockBeacon, _context: &mut Context<Self>) -> Self::Result {
+        let BlockBeacon(mac, blocked) = msg;
+        if blocked {
+            self.blocked.insert(mac);
+        } else {
+            self.blocked.remove(&mac);
+        }
+    }
+}
+
 pub struct GetDiagnosticData;
 impl Message for GetDiagnosticData {
     type Result = Result<common::DiagnosticData, AkError>;
@@ -555,7 +1003,9 @@ impl Handler<GetDiagnosticData> for BeaconManager {
     type Result = Result<common::DiagnosticData, AkError>;
 
     fn handle(&mut self, _msg: GetDiagnosticData, _context: &mut Context<Self>) -> Self::Result {
-        let res = self.diagnostic_data.clone();
+        let mut res = self.diagnostic_data.clone();
+        res.command_latency = self.latency.values().cloned().collect();
+        res.alerts = self.alerts.iter().cloned().collect();
         self.diagnostic_data.tag_data = Vec::new();
         Ok(res)
     }
@@ -574,3 +1024,80 @@ impl Handler<OutBeaconData> for BeaconManager {
         Ok(self.beacons.iter().map(|(_mac, beacon)| beacon.realtime.clone()).collect())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix::System;
+    use crate::repository::memory::MemoryRepository;
+    use futures::Future;
+    use tokio::timer::Delay;
+
+    #[test]
+    fn dummy_beacons_locate_users() {
+        let memory = MemoryRepository::new();
+        let tag_mac = ShortAddress::from_bytes(&[0, 1]).unwrap();
+        {
+            let mut data = memory.data.lock().unwrap();
+            data.beacons = [(0.0, 0.0), (10.0, 0.0), (10.0, 10.0), (0.0, 10.0)]
+                .iter()
+                .enumerate()
+                .map(|(i, (x, y))| {
+                    let mut b = Beacon::new();
+                    b.id = i as i32 + 1;
+                    b.ip = IpAddr::V4(Ipv4Addr::new(10, 0, 0, i as u8 + 2));
+                    b.mac_address = MacAddress8::from_bytes(&[i as u8 + 1, 0, 0, 0, 0, 0, 0, 0]).unwrap();
+                    b.coordinates = na::Vector2::new(*x, *y);
+                    b.map_id = Some(1);
+                    b
+                })
+                .collect();
+            let mut user = TrackedUser::new();
+            user.id = 7;
+            user.mac_address = Some(tag_mac);
+            data.users = vec![user];
+        }
+        let repositories = Repositories::cached(memory.clone());
+
+        let mut config = BeaconConfig::default();
+        config.simulator.interval_ms = 100;
+        config.simulator.seed = Some(1);
+        config.simulator.range_noise = 0.0;
+        config.simulator.nlos_chance = 0.0;
+        config.simulator.dropout_chance = 0.0;
+        config.simulator.latency_ms = 0;
+        config.simulator.latency_jitter_ms = 0;
+
+        System::run(move || {
+            let push = PushBroadcaster::new().start();
+            let processor = DataProcessor::new(push.clone(), repositories.clone()).start();
+            let capture = Capture::new(std::env::temp_dir().to_string_lossy().into_owned()).start();
+            let manager = BeaconManager::new(processor.clone(), push, capture, repositories, config);
+
+            // the beacons and emergency are loaded once the manager has started
+            let task = Delay::new(Instant::now() + Duration::from_millis(100))
+                .map(move |_| {
+                    manager.do_send(BMCommand::ScanBeacons);
+                    // the dummy beacons only range the tags during an emergency
+                    manager.do_send(BMCommand::StartEmergency(None));
+                })
+                .and_then(|_| Delay::new(Instant::now() + Duration::from_millis(1500)))
+                .map_err(|_| panic!("timer failed"))
+                .and_then(move |_| processor.send(OutUserData).map_err(|_| panic!("data processor mailbox closed")))
+                .map(move |users| {
+                    let users = users.unwrap();
+                    assert_eq!(users.len(), 1);
+                    assert_eq!(users[0].id, 7);
+                    assert_eq!(users[0].map_id, Some(1));
+
+                    let data = memory.data.lock().unwrap();
+                    assert!(data.emergency.active);
+                    assert_eq!(data.emergency_events.len(), 1);
+                    assert!(!data.locations.is_empty());
+                    assert!(data.locations.iter().all(|record| record.user_id == 7));
+                    System::current().stop();
+                });
+            Arbiter::spawn(task);
+        }).unwrap();
+    }
+}
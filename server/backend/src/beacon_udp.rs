@@ -1,5 +1,9 @@
-// Unfortunately, due to using UDP sockets and broadcasts, it is difficult to associate a single
-// message from the manager with a set of responses to the j
+// Commands are sent with the request id from the manager as their sequence number, beacons using
+// the binary format echo it back so the manager can match each reply to its request.
+// Messages from beacons are checked before they reach the manager. A beacon with a key must sign
+// every message with it, and beacons without a key are only heard when unsigned messages are
+// allowed. Anything else is dropped and reported to the manager as an alert, a beacon without a
+// key is also reported as discovered so that it can be registered and given one.
 extern crate actix;
 extern crate tokio;
 extern crate futures;
@@ -9,22 +13,44 @@ use actix::io::{ WriteHandler, SinkWrite, };
 use actix::prelude::*;
 use actix::{ Actor, Context, StreamHandler, };
 use bytes::{ BytesMut, Bytes };
+use chrono::Utc;
+use common::{ BeaconAlert, MacAddress8, };
+use common::beacon_protocol::{ self, Message, Packet, ReplayWindow, WireFormat, };
 use crate::beacon_manager::*;
-use crate::conn_common;
+use crate::capture::{ Capture, CapturedFrame, };
+use crate::conn_common::{ self, Received, };
+use crate::models::beacon::BeaconKey;
+use crate::repository::Repositories;
 use futures::stream::SplitSink;
-use futures::{ Stream, };
+use futures::{ Future, Stream, };
 use ipnet::Ipv4Net;
+use net2::UdpBuilder;
+use std::collections::HashMap;
 use std::io;
 use std::net::{ Ipv4Addr, IpAddr, };
 use std::net::SocketAddr;
+use std::time::{ Duration, Instant, };
 use tokio::codec::BytesCodec;
 use tokio::net::{ UdpSocket, UdpFramed };
+use tokio::reactor::Handle;
+
+// an address only raises one alert in this time, so a flood of bad messages is not a flood of alerts
+const ALERT_INTERVAL: Duration = Duration::from_secs(10);
 
 pub struct BeaconUDP {
     bound_ip: Ipv4Net,
     bound_port: u16,
     manager: Addr<BeaconManager>,
+    capture: Addr<Capture>,
     sink: SinkWrite<SplitSink<UdpFramed<BytesCodec>>>,
+    // the negotiated format of each beacon, beacons that are not in here use the legacy format
+    formats: HashMap<IpAddr, WireFormat>,
+    allow_unsigned: bool,
+    keys: HashMap<MacAddress8, BeaconKey>,
+    // the counters accepted from each beacon that signs its messages
+    windows: HashMap<MacAddress8, ReplayWindow>,
+    alerted: HashMap<IpAddr, Instant>,
+    repositories: Repositories,
 }
 
 impl WriteHandler<io::Error> for BeaconUDP {
@@ -50,68 +76,234 @@ struct Frame {
 }
 
 impl StreamHandler<Frame, io::Error> for BeaconUDP {
-    fn handle(&mut self, msg: Frame, _: &mut Context<Self>) {
-        let response = String::from_utf8_lossy(&msg.data);
-        match conn_common::parse_message(&response, msg.addr.ip()) {
-            Ok(bm_response) => {
-                self.manager
-                    .do_send(bm_response);
+    fn handle(&mut self, msg: Frame, context: &mut Context<Self>) {
+        let ip = msg.addr.ip();
+        // everything that arrives is captured, including what is rejected below
+        self.capture.do_send(CapturedFrame::new(ip, &msg.data));
+        match conn_common::parse_message(&msg.data) {
+            Ok(received) => {
+                let mac = match received.message.beacon_mac() {
+                    Some(mac) => mac,
+                    // a message meant for beacons, ie our own broadcast
+                    None => return,
+                };
+                if let Err(reason) = self.authenticate(context, mac, &msg.data, &received) {
+                    if !self.keys.contains_key(&mac) {
+                        self.manager.do_send(InDiscoveredBeacon(mac, ip));
+                    }
+                    self.alert(ip, Some(mac), reason);
+                    return;
+                }
+
+                // a beacon that rebooted into older firmware falls back to the legacy format
+                if self.formats.insert(ip, received.format) != Some(received.format) {
+                    println!("beacon {} is using {:?}", ip, received.format);
+                }
+
+                if let Some(bm_response) = conn_common::to_response(received.message, received.sequence, ip) {
+                    self.manager
+                        .do_send(bm_response);
+                }
             },
             Err(e) => {
-                println!("failed to parse message from udp beacon: {}, {}", e, response);
+                println!("failed to parse message from udp beacon: {}, {}", e, String::from_utf8_lossy(&msg.data));
+                self.alert(ip, None, e.to_string());
             }
         }
     }
 }
 
+impl Handler<SetBeaconKey> for BeaconUDP {
+    type Result = ();
+
+    fn handle(&mut self, msg: SetBeaconKey, _context: &mut Context<Self>) -> Self::Result {
+        // the counters are kept through a rotation, otherwise frames signed with the previous key
+        // could be replayed
+        let SetBeaconKey(mac, opt_key) = msg;
+        match opt_key {
+            Some(key) => {
+                self.keys.insert(mac, key);
+            },
+            None => {
+                self.keys.remove(&mac);
+                self.windows.remove(&mac);
+            },
+        }
+    }
+}
+
 impl Handler<BeaconCommand> for BeaconUDP {
     type Result = Result<(), ()>;
 
     fn handle(&mut self, msg: BeaconCommand, _context: &mut Context<Self>) -> Self::Result {
-        let (command, ip) = match msg {
-            BeaconCommand::StartEmergency(opt_ip)   => self.build_request("[start]".to_owned(), opt_ip),
-            BeaconCommand::EndEmergency(opt_ip)     => self.build_request("[end]".to_owned(), opt_ip),
-            BeaconCommand::Ping(opt_ip)             => self.build_request("[ping]".to_owned(), opt_ip),
-            BeaconCommand::Reboot(opt_ip)           => self.build_request("[reboot]".to_owned(), opt_ip),
-            BeaconCommand::SetIp(ip)                => self.build_request(format!("[setip|{}]", ip), None),
+        let (id, message, opt_ip) = match msg {
+            BeaconCommand::StartEmergency(id, opt_ip)   => (id, Message::Start, opt_ip),
+            BeaconCommand::EndEmergency(id, opt_ip)     => (id, Message::End, opt_ip),
+            BeaconCommand::Ping(id, opt_ip)             => (id, Message::Ping, opt_ip),
+            BeaconCommand::Reboot(id, opt_ip)           => (id, Message::Reboot, opt_ip),
+            BeaconCommand::SetIp(id, ip)                => (id, Message::SetIp(ip), None),
         };
 
-        self.sink
-            .write((Bytes::from(command), ip))
-            .map(|_s| {})
-            .map_err(|_e| {})
+        // broadcast pings also offer the binary format to beacons that have not negotiated yet,
+        // ie after they reboot or join the network
+        if let (Message::Ping, None) = (&message, opt_ip) {
+            self.send_hello();
+        }
+        self.send(id, message, opt_ip)
     }
 }
 
 impl BeaconUDP {
-    pub fn new(manager: Addr<BeaconManager>, ip: Ipv4Net, port: u16) -> Addr<BeaconUDP> {
+    pub fn new(manager: Addr<BeaconManager>, capture: Addr<Capture>, repositories: Repositories, ip: Ipv4Net, port: u16, keys: HashMap<MacAddress8, BeaconKey>, allow_unsigned: bool) -> Addr<BeaconUDP> {
         // TODO test is this necessary?
         let bind_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), port);
 
-        let sock = UdpSocket::bind(&bind_addr).unwrap();
+        // the port can be shared, ie with the beacon emulator listening on loopback addresses
+        let std_sock = UdpBuilder::new_v4()
+            .and_then(|builder| builder.reuse_address(true)?.bind(&bind_addr))
+            .unwrap();
+        let sock = UdpSocket::from_std(std_sock, &Handle::default()).unwrap();
         sock.set_broadcast(true).expect("could not set broadcast");
 
         let (sink, stream) = UdpFramed::new(sock, BytesCodec::new()).split();
         BeaconUDP::create(move |context| {
             context.add_stream(stream.map(|(data, sender)| Frame { data, addr: sender }));
             let sw = SinkWrite::new(sink, context);
-            BeaconUDP {
+            // carry on from the counters accepted before the backend was restarted
+            let windows = keys.iter()
+                .filter_map(|(mac, key)| key.counter.map(|counter| (*mac, ReplayWindow::resume(counter))))
+                .collect();
+            let mut connection = BeaconUDP {
                 bound_ip: ip,
                 bound_port: port,
                 manager,
+                capture,
                 sink: sw,
-            }
+                formats: HashMap::new(),
+                allow_unsigned,
+                keys,
+                windows,
+                alerted: HashMap::new(),
+                repositories,
+            };
+            connection.send_hello();
+            connection
         })
     }
 
-    pub fn build_request<'a>(&mut self, command: String, opt_ip: Option<IpAddr>) -> (String, SocketAddr) {
-        if let Some(ip) = opt_ip {
-            let addr = SocketAddr::new(ip, self.bound_port);
-            (command, addr)
+    // checks that the message was sent by the beacon it claims to be from
+    fn authenticate(&mut self, context: &mut Context<Self>, mac: MacAddress8, data: &[u8], received: &Received) -> Result<(), String> {
+        let key = match (self.keys.get_mut(&mac), &received.signature) {
+            (Some(key), Some(_signature)) => key,
+            (Some(_key), None) => return Err("unsigned message from a beacon with a key".to_string()),
+            (None, _) if self.allow_unsigned => return Ok(()),
+            (None, Some(_signature)) => return Err("signed message from a beacon without a key".to_string()),
+            (None, None) => return Err("unsigned message".to_string()),
+        };
+        let signature = received.signature.as_ref().unwrap();
+
+        if beacon_protocol::verify(data, signature, &key.current) {
+            // the beacon has the current key, the previous key is no longer accepted, also after
+            // the backend is restarted
+            if key.previous.take().is_some() {
+                let fut = self.repositories.beacons.clear_previous_beacon_key(mac, key.current.clone())
+                    .map_err(move |e| {
+                        println!("failed to clear the previous key of beacon {}: {:?}", mac.to_hex_string(), e);
+                    });
+                context.spawn(fut.into_actor(self));
+            }
         } else {
-            let broadcast = SocketAddr::new(IpAddr::V4(self.bound_ip.broadcast()), self.bound_port);
-            (command, broadcast)
+            let previous = key.previous.as_ref().map_or(false, |previous| beacon_protocol::verify(data, signature, previous));
+            if !previous {
+                return Err("invalid signature".to_string());
+            }
+        }
+
+        let window = self.windows.entry(mac).or_insert_with(ReplayWindow::new);
+        let highest = window.highest();
+        if !window.accept(signature.counter) {
+            return Err(format!("replayed message, counter {}", signature.counter));
         }
+        if window.highest() != highest {
+            let fut = self.repositories.beacons.update_beacon_counter(mac, signature.counter)
+                .map_err(move |e| {
+                    println!("failed to save the counter of beacon {}: {:?}", mac.to_hex_string(), e);
+                });
+            context.spawn(fut.into_actor(self));
+        }
+        Ok(())
+    }
+
+    fn alert(&mut self, ip: IpAddr, mac: Option<MacAddress8>, reason: String) {
+        let now = Instant::now();
+        if let Some(last) = self.alerted.get(&ip) {
+            if now.duration_since(*last) < ALERT_INTERVAL {
+                return;
+            }
+        }
+        self.alerted.retain(|_ip, last| now.duration_since(*last) < ALERT_INTERVAL);
+        self.alerted.insert(ip, now);
+
+        println!("rejected message from {}: {}", ip, reason);
+        self.manager.do_send(InBeaconAlert(BeaconAlert {
+            ip,
+            mac,
+            reason,
+            timestamp: Utc::now(),
+        }));
+    }
+
+    fn send_hello(&mut self) {
+        // the hello does not answer to the manager, so it does not use a request id
+        let hello = Message::Hello(beacon_protocol::SUPPORTED_VERSIONS.to_vec());
+        let _ = self.send_binary(0, hello, beacon_protocol::VERSION, None);
+    }
+
+    // sends the message to a beacon in the format it negotiated. A broadcast goes out in every
+    // format in use, beacons that negotiated the binary format ignore the legacy copy.
+    fn send(&mut self, id: RequestId, message: Message, opt_ip: Option<IpAddr>) -> Result<(), ()> {
+        match opt_ip {
+            Some(ip) => {
+                match self.formats.get(&ip).cloned().unwrap_or(WireFormat::Legacy) {
+                    WireFormat::Binary(version) => self.send_binary(id, message, version, Some(ip)),
+                    WireFormat::Legacy => self.send_legacy(&message, Some(ip)),
+                }
+            },
+            None => {
+                let any_binary = self.formats.values().any(|f| *f != WireFormat::Legacy);
+                let legacy = self.send_legacy(&message, None);
+                if any_binary {
+                    self.send_binary(id, message, beacon_protocol::VERSION, None).and(legacy)
+                } else {
+                    legacy
+                }
+            },
+        }
+    }
+
+    fn send_binary(&mut self, id: RequestId, message: Message, version: u8, opt_ip: Option<IpAddr>) -> Result<(), ()> {
+        let mut packet = Packet::new(id, message);
+        packet.version = version;
+        let frame = beacon_protocol::encode(&packet);
+        self.write(Bytes::from(frame), opt_ip)
+    }
+
+    fn send_legacy(&mut self, message: &Message, opt_ip: Option<IpAddr>) -> Result<(), ()> {
+        match beacon_protocol::encode_legacy(message) {
+            Some(text) => self.write(Bytes::from(text), opt_ip),
+            None => Err(()),
+        }
+    }
+
+    fn write(&mut self, data: Bytes, opt_ip: Option<IpAddr>) -> Result<(), ()> {
+        let addr = match opt_ip {
+            Some(ip) => SocketAddr::new(ip, self.bound_port),
+            None => SocketAddr::new(IpAddr::V4(self.bound_ip.broadcast()), self.bound_port),
+        };
+        self.sink
+            .write((data, addr))
+            .map(|_s| {})
+            .map_err(|_e| {})
     }
 
 }
@@ -0,0 +1,229 @@
+// Records exactly what the udp connections receive, before it is parsed or checked, so that a
+// session in the field can be replayed into a backend running with dummy beacons. A capture is a
+// file of json lines, one for every frame, with when and where the frame came from and its bytes
+// in hex. Captures are started and stopped by an admin, and are written to the capture directory.
+
+use actix::prelude::*;
+use common::*;
+use crate::ak_error::AkError;
+use crate::hex::{ decode_hex, encode_hex, };
+use serde_derive::{ Deserialize, Serialize, };
+use std::fs::{ self, File, OpenOptions, };
+use std::io::{ self, Write, };
+use std::net::IpAddr;
+use std::path::Path;
+
+const MAX_NAME_LENGTH: usize = 64;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CapturedFrame {
+    // hex encoded
+    pub data: String,
+    pub ip: IpAddr,
+    pub timestamp: DateTime<Utc>,
+}
+
+impl Message for CapturedFrame {
+    type Result = ();
+}
+
+impl CapturedFrame {
+    pub fn new(ip: IpAddr, data: &[u8]) -> CapturedFrame {
+        CapturedFrame {
+            data: encode_hex(data),
+            ip,
+            timestamp: Utc::now(),
+        }
+    }
+
+    pub fn bytes(&self) -> Option<Vec<u8>> {
+        decode_hex(&self.data)
+    }
+}
+
+// every frame of a capture file, in the order they were received
+pub fn read_capture(file: &str) -> Result<Vec<CapturedFrame>, String> {
+    let contents = fs::read_to_string(file)
+        .map_err(|e| format!("failed to read capture {}: {}", file, e))?;
+    contents
+        .lines()
+        .enumerate()
+        .filter(|(_index, line)| !line.trim().is_empty())
+        .map(|(index, line)| {
+            serde_json::from_str::<CapturedFrame>(line)
+                .map_err(|e| e.to_string())
+                .and_then(|frame| match frame.bytes() {
+                    Some(_bytes) => Ok(frame),
+                    None => Err("data is not hex encoded".to_string()),
+                })
+                .map_err(|e| format!("invalid frame on line {} of capture {}: {}", index + 1, file, e))
+        })
+        .collect()
+}
+
+// names become file names, so they are kept to characters that are safe in a path
+fn valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= MAX_NAME_LENGTH
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+pub struct Capture {
+    directory: String,
+    file: Option<File>,
+    status: CaptureStatus,
+}
+
+impl Capture {
+    pub fn new(directory: String) -> Capture {
+        Capture {
+            directory,
+            file: None,
+            status: CaptureStatus::default(),
+        }
+    }
+
+    fn stop(&mut self) {
+        if self.file.take().is_some() {
+            println!("stopped capture {} after {} frames", self.status.file.as_ref().map_or("", |f| f.as_str()), self.status.frames);
+        }
+        self.status.active = false;
+    }
+}
+
+impl Actor for Capture {
+    type Context = Context<Self>;
+}
+
+impl Handler<CapturedFrame> for Capture {
+    type Result = ();
+
+    fn handle(&mut self, msg: CapturedFrame, _context: &mut Context<Self>) -> Self::Result {
+        let file = match &mut self.file {
+            Some(file) => file,
+            None => return,
+        };
+        let written = serde_json::to_string(&msg)
+            .map_err(|e| e.to_string())
+            .and_then(|line| writeln!(file, "{}", line).map_err(|e| e.to_string()));
+        match written {
+            Ok(()) => self.status.frames += 1,
+            Err(e) => {
+                // a full disk should not fail the connections, the capture is just cut short
+                println!("failed to write capture: {}", e);
+                self.stop();
+            },
+        }
+    }
+}
+
+pub struct StartCapture(pub CaptureRequest);
+
+impl Message for StartCapture {
+    type Result = Result<CaptureStatus, AkError>;
+}
+
+impl Handler<StartCapture> for Capture {
+    type Result = Result<CaptureStatus, AkError>;
+
+    fn handle(&mut self, msg: StartCapture, _context: &mut Context<Self>) -> Self::Result {
+        if self.file.is_some() {
+            return Err(AkError::bad_request("a capture is already running"));
+        }
+        let name = match msg.0.name {
+            Some(name) => name,
+            None => Utc::now().format("capture-%Y%m%d-%H%M%S").to_string(),
+        };
+        if !valid_name(&name) {
+            return Err(AkError::validation(&format!("capture names must be up to {} letters, numbers, - or _", MAX_NAME_LENGTH)));
+        }
+
+        let path = Path::new(&self.directory).join(format!("{}.jsonl", name));
+        let opened = fs::create_dir_all(&self.directory)
+            .and_then(|_| OpenOptions::new().write(true).create_new(true).open(&path));
+        let file = match opened {
+            Ok(file) => file,
+            Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                return Err(AkError::bad_request(&format!("a capture named {} already exists", name)));
+            },
+            Err(e) => {
+                println!("failed to create capture {}: {}", path.display(), e);
+                return Err(AkError::internal());
+            },
+        };
+
+        println!("started capture {}", path.display());
+        self.file = Some(file);
+        self.status = CaptureStatus {
+            file: Some(path.to_string_lossy().into_owned()),
+            active: true,
+            frames: 0,
+            started_at: Some(Utc::now()),
+        };
+        Ok(self.status.clone())
+    }
+}
+
+pub struct StopCapture;
+
+impl Message for StopCapture {
+    type Result = Result<CaptureStatus, AkError>;
+}
+
+impl Handler<StopCapture> for Capture {
+    type Result = Result<CaptureStatus, AkError>;
+
+    fn handle(&mut self, _msg: StopCapture, _context: &mut Context<Self>) -> Self::Result {
+        self.stop();
+        Ok(self.status.clone())
+    }
+}
+
+pub struct OutCaptureStatus;
+
+impl Message for OutCaptureStatus {
+    type Result = Result<CaptureStatus, AkError>;
+}
+
+impl Handler<OutCaptureStatus> for Capture {
+    type Result = Result<CaptureStatus, AkError>;
+
+    fn handle(&mut self, _msg: OutCaptureStatus, _context: &mut Context<Self>) -> Self::Result {
+        Ok(self.status.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn names() {
+        assert!(valid_name("site-visit_2"));
+        assert!(!valid_name(""));
+        assert!(!valid_name("../config"));
+        assert!(!valid_name("a b"));
+        assert!(!valid_name(&"a".repeat(MAX_NAME_LENGTH + 1)));
+    }
+
+    #[test]
+    fn read_frames() {
+        let file = std::env::temp_dir()
+            .join(format!("ak_capture_{}.jsonl", std::process::id()))
+            .to_string_lossy()
+            .into_owned();
+        let frame = CapturedFrame::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)), &[0xa5, 0x01, 0xff]);
+        let line = serde_json::to_string(&frame).unwrap();
+
+        fs::write(&file, format!("{}\n\n{}\n", line, line)).unwrap();
+        let frames = read_capture(&file).unwrap();
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[1].bytes(), Some(vec![0xa5, 0x01, 0xff]));
+        assert_eq!(frames[1].ip, frame.ip);
+
+        fs::write(&file, format!("{}\n{}\n", line, line.replace("a501ff", "zz"))).unwrap();
+        assert!(read_capture(&file).unwrap_err().contains("line 2"));
+        fs::remove_file(&file).unwrap();
+    }
+}
@@ -0,0 +1,495 @@
+// Runtime configuration of the backend. Values are read from a toml file, then overridden by
+// any command line arguments, and checked once before the webserver is started.
+// Every field has a default, so the file only needs to contain the values that differ.
+
+use clap::{ App, Arg, ArgMatches, };
+use crate::capture;
+use crate::cookie_policy::CookieConfig;
+use crate::db_utils::{ ADMIN_CONNECTION, DEFAULT_CONNECTION, RESPONDER_CONNECTION, };
+use serde_derive::{ Deserialize, Serialize, };
+use simulator::SimulatorConfig;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+
+pub const DEFAULT_CONFIG_FILE: &str = "akriveia.toml";
+const REDACTED: &str = "<redacted>";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub bind: String,
+    pub static_dir: String,
+}
+
+impl Default for ServerConfig {
+    fn default() -> ServerConfig {
+        ServerConfig {
+            bind: "0.0.0.0:8080".to_string(),
+            static_dir: "static".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseConfig {
+    // used by the actors and for system configuration
+    pub connection: String,
+    pub admin_connection: String,
+    pub responder_connection: String,
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> DatabaseConfig {
+        DatabaseConfig {
+            connection: DEFAULT_CONNECTION.to_string(),
+            admin_connection: ADMIN_CONNECTION.to_string(),
+            responder_connection: RESPONDER_CONNECTION.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BeaconConfig {
+    pub use_dummy: bool,
+    pub use_udp: bool,
+    pub ping_interval_ms: u64,
+    pub emergency_ping_interval_ms: u64,
+    // how long a beacon has to reply to a request before it is retried
+    pub response_threshold_ms: u64,
+    // retries before a beacon is rebooted, and then marked unknown
+    pub retries_threshold: u32,
+    // ranges further than this are discarded as garbage data, in meters
+    pub max_distance: f64,
+    // accept unsigned messages from beacons that have not been provisioned with a key
+    pub allow_unsigned: bool,
+    pub simulator: SimulatorConfig,
+    pub replay: ReplayConfig,
+}
+
+impl Default for BeaconConfig {
+    fn default() -> BeaconConfig {
+        BeaconConfig {
+            use_dummy: true,
+            use_udp: false,
+            ping_interval_ms: 100000,
+            emergency_ping_interval_ms: 10000,
+            response_threshold_ms: 2000,
+            retries_threshold: 4,
+            max_distance: 50.0,
+            allow_unsigned: false,
+            simulator: SimulatorConfig::default(),
+            replay: ReplayConfig::default(),
+        }
+    }
+}
+
+impl BeaconConfig {
+    pub fn ping_interval(&self) -> Duration {
+        Duration::from_millis(self.ping_interval_ms)
+    }
+
+    pub fn emergency_ping_interval(&self) -> Duration {
+        Duration::from_millis(self.emergency_ping_interval_ms)
+    }
+
+    pub fn response_threshold(&self) -> Duration {
+        Duration::from_millis(self.response_threshold_ms)
+    }
+}
+
+// a capture of real beacons played back by the dummy beacons, in place of the simulated tags
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReplayConfig {
+    // the tags are simulated when not set
+    pub file: Option<String>,
+    // 2.0 plays the frames twice as fast as they were received
+    pub speed: f64,
+    // start over at the first frame after the last one
+    pub looped: bool,
+}
+
+impl Default for ReplayConfig {
+    fn default() -> ReplayConfig {
+        ReplayConfig {
+            file: None,
+            speed: 1.0,
+            looped: false,
+        }
+    }
+}
+
+impl ReplayConfig {
+    fn validate(&self) -> Result<(), String> {
+        if !self.speed.is_finite() || self.speed <= 0.0 {
+            return Err(format!("beacons.replay.speed must be a positive speed, not {}", self.speed));
+        }
+        if let Some(file) = &self.file {
+            let frames = capture::read_capture(file)?;
+            if frames.is_empty() {
+                return Err(format!("beacons.replay.file {} has no frames", file));
+            }
+            // every frame would be due at once, and the capture would be played over and over
+            if self.looped && frames.iter().all(|frame| frame.timestamp <= frames[0].timestamp) {
+                return Err(format!("beacons.replay.file {} takes no time to play, so it cannot be looped", file));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CaptureConfig {
+    // where captures of the udp beacons are written
+    pub directory: String,
+}
+
+impl Default for CaptureConfig {
+    fn default() -> CaptureConfig {
+        CaptureConfig {
+            directory: "captures".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SessionConfig {
+    // hex encoded signing key, when set the key file is not used
+    pub key: Option<String>,
+    pub key_file: String,
+    // how long a login lasts
+    pub hours: i64,
+    // strict, lax or none
+    pub same_site: String,
+    // only send the cookie over https
+    pub secure: bool,
+}
+
+impl Default for SessionConfig {
+    fn default() -> SessionConfig {
+        SessionConfig {
+            key: None,
+            key_file: "session.key".to_string(),
+            hours: 12,
+            same_site: "strict".to_string(),
+            secure: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub database: DatabaseConfig,
+    pub beacons: BeaconConfig,
+    pub capture: CaptureConfig,
+    pub session: SessionConfig,
+}
+
+impl Config {
+    pub fn from_toml(contents: &str) -> Result<Config, String> {
+        toml::from_str(contents).map_err(|e| format!("invalid config file: {}", e))
+    }
+
+    // reads the config file named on the command line, or the default file if it exists,
+    // then applies the command line overrides.
+    pub fn from_args() -> Result<Config, String> {
+        let matches = cli().get_matches();
+
+        let mut config = match matches.value_of("config") {
+            Some(file) => Config::from_file(file)?,
+            None if Path::new(DEFAULT_CONFIG_FILE).exists() => Config::from_file(DEFAULT_CONFIG_FILE)?,
+            None => Config::default(),
+        };
+        config.apply_args(&matches)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn from_file(file: &str) -> Result<Config, String> {
+        let contents = fs::read_to_string(file)
+            .map_err(|e| format!("failed to read config file {}: {}", file, e))?;
+        Config::from_toml(&contents)
+    }
+
+    fn apply_args(&mut self, matches: &ArgMatches) -> Result<(), String> {
+        set_arg(matches, "bind", &mut self.server.bind)?;
+        set_arg(matches, "static_dir", &mut self.server.static_dir)?;
+
+        set_arg(matches, "db", &mut self.database.connection)?;
+        set_arg(matches, "admin_db", &mut self.database.admin_connection)?;
+        set_arg(matches, "responder_db", &mut self.database.responder_connection)?;
+
+        set_arg(matches, "dummy_beacons", &mut self.beacons.use_dummy)?;
+        set_arg(matches, "udp_beacons", &mut self.beacons.use_udp)?;
+        set_arg(matches, "ping_interval", &mut self.beacons.ping_interval_ms)?;
+        set_arg(matches, "emergency_ping_interval", &mut self.beacons.emergency_ping_interval_ms)?;
+        set_arg(matches, "response_threshold", &mut self.beacons.response_threshold_ms)?;
+        set_arg(matches, "retries_threshold", &mut self.beacons.retries_threshold)?;
+        set_arg(matches, "max_distance", &mut self.beacons.max_distance)?;
+        set_arg(matches, "allow_unsigned", &mut self.beacons.allow_unsigned)?;
+        set_optional_arg(matches, "scenario", &mut self.beacons.simulator.scenario_file);
+        set_optional_arg(matches, "ground_truth", &mut self.beacons.simulator.ground_truth_file);
+        set_optional_arg(matches, "replay", &mut self.beacons.replay.file);
+        set_arg(matches, "replay_speed", &mut self.beacons.replay.speed)?;
+
+        set_arg(matches, "capture_dir", &mut self.capture.directory)?;
+
+        set_arg(matches, "session_key_file", &mut self.session.key_file)?;
+        set_arg(matches, "session_hours", &mut self.session.hours)?;
+        set_arg(matches, "same_site", &mut self.session.same_site)?;
+        set_arg(matches, "secure_cookie", &mut self.session.secure)?;
+        Ok(())
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        SocketAddr::from_str(&self.server.bind)
+            .map_err(|_e| format!("server.bind must be an address and port, not {}", self.server.bind))?;
+        if !Path::new(&self.server.static_dir).is_dir() {
+            return Err(format!("server.static_dir {} is not a directory", self.server.static_dir));
+        }
+
+        let connections = [
+            ("database.connection", &self.database.connection),
+            ("database.admin_connection", &self.database.admin_connection),
+            ("database.responder_connection", &self.database.responder_connection),
+        ];
+        for (name, connection) in connections.iter() {
+            connection.parse::<tokio_postgres::Config>()
+                .map_err(|e| format!("{} is not a valid connection string: {}", name, e))?;
+        }
+
+        let beacons = &self.beacons;
+        if beacons.response_threshold_ms == 0 {
+            return Err("beacons.response_threshold_ms must be greater than 0".to_string());
+        }
+        // a ping has to be answered, or retried, before the next one is sent
+        if beacons.ping_interval_ms <= beacons.response_threshold_ms {
+            return Err("beacons.ping_interval_ms must be greater than beacons.response_threshold_ms".to_string());
+        }
+        if beacons.emergency_ping_interval_ms <= beacons.response_threshold_ms {
+            return Err("beacons.emergency_ping_interval_ms must be greater than beacons.response_threshold_ms".to_string());
+        }
+        if beacons.retries_threshold == 0 {
+            return Err("beacons.retries_threshold must be at least 1".to_string());
+        }
+        if !beacons.max_distance.is_finite() || beacons.max_distance <= 0.0 {
+            return Err(format!("beacons.max_distance must be a positive distance, not {}", beacons.max_distance));
+        }
+        beacons.simulator.validate().map_err(|e| format!("beacons.{}", e))?;
+        beacons.replay.validate()?;
+        if beacons.replay.file.is_some() && !beacons.use_dummy {
+            return Err("beacons.replay.file is played by the dummy beacons, beacons.use_dummy must be true".to_string());
+        }
+        if self.capture.directory.trim().is_empty() {
+            return Err("capture.directory must not be empty".to_string());
+        }
+
+        CookieConfig::from_config(&self.session).map(|_cookie| ())
+    }
+
+    // the config as shown to admins, without passwords or keys
+    pub fn redacted(&self) -> Config {
+        let mut config = self.clone();
+        config.database.connection = redact_password(&config.database.connection);
+        config.database.admin_connection = redact_password(&config.database.admin_connection);
+        config.database.responder_connection = redact_password(&config.database.responder_connection);
+        if config.session.key.is_some() {
+            config.session.key = Some(REDACTED.to_string());
+        }
+        config
+    }
+}
+
+fn cli<'a, 'b>() -> App<'a, 'b> {
+    let value = |name: &'a str, long: &'a str, help: &'a str| {
+        Arg::with_name(name)
+            .long(long)
+            .takes_value(true)
+            .help(help)
+    };
+
+    App::new("backend")
+        .about("Akriveia webserver and beacon manager")
+        .arg(value("config", "config", "toml config file, defaults to akriveia.toml when it exists").value_name("FILE"))
+        .arg(value("bind", "bind", "address and port of the webserver"))
+        .arg(value("static_dir", "static-dir", "directory of the frontend files"))
+        .arg(value("db", "db", "connection string of the system database login"))
+        .arg(value("admin_db", "admin-db", "connection string of the admin database login"))
+        .arg(value("responder_db", "responder-db", "connection string of the responder database login"))
+        .arg(value("dummy_beacons", "dummy-beacons", "simulate beacons, true or false"))
+        .arg(value("udp_beacons", "udp-beacons", "talk to beacons over udp, true or false"))
+        .arg(value("ping_interval", "ping-interval", "milliseconds between pings while idle"))
+        .arg(value("emergency_ping_interval", "emergency-ping-interval", "milliseconds between pings during an emergency"))
+        .arg(value("response_threshold", "response-threshold", "milliseconds a beacon has to reply before it is retried"))
+        .arg(value("retries_threshold", "retries-threshold", "retries before a beacon is rebooted"))
+        .arg(value("max_distance", "max-distance", "ranges further than this many meters are discarded"))
+        .arg(value("allow_unsigned", "allow-unsigned", "accept unsigned messages from beacons without a key, true or false"))
+        .arg(value("scenario", "scenario", "toml file of scripted paths for the simulated tags").value_name("FILE"))
+        .arg(value("ground_truth", "ground-truth", "file the true locations of the simulated tags are appended to").value_name("FILE"))
+        .arg(value("replay", "replay", "capture played back by the dummy beacons instead of simulating tags").value_name("FILE"))
+        .arg(value("replay_speed", "replay-speed", "how many times faster than it was recorded the capture is played back"))
+        .arg(value("capture_dir", "capture-dir", "directory captures of the udp beacons are written to"))
+        .arg(value("session_key_file", "session-key-file", "file the generated session key is kept in"))
+        .arg(value("session_hours", "session-hours", "how long a login lasts"))
+        .arg(value("same_site", "same-site", "SameSite attribute of the session cookie, strict, lax or none"))
+        .arg(value("secure_cookie", "secure-cookie", "only send the session cookie over https, true or false"))
+}
+
+fn set_arg<T: FromStr>(matches: &ArgMatches, name: &str, value: &mut T) -> Result<(), String> {
+    if let Some(raw) = matches.value_of(name) {
+        *value = raw.parse::<T>()
+            .map_err(|_e| format!("invalid value for --{}: {}", name.replace('_', "-"), raw))?;
+    }
+    Ok(())
+}
+
+fn set_optional_arg(matches: &ArgMatches, name: &str, value: &mut Option<String>) {
+    if let Some(raw) = matches.value_of(name) {
+        *value = Some(raw.to_string());
+    }
+}
+
+fn redact_password(params: &str) -> String {
+    params
+        .split_whitespace()
+        .map(|pair| {
+            if pair.starts_with("password=") {
+                format!("password={}", REDACTED)
+            } else {
+                pair.to_string()
+            }
+        })
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn partial_file() {
+        let config = Config::from_toml("
+            [server]
+            bind = \"127.0.0.1:9000\"
+
+            [beacons]
+            use_udp = true
+            max_distance = 30.0
+        ").unwrap();
+
+        assert_eq!(config.server.bind, "127.0.0.1:9000");
+        assert_eq!(config.server.static_dir, ServerConfig::default().static_dir);
+        assert!(config.beacons.use_udp);
+        assert!(config.beacons.use_dummy);
+        assert_eq!(config.beacons.max_distance, 30.0);
+        assert_eq!(config.beacons.retries_threshold, BeaconConfig::default().retries_threshold);
+        assert!(Config::from_toml("[beacons]\nunknown = 1").is_err());
+
+        let config = Config::from_toml("[beacons.simulator]\nseed = 7\nrange_noise = 0.0").unwrap();
+        assert_eq!(config.beacons.simulator.seed, Some(7));
+        assert_eq!(config.beacons.simulator.range_noise, 0.0);
+        assert_eq!(config.beacons.simulator.interval_ms, SimulatorConfig::default().interval_ms);
+
+        let config = Config::from_toml("[beacons.replay]\nspeed = 4.0\n[capture]\ndirectory = \"field\"").unwrap();
+        assert_eq!(config.beacons.replay.speed, 4.0);
+        assert_eq!(config.beacons.replay.file, None);
+        assert_eq!(config.capture.directory, "field");
+    }
+
+    #[test]
+    fn overrides() {
+        let matches = cli().get_matches_from(vec![
+            "backend",
+            "--bind", "127.0.0.1:9000",
+            "--udp-beacons", "true",
+            "--ping-interval", "5000",
+            "--ground-truth", "truth.jsonl",
+            "--replay", "site.jsonl",
+            "--replay-speed", "10",
+        ]);
+        let mut config = Config::default();
+        config.apply_args(&matches).unwrap();
+        assert_eq!(config.server.bind, "127.0.0.1:9000");
+        assert!(config.beacons.use_udp);
+        assert_eq!(config.beacons.ping_interval(), Duration::from_millis(5000));
+        assert_eq!(config.beacons.simulator.ground_truth_file, Some("truth.jsonl".to_string()));
+        assert_eq!(config.beacons.replay.file, Some("site.jsonl".to_string()));
+        assert_eq!(config.beacons.replay.speed, 10.0);
+
+        let matches = cli().get_matches_from(vec!["backend", "--retries-threshold", "many"]);
+        assert!(Config::default().apply_args(&matches).is_err());
+    }
+
+    #[test]
+    fn validation() {
+        let mut config = Config::default();
+        config.server.static_dir = ".".to_string();
+        assert!(config.validate().is_ok());
+
+        let mut invalid = config.clone();
+        invalid.server.bind = "localhost".to_string();
+        assert!(invalid.validate().is_err());
+
+        let mut invalid = config.clone();
+        invalid.beacons.ping_interval_ms = invalid.beacons.response_threshold_ms;
+        assert!(invalid.validate().is_err());
+
+        let mut invalid = config.clone();
+        invalid.beacons.max_distance = -1.0;
+        assert!(invalid.validate().is_err());
+
+        let mut invalid = config.clone();
+        invalid.beacons.simulator.dropout_chance = 1.5;
+        assert!(invalid.validate().is_err());
+
+        let mut invalid = config.clone();
+        invalid.beacons.simulator.scenario_file = Some("missing_scenario.toml".to_string());
+        assert!(invalid.validate().is_err());
+
+        let mut invalid = config.clone();
+        invalid.beacons.replay.speed = 0.0;
+        assert!(invalid.validate().is_err());
+
+        let mut invalid = config.clone();
+        invalid.beacons.replay.file = Some("missing_capture.jsonl".to_string());
+        assert!(invalid.validate().is_err());
+
+        let file = std::env::temp_dir()
+            .join(format!("ak_config_capture_{}.jsonl", std::process::id()))
+            .to_string_lossy()
+            .into_owned();
+        let frame = capture::CapturedFrame::new("10.0.0.2".parse().unwrap(), &[0xa5]);
+        fs::write(&file, serde_json::to_string(&frame).unwrap()).unwrap();
+        let mut single = config.clone();
+        single.beacons.replay.file = Some(file.clone());
+        assert!(single.validate().is_ok());
+        single.beacons.replay.looped = true;
+        assert!(single.validate().is_err());
+        fs::remove_file(&file).unwrap();
+
+        let mut invalid = config.clone();
+        invalid.session.same_site = "sometimes".to_string();
+        assert!(invalid.validate().is_err());
+    }
+
+    #[test]
+    fn redacted() {
+        let mut config = Config::default();
+        config.session.key = Some("00".repeat(32));
+        let redacted = config.redacted();
+        assert!(!redacted.database.connection.contains("password=postgres"));
+        assert!(redacted.database.connection.contains("password=<redacted>"));
+        assert!(redacted.database.connection.contains("dbname=ak"));
+        assert_eq!(redacted.session.key, Some(REDACTED.to_string()));
+    }
+}
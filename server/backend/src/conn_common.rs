@@ -1,108 +1,62 @@
-
 use common::*;
-use crate::beacon_manager::BMResponse;
-use regex::Regex;
-use std::error::Error;
-use std::fmt;
+use common::beacon_protocol::{ self, Message, ProtocolError, Signature, WireFormat, };
+use crate::beacon_manager::{ BMResponse, RequestId, };
 use chrono::Utc;
 use std::net::IpAddr;
 
-#[derive(Debug)]
-pub enum MessageError {
-    ParseFormat,
-    ParseFloat,
-    ParseMac,
-}
-
-impl fmt::Display for MessageError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            MessageError::ParseFormat => write!(f, "Invalid message format"),
-            MessageError::ParseFloat => write!(f, "Failed to parse float"),
-            MessageError::ParseMac => write!(f, "Failed to parse mac address"),
-        }
-    }
-}
-
-impl Error for MessageError {
-     fn cause(&self) -> Option<&dyn Error> {
-         None
-     }
- }
-
-impl From<eui64::ParseError> for MessageError {
-    fn from(_item: eui64::ParseError) -> Self {
-        MessageError::ParseMac
-    }
+// a message from a beacon, along with the format it was sent in
+pub struct Received {
+    pub format: WireFormat,
+    // only binary frames carry a sequence number
+    pub sequence: Option<u16>,
+    // only binary frames can be signed, the signature is not verified yet
+    pub signature: Option<Signature>,
+    pub message: Message,
 }
 
-impl From<common::short_address::ParseError> for MessageError {
-    fn from(_item: common::short_address::ParseError) -> Self {
-        MessageError::ParseMac
-    }
-}
-
-impl From<std::num::ParseFloatError> for MessageError {
-    fn from(_item: std::num::ParseFloatError) -> Self {
-        MessageError::ParseFloat
+pub fn parse_message(data: &[u8]) -> Result<Received, ProtocolError> {
+    if beacon_protocol::is_binary(data) {
+        beacon_protocol::decode_signed(data)
+            .map(|(packet, signature)| Received {
+                format: WireFormat::Binary(packet.version),
+                sequence: Some(packet.sequence),
+                signature,
+                message: packet.message,
+            })
+    } else {
+        beacon_protocol::decode_legacy(&String::from_utf8_lossy(data))
+            .map(|message| Received {
+                format: WireFormat::Legacy,
+                sequence: None,
+                signature: None,
+                message,
+            })
     }
 }
 
-pub fn parse_message(message: &str, source_ip: IpAddr) -> Result<BMResponse, MessageError> {
-    let brack_start = message.find('[');
-    let brack_end = message.find(']');
-    if let Some((start, end)) = brack_start.and_then(|start| brack_end.map(|end| (start, end))) {
-        let slice = &message[start+1..end];
-        let split: Vec<&str> = slice.split("|").collect();
-        if split.len() >= 2 {
-            let beacon_mac = MacAddress8::parse_str(split[0])?;
-            let command_type = split[1];
-            match command_type {
-                "start_ack" => {
-                    Ok(BMResponse::Start(source_ip, beacon_mac))
-                },
-                "end_ack" => {
-                    Ok(BMResponse::End(source_ip, beacon_mac))
-                },
-                "ping_ack" => {
-                    Ok(BMResponse::Ping(source_ip, beacon_mac))
-                },
-                "reboot_ack" => {
-                    Ok(BMResponse::Reboot(source_ip, beacon_mac))
-                },
-                "range_ack" => {
-                    println!("short address is: {}", split[2]);
-                    let tag_mac = ShortAddress::parse_str(split[2])?;
-                    let distance = split[3];
-                    let reg = Regex::new(r"/[^$0-9]+/").unwrap();
-                    let distance_stripped = reg.replace_all(&distance, "");
-
-                    // remove the last character every time, idk why but there is always
-                    // a newline at the end of rssi_stripped. from_str_radix REQUIRES
-                    // all numeric characters.
-                    if distance_stripped.len() <= 0 {
-                        return Err(MessageError::ParseFormat)
-                    }
-                    let distance_numeric = distance_stripped[..distance_stripped.len() - 1].parse::<f64>()?;
-
-                    Ok(BMResponse::TagData(source_ip, common::TagData {
-                        beacon_mac: beacon_mac.clone(),
-                        tag_distance: distance_numeric,
-                        tag_mac,
-                        timestamp: Utc::now(),
-                    }))
-                },
-                _ => {
-                    println!("unkown command");
-                    Err(MessageError::ParseFormat)
-                },
-            }
-        } else {
-            // invalid command
-            Err(MessageError::ParseFormat)
-        }
-    } else {
-        // incomplete transmission
-        Err(MessageError::ParseFormat)
+// the response the manager cares about, None for messages that are only meaningful to the
+// connection itself, or are not sent by beacons.
+pub fn to_response(message: Message, sequence: Option<RequestId>, source_ip: IpAddr) -> Option<BMResponse> {
+    match message {
+        Message::StartAck(mac) => Some(BMResponse::Start(source_ip, mac, sequence)),
+        Message::EndAck(mac) => Some(BMResponse::End(source_ip, mac, sequence)),
+        Message::PingAck(mac) => Some(BMResponse::Ping(source_ip, mac, sequence)),
+        Message::RebootAck(mac) => Some(BMResponse::Reboot(source_ip, mac, sequence)),
+        Message::SetIpAck(mac) => Some(BMResponse::SetIp(source_ip, mac, sequence)),
+        Message::Range(beacon_mac, tag_mac, tag_distance) => {
+            Some(BMResponse::TagData(source_ip, TagData {
+                beacon_mac,
+                tag_distance,
+                tag_mac,
+                timestamp: Utc::now(),
+            }))
+        },
+        Message::HelloAck(..)
+            | Message::Hello(_)
+            | Message::Start
+            | Message::End
+            | Message::Ping
+            | Message::Reboot
+            | Message::SetIp(_) => None,
     }
 }
@@ -0,0 +1,126 @@
+use actix_web::{ web, HttpRequest, HttpResponse, };
+use actix_identity::Identity;
+use common::*;
+use crate::AKData;
+use crate::auth;
+use crate::db_utils;
+use crate::models::account;
+use crate::models::session;
+use futures::future::{ err, Either, ok, Future, };
+use crate::ak_error::AkError;
+
+pub fn get_accounts(uid: Identity, state: AKData, _req: HttpRequest) -> impl Future<Item=HttpResponse, Error=AkError> {
+    let system_state = state.clone();
+    auth::require_admin(&uid, &state)
+        .and_then(move |_session| {
+            db_utils::connect_system(&system_state)
+        })
+        .and_then(|client| {
+            account::select_accounts(client)
+        })
+        .map(|(_client, accounts)| {
+            HttpResponse::Ok().json(Ok::<_, AkError>(accounts))
+        })
+}
+
+// new account
+pub fn post_account(uid: Identity, state: AKData, _req: HttpRequest, payload: web::Json<NewAccount>) -> impl Future<Item=HttpResponse, Error=AkError> {
+    let system_state = state.clone();
+    auth::require_admin(&uid, &state)
+        .and_then(move |_session| {
+            let NewAccount { name, pw, role } = payload.into_inner();
+            if name.is_empty() {
+                return Either::B(err(AkError::validation("account name must not be empty")));
+            }
+            let hash = match auth::validate_password(&pw).and_then(|_| auth::hash_password(&pw)) {
+                Ok(hash) => hash,
+                Err(e) => return Either::B(err(e)),
+            };
+
+            Either::A(db_utils::connect_system(&system_state)
+                .and_then(move |client| {
+                    account::insert_account(client, name, hash, role)
+                })
+            )
+        })
+        .and_then(|(_client, opt_account)| {
+            match opt_account {
+                Some(a) => ok(HttpResponse::Ok().json(Ok::<_, AkError>(a))),
+                None => err(AkError::internal()),
+            }
+        })
+}
+
+// update the name, role, or disabled state of an account
+pub fn put_account(uid: Identity, state: AKData, _req: HttpRequest, payload: web::Json<Account>) -> impl Future<Item=HttpResponse, Error=AkError> {
+    let system_state = state.clone();
+    let cache_state = state.clone();
+    auth::require_admin(&uid, &state)
+        .and_then(move |session| {
+            let account = payload.into_inner();
+            // make sure there is always an admin that can log in
+            if account.id == session.account_id && (account.disabled || account.role != AccountRole::Admin) {
+                return Either::B(err(AkError::validation("cannot disable or demote your own account")));
+            }
+
+            Either::A(db_utils::connect_system(&system_state)
+                .and_then(move |client| {
+                    account::update_account(client, account)
+                })
+                .and_then(|(client, opt_account)| {
+                    match opt_account {
+                        Some(a) => Either::A(
+                            if a.disabled {
+                                Either::A(session::delete_account_sessions(client, a.id)
+                                    .map(move |(_client, _count)| a)
+                                )
+                            } else {
+                                Either::B(ok(a))
+                            }
+                        ),
+                        None => Either::B(err(AkError::not_found())),
+                    }
+                })
+            )
+        })
+        .map(move |account| {
+            auth::forget_account(&cache_state, account.id);
+            HttpResponse::Ok().json(Ok::<_, AkError>(account))
+        })
+}
+
+// sets a new password, and logs out every session of the account
+pub fn reset_account(uid: Identity, state: AKData, req: HttpRequest, payload: web::Json<PasswordReset>) -> impl Future<Item=HttpResponse, Error=AkError> {
+    let system_state = state.clone();
+    let cache_state = state.clone();
+    let id = req.match_info().get("id").unwrap_or("-1").parse::<i32>();
+    auth::require_admin(&uid, &state)
+        .and_then(move |_session| {
+            let id = match id {
+                Ok(id) if id != -1 => id,
+                _ => return Either::B(err(AkError::not_found())),
+            };
+            let hash = match auth::validate_password(&payload.pw).and_then(|_| auth::hash_password(&payload.pw)) {
+                Ok(hash) => hash,
+                Err(e) => return Either::B(err(e)),
+            };
+
+            Either::A(db_utils::connect_system(&system_state)
+                .and_then(move |client| {
+                    account::update_account_password(client, id, hash)
+                })
+                .and_then(|(client, opt_account)| {
+                    match opt_account {
+                        Some(a) => Either::A(session::delete_account_sessions(client, a.id)
+                            .map(move |(_client, _count)| a)
+                        ),
+                        None => Either::B(err(AkError::not_found())),
+                    }
+                })
+            )
+        })
+        .map(move |account| {
+            auth::forget_account(&cache_state, account.id);
+            HttpResponse::Ok().json(Ok::<_, AkError>(account))
+        })
+}
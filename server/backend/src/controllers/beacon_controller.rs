@@ -1,19 +1,30 @@
 use actix_web::{ web, HttpRequest, HttpResponse, };
 use crate::AKData;
-use crate::beacon_manager::{ OutBeaconData, BMCommand, };
+use crate::auth;
+use crate::beacon_manager::{ AdoptBeacon, BlockBeacon, OutBeaconData, OutDiscoveredBeacons, BMCommand, RemoveBeacon, SetBeaconKey, SetEmergency, UpdateBeacon, };
 use crate::db_utils;
+use crate::hex;
 use crate::models::beacon;
+use crate::models::beacon_blocklist;
 use futures::{ future::err, future::ok, Future, future::Either, };
 use serde_derive::{ Deserialize, };
 use actix_identity::Identity;
-use common::BeaconRequest;
+use common::{ BeaconRequest, BlockBeaconRequest, Utc, };
+use common::beacon_protocol;
 use crate::ak_error::AkError;
+use rand::RngCore;
+use rand::rngs::OsRng;
 
 #[derive(Deserialize)]
 pub struct GetParams {
     prefetch: Option<bool>,
 }
 
+// the actors look beacons up in a cache, which is stale once a beacon is edited
+fn invalidate_beacons(state: &AKData) {
+    state.lock().unwrap().repositories.beacons.invalidate();
+}
+
 pub fn beacons_status(_uid: Identity, state: AKData) -> impl Future<Item=HttpResponse, Error=AkError> {
     let s = state.lock().unwrap();
     s.beacon_manager
@@ -29,26 +40,110 @@ pub fn beacons_status(_uid: Identity, state: AKData) -> impl Future<Item=HttpRes
         }})
 }
 
-pub fn beacon_command(_uid: Identity, state: AKData, payload: web::Json<common::BeaconRequest>) -> impl Future<Item=HttpResponse, Error=AkError> {
-    let s = state.lock().unwrap();
-    let command = match payload.0 {
-        BeaconRequest::StartEmergency(mac) => BMCommand::StartEmergency(mac),
-        BeaconRequest::EndEmergency(mac) => BMCommand::EndEmergency(mac),
-        BeaconRequest::Ping(mac) => BMCommand::Ping(mac),
-        BeaconRequest::Reboot(mac) => BMCommand::Reboot(mac),
-        BeaconRequest::SetIp(ip) => BMCommand::SetIp(ip),
-    };
-    s.beacon_manager
-        .send(command)
-        .then(|res| {
-            match res {
-                Ok(_data) => {
-                    ok(HttpResponse::Ok().json(Ok::<_, AkError>(())))
+// site-wide starts and ends go through the same path as /system/emergency, so they are logged
+// with the account that made them
+pub fn beacon_command(uid: Identity, state: AKData, payload: web::Json<common::BeaconRequest>) -> impl Future<Item=HttpResponse, Error=AkError> {
+    let manager_state = state.clone();
+    auth::authenticate(&uid, &state)
+        .and_then(move |session| {
+            let site_wide = |active| SetEmergency {
+                active,
+                account: session.name,
+                reason: None,
+                map_ids: None,
+            };
+            let s = manager_state.lock().unwrap();
+            let sent = match payload.0 {
+                BeaconRequest::StartEmergency(None) => Either::B(s.beacon_manager.send(site_wide(true))),
+                BeaconRequest::EndEmergency(None) => Either::B(s.beacon_manager.send(site_wide(false))),
+                request => {
+                    let command = match request {
+                        BeaconRequest::StartEmergency(mac) => BMCommand::StartEmergency(mac),
+                        BeaconRequest::EndEmergency(mac) => BMCommand::EndEmergency(mac),
+                        BeaconRequest::Ping(mac) => BMCommand::Ping(mac),
+                        BeaconRequest::Reboot(mac) => BMCommand::Reboot(mac),
+                        BeaconRequest::SetIp(ip) => BMCommand::SetIp(ip),
+                    };
+                    Either::A(s.beacon_manager.send(command))
                 },
-                _ => {
-                    err(AkError::internal())
-                }
-        }})
+            };
+            sent.then(|res| {
+                match res {
+                    Ok(Ok(_)) => {
+                        ok(HttpResponse::Ok().json(Ok::<_, AkError>(())))
+                    },
+                    _ => {
+                        err(AkError::internal())
+                    }
+            }})
+        })
+}
+
+// beacons that have messaged the server without being registered, and blocked beacons
+pub fn get_discovered_beacons(uid: Identity, state: AKData) -> impl Future<Item=HttpResponse, Error=AkError> {
+    let manager_state = state.clone();
+    auth::require_admin(&uid, &state)
+        .and_then(move |_session| {
+            manager_state.lock().unwrap().beacon_manager
+                .send(OutDiscoveredBeacons)
+                .then(|res| {
+                    match res {
+                        Ok(data) => {
+                            ok(HttpResponse::Ok().json(data))
+                        },
+                        _ => {
+                            err(AkError::internal())
+                        }
+                }})
+        })
+}
+
+// registers a discovered beacon, with its name and placement, in one step
+pub fn adopt_beacon(uid: Identity, state: AKData, payload: web::Json<common::Beacon>) -> impl Future<Item=HttpResponse, Error=AkError> {
+    let system_state = state.clone();
+    let manager_state = state.clone();
+    auth::require_admin(&uid, &state)
+        .and_then(move |_session| {
+            db_utils::connect_system(&system_state)
+        })
+        .and_then(move |client| {
+            let beacon = payload.0;
+            beacon_blocklist::delete_blocked_beacon(client, beacon.mac_address)
+                .and_then(move |client| {
+                    beacon::insert_beacon(client, beacon)
+                })
+        })
+        .and_then(move |(_client, opt_beacon)| {
+            match opt_beacon {
+                Some(b) => {
+                    invalidate_beacons(&manager_state);
+                    manager_state.lock().unwrap().beacon_manager.do_send(AdoptBeacon(b.clone()));
+                    ok(HttpResponse::Ok().json(Ok::<_, AkError>(b)))
+                },
+                None => err(AkError::not_found()),
+            }
+        })
+}
+
+pub fn block_beacon(uid: Identity, state: AKData, payload: web::Json<BlockBeaconRequest>) -> impl Future<Item=HttpResponse, Error=AkError> {
+    let system_state = state.clone();
+    let manager_state = state.clone();
+    let request = payload.0;
+    auth::require_admin(&uid, &state)
+        .and_then(move |_session| {
+            db_utils::connect_system(&system_state)
+        })
+        .and_then(move |client| {
+            let task = if request.blocked {
+                Either::A(beacon_blocklist::insert_blocked_beacon(client, request.mac_address, Utc::now()))
+            } else {
+                Either::B(beacon_blocklist::delete_blocked_beacon(client, request.mac_address))
+            };
+            task.map(move |_client| {
+                manager_state.lock().unwrap().beacon_manager.do_send(BlockBeacon(request.mac_address, request.blocked));
+                HttpResponse::Ok().json(Ok::<_, AkError>(()))
+            })
+        })
 }
 
 pub fn get_beacon(uid: Identity, state: AKData, req: HttpRequest, params: web::Query<GetParams>) -> impl Future<Item=HttpResponse, Error=AkError> {
@@ -126,13 +221,17 @@ pub fn get_beacons(uid: Identity, state: AKData, _req: HttpRequest, params: web:
 
 // new beacon
 pub fn post_beacon(uid: Identity, state: AKData, _req: HttpRequest, payload: web::Json<common::Beacon>) -> impl Future<Item=HttpResponse, Error=AkError> {
+    let cache_state = state.clone();
     db_utils::connect_id(&uid, &state)
         .and_then(move |client| {
             beacon::insert_beacon(client, payload.0)
         })
-        .and_then(|(_client, beacon)| {
+        .and_then(move |(_client, beacon)| {
             match beacon {
-                Some(b) => ok(HttpResponse::Ok().json(Ok::<_, AkError>(b))),
+                Some(b) => {
+                    invalidate_beacons(&cache_state);
+                    ok(HttpResponse::Ok().json(Ok::<_, AkError>(b)))
+                },
                 None => err(AkError::not_found()),
             }
         })
@@ -140,13 +239,18 @@ pub fn post_beacon(uid: Identity, state: AKData, _req: HttpRequest, payload: web
 
 // update beacon
 pub fn put_beacon(uid: Identity, state: AKData, _req: HttpRequest, payload: web::Json<common::Beacon>) -> impl Future<Item=HttpResponse, Error=AkError> {
+    let cache_state = state.clone();
     db_utils::connect_id(&uid, &state)
         .and_then(move |client| {
             beacon::update_beacon(client, payload.0)
         })
-        .and_then(|(_client, beacon)| {
+        .and_then(move |(_client, beacon)| {
             match beacon {
-                Some(b) => ok(HttpResponse::Ok().json(Ok::<_, AkError>(b))),
+                Some(b) => {
+                    invalidate_beacons(&cache_state);
+                    cache_state.lock().unwrap().beacon_manager.do_send(UpdateBeacon(b.clone()));
+                    ok(HttpResponse::Ok().json(Ok::<_, AkError>(b)))
+                },
                 None => err(AkError::not_found()),
             }
         })
@@ -156,11 +260,14 @@ pub fn delete_beacon(uid: Identity, state: AKData, req: HttpRequest) -> impl Fut
     let id = req.match_info().get("id").unwrap_or("-1").parse::<i32>();
     match id {
         Ok(id) if id != -1 => {
+            let cache_state = state.clone();
             Either::A(db_utils::connect_id(&uid, &state)
                 .and_then(move |client| {
                     beacon::delete_beacon(client, id)
                 })
-                .map(|_client| {
+                .map(move |_client| {
+                    invalidate_beacons(&cache_state);
+                    cache_state.lock().unwrap().beacon_manager.do_send(RemoveBeacon(id));
                     HttpResponse::Ok().json(Ok::<_, AkError>(()))
                 })
             )
@@ -171,3 +278,68 @@ pub fn delete_beacon(uid: Identity, state: AKData, req: HttpRequest) -> impl Fut
     }
 }
 
+// generates a new signing key for the beacon, replacing its current key. The key is only returned
+// here, the previous key keeps working until the beacon has been flashed with the new one.
+pub fn post_beacon_key(uid: Identity, state: AKData, req: HttpRequest) -> impl Future<Item=HttpResponse, Error=AkError> {
+    let id = req.match_info().get("id").unwrap_or("-1").parse::<i32>();
+    let system_state = state.clone();
+    let manager_state = state.clone();
+    match id {
+        Ok(id) if id != -1 => {
+            Either::A(auth::require_admin(&uid, &state)
+                .and_then(move |_session| {
+                    db_utils::connect_system(&system_state)
+                })
+                .and_then(move |client| {
+                    let mut key = vec![0u8; beacon_protocol::KEY_LENGTH];
+                    OsRng.fill_bytes(&mut key);
+                    beacon::update_beacon_key(client, id, key, Utc::now())
+                })
+                .and_then(move |(_client, opt_key)| {
+                    match opt_key {
+                        Some((mac, key)) => {
+                            let hex = hex::encode_hex(&key.current);
+                            manager_state.lock().unwrap().beacon_manager.do_send(SetBeaconKey(mac, Some(key)));
+                            ok(HttpResponse::Ok().json(Ok::<_, AkError>(hex)))
+                        },
+                        None => err(AkError::not_found()),
+                    }
+                })
+            )
+        },
+        _ => {
+            Either::B(err(AkError::not_found()))
+        }
+    }
+}
+
+// the beacon will have to send unsigned messages, which are rejected unless they are allowed
+pub fn delete_beacon_key(uid: Identity, state: AKData, req: HttpRequest) -> impl Future<Item=HttpResponse, Error=AkError> {
+    let id = req.match_info().get("id").unwrap_or("-1").parse::<i32>();
+    let system_state = state.clone();
+    let manager_state = state.clone();
+    match id {
+        Ok(id) if id != -1 => {
+            Either::A(auth::require_admin(&uid, &state)
+                .and_then(move |_session| {
+                    db_utils::connect_system(&system_state)
+                })
+                .and_then(move |client| {
+                    beacon::delete_beacon_key(client, id)
+                })
+                .and_then(move |(_client, opt_mac)| {
+                    match opt_mac {
+                        Some(mac) => {
+                            manager_state.lock().unwrap().beacon_manager.do_send(SetBeaconKey(mac, None));
+                            ok(HttpResponse::Ok().json(Ok::<_, AkError>(())))
+                        },
+                        None => err(AkError::not_found()),
+                    }
+                })
+            )
+        },
+        _ => {
+            Either::B(err(AkError::not_found()))
+        }
+    }
+}
@@ -3,7 +3,10 @@ use actix_web::{ web, HttpRequest, HttpResponse, };
 use common::*;
 use crate::AKData;
 use crate::db_utils;
+use crate::models::beacon;
+use crate::models::location_history;
 use crate::models::map;
+use na;
 use futures::{ Stream, future::err, future::ok, Future, future::Either, };
 use actix_identity::Identity;
 use crate::ak_error::AkError;
@@ -52,6 +55,113 @@ pub fn get_map_blueprint(uid: Identity, state: AKData, req: HttpRequest) -> impl
     }
 }
 
+pub fn get_map_history(uid: Identity, state: AKData, req: HttpRequest, params: web::Query<HistoryParams>) -> impl Future<Item=HttpResponse, Error=AkError> {
+    let id = req.match_info().get("id").unwrap_or("-1").parse::<i32>();
+    let (from, to) = params.range();
+    match id {
+        Ok(id) if id != -1 => {
+            Either::A(db_utils::connect_id(&uid, &state)
+                .and_then(move |client| {
+                    location_history::select_map_history(client, id, from, to)
+                })
+                .map(|(_client, history)| {
+                    HttpResponse::Ok().json(Ok::<_, AkError>(history))
+                })
+            )
+        },
+        _ => {
+            Either::B(err(AkError::not_found()))
+        }
+    }
+}
+
+// how many recorded locations are sent in each page of a replay
+const REPLAY_PAGE_SIZE: i64 = 1000;
+
+// rebuild the realtime data that was shown when the location was recorded
+fn replay_frame(record: LocationRecord, user: TrackedUser, beacons: &Vec<Beacon>) -> Option<RealtimeUserData> {
+    // the tag may have been taken off the user since, there is no address to display
+    let addr = user.mac_address?;
+    // the beacons are drawn where they were when the location was recorded, they may have been
+    // moved or removed since, only the names are today's
+    let beacon_tofs = record.beacons
+        .iter()
+        .zip(record.beacon_coordinates.iter())
+        .zip(record.distances.iter())
+        .map(|((beacon_id, location), distance)| {
+            BeaconTOFToUser {
+                name: beacons.iter().find(|b| b.id == *beacon_id).map(|b| b.name.clone()).unwrap_or_default(),
+                location: *location,
+                distance_to_tag: *distance,
+            }
+        })
+        .collect();
+    // only the total uncertainty is recorded, so the error is shown as a circle
+    let axis = record.uncertainty / std::f64::consts::SQRT_2;
+
+    Some(RealtimeUserData {
+        addr,
+        beacon_tofs,
+        coordinates: record.coordinates,
+        error_ellipse: tag_filter::CovarianceEllipse {
+            semi_major: axis,
+            semi_minor: axis,
+            angle: 0.0,
+        },
+        id: user.id,
+        last_active: record.timestamp,
+        map_id: record.map_id,
+        name: user.name,
+        uncertainty: record.uncertainty,
+        velocity: na::Vector2::new(0.0, 0.0),
+    })
+}
+
+// recorded locations on a map in chronological order, a page at a time
+pub fn get_map_replay(uid: Identity, state: AKData, req: HttpRequest, params: web::Query<HistoryParams>, cursor: Option<web::Query<ReplayCursor>>) -> impl Future<Item=HttpResponse, Error=AkError> {
+    let id = req.match_info().get("id").unwrap_or("-1").parse::<i32>();
+    let (from, to) = params.range();
+    // ids are positive, so the first page starts with the locations at the start of the window
+    let after = match cursor {
+        Some(cursor) => (cursor.after, cursor.after_id),
+        None => (from, -1),
+    };
+    match id {
+        Ok(id) if id != -1 => {
+            Either::A(db_utils::connect_id(&uid, &state)
+                .and_then(move |client| {
+                    location_history::select_map_replay(client, id, after, to, REPLAY_PAGE_SIZE)
+                })
+                .and_then(move |(client, history)| {
+                    // a full page may not be the last
+                    let next = match history.last() {
+                        Some((record, _user)) if history.len() as i64 == REPLAY_PAGE_SIZE => Some(ReplayCursor {
+                            after: record.timestamp,
+                            after_id: record.id,
+                        }),
+                        _ => None,
+                    };
+                    // the beacons may have been moved to another map since, they are all looked up by id
+                    beacon::select_beacons(client)
+                        .map(move |(_client, beacons)| {
+                            let frames: Vec<RealtimeUserData> = history
+                                .into_iter()
+                                .filter_map(|(record, user)| replay_frame(record, user, &beacons))
+                                .collect();
+                            HttpResponse::Ok().json(Ok::<_, AkError>(ReplayPage {
+                                frames,
+                                next,
+                            }))
+                        })
+                })
+            )
+        },
+        _ => {
+            Either::B(err(AkError::not_found()))
+        }
+    }
+}
+
 pub fn get_maps(uid: Identity, state: AKData, _req: HttpRequest) -> impl Future<Item=HttpResponse, Error=AkError> {
     db_utils::connect_id(&uid, &state)
         .and_then(move |client| {
@@ -122,11 +232,14 @@ pub fn delete_map(uid: Identity, state: AKData, req: HttpRequest) -> impl Future
     let id = req.match_info().get("id").unwrap_or("-1").parse::<i32>();
     match id {
         Ok(id) if id != -1 => {
+            let cache_state = state.clone();
             Either::A(db_utils::connect_id(&uid, &state)
                 .and_then(move |client| {
                     map::delete_map(client, id)
                 })
-                .map(|_client| {
+                .map(move |_client| {
+                    // the beacons on the map were taken off it
+                    cache_state.lock().unwrap().repositories.beacons.invalidate();
                     HttpResponse::Ok().json(Ok::<_, AkError>(()))
                 })
             )
@@ -1,7 +1,11 @@
 
+pub mod account_controller;
 pub mod beacon_controller;
 pub mod map_controller;
+pub mod motion_controller;
 pub mod network_interface_controller;
+pub mod push_controller;
 pub mod system_controller;
 pub mod user_controller;
 pub mod session_controller;
+pub mod zone_controller;
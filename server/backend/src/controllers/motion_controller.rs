@@ -0,0 +1,53 @@
+use actix_web::{ HttpRequest, HttpResponse, };
+use crate::AKData;
+use crate::auth;
+use crate::data_processor::{ AcknowledgeMotionAlert, OutMotionAlerts, };
+use futures::{ future::err, future::ok, Future, future::Either, };
+use actix_identity::Identity;
+use crate::ak_error::AkError;
+
+// users that have stopped moving, or whose tags went silent, during an emergency
+pub fn get_motion_alerts(state: AKData, _req: HttpRequest) -> impl Future<Item=HttpResponse, Error=AkError> {
+    let s = state.lock().unwrap();
+    s.data_processor
+        .send(OutMotionAlerts)
+        .then(|res| {
+            match res {
+                Ok(data) => {
+                    ok(HttpResponse::Ok().json(data))
+                },
+                _ => {
+                    err(AkError::internal())
+                }
+        }})
+}
+
+// any responder can acknowledge an alert, the account is recorded on it
+pub fn post_acknowledge(id: Identity, state: AKData, req: HttpRequest) -> impl Future<Item=HttpResponse, Error=AkError> {
+    let alert_id = req.match_info().get("id").unwrap_or("").parse::<u64>();
+    match alert_id {
+        Ok(alert_id) => {
+            let processor_state = state.clone();
+            Either::A(auth::authenticate(&id, &state)
+                .and_then(move |session| {
+                    let s = processor_state.lock().unwrap();
+                    s.data_processor
+                        .send(AcknowledgeMotionAlert {
+                            id: alert_id,
+                            account: session.name,
+                        })
+                        .then(|res| {
+                            match res {
+                                Ok(Ok(alert)) => ok(HttpResponse::Ok().json(Ok::<_, AkError>(alert))),
+                                Ok(Err(e)) => err(e),
+                                _ => err(AkError::internal()),
+                            }
+                        })
+                })
+            )
+        },
+        _ => {
+            Either::B(err(AkError::not_found()))
+        }
+    }
+}
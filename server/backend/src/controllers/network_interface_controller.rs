@@ -6,11 +6,11 @@ use crate::models::network_interface;
 use futures::{ future::err, future::ok, Future, future::Either, };
 use crate::ak_error::AkError;
 
-pub fn get_network_interface(_state: AKData, req: HttpRequest) -> impl Future<Item=HttpResponse, Error=AkError> {
+pub fn get_network_interface(state: AKData, req: HttpRequest) -> impl Future<Item=HttpResponse, Error=AkError> {
     let id = req.match_info().get("id").unwrap_or("-1").parse::<i32>();
     match id {
         Ok(id) if id != -1 => {
-            Either::A(db_utils::connect(db_utils::DEFAULT_CONNECTION)
+            Either::A(db_utils::connect_system(&state)
                 .and_then(move |client| {
                     network_interface::select_network_interface(client, id)
                 })
@@ -28,8 +28,8 @@ pub fn get_network_interface(_state: AKData, req: HttpRequest) -> impl Future<It
     }
 }
 
-pub fn get_network_interfaces(_state: AKData, _req: HttpRequest) -> impl Future<Item=HttpResponse, Error=AkError> {
-    db_utils::connect(db_utils::DEFAULT_CONNECTION)
+pub fn get_network_interfaces(state: AKData, _req: HttpRequest) -> impl Future<Item=HttpResponse, Error=AkError> {
+    db_utils::connect_system(&state)
         .and_then(move |client| {
             network_interface::select_network_interfaces(client)
         })
@@ -39,8 +39,8 @@ pub fn get_network_interfaces(_state: AKData, _req: HttpRequest) -> impl Future<
 }
 
 // new iface
-pub fn post_network_interface(_state: AKData, _req: HttpRequest, payload: web::Json<NetworkInterface>) -> impl Future<Item=HttpResponse, Error=AkError> {
-    db_utils::connect(db_utils::DEFAULT_CONNECTION)
+pub fn post_network_interface(state: AKData, _req: HttpRequest, payload: web::Json<NetworkInterface>) -> impl Future<Item=HttpResponse, Error=AkError> {
+    db_utils::connect_system(&state)
         .and_then(move |client| {
             network_interface::insert_network_interface(client, payload.0)
         })
@@ -53,8 +53,8 @@ pub fn post_network_interface(_state: AKData, _req: HttpRequest, payload: web::J
 }
 
 // update iface
-pub fn put_network_interface(_state: AKData, _req: HttpRequest, payload: web::Json<NetworkInterface>) -> impl Future<Item=HttpResponse, Error=AkError> {
-    db_utils::connect(db_utils::DEFAULT_CONNECTION)
+pub fn put_network_interface(state: AKData, _req: HttpRequest, payload: web::Json<NetworkInterface>) -> impl Future<Item=HttpResponse, Error=AkError> {
+    db_utils::connect_system(&state)
         .and_then(move |client| {
             network_interface::update_network_interface(client, payload.0)
         })
@@ -66,11 +66,11 @@ pub fn put_network_interface(_state: AKData, _req: HttpRequest, payload: web::Js
         })
 }
 
-pub fn delete_network_interface(_state: AKData, req: HttpRequest) -> impl Future<Item=HttpResponse, Error=AkError> {
+pub fn delete_network_interface(state: AKData, req: HttpRequest) -> impl Future<Item=HttpResponse, Error=AkError> {
     let id = req.match_info().get("id").unwrap_or("-1").parse::<i32>();
     match id {
         Ok(id) if id != -1 => {
-            Either::A(db_utils::connect(db_utils::DEFAULT_CONNECTION)
+            Either::A(db_utils::connect_system(&state)
                 .and_then(move |client| {
                     network_interface::delete_network_interface(client, id)
                 })
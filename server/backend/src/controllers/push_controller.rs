@@ -0,0 +1,17 @@
+use actix_identity::Identity;
+use actix_web::{ web, HttpRequest, HttpResponse, };
+use actix_web_actors::ws;
+use crate::AKData;
+use crate::ak_error::AkError;
+use crate::auth;
+use crate::push::PushSession;
+use futures::Future;
+
+pub fn push(uid: Identity, state: AKData, req: HttpRequest, stream: web::Payload) -> impl Future<Item=HttpResponse, Error=actix_web::Error> {
+    let broadcaster = state.lock().unwrap().push.clone();
+    auth::authenticate(&uid, &state)
+        .map_err(actix_web::Error::from)
+        .and_then(move |_session| {
+            ws::start(PushSession::new(broadcaster), &req, stream)
+        })
+}
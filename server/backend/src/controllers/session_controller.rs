@@ -1,46 +1,77 @@
-
-use actix_web::{ Error, web, HttpRequest, HttpResponse, };
+use actix_web::{ web, HttpRequest, HttpResponse, };
 use actix_identity::Identity;
 use common::*;
 use crate::AKData;
+use crate::auth;
 use crate::db_utils;
+use crate::models::account;
+use crate::models::session;
 use futures::future::{ err, Either, ok, Future, };
 use crate::ak_error::AkError;
 
 pub fn login(id: Identity, state: AKData, payload: web::Json<LoginInfo>, _req: HttpRequest) -> impl Future<Item=HttpResponse, Error=AkError> {
-    if payload.name == "responder" {
-        let mut info = LoginInfo::new();
-        info.name = payload.name.clone();
-        info.pw = payload.name.clone();
-        id.remember(payload.name.clone());
-        let mut s = state.lock().unwrap();
-        s.pools.insert(info.name.clone(), info);
-        Either::A(ok(HttpResponse::Ok().finish()))
-    } else {
-        Either::B(db_utils::connect_login(&payload.0)
-            .map(move |_client| {
-                id.remember(payload.name.clone());
-                let mut s = state.lock().unwrap();
-                s.pools.insert(payload.name.clone(), payload.0);
-                HttpResponse::Ok().finish()
-            })
-            .map_err(|_postgres_err| {
-                AkError::unauthorized()
-            })
-        )
-    }
+    let LoginInfo { name, pw } = payload.into_inner();
+    let now = Utc::now();
+    let session_hours = state.lock().unwrap().cookie.max_age_hours;
+    let cache_state = state.clone();
+
+    db_utils::connect_system(&state)
+        .and_then(move |client| {
+            account::select_account_login(client, name)
+        })
+        .and_then(move |(client, opt_login)| {
+            // the password is dropped along with this closure
+            match opt_login {
+                Some((account, hash)) if !account.disabled && auth::verify_password(&hash, &pw) => {
+                    ok((client, account))
+                },
+                _ => err(AkError::unauthorized()),
+            }
+        })
+        .and_then(move |(client, account)| {
+            let token = auth::new_token();
+            let session = auth::Session::new(&account, auth::session_expiry(now, session_hours));
+            session::delete_expired_sessions(client, now)
+                .and_then(move |(client, _count)| {
+                    session::insert_session(client, token.clone(), account.id, now, session.expires)
+                        .map(move |client| (client, token, session))
+                })
+                .and_then(move |(client, token, session)| {
+                    account::update_account_login(client, session.account_id, now)
+                        .map(move |_client| (token, session))
+                })
+        })
+        .map(move |(token, session)| {
+            id.remember(token.clone());
+            let info = session.info();
+            cache_state.lock().unwrap().sessions.insert(token, session);
+            HttpResponse::Ok().json(Ok::<_, AkError>(info))
+        })
 }
 
-pub fn check(id: Identity, _state: AKData, _req: HttpRequest) -> impl Future<Item=HttpResponse, Error=AkError> {
-    if let Some(_name) = id.identity() {
-        ok(HttpResponse::Ok().finish())
-    } else {
-        err(AkError::unauthorized())
-    }
+pub fn check(id: Identity, state: AKData, _req: HttpRequest) -> impl Future<Item=HttpResponse, Error=AkError> {
+    auth::authenticate(&id, &state)
+        .map(|session| {
+            HttpResponse::Ok().json(Ok::<_, AkError>(session.info()))
+        })
 }
 
-pub fn logout(id: Identity, _state: AKData, _req: HttpRequest) -> impl Future<Item=HttpResponse, Error=Error> {
+pub fn logout(id: Identity, state: AKData, _req: HttpRequest) -> impl Future<Item=HttpResponse, Error=AkError> {
+    let opt_token = id.identity();
     id.forget();
-    ok(HttpResponse::Ok().finish())
-}
 
+    match opt_token {
+        Some(token) => {
+            state.lock().unwrap().sessions.remove(&token);
+            Either::A(db_utils::connect_system(&state)
+                .and_then(move |client| {
+                    session::delete_session(client, token)
+                })
+                .map(|_client| {
+                    HttpResponse::Ok().finish()
+                })
+            )
+        },
+        None => Either::B(ok(HttpResponse::Ok().finish())),
+    }
+}
@@ -4,22 +4,55 @@ use actix_web::{ web, HttpRequest, HttpResponse, };
 use common::*;
 use crate::AKData;
 use crate::WatcherCommand;
-use crate::beacon_manager::{ BMCommand, GetDiagnosticData, };
-use futures::{ future::err, future::ok, Future, };
+use crate::auth;
+use crate::beacon_manager::{ BMCommand, GetDiagnosticData, OutEmergencyStatus, SetEmergency, };
+use crate::capture::{ OutCaptureStatus, StartCapture, StopCapture, };
+use crate::data_processor::{ DPMessage, OutFilterConfig, OutMotionConfig, OutRetention, OutUserData, };
+use crate::db_utils;
+use crate::models::emergency_event;
+use crate::models::map;
+use crate::models::retention;
+use crate::models::session;
+use crate::models::user;
+use crate::models::zone;
+use crate::models::zone_event;
+use common::motion::MotionConfig;
+use common::muster::{ self, MusterReport, };
+use common::tag_filter::FilterConfig;
+use futures::{ future::err, future::ok, Future, future::Either, };
 use actix::Arbiter;
 use std::time::Duration;
 use crate::ak_error::AkError;
 
-pub fn post_emergency(state: AKData, _req: HttpRequest, payload: web::Json<SystemCommandResponse>) -> impl Future<Item=HttpResponse, Error=AkError> {
-    let s = state.lock().unwrap();
-    let command = if payload.emergency {
-        BMCommand::StartEmergency(None)
-    } else {
-        BMCommand::EndEmergency(None)
-    };
+pub fn post_emergency(id: Identity, state: AKData, _req: HttpRequest, payload: web::Json<SystemCommandResponse>) -> impl Future<Item=HttpResponse, Error=AkError> {
+    let manager_state = state.clone();
+    auth::authenticate(&id, &state)
+        .and_then(move |session| {
+            let command = SetEmergency {
+                active: payload.emergency,
+                account: session.name,
+                reason: payload.reason.clone(),
+                map_ids: payload.map_ids.clone(),
+            };
+            let s = manager_state.lock().unwrap();
+            s.beacon_manager
+                .send(command)
+                .then(|res| {
+                    match res {
+                        Ok(data) => {
+                            ok(HttpResponse::Ok().json(data))
+                        },
+                        _ => {
+                            err(AkError::internal())
+                        }
+                }})
+        })
+}
 
+pub fn get_emergency(state: AKData, _req: HttpRequest) -> impl Future<Item=HttpResponse, Error=AkError> {
+    let s = state.lock().unwrap();
     s.beacon_manager
-        .send(command)
+        .send(BMCommand::GetEmergency)
         .then(|res| {
             match res {
                 Ok(data) => {
@@ -31,10 +64,11 @@ pub fn post_emergency(state: AKData, _req: HttpRequest, payload: web::Json<Syste
         }})
 }
 
-pub fn get_emergency(state: AKData, _req: HttpRequest) -> impl Future<Item=HttpResponse, Error=AkError> {
+// who started the current, or last, emergency and when
+pub fn get_emergency_status(state: AKData, _req: HttpRequest) -> impl Future<Item=HttpResponse, Error=AkError> {
     let s = state.lock().unwrap();
     s.beacon_manager
-        .send(BMCommand::GetEmergency)
+        .send(OutEmergencyStatus)
         .then(|res| {
             match res {
                 Ok(data) => {
@@ -46,6 +80,130 @@ pub fn get_emergency(state: AKData, _req: HttpRequest) -> impl Future<Item=HttpR
         }})
 }
 
+pub fn get_emergency_events(uid: Identity, state: AKData, _req: HttpRequest) -> impl Future<Item=HttpResponse, Error=AkError> {
+    db_utils::connect_id(&uid, &state)
+        .and_then(|client| {
+            emergency_event::select_emergency_events(client)
+        })
+        .map(|(_client, events)| {
+            HttpResponse::Ok().json(Ok::<_, AkError>(events))
+        })
+}
+
+// an emergency along with every user located while it was going
+pub fn get_emergency_event(uid: Identity, state: AKData, req: HttpRequest) -> impl Future<Item=HttpResponse, Error=AkError> {
+    let id = req.match_info().get("id").unwrap_or("-1").parse::<i32>();
+    match id {
+        Ok(id) if id != -1 => {
+            Either::A(db_utils::connect_id(&uid, &state)
+                .and_then(move |client| {
+                    emergency_event::select_emergency_event(client, id)
+                })
+                .and_then(|(client, opt_event)| {
+                    match opt_event {
+                        Some(event) => {
+                            let to = event.ended_at.unwrap_or(Utc::now());
+                            let from = event.started_at;
+                            let event_id = event.id;
+                            Either::A(emergency_event::select_users_seen(client, from, to, event.map_ids.clone())
+                                .and_then(move |(client, users)| {
+                                    zone_event::select_zone_events(client, from, to)
+                                        .map(move |(client, zone_events)| (client, users, zone_events))
+                                })
+                                .and_then(move |(client, users, zone_events)| {
+                                    emergency_event::select_emergency_scope_changes(client, event_id)
+                                        .map(move |(_client, scope_changes)| (users, zone_events, scope_changes))
+                                })
+                                .map(move |(users, zone_events, scope_changes)| {
+                                    // only the zones on the maps the emergency covered
+                                    let zone_events = zone_events
+                                        .into_iter()
+                                        .filter(|e| match (&event.map_ids, e.map_id) {
+                                            (None, _) => true,
+                                            (Some(ids), Some(id)) => ids.contains(&id),
+                                            (Some(_), None) => false,
+                                        })
+                                        .collect();
+                                    HttpResponse::Ok().json(Ok::<_, AkError>(EmergencyEventDetail {
+                                        event,
+                                        scope_changes,
+                                        users,
+                                        zone_events,
+                                    }))
+                                })
+                            )
+                        },
+                        None => Either::B(err(AkError::not_found())),
+                    }
+                })
+            )
+        },
+        _ => {
+            Either::B(err(AkError::not_found()))
+        },
+    }
+}
+
+// everyone from the database, placed by the data processor's latest positions where it has them
+fn muster_report(uid: &Identity, state: &AKData) -> impl Future<Item=MusterReport, Error=AkError> {
+    let (data_processor, beacon_manager) = {
+        let s = state.lock().unwrap();
+        (s.data_processor.clone(), s.beacon_manager.clone())
+    };
+    db_utils::connect_id(uid, state)
+        .and_then(|client| {
+            user::select_users(client, false)
+        })
+        .and_then(|(client, users)| {
+            zone::select_zones(client)
+                .map(move |(client, zones)| (client, users, zones))
+        })
+        .and_then(|(client, users, zones)| {
+            map::select_maps(client)
+                .map(move |(_client, maps)| (users, zones, maps))
+        })
+        .and_then(move |(users, zones, maps)| {
+            data_processor
+                .send(OutUserData)
+                .then(|res| {
+                    match res {
+                        Ok(Ok(realtime)) => ok((users, zones, maps, realtime)),
+                        _ => err(AkError::internal()),
+                    }
+                })
+        })
+        .and_then(move |(users, zones, maps, realtime)| {
+            beacon_manager
+                .send(OutEmergencyStatus)
+                .then(move |res| {
+                    match res {
+                        Ok(Ok(emergency)) => ok(muster::report(users, &realtime, &zones, &maps, emergency, Utc::now())),
+                        _ => err(AkError::internal()),
+                    }
+                })
+        })
+}
+
+// who has reached a muster point, who was last seen inside, and who has never been seen
+pub fn get_muster_report(uid: Identity, state: AKData, _req: HttpRequest) -> impl Future<Item=HttpResponse, Error=AkError> {
+    muster_report(&uid, &state)
+        .map(|report| {
+            HttpResponse::Ok().json(Ok::<_, AkError>(report))
+        })
+}
+
+// the same report as a spreadsheet, kept with the incident records
+pub fn get_muster_export(uid: Identity, state: AKData, _req: HttpRequest) -> impl Future<Item=HttpResponse, Error=AkError> {
+    muster_report(&uid, &state)
+        .map(|report| {
+            let filename = format!("muster_{}.csv", report.generated_at.format("%Y%m%d_%H%M%S"));
+            HttpResponse::Ok()
+                .content_type("text/csv")
+                .header("Content-Disposition", format!("attachment; filename=\"{}\"", filename))
+                .body(muster::to_csv(&report))
+        })
+}
+
 pub fn diagnostics(state: AKData, _req: HttpRequest) -> impl Future<Item=HttpResponse, Error=AkError> {
     let s = state.lock().unwrap();
     s.beacon_manager
@@ -61,44 +219,278 @@ pub fn diagnostics(state: AKData, _req: HttpRequest) -> impl Future<Item=HttpRes
         }})
 }
 
+pub fn get_filter_config(state: AKData, _req: HttpRequest) -> impl Future<Item=HttpResponse, Error=AkError> {
+    let s = state.lock().unwrap();
+    s.data_processor
+        .send(OutFilterConfig)
+        .then(|res| {
+            match res {
+                Ok(data) => {
+                    ok(HttpResponse::Ok().json(data))
+                },
+                _ => {
+                    err(AkError::internal())
+                }
+        }})
+}
+
+pub fn put_filter_config(id: Identity, state: AKData, payload: web::Json<FilterConfig>) -> impl Future<Item=HttpResponse, Error=AkError> {
+    if let Err(reason) = payload.validate() {
+        return Either::B(err(AkError::validation(reason)));
+    }
+    let processor_state = state.clone();
+    Either::A(auth::require_admin(&id, &state)
+        .and_then(move |_session| {
+            let s = processor_state.lock().unwrap();
+            s.data_processor
+                .send(DPMessage::SetFilterConfig(payload.0))
+                .then(|res| {
+                    match res {
+                        Ok(Ok(_)) => {
+                            ok(HttpResponse::Ok().json(Ok::<_, AkError>(())))
+                        },
+                        _ => {
+                            err(AkError::internal())
+                        }
+                }})
+        })
+    )
+}
+
+pub fn get_retention(state: AKData, _req: HttpRequest) -> impl Future<Item=HttpResponse, Error=AkError> {
+    let s = state.lock().unwrap();
+    s.data_processor
+        .send(OutRetention)
+        .then(|res| {
+            match res {
+                Ok(data) => {
+                    ok(HttpResponse::Ok().json(data))
+                },
+                _ => {
+                    err(AkError::internal())
+                }
+        }})
+}
+
+// saved before the data processor is told, so the retention is kept through a restart
+pub fn put_retention(id: Identity, state: AKData, payload: web::Json<RetentionConfig>) -> impl Future<Item=HttpResponse, Error=AkError> {
+    let processor_state = state.clone();
+    let retention = payload.0;
+    auth::require_admin(&id, &state)
+        .and_then(move |_session| {
+            db_utils::connect_id(&id, &state)
+        })
+        .and_then(move |client| {
+            retention::update_retention(client, retention.clone())
+                .map(move |_client| retention)
+        })
+        .and_then(move |retention| {
+            let s = processor_state.lock().unwrap();
+            s.data_processor
+                .send(DPMessage::SetRetention(retention))
+                .then(|res| {
+                    match res {
+                        Ok(Ok(_)) => {
+                            ok(HttpResponse::Ok().json(Ok::<_, AkError>(())))
+                        },
+                        _ => {
+                            err(AkError::internal())
+                        }
+                }})
+        })
+}
+
+pub fn get_motion_config(state: AKData, _req: HttpRequest) -> impl Future<Item=HttpResponse, Error=AkError> {
+    let s = state.lock().unwrap();
+    s.data_processor
+        .send(OutMotionConfig)
+        .then(|res| {
+            match res {
+                Ok(data) => {
+                    ok(HttpResponse::Ok().json(data))
+                },
+                _ => {
+                    err(AkError::internal())
+                }
+        }})
+}
+
+pub fn put_motion_config(id: Identity, state: AKData, payload: web::Json<MotionConfig>) -> impl Future<Item=HttpResponse, Error=AkError> {
+    if let Err(reason) = payload.validate() {
+        return Either::B(err(AkError::validation(reason)));
+    }
+    let processor_state = state.clone();
+    Either::A(auth::require_admin(&id, &state)
+        .and_then(move |_session| {
+            let s = processor_state.lock().unwrap();
+            s.data_processor
+                .send(DPMessage::SetMotionConfig(payload.0))
+                .then(|res| {
+                    match res {
+                        Ok(Ok(_)) => {
+                            ok(HttpResponse::Ok().json(Ok::<_, AkError>(())))
+                        },
+                        _ => {
+                            err(AkError::internal())
+                        }
+                }})
+        })
+    )
+}
+
+// the active configuration, without passwords or keys
+pub fn get_config(id: Identity, state: AKData, _req: HttpRequest) -> impl Future<Item=HttpResponse, Error=AkError> {
+    let config_state = state.clone();
+    auth::require_admin(&id, &state)
+        .map(move |_session| {
+            let config = config_state.lock().unwrap().config.redacted();
+            HttpResponse::Ok().json(Ok::<_, AkError>(config))
+        })
+}
+
 pub fn ping(_req: HttpRequest) -> impl Future<Item=HttpResponse, Error=AkError> {
     ok(HttpResponse::Ok().json(Ok::<_, AkError>(())))
 }
 
-pub fn restart(id: Identity, state: AKData, payload: web::Json<SystemCommand>) -> Result<HttpResponse, AkError> {
-    if let Some(name) = id.identity() {
-        if name == "admin" {
-            let s = state.lock().unwrap();
+pub fn restart(id: Identity, state: AKData, payload: web::Json<SystemCommand>) -> impl Future<Item=HttpResponse, Error=AkError> {
+    let watcher_state = state.clone();
+    auth::require_admin(&id, &state)
+        .map(move |_session| {
             let command = match payload.0 {
                 SystemCommand::StartNormal => WatcherCommand::StartNormal,
                 SystemCommand::RebuildDB => WatcherCommand::RebuildDB,
                 SystemCommand::RebuildDemoDB => WatcherCommand::RebuildDemoDB,
             };
-            match s.tx.send(command) {
-                Ok(()) => {},
-                Err(e) => {
-                    println!("Failed to notify watcher we are shutting down {}", e);
-                },
+            shutdown(&watcher_state, command);
+
+            HttpResponse::Ok().finish()
+        })
+}
+
+// logs out every account, returns the number of sessions that were removed
+pub fn logout_all_sessions(id: Identity, state: AKData, _req: HttpRequest) -> impl Future<Item=HttpResponse, Error=AkError> {
+    let system_state = state.clone();
+    let cache_state = state.clone();
+    auth::require_admin(&id, &state)
+        .and_then(move |_session| {
+            db_utils::connect_system(&system_state)
+        })
+        .and_then(|client| {
+            session::delete_all_sessions(client)
+        })
+        .map(move |(_client, count)| {
+            auth::forget_all(&cache_state);
+            HttpResponse::Ok().json(Ok::<_, AkError>(count))
+        })
+}
+
+// replaces the generated cookie signing key, the webserver restarts to start using it.
+// a key set with session.key in the config file has to be changed there instead.
+pub fn rotate_session_key(id: Identity, state: AKData, _req: HttpRequest) -> impl Future<Item=HttpResponse, Error=AkError> {
+    let system_state = state.clone();
+    let watcher_state = state.clone();
+    auth::require_admin(&id, &state)
+        .and_then(move |_session| {
+            let cookie = system_state.lock().unwrap().cookie.clone();
+            if cookie.is_key_configured() {
+                return Either::B(err(AkError::validation("the session key is configured, and cannot be rotated")));
+            }
+            if let Err(e) = cookie.rotate_key() {
+                println!("failed to rotate session key: {}", e);
+                return Either::B(err(AkError::internal()));
             }
 
-            // HACK, attempt to give the request enough time to reply to the client before
-            // shutting down
-            println!("initiating shutdown");
-            let shutdown_fut = tokio::timer::Delay::new(tokio::clock::now() + Duration::from_millis(500))
-                .map(|_| {
-                    println!("shutting down now");
-                    let system = System::current();
-                    system.stop();
+            Either::A(db_utils::connect_system(&system_state)
+                .and_then(|client| {
+                    session::delete_all_sessions(client)
                 })
-                .map_err(|_e| {
-                });
-            Arbiter::spawn(shutdown_fut);
-
-            Ok(HttpResponse::Ok().finish())
-        } else {
-            Err(AkError::unauthorized())
-        }
-    } else {
-        Err(AkError::unauthorized())
+            )
+        })
+        .map(move |(_client, _count)| {
+            auth::forget_all(&watcher_state);
+            shutdown(&watcher_state, WatcherCommand::StartNormal);
+            HttpResponse::Ok().json(Ok::<_, AkError>(()))
+        })
+}
+
+pub fn get_capture(id: Identity, state: AKData, _req: HttpRequest) -> impl Future<Item=HttpResponse, Error=AkError> {
+    let capture_state = state.clone();
+    auth::require_admin(&id, &state)
+        .and_then(move |_session| {
+            let s = capture_state.lock().unwrap();
+            s.capture
+                .send(OutCaptureStatus)
+                .then(|res| {
+                    match res {
+                        Ok(data) => {
+                            ok(HttpResponse::Ok().json(data))
+                        },
+                        _ => {
+                            err(AkError::internal())
+                        }
+                }})
+        })
+}
+
+// records every frame from the udp beacons until it is stopped
+pub fn start_capture(id: Identity, state: AKData, payload: web::Json<CaptureRequest>) -> impl Future<Item=HttpResponse, Error=AkError> {
+    let capture_state = state.clone();
+    auth::require_admin(&id, &state)
+        .and_then(move |_session| {
+            let s = capture_state.lock().unwrap();
+            s.capture
+                .send(StartCapture(payload.0))
+                .then(|res| {
+                    match res {
+                        Ok(data) => {
+                            ok(HttpResponse::Ok().json(data))
+                        },
+                        _ => {
+                            err(AkError::internal())
+                        }
+                }})
+        })
+}
+
+pub fn stop_capture(id: Identity, state: AKData, _req: HttpRequest) -> impl Future<Item=HttpResponse, Error=AkError> {
+    let capture_state = state.clone();
+    auth::require_admin(&id, &state)
+        .and_then(move |_session| {
+            let s = capture_state.lock().unwrap();
+            s.capture
+                .send(StopCapture)
+                .then(|res| {
+                    match res {
+                        Ok(data) => {
+                            ok(HttpResponse::Ok().json(data))
+                        },
+                        _ => {
+                            err(AkError::internal())
+                        }
+                }})
+        })
+}
+
+// tells the watcher how to start the next webserver, then stops this one
+fn shutdown(state: &AKData, command: WatcherCommand) {
+    let s = state.lock().unwrap();
+    match s.tx.send(command) {
+        Ok(()) => {},
+        Err(e) => {
+            println!("Failed to notify watcher we are shutting down {}", e);
+        },
     }
+
+    // HACK, attempt to give the request enough time to reply to the client before
+    // shutting down
+    println!("initiating shutdown");
+    let shutdown_fut = tokio::timer::Delay::new(tokio::clock::now() + Duration::from_millis(500))
+        .map(|_| {
+            println!("shutting down now");
+            let system = System::current();
+            system.stop();
+        })
+        .map_err(|_e| {
+        });
+    Arbiter::spawn(shutdown_fut);
 }
@@ -2,8 +2,9 @@
 use actix_web::{ web, HttpRequest, HttpResponse, };
 use common::*;
 use crate::AKData;
-use crate::data_processor::OutUserData;
+use crate::data_processor::{ DPMessage, OutFloorTransitions, OutUserData, };
 use crate::db_utils;
+use crate::models::location_history;
 use crate::models::user;
 use futures::{ future::err, future::ok, Future, future::Either, };
 use serde_derive::{ Deserialize, };
@@ -16,6 +17,14 @@ pub struct GetParams {
     include_contacts: Option<bool>,
 }
 
+// the actors look users up in a cache, and the data processor keeps its own copy of the users it
+// is locating, both are stale once a user is edited
+fn reload_users(state: &AKData) {
+    let state = state.lock().unwrap();
+    state.repositories.users.invalidate();
+    state.data_processor.do_send(DPMessage::ReloadUsers);
+}
+
 pub fn users_status(_uid: Identity, state: AKData, _req: HttpRequest) -> impl Future<Item=HttpResponse, Error=AkError> {
     let s = state.lock().unwrap();
     s.data_processor
@@ -31,6 +40,21 @@ pub fn users_status(_uid: Identity, state: AKData, _req: HttpRequest) -> impl Fu
         }})
 }
 
+pub fn users_floor_transitions(_uid: Identity, state: AKData, _req: HttpRequest) -> impl Future<Item=HttpResponse, Error=AkError> {
+    let s = state.lock().unwrap();
+    s.data_processor
+        .send(OutFloorTransitions)
+        .then(|res| {
+            match res {
+                Ok(data) => {
+                    ok(HttpResponse::Ok().json(data))
+                },
+                _ => {
+                    err(AkError::internal())
+                }
+        }})
+}
+
 pub fn get_user(uid: Identity, state: AKData, req: HttpRequest, params: web::Query<GetParams>) -> impl Future<Item=HttpResponse, Error=AkError> {
     let id = req.match_info().get("id").unwrap_or("-1").parse::<i32>();
     let prefetch = params.prefetch.unwrap_or(false);
@@ -62,6 +86,26 @@ pub fn get_user(uid: Identity, state: AKData, req: HttpRequest, params: web::Que
     }
 }
 
+pub fn get_user_history(uid: Identity, state: AKData, req: HttpRequest, params: web::Query<HistoryParams>) -> impl Future<Item=HttpResponse, Error=AkError> {
+    let id = req.match_info().get("id").unwrap_or("-1").parse::<i32>();
+    let (from, to) = params.range();
+    match id {
+        Ok(id) if id != -1 => {
+            Either::A(db_utils::connect_id(&uid, &state)
+                .and_then(move |client| {
+                    location_history::select_user_history(client, id, from, to)
+                })
+                .map(|(_client, history)| {
+                    HttpResponse::Ok().json(Ok::<_, AkError>(history))
+                })
+            )
+        },
+        _ => {
+            Either::B(err(AkError::not_found()))
+        },
+    }
+}
+
 pub fn get_users(uid: Identity, state: AKData, _req: HttpRequest, params: web::Query<GetParams>) -> impl Future<Item=HttpResponse, Error=AkError> {
     let include_contacts = params.include_contacts.unwrap_or(true);
     db_utils::connect_id(&uid, &state)
@@ -76,6 +120,7 @@ pub fn get_users(uid: Identity, state: AKData, _req: HttpRequest, params: web::Q
 // new user
 pub fn post_user(uid: Identity, state: AKData, _req: HttpRequest, payload: web::Json<(TrackedUser, Option<TrackedUser>)>) -> impl Future<Item=HttpResponse, Error=AkError> {
     let (user, opt_e_user) = payload.into_inner();
+    let processor_state = state.clone();
 
     db_utils::connect_id(&uid, &state)
         .and_then(move |client| {
@@ -102,9 +147,12 @@ pub fn post_user(uid: Identity, state: AKData, _req: HttpRequest, payload: web::
                     }
                 })
         })
-        .and_then(|(_client, user, opt_e_user)| {
+        .and_then(move |(_client, user, opt_e_user)| {
             match user {
-                Some(u) => ok(HttpResponse::Ok().json(Ok::<_, AkError>((u, opt_e_user)))),
+                Some(u) => {
+                    reload_users(&processor_state);
+                    ok(HttpResponse::Ok().json(Ok::<_, AkError>((u, opt_e_user))))
+                },
                 None => err(AkError::not_found()),
             }
         })
@@ -112,6 +160,7 @@ pub fn post_user(uid: Identity, state: AKData, _req: HttpRequest, payload: web::
 
 pub fn put_user(uid: Identity, state: AKData, _req: HttpRequest, payload: web::Json<(TrackedUser, Option<TrackedUser>)>) -> impl Future<Item=HttpResponse, Error=AkError> {
     let (user, opt_e_user) = payload.into_inner();
+    let processor_state = state.clone();
 
     db_utils::connect_id(&uid, &state)
         .and_then(|client| {
@@ -136,9 +185,12 @@ pub fn put_user(uid: Identity, state: AKData, _req: HttpRequest, payload: web::J
                     }
                 })
         })
-        .and_then(|(_client, opt_user, opt_e_user)| {
+        .and_then(move |(_client, opt_user, opt_e_user)| {
             match opt_user {
-                Some(u) => ok(HttpResponse::Ok().json(Ok::<_, AkError>((u, opt_e_user)))),
+                Some(u) => {
+                    reload_users(&processor_state);
+                    ok(HttpResponse::Ok().json(Ok::<_, AkError>((u, opt_e_user))))
+                },
                 None => err(AkError::not_found()),
             }
         })
@@ -148,11 +200,13 @@ pub fn delete_user(uid: Identity, state: AKData, req: HttpRequest) -> impl Futur
     let id = req.match_info().get("id").unwrap_or("-1").parse::<i32>();
     match id {
         Ok(id) => {
+            let processor_state = state.clone();
             Either::A(db_utils::connect_id(&uid, &state)
                 .and_then(move |client| {
                     user::delete_user(client, id)
                 })
-                .map(|_client| {
+                .map(move |_client| {
+                    reload_users(&processor_state);
                     HttpResponse::Ok().json(Ok::<_, AkError>(()))
                 })
             )
@@ -0,0 +1,112 @@
+use actix_web::{ web, HttpRequest, HttpResponse, };
+use common::*;
+use common::zones::Zone;
+use crate::AKData;
+use crate::data_processor::DPMessage;
+use crate::db_utils;
+use crate::models::zone;
+use crate::models::zone_event;
+use futures::{ future::err, future::ok, Future, future::Either, };
+use actix_identity::Identity;
+use crate::ak_error::AkError;
+
+// the data processor keeps its own copy of the zones to check positions against
+fn reload_zones(state: &AKData) {
+    state.lock().unwrap().data_processor.do_send(DPMessage::ReloadZones);
+}
+
+pub fn get_zones_for_map(uid: Identity, state: AKData, req: HttpRequest) -> impl Future<Item=HttpResponse, Error=AkError> {
+    let id = req.match_info().get("id").unwrap_or("-1").parse::<i32>();
+    match id {
+        Ok(id) if id != -1 => {
+            Either::A(db_utils::connect_id(&uid, &state)
+                .and_then(move |client| {
+                    zone::select_zones_for_map(client, id)
+                })
+                .map(|(_client, zones)| {
+                    HttpResponse::Ok().json(Ok::<_, AkError>(zones))
+                })
+            )
+        },
+        _ => {
+            Either::B(err(AkError::not_found()))
+        }
+    }
+}
+
+// new zone
+pub fn post_zone(uid: Identity, state: AKData, _req: HttpRequest, payload: web::Json<Zone>) -> impl Future<Item=HttpResponse, Error=AkError> {
+    if let Err(reason) = payload.validate() {
+        return Either::B(err(AkError::validation(reason)));
+    }
+    let processor_state = state.clone();
+    Either::A(db_utils::connect_id(&uid, &state)
+        .and_then(move |client| {
+            zone::insert_zone(client, payload.0)
+        })
+        .and_then(move |(_client, opt_zone)| {
+            match opt_zone {
+                Some(z) => {
+                    reload_zones(&processor_state);
+                    ok(HttpResponse::Ok().json(Ok::<_, AkError>(z)))
+                },
+                None => err(AkError::not_found()),
+            }
+        })
+    )
+}
+
+// update zone
+pub fn put_zone(uid: Identity, state: AKData, _req: HttpRequest, payload: web::Json<Zone>) -> impl Future<Item=HttpResponse, Error=AkError> {
+    if let Err(reason) = payload.validate() {
+        return Either::B(err(AkError::validation(reason)));
+    }
+    let processor_state = state.clone();
+    Either::A(db_utils::connect_id(&uid, &state)
+        .and_then(move |client| {
+            zone::update_zone(client, payload.0)
+        })
+        .and_then(move |(_client, opt_zone)| {
+            match opt_zone {
+                Some(z) => {
+                    reload_zones(&processor_state);
+                    ok(HttpResponse::Ok().json(Ok::<_, AkError>(z)))
+                },
+                None => err(AkError::not_found()),
+            }
+        })
+    )
+}
+
+pub fn delete_zone(uid: Identity, state: AKData, req: HttpRequest) -> impl Future<Item=HttpResponse, Error=AkError> {
+    let id = req.match_info().get("id").unwrap_or("-1").parse::<i32>();
+    match id {
+        Ok(id) if id != -1 => {
+            let processor_state = state.clone();
+            Either::A(db_utils::connect_id(&uid, &state)
+                .and_then(move |client| {
+                    zone::delete_zone(client, id)
+                })
+                .map(move |_client| {
+                    reload_zones(&processor_state);
+                    HttpResponse::Ok().json(Ok::<_, AkError>(()))
+                })
+            )
+        },
+        _ => {
+            Either::B(err(AkError::not_found()))
+        }
+    }
+}
+
+// entries, exits and overstays for the incident log, most recent first
+pub fn get_zone_events(uid: Identity, state: AKData, _req: HttpRequest, params: web::Query<HistoryParams>) -> impl Future<Item=HttpResponse, Error=AkError> {
+    let (from, to) = params.range();
+    db_utils::connect_id(&uid, &state)
+        .and_then(move |client| {
+            zone_event::select_zone_events(client, from, to)
+        })
+        .map(|(_client, events)| {
+            HttpResponse::Ok().json(Ok::<_, AkError>(events))
+        })
+}
@@ -0,0 +1,155 @@
+// Settings for the identity cookie, and the key that signs it. The key is generated on the first
+// run and kept in a file, so that sessions survive a restart. Replacing the key makes every
+// existing cookie unreadable, so the sessions of the old key are deleted along with it.
+
+use actix_identity::CookieIdentityPolicy;
+use actix_web::cookie::SameSite;
+use crate::config::SessionConfig;
+use crate::hex::{ decode_hex, encode_hex, };
+use rand::RngCore;
+use rand::rngs::OsRng;
+use std::fs::{ self, OpenOptions, };
+use std::io::{ self, Write, };
+use std::os::unix::fs::OpenOptionsExt;
+
+pub const COOKIE_NAME: &str = "session_token";
+pub const KEY_LENGTH: usize = 32;
+
+#[derive(Debug, Clone)]
+pub struct CookieConfig {
+    // when set, the key is not read from or written to the key file
+    pub key: Option<Vec<u8>>,
+    pub key_file: String,
+    // also the lifetime of the server side session
+    pub max_age_hours: i64,
+    pub same_site: SameSite,
+    pub secure: bool,
+}
+
+impl CookieConfig {
+    pub fn new() -> CookieConfig {
+        let defaults = SessionConfig::default();
+        CookieConfig {
+            key: None,
+            key_file: defaults.key_file,
+            max_age_hours: defaults.hours,
+            same_site: SameSite::Strict,
+            secure: defaults.secure,
+        }
+    }
+
+    pub fn from_config(session: &SessionConfig) -> Result<CookieConfig, String> {
+        let mut config = CookieConfig::new();
+
+        if let Some(key) = &session.key {
+            match decode_hex(key.trim()) {
+                Some(ref bytes) if bytes.len() >= KEY_LENGTH => config.key = Some(bytes.clone()),
+                _ => return Err(format!("session.key must be at least {} hex encoded bytes", KEY_LENGTH)),
+            }
+        }
+        if session.key_file.is_empty() {
+            return Err("session.key_file must not be empty".to_string());
+        }
+        config.key_file = session.key_file.clone();
+        if session.hours <= 0 {
+            return Err(format!("session.hours must be a positive number of hours, not {}", session.hours));
+        }
+        config.max_age_hours = session.hours;
+        config.same_site = parse_same_site(&session.same_site)
+            .ok_or(format!("session.same_site must be strict, lax or none, not {}", session.same_site))?;
+        config.secure = session.secure;
+
+        Ok(config)
+    }
+
+    pub fn is_key_configured(&self) -> bool {
+        self.key.is_some()
+    }
+
+    // returns the signing key, and whether it was generated because there was no key yet
+    pub fn load_key(&self) -> io::Result<(Vec<u8>, bool)> {
+        if let Some(key) = &self.key {
+            return Ok((key.clone(), false));
+        }
+
+        match fs::read_to_string(&self.key_file) {
+            Ok(contents) => {
+                match decode_hex(contents.trim()) {
+                    Some(ref key) if key.len() >= KEY_LENGTH => Ok((key.clone(), false)),
+                    _ => Err(io::Error::new(io::ErrorKind::InvalidData, format!("{} does not contain a valid key", self.key_file))),
+                }
+            },
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+                self.rotate_key().map(|key| (key, true))
+            },
+            Err(e) => Err(e),
+        }
+    }
+
+    // writes a new key to the key file, the key is used once the webserver restarts
+    pub fn rotate_key(&self) -> io::Result<Vec<u8>> {
+        let mut key = vec![0u8; KEY_LENGTH];
+        OsRng.fill_bytes(&mut key);
+
+        // write the new key beside the old one, so a failure cannot leave a partial key behind
+        let temp_file = format!("{}.new", self.key_file);
+        {
+            let mut file = OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .mode(0o600)
+                .open(&temp_file)?;
+            file.write_all(encode_hex(&key).as_bytes())?;
+            file.sync_all()?;
+        }
+        fs::rename(&temp_file, &self.key_file)?;
+        Ok(key)
+    }
+
+    pub fn policy(&self, key: &[u8]) -> CookieIdentityPolicy {
+        CookieIdentityPolicy::new(key)
+            .name(COOKIE_NAME)
+            .path("/")
+            .max_age(self.max_age_hours * 60 * 60)
+            .same_site(self.same_site)
+            .secure(self.secure)
+    }
+}
+
+fn parse_same_site(value: &str) -> Option<SameSite> {
+    match value.to_lowercase().as_str() {
+        "strict" => Some(SameSite::Strict),
+        "lax" => Some(SameSite::Lax),
+        "none" => Some(SameSite::None),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generated_once() {
+        let mut config = CookieConfig::new();
+        config.key_file = std::env::temp_dir()
+            .join(format!("ak_session_{}.key", std::process::id()))
+            .to_string_lossy()
+            .into_owned();
+        let _ = fs::remove_file(&config.key_file);
+
+        let (first, generated) = config.load_key().unwrap();
+        assert!(generated);
+        assert_eq!(first.len(), KEY_LENGTH);
+
+        let (second, generated) = config.load_key().unwrap();
+        assert!(!generated);
+        assert_eq!(first, second);
+
+        let rotated = config.rotate_key().unwrap();
+        assert!(rotated != first);
+        assert_eq!(config.load_key().unwrap().0, rotated);
+        fs::remove_file(&config.key_file).unwrap();
+    }
+}
@@ -3,84 +3,224 @@ use actix::prelude::*;
 use actix::fut as afut;
 use actix_web::Result;
 use common::{ MacAddress8, ShortAddress, };
-use crate::db_utils;
-use crate::models::beacon;
-use crate::models::user;
+use crate::push::{ Publish, PushBroadcaster, };
+use crate::repository::Repositories;
 use futures::future as fut;
-use na;
 use std::collections::{ BTreeMap, VecDeque };
 use std::io;
+use std::time::Duration as StdDuration;
 use common::*;
-use chrono::{ Utc, };
+use common::positioning::{ self, LocateError, TagRanges, };
+use common::tag_filter::{ FilterConfig, TagFilter, };
+use common::floor_selection::FloorTransition;
+use common::emergency_scope;
+use common::motion::{ self, MotionAlert, MotionChange, MotionConfig, MotionTracker, };
+use common::zones::{ Zone, ZoneTracker, };
+use chrono::{ Duration, Utc, };
 use crate::ak_error::AkError;
 
-const LOCATION_HISTORY_SIZE: usize = 5;
+const MAX_FLOOR_TRANSITIONS: usize = 256;
+// how often location history past the retention period is deleted
+const HISTORY_PRUNE_INTERVAL: StdDuration = StdDuration::from_secs(60 * 60);
+// how often users are checked for not moving, or for tags that went silent
+const MOTION_CHECK_INTERVAL: StdDuration = StdDuration::from_secs(1);
 
 // contains a vector of tag data from multiple beacons
 #[derive(Debug)]
 struct TagHistory {
     pub user: RealtimeUserData,
-    pub beacon_history: BTreeMap<MacAddress8, VecDeque<f64>>,
+    pub ranges: TagRanges,
+    pub filter: TagFilter,
+    pub motion: MotionTracker,
+    pub zones: ZoneTracker,
 }
 
 pub struct DataProcessor {
     // this hash maps the id_tag mac address to data points for that id tag.
-    // TODO support floors
     // TODO init with db data?
     // this tree maps tag mac addresses to users
     // scanning the entire tree for all entries will likely be a very common,
     // so hash is likely not a good choice.
     users: BTreeMap<ShortAddress, Box<TagHistory>>,
+    // kept in step by the beacon manager, users are only watched for not moving during an emergency
+    emergency: EmergencyStatus,
+    filter_config: FilterConfig,
+    floor_transitions: VecDeque<FloorTransition>,
+    motion_alerts: Vec<MotionAlert>,
+    motion_config: MotionConfig,
+    next_motion_alert: u64,
+    push: Addr<PushBroadcaster>,
+    repositories: Repositories,
+    // None until it is loaded, nothing is pruned before then
+    retention: Option<RetentionConfig>,
+    zones: Vec<Zone>,
 }
 
 impl DataProcessor {
-    pub fn new() -> DataProcessor {
+    pub fn new(push: Addr<PushBroadcaster>, repositories: Repositories) -> DataProcessor {
         DataProcessor {
             users: BTreeMap::new(),
+            emergency: EmergencyStatus::new(),
+            filter_config: FilterConfig::new(),
+            floor_transitions: VecDeque::new(),
+            motion_alerts: Vec::new(),
+            motion_config: MotionConfig::new(),
+            next_motion_alert: 1,
+            push,
+            repositories,
+            retention: None,
+            zones: Vec::new(),
         }
     }
 
-    fn calc_trilaterate(sorted_beacons: &Vec<common::Beacon>, sorted_data: &Vec<common::TagData>) -> na::Vector2<f64> {
-        if sorted_data.len() < 3 {
-            panic!("not enough data points to trilaterate");
-        }
-        if sorted_beacons.len() < 3 {
-            panic!("not enough beacons to trilaterate");
-        }
+    fn load_zones(&mut self, context: &mut Context<Self>) {
+        let fut = self.repositories.maps.zones()
+            .into_actor(self)
+            .map(|zones, actor, _context| {
+                actor.zones = zones;
+            })
+            .map_err(|e, _actor, _context| {
+                println!("data processor: failed to load zones: {}", e);
+            });
+        context.spawn(fut);
+    }
 
-        assert!(sorted_beacons[0].mac_address == sorted_data[0].beacon_mac);
-        assert!(sorted_beacons[1].mac_address == sorted_data[1].beacon_mac);
-        assert!(sorted_beacons[2].mac_address == sorted_data[2].beacon_mac);
+    // users were edited, tags that now belong to someone else, or no one, are looked up again by
+    // their next range
+    fn reload_users(&mut self, context: &mut Context<Self>) {
+        let fut = self.repositories.users.tagged_users()
+            .into_actor(self)
+            .map(|users, actor, _context| {
+                actor.users.retain(|addr, hist| {
+                    match users.iter().find(|u| u.mac_address == Some(*addr)) {
+                        Some(user) if user.id == hist.user.id => {
+                            hist.user.name = user.name.clone();
+                            true
+                        },
+                        _ => false,
+                    }
+                });
+            })
+            .map_err(|e, _actor, _context| {
+                println!("data processor: failed to reload users: {}", e);
+            });
+        context.spawn(fut);
+    }
 
-        let bloc1 = sorted_beacons[0].coordinates;
-        let bloc2 = sorted_beacons[1].coordinates;
-        let bloc3 = sorted_beacons[2].coordinates;
+    fn load_retention(&mut self, context: &mut Context<Self>) {
+        let fut = self.repositories.system.retention()
+            .into_actor(self)
+            .map(|retention, actor, context| {
+                // a retention set through the api while loading is newer
+                if actor.retention.is_none() {
+                    actor.retention = Some(retention);
+                }
+                actor.prune_history(context);
+            })
+            .map_err(|e, _actor, _context| {
+                println!("data processor: failed to load retention, nothing is pruned: {}", e);
+            });
+        context.spawn(fut);
+    }
 
-        let d1 = sorted_data[0].tag_distance;
-        let d2 = sorted_data[1].tag_distance;
-        let d3 = sorted_data[2].tag_distance;
+    fn prune_history(&mut self, context: &mut Context<Self>) {
+        let days = match &self.retention {
+            Some(retention) if retention.location_history_days > 0 => retention.location_history_days,
+            _ => return,
+        };
 
-        // Trilateration solver
-        let a = -2.0 * bloc1.x + 2.0 * bloc2.x;
-        let b = -2.0 * bloc1.y + 2.0 * bloc2.y;
-        let c = d1 * d1 - d2 * d2 - bloc1.x * bloc1.x + bloc2.x * bloc2.x - bloc1.y * bloc1.y + bloc2.y * bloc2.y;
-        let d = -2.0 * bloc2.x + 2.0 * bloc3.x;
-        let e = -2.0 * bloc2.y + 2.0 * bloc3.y;
-        let f = d2 * d2 - d3 * d3 - bloc2.x * bloc2.x + bloc3.x * bloc3.x - bloc2.y * bloc2.y + bloc3.y * bloc3.y;
+        let cutoff = Utc::now() - Duration::days(days as i64);
+        let fut = self.repositories.users.delete_history_before(cutoff)
+            .map(|count| {
+                if count > 0 {
+                    println!("data processor: pruned {} location history entries", count);
+                }
+            })
+            .map_err(|e| {
+                println!("data processor: failed to prune location history: {}", e);
+            });
+        context.spawn(fut.into_actor(self));
+    }
 
-        let x = (c * e - f * b) / (e * a - b * d);
-        let y = (c * d - a * f) / (b * d - a * e);
+    fn check_motion(&mut self) {
+        let now = Utc::now();
+        let mut changed = Vec::new();
+        for (_addr, hist) in self.users.iter_mut() {
+            let watching = emergency_scope::covers(&self.emergency, hist.user.map_id);
+            for change in hist.motion.check(&self.motion_config, watching, now, hist.user.last_active) {
+                match change {
+                    MotionChange::Raised(kind) => {
+                        let mut alert = MotionAlert::new();
+                        alert.id = self.next_motion_alert;
+                        alert.coordinates = hist.user.coordinates;
+                        alert.kind = kind;
+                        alert.last_active = hist.user.last_active;
+                        alert.map_id = hist.user.map_id;
+                        alert.raised_at = now;
+                        alert.user_id = hist.user.id;
+                        alert.user_name = hist.user.name.clone();
+                        self.next_motion_alert += 1;
+                        println!("data processor: {} for user {}", kind, hist.user.name);
+                        changed.push(alert);
+                    },
+                    MotionChange::Cleared(kind) => {
+                        let user_id = hist.user.id;
+                        let open = self.motion_alerts
+                            .iter()
+                            .find(|a| a.user_id == user_id && a.kind == kind && a.cleared_at.is_none());
+                        if let Some(alert) = open {
+                            let mut alert = alert.clone();
+                            alert.cleared_at = Some(now);
+                            changed.push(alert);
+                        }
+                    },
+                }
+            }
+        }
+
+        for alert in changed {
+            self.push.do_send(Publish(PushMessage::MotionAlert(alert.clone())));
+            motion::merge_alert(&mut self.motion_alerts, alert);
+        }
+    }
 
-        na::Vector2::new(x as f64, y as f64)
+    fn log_floor_transition(&mut self, transition: FloorTransition) {
+        println!(
+            "data processor: tag {} moved from map {:?} to map {:?}",
+            transition.addr,
+            transition.from_map_id,
+            transition.to_map_id
+        );
+        self.floor_transitions.push_back(transition);
+        if self.floor_transitions.len() > MAX_FLOOR_TRANSITIONS {
+            self.floor_transitions.pop_front();
+        }
     }
 }
 
 impl Actor for DataProcessor {
     type Context = Context<Self>;
+
+    fn started(&mut self, context: &mut Context<Self>) {
+        self.load_zones(context);
+        self.load_retention(context);
+        context.run_interval(HISTORY_PRUNE_INTERVAL, |actor, context| {
+            actor.prune_history(context);
+        });
+        context.run_interval(MOTION_CHECK_INTERVAL, |actor, _context| {
+            actor.check_motion();
+        });
+    }
 }
 
 pub enum DPMessage {
     ResetData, // Reset the stored data
+    SetFilterConfig(FilterConfig), // Change how tag positions are smoothed
+    SetRetention(RetentionConfig), // Change how long location history is kept
+    ReloadZones, // The zones were changed in the database
+    ReloadUsers, // The users were changed in the database
+    SetEmergency(EmergencyStatus), // The emergency was started, rescoped or ended
+    SetMotionConfig(MotionConfig), // Change when users are reported for not moving
 }
 impl Message for DPMessage {
     type Result = Result<u64, io::Error>;
@@ -89,11 +229,41 @@ impl Message for DPMessage {
 impl Handler<DPMessage> for DataProcessor {
     type Result = Result<u64, io::Error>;
 
-    fn handle (&mut self, msg: DPMessage, _: &mut Context<Self>) -> Self::Result {
+    fn handle (&mut self, msg: DPMessage, context: &mut Context<Self>) -> Self::Result {
         match msg {
             DPMessage::ResetData => {
                 self.users.clear();
             },
+            DPMessage::SetFilterConfig(config) => {
+                // restart every filter, the old state was estimated with different parameters
+                for (_addr, hist) in self.users.iter_mut() {
+                    hist.filter = TagFilter::new(config.clone());
+                }
+                self.filter_config = config;
+            },
+            DPMessage::SetRetention(retention) => {
+                self.retention = Some(retention);
+                self.prune_history(context);
+            },
+            DPMessage::ReloadZones => {
+                self.load_zones(context);
+            },
+            DPMessage::ReloadUsers => {
+                self.reload_users(context);
+            },
+            DPMessage::SetEmergency(emergency) => {
+                if emergency.active && !self.emergency.active {
+                    // only time spent still during the emergency counts
+                    for (_addr, hist) in self.users.iter_mut() {
+                        hist.motion.reset();
+                    }
+                }
+                self.emergency = emergency;
+                self.check_motion();
+            },
+            DPMessage::SetMotionConfig(config) => {
+                self.motion_config = config;
+            },
         }
 
         Ok(1)
@@ -105,79 +275,53 @@ pub struct InLocationData(pub common::TagData);
 impl Message for InLocationData {
     type Result = Result<(), ()>;
 }
-fn append_history(tag_entry: &mut Box<TagHistory>, tag_data: &common::TagData) {
-    if let Some(beacon_entry) = tag_entry.beacon_history.get_mut(&tag_data.beacon_mac) {
-        beacon_entry.push_back(tag_data.tag_distance);
-        if beacon_entry.len() > LOCATION_HISTORY_SIZE {
-            beacon_entry.pop_front();
-        }
-    } else {
-        let mut deque = VecDeque::new();
-        deque.push_back(tag_data.tag_distance);
-        tag_entry.beacon_history.insert(tag_data.beacon_mac.clone(), deque);
-    }
-    tag_entry.user.last_active = tag_data.timestamp;
-}
-
 impl Handler<InLocationData> for DataProcessor {
     type Result = ResponseActFuture<Self, (), ()>;
 
     fn handle (&mut self, msg: InLocationData, _: &mut Context<Self>) -> Self::Result {
         let tag_data = msg.0.clone();
         let tag_data_update = msg.0;
+        let filter_type = self.filter_config.filter_type;
 
         // append the data to in memory structures,
         // then if there are enough data points, return them in opt_averages
         // so that they can be used to trilaterate
         let prep_fut = match self.users.get_mut(&tag_data.tag_mac) {
-            Some(mut tag_entry) => {
-                append_history(&mut tag_entry, &tag_data);
-
-                // TODO pick the most recent 3 beacons
-                if tag_entry.beacon_history.len() >= 3 {
-                    let averaged_data: Vec<common::TagData> = tag_entry.beacon_history.iter().map(|(beacon_mac, hist_vec)| {
-                        common::TagData {
-                            tag_mac: tag_entry.user.addr.clone(),
-                            beacon_mac: beacon_mac.clone(),
-                            tag_distance: hist_vec.into_iter().sum::<f64>() / hist_vec.len() as f64,
-                            timestamp: tag_entry.user.last_active,
-                        }
-                    }).collect();
+            Some(tag_entry) => {
+                tag_entry.ranges.append(&tag_data);
+                tag_entry.user.last_active = tag_data.timestamp;
 
-                    afut::Either::A(fut::ok(averaged_data).into_actor(self))
-                } else {
-                    afut::Either::A(fut::err(()).into_actor(self))
+                match tag_entry.ranges.averages(tag_entry.user.addr, filter_type, tag_entry.user.last_active) {
+                    Some(averaged_data) => afut::Either::A(fut::ok(averaged_data).into_actor(self)),
+                    None => afut::Either::A(fut::err(()).into_actor(self)),
                 }
             },
             None => {
                 // create new entry
                 let tag_mac = tag_data.tag_mac;
 
-                afut::Either::B(db_utils::default_connect()
-                    .map_err(AkError::from)
-                    .and_then(move |client| {
-                        user::select_user_by_short(client, tag_mac)
-                    })
+                afut::Either::B(self.repositories.users.user_by_short(tag_mac)
                     .map_err(|_x| {})
                     .into_actor(self)
-                    .map(move |(_client, opt_user), actor, _context| {
+                    .map(move |opt_user, actor, _context| {
                         match opt_user {
                             Some(u) => {
                                 let mut hash_entry = TagHistory {
                                     user: RealtimeUserData::from(u),
-                                    beacon_history: BTreeMap::new(),
+                                    ranges: TagRanges::new(),
+                                    filter: TagFilter::new(actor.filter_config.clone()),
+                                    motion: MotionTracker::new(),
+                                    zones: ZoneTracker::new(),
                                 };
 
-                                let mut deque = VecDeque::new();
-                                deque.push_back(tag_data.tag_distance);
-                                hash_entry.beacon_history.insert(tag_data.beacon_mac.clone(), deque);
+                                hash_entry.ranges.append(&tag_data);
                                 actor.users.insert(tag_data.tag_mac.clone(), Box::new(hash_entry));
-                                fut::err::<Vec<TagData>, _>(()).into_actor(actor)
+                                fut::err::<Vec<(TagData, f64)>, _>(()).into_actor(actor)
                             },
                             None => {
                                 // user doesn't exist, cannot continue processing.
                                 println!("tag {} does not have an associated user, make one", tag_data.tag_mac);
-                                fut::err::<Vec<TagData>, _>(()).into_actor(actor)
+                                fut::err::<Vec<(TagData, f64)>, _>(()).into_actor(actor)
                             }
                         }
                     })
@@ -188,89 +332,100 @@ impl Handler<InLocationData> for DataProcessor {
         };
 
         let fut = prep_fut
-            .and_then(move |mut averages, actor, _context| {
+            .and_then(move |averages, actor, _context| {
                 // perform trilateration
                 let beacon_macs: Vec<MacAddress8> = averages
                     .iter()
-                    .map(|tagdata| tagdata.beacon_mac)
+                    .map(|(tagdata, _weight)| tagdata.beacon_mac)
                     .collect();
                 assert!(beacon_macs.len() >= 3);
 
-                let fut = db_utils::default_connect()
-                    .map_err(AkError::from)
-                    .and_then(|client| {
-                        beacon::select_beacons_by_mac(client, beacon_macs)
-                    })
+                let fut = actor.repositories.beacons.beacons_by_mac(beacon_macs)
                     .into_actor(actor)
                     .map_err(|_e, _, _| {})
-                    .and_then(move |(client, beacons), actor, _context| {
-                        for b in &beacons {
-                            if b.map_id.is_none() {
-                                // beacon is not attached to a map, trilateration is meaningless
+                    .and_then(move |beacons, actor, _context| {
+                        let current_map_id = actor.users
+                            .get(&tag_data_update.tag_mac)
+                            .and_then(|hist| hist.user.map_id);
+                        let fix = match positioning::locate(beacons, averages, current_map_id) {
+                            Ok(fix) => fix,
+                            // no single floor has enough beacons to trilaterate
+                            Err(LocateError::NoFloor) => return afut::Either::B(afut::err(())),
+                            Err(e) => {
+                                // keep the last known location, the next range may resolve it
+                                println!("data processor: failed to locate tag {}: {}", tag_data_update.tag_mac, e);
                                 return afut::Either::B(afut::err(()));
-                            }
-                        }
-
-                        if beacons.len() < 3 {
-                            println!("data processor: beacons length is too short");
-                            // not enough beacons to trilaterate
-                            actor.users.clear();
-                            return afut::Either::B(afut::err(()));
-                        }
-
-                        if averages.len() < 3 {
-                            println!("data processor: averages length is too short");
-                            // not enough data points to trilaterate
-                            actor.users.clear();
-                            return afut::Either::B(afut::err(()));
-                        }
-
-                        let sorted_beacons = beacons;
-                        let mut sorted_data: Vec<TagData> = Vec::new();
-                        let mut beacon_sources: Vec<BeaconTOFToUser> = Vec::new();
-
-                        sorted_beacons.iter().for_each(|beacon| {
-                            if let Some(index) = averages.iter().position(|t| t.beacon_mac == beacon.mac_address) {
-                                let data = averages.swap_remove(index);
-                                beacon_sources.push(BeaconTOFToUser {
+                            },
+                        };
+                        let map_id = fix.map_id;
+                        let timestamp = fix.timestamp;
+                        let beacon_sources: Vec<BeaconTOFToUser> = fix.beacons
+                            .iter()
+                            .zip(fix.ranges.iter())
+                            .map(|(beacon, (data, _weight))| {
+                                BeaconTOFToUser {
                                     name: beacon.name.clone(),
                                     location: beacon.coordinates,
                                     distance_to_tag: data.tag_distance,
-                                });
-
-                                sorted_data.push(data);
-                            }
-                        });
-
-                        if sorted_beacons.len() != sorted_data.len() || sorted_beacons.len() != beacon_sources.len() {
-                            println!("data processor: detected stale data");
-                            // likely using stale data.
-                            actor.users.clear();
-                            return afut::Either::B(afut::err(()));
-                        }
-
-                        // perform trilateration calculation
-                        let new_tag_location = Self::calc_trilaterate(&sorted_beacons, &sorted_data);
-                        let timestamp = sorted_data.iter().fold(Utc.timestamp(0, 0), |max, tag_point| {
-                            if max < tag_point.timestamp {
-                                tag_point.timestamp
-                            } else {
-                                max
-                            }
-                        });
-                        let map_id = sorted_beacons[0].map_id; // TODO HACK.
+                                }
+                            })
+                            .collect();
 
                         // update the user information
+                        let mut opt_transition = None;
                         let update_db_fut = match actor.users.get_mut(&tag_data_update.tag_mac) {
                             Some(hist) => {
+                                if hist.user.map_id != Some(map_id) {
+                                    // positions on different maps are unrelated, start smoothing over
+                                    hist.filter.reset();
+                                    opt_transition = Some(FloorTransition {
+                                        addr: hist.user.addr,
+                                        from_map_id: hist.user.map_id,
+                                        to_map_id: Some(map_id),
+                                        timestamp,
+                                        user_id: hist.user.id,
+                                    });
+                                }
+
+                                let filtered = hist.filter.update(&fix.solution, timestamp);
+                                let record = LocationRecord {
+                                    id: -1,
+                                    beacons: fix.beacons.iter().map(|b| b.id).collect(),
+                                    beacon_coordinates: fix.beacons.iter().map(|b| b.coordinates).collect(),
+                                    coordinates: filtered.coordinates,
+                                    distances: fix.ranges.iter().map(|(t, _weight)| t.tag_distance).collect(),
+                                    map_id: Some(map_id),
+                                    timestamp,
+                                    uncertainty: filtered.uncertainty(),
+                                    user_id: hist.user.id,
+                                };
                                 hist.user.beacon_tofs = beacon_sources;
-                                hist.user.coordinates = new_tag_location;
+                                hist.user.coordinates = filtered.coordinates;
+                                hist.user.error_ellipse = filtered.ellipse();
+                                hist.user.uncertainty = filtered.uncertainty();
+                                hist.user.velocity = filtered.velocity;
                                 hist.user.last_active = timestamp;
-                                hist.user.map_id = map_id;
+                                hist.user.map_id = Some(map_id);
+                                hist.motion.update(&actor.motion_config, Some(map_id), &filtered.coordinates, timestamp);
+                                actor.push.do_send(Publish(PushMessage::UserMoved(hist.user.clone())));
+
+                                let mut zone_events = hist.zones.update(&actor.zones, Some(map_id), &filtered.coordinates, timestamp);
+                                for event in zone_events.iter_mut() {
+                                    event.user_id = hist.user.id;
+                                    event.user_name = hist.user.name.clone();
+                                    actor.push.do_send(Publish(PushMessage::ZoneEvent(event.clone())));
+                                }
+                                let users = actor.repositories.users.clone();
+                                let maps = actor.repositories.maps.clone();
                                 afut::Either::A(
-                                    user::update_user_from_realtime(client, hist.user.clone())
+                                    actor.repositories.users.update_user_from_realtime(hist.user.clone())
+                                        .and_then(move |_opt_user| {
+                                            users.insert_location(record)
+                                        })
+                                        .and_then(move |()| {
+                                            maps.insert_zone_events(zone_events)
+                                        })
                                         .map_err(|_e| {})
-                                        .map(|(_client, _opt_user)| { })
                                         .into_actor(actor)
                                 )
                             },
@@ -281,6 +436,10 @@ impl Handler<InLocationData> for DataProcessor {
                             }
                         };
 
+                        if let Some(transition) = opt_transition {
+                            actor.log_floor_transition(transition);
+                        }
+
                         update_db_fut
                     });
                 fut
@@ -306,3 +465,200 @@ impl Handler<OutUserData> for DataProcessor {
         Ok(self.users.iter().map(|(_addr, hist)| hist.user.clone()).collect())
     }
 }
+
+pub struct OutFilterConfig;
+
+impl Message for OutFilterConfig {
+    type Result = Result<FilterConfig, AkError>;
+}
+
+impl Handler<OutFilterConfig> for DataProcessor {
+    type Result = Result<FilterConfig, AkError>;
+
+    fn handle (&mut self, _msg: OutFilterConfig, _: &mut Context<Self>) -> Self::Result {
+        Ok(self.filter_config.clone())
+    }
+}
+
+pub struct OutFloorTransitions;
+
+impl Message for OutFloorTransitions {
+    type Result = Result<Vec<FloorTransition>, AkError>;
+}
+
+impl Handler<OutFloorTransitions> for DataProcessor {
+    type Result = Result<Vec<FloorTransition>, AkError>;
+
+    fn handle (&mut self, _msg: OutFloorTransitions, _: &mut Context<Self>) -> Self::Result {
+        Ok(self.floor_transitions.iter().cloned().collect())
+    }
+}
+
+pub struct OutRetention;
+
+impl Message for OutRetention {
+    type Result = Result<RetentionConfig, AkError>;
+}
+
+impl Handler<OutRetention> for DataProcessor {
+    type Result = Result<RetentionConfig, AkError>;
+
+    fn handle (&mut self, _msg: OutRetention, _: &mut Context<Self>) -> Self::Result {
+        self.retention.clone().ok_or_else(AkError::internal)
+    }
+}
+
+pub struct OutMotionConfig;
+
+impl Message for OutMotionConfig {
+    type Result = Result<MotionConfig, AkError>;
+}
+
+impl Handler<OutMotionConfig> for DataProcessor {
+    type Result = Result<MotionConfig, AkError>;
+
+    fn handle (&mut self, _msg: OutMotionConfig, _: &mut Context<Self>) -> Self::Result {
+        Ok(self.motion_config.clone())
+    }
+}
+
+pub struct OutMotionAlerts;
+
+impl Message for OutMotionAlerts {
+    type Result = Result<Vec<MotionAlert>, AkError>;
+}
+
+impl Handler<OutMotionAlerts> for DataProcessor {
+    type Result = Result<Vec<MotionAlert>, AkError>;
+
+    fn handle (&mut self, _msg: OutMotionAlerts, _: &mut Context<Self>) -> Self::Result {
+        Ok(self.motion_alerts.clone())
+    }
+}
+
+// a responder has seen the alert, and is dealing with it
+pub struct AcknowledgeMotionAlert {
+    pub id: u64,
+    pub account: String,
+}
+
+impl Message for AcknowledgeMotionAlert {
+    type Result = Result<MotionAlert, AkError>;
+}
+
+impl Handler<AcknowledgeMotionAlert> for DataProcessor {
+    type Result = Result<MotionAlert, AkError>;
+
+    fn handle (&mut self, msg: AcknowledgeMotionAlert, _: &mut Context<Self>) -> Self::Result {
+        let mut alert = match self.motion_alerts.iter().find(|a| a.id == msg.id) {
+            Some(alert) => alert.clone(),
+            None => return Err(AkError::not_found()),
+        };
+        if alert.acknowledged_at.is_none() {
+            alert.acknowledged_at = Some(Utc::now());
+            alert.acknowledged_by = Some(msg.account);
+            self.push.do_send(Publish(PushMessage::MotionAlert(alert.clone())));
+            motion::merge_alert(&mut self.motion_alerts, alert.clone());
+        }
+        Ok(alert)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix::System;
+    use common::zones::ZoneEventKind;
+    use crate::repository::memory::MemoryRepository;
+    use futures::{ Future, Stream, };
+
+    fn anchors() -> Vec<Beacon> {
+        [(0.0, 0.0), (10.0, 0.0), (10.0, 10.0), (0.0, 10.0)]
+            .iter()
+            .enumerate()
+            .map(|(i, (x, y))| {
+                let mut b = Beacon::new();
+                b.id = i as i32 + 1;
+                b.mac_address = MacAddress8::from_bytes(&[i as u8 + 1, 0, 0, 0, 0, 0, 0, 0]).unwrap();
+                b.coordinates = na::Vector2::new(*x, *y);
+                b.map_id = Some(1);
+                b
+            })
+            .collect()
+    }
+
+    #[test]
+    fn locates_without_database() {
+        let memory = MemoryRepository::new();
+        let tag_mac = ShortAddress::from_bytes(&[0, 1]).unwrap();
+        {
+            let mut data = memory.data.lock().unwrap();
+            data.beacons = anchors();
+            let mut user = TrackedUser::new();
+            user.id = 7;
+            user.mac_address = Some(tag_mac);
+            user.name = "user_0".to_string();
+            data.users = vec![user];
+            let mut zone = Zone::new();
+            zone.id = 1;
+            zone.map_id = 1;
+            zone.name = "office".to_string();
+            zone.polygon = vec![
+                na::Vector2::new(0.0, 0.0),
+                na::Vector2::new(5.0, 0.0),
+                na::Vector2::new(5.0, 5.0),
+                na::Vector2::new(0.0, 5.0),
+            ];
+            data.zones = vec![zone];
+        }
+        let repositories = Repositories::cached(memory.clone());
+
+        let truth = na::Vector2::new(3.0, 4.0);
+        let start = Utc::now();
+        let ranges: Vec<TagData> = (0..3)
+            .flat_map(|round| anchors().into_iter().map(move |b| (round, b)))
+            .map(|(round, b)| {
+                TagData {
+                    beacon_mac: b.mac_address,
+                    tag_distance: (truth - b.coordinates).norm(),
+                    tag_mac,
+                    timestamp: start + Duration::milliseconds(round * 100),
+                }
+            })
+            .collect();
+
+        System::run(move || {
+            let processor = DataProcessor::new(PushBroadcaster::new().start(), repositories).start();
+
+            let p = processor.clone();
+            let task = futures::stream::iter_ok(ranges)
+                // one at a time, the first range of the tag looks up its user before the rest arrive
+                .for_each(move |range| {
+                    p.send(InLocationData(range)).map(|_located| {})
+                })
+                .and_then(move |_| processor.send(OutUserData))
+                .map(move |users| {
+                    let users = users.unwrap();
+                    assert_eq!(users.len(), 1);
+                    assert_eq!(users[0].id, 7);
+                    assert_eq!(users[0].map_id, Some(1));
+                    assert!((users[0].coordinates - truth).norm() < 0.05);
+
+                    let data = memory.data.lock().unwrap();
+                    assert!(data.locations.len() >= 2);
+                    assert!(data.locations.iter().all(|record| record.user_id == 7));
+                    assert_eq!(data.locations.last().unwrap().beacons.len(), 4);
+                    assert_eq!(data.users[0].map_id, Some(1));
+                    assert_eq!(data.zone_events.len(), 1);
+                    assert_eq!(data.zone_events[0].kind, ZoneEventKind::Enter);
+                    assert_eq!(data.zone_events[0].user_id, 7);
+                    // every range after the first was answered from the caches
+                    assert_eq!(data.user_reads, 1);
+                    assert_eq!(data.beacon_reads, 1);
+                    System::current().stop();
+                })
+                .map_err(|_| panic!("data processor mailbox closed"));
+            Arbiter::spawn(task);
+        }).unwrap();
+    }
+}
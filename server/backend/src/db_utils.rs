@@ -1,21 +1,260 @@
 use tokio_postgres::NoTls;
-use common::LoginInfo;
-use futures::{ future::err, Future, future::Either, };
+use tokio_postgres::Statement;
+use tokio_postgres::types::Type;
+use common::AccountRole;
+use futures::{ future::err, Future, future::Either, future::ok, };
+use futures::sync::oneshot;
 use crate::AKData;
 use actix_identity::Identity;
 use crate::ak_error::AkError;
+use crate::auth;
+use crate::config::DatabaseConfig;
+use std::collections::{ HashMap, VecDeque, };
+use std::ops::{ Deref, DerefMut, };
+use std::sync::{ Arc, Mutex, };
 
+// defaults for the database section of the config
 pub const DEFAULT_CONNECTION: &str = "dbname=ak host=localhost password=postgres user=postgres";
+// the database logins used by each account role, these are created with the schema.
+pub const ADMIN_CONNECTION: &str = "dbname=ak host=localhost password=admin user=admin";
+pub const RESPONDER_CONNECTION: &str = "dbname=ak host=localhost password=responder user=responder";
+// connections shared by the beacon manager, data processor and the other actors
+pub const SYSTEM_POOL_SIZE: usize = 16;
+// connections shared by every session logged in with the same account role
+pub const ROLE_POOL_SIZE: usize = 8;
 
-pub fn default_connect() -> impl Future<Item=tokio_postgres::Client, Error=AkError> {
-    connect(DEFAULT_CONNECTION)
+// prepared statements are only valid on the connection that prepared them,
+// so every connection keeps its own cache.
+type StatementCache = Arc<Mutex<HashMap<(String, Vec<Type>), Statement>>>;
+
+struct Connection {
+    client: tokio_postgres::Client,
+    statements: StatementCache,
+}
+
+impl Connection {
+    fn new(client: tokio_postgres::Client) -> Connection {
+        Connection {
+            client,
+            statements: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+}
+
+struct PoolState {
+    idle: Vec<Connection>,
+    // number of connections that are either idle or checked out
+    open: usize,
+    waiters: VecDeque<oneshot::Sender<Connection>>,
+}
+
+struct PoolInner {
+    max_size: usize,
+    params: String,
+    state: Mutex<PoolState>,
 }
 
-pub fn connect_login(user: &LoginInfo) -> impl Future<Item=tokio_postgres::Client, Error=AkError> {
-    connect(&format!("dbname=ak host=localhost user={} password={}", user.name, user.pw))
+// A pool of connections to the database, all using the same login.
+// Cloning the pool is cheap, and every clone shares the same connections.
+#[derive(Clone)]
+pub struct DbPool {
+    inner: Arc<PoolInner>,
+}
+
+impl DbPool {
+    pub fn new(params: &str, max_size: usize) -> DbPool {
+        assert!(max_size > 0);
+        DbPool {
+            inner: Arc::new(PoolInner {
+                max_size,
+                params: params.to_string(),
+                state: Mutex::new(PoolState {
+                    idle: Vec::new(),
+                    open: 0,
+                    waiters: VecDeque::new(),
+                }),
+            }),
+        }
+    }
+
+    pub fn system(config: &DatabaseConfig) -> DbPool {
+        DbPool::new(&config.connection, SYSTEM_POOL_SIZE)
+    }
+
+    pub fn role(role: AccountRole, config: &DatabaseConfig) -> DbPool {
+        match role {
+            AccountRole::Admin => DbPool::new(&config.admin_connection, ROLE_POOL_SIZE),
+            AccountRole::Responder => DbPool::new(&config.responder_connection, ROLE_POOL_SIZE),
+        }
+    }
+
+    // Checks out a connection, the connection is returned to the pool when it is dropped.
+    // If every connection is in use, this waits for one to be returned.
+    pub fn get(&self) -> impl Future<Item=PooledClient, Error=AkError> {
+        let mut state = self.inner.state.lock().unwrap();
+
+        while let Some(connection) = state.idle.pop() {
+            if connection.client.is_closed() {
+                state.open -= 1;
+            } else {
+                return Either::A(ok(PooledClient::pooled(connection, self.clone())));
+            }
+        }
+
+        if state.open < self.inner.max_size {
+            state.open += 1;
+            let pool = self.clone();
+            let failed_pool = self.clone();
+            Either::B(Either::A(connect_client(&self.inner.params)
+                .map(move |client| {
+                    PooledClient::pooled(Connection::new(client), pool)
+                })
+                .map_err(move |e| {
+                    failed_pool.connect_failed(false);
+                    e
+                })
+            ))
+        } else {
+            let (tx, rx) = oneshot::channel();
+            state.waiters.push_back(tx);
+            let pool = self.clone();
+            Either::B(Either::B(rx
+                .map(move |connection| {
+                    PooledClient::pooled(connection, pool)
+                })
+                .map_err(|_canceled| {
+                    AkError::internal()
+                })
+            ))
+        }
+    }
+
+    fn release(&self, connection: Connection) {
+        let mut state = self.inner.state.lock().unwrap();
+
+        if connection.client.is_closed() {
+            state.open -= 1;
+            // nothing will be returned for the waiters to use, so open another connection
+            self.reconnect(&mut state);
+            return;
+        }
+
+        let mut connection = connection;
+        while let Some(waiter) = state.waiters.pop_front() {
+            match waiter.send(connection) {
+                Ok(()) => return,
+                // the waiter gave up, try the next one
+                Err(returned) => connection = returned,
+            }
+        }
+        state.idle.push(connection);
+    }
+
+    // opens a connection for the oldest waiter, after a connection was lost or could not be made
+    fn reconnect(&self, state: &mut PoolState) {
+        if state.waiters.is_empty() || state.open >= self.inner.max_size {
+            return;
+        }
+
+        state.open += 1;
+        let pool = self.clone();
+        let failed_pool = self.clone();
+        tokio::spawn(connect_client(&self.inner.params)
+            .map(move |client| {
+                pool.release(Connection::new(client));
+            })
+            .map_err(move |e| {
+                println!("failed to reconnect to the database: {:?}", e);
+                failed_pool.connect_failed(true);
+            })
+        );
+    }
+
+    // a connection could not be made. when it was made for the oldest waiter, that waiter fails
+    // rather than waiting forever, and the next one is given another try.
+    fn connect_failed(&self, for_waiter: bool) {
+        let mut state = self.inner.state.lock().unwrap();
+        state.open -= 1;
+        if for_waiter {
+            state.waiters.pop_front();
+        }
+        self.reconnect(&mut state);
+    }
 }
 
-pub fn connect(params: &str) -> impl Future<Item=tokio_postgres::Client, Error=AkError> {
+// A connection to the database, which caches the statements it prepares.
+// Dereferences to the underlying tokio_postgres::Client for everything else.
+pub struct PooledClient {
+    connection: Option<Connection>,
+    pool: DbPool,
+}
+
+impl PooledClient {
+    fn pooled(connection: Connection, pool: DbPool) -> PooledClient {
+        PooledClient {
+            connection: Some(connection),
+            pool,
+        }
+    }
+
+    fn connection(&mut self) -> &mut Connection {
+        self.connection.as_mut().unwrap()
+    }
+
+    pub fn prepare(&mut self, query: &str) -> impl Future<Item=Statement, Error=tokio_postgres::Error> {
+        self.prepare_typed(query, &[])
+    }
+
+    pub fn prepare_typed(&mut self, query: &str, param_types: &[Type]) -> impl Future<Item=Statement, Error=tokio_postgres::Error> {
+        let connection = self.connection();
+        let key = (query.to_string(), param_types.to_vec());
+        let cached = connection.statements.lock().unwrap().get(&key).cloned();
+
+        match cached {
+            Some(statement) => Either::A(ok(statement)),
+            None => {
+                let statements = connection.statements.clone();
+                Either::B(connection.client
+                    .prepare_typed(query, param_types)
+                    .map(move |statement| {
+                        statements.lock().unwrap().insert(key, statement.clone());
+                        statement
+                    })
+                )
+            },
+        }
+    }
+}
+
+impl Deref for PooledClient {
+    type Target = tokio_postgres::Client;
+
+    fn deref(&self) -> &tokio_postgres::Client {
+        &self.connection.as_ref().unwrap().client
+    }
+}
+
+impl DerefMut for PooledClient {
+    fn deref_mut(&mut self) -> &mut tokio_postgres::Client {
+        &mut self.connection().client
+    }
+}
+
+impl Drop for PooledClient {
+    fn drop(&mut self) {
+        if let Some(connection) = self.connection.take() {
+            self.pool.release(connection);
+        }
+    }
+}
+
+// tests connect outside of the pools owned by the running server
+#[cfg(test)]
+pub fn default_connect() -> impl Future<Item=PooledClient, Error=AkError> {
+    DbPool::new(DEFAULT_CONNECTION, 1).get()
+}
+
+fn connect_client(params: &str) -> impl Future<Item=tokio_postgres::Client, Error=AkError> {
     tokio_postgres::connect(params, NoTls)
         .map(|(client, connection)| {
             let connection = connection.map_err(|e| eprintln!("connect db error: {}", e));
@@ -25,20 +264,72 @@ pub fn connect(params: &str) -> impl Future<Item=tokio_postgres::Client, Error=A
         .map_err(AkError::from)
 }
 
-pub fn connect_id(id: &Identity, state: &AKData) -> impl Future<Item=tokio_postgres::Client, Error=AkError> {
-    let conn_fut = if let Some(name) = id.identity() {
-        let s = state.lock().unwrap();
-        if let Some(info) = s.pools.get(&name) {
-            Either::A(connect_login(info)
-                .map_err(AkError::from)
-            )
-        } else {
-            Either::B(err(AkError::internal()))
-        }
-    } else {
-        Either::B(err(AkError::unauthorized()))
-    };
+pub fn connect_system(state: &AKData) -> impl Future<Item=PooledClient, Error=AkError> {
+    let pool = state.lock().unwrap().system_pool.clone();
+    pool.get()
+}
 
-    conn_fut
+// connects with the database login of the session's account role
+pub fn connect_id(id: &Identity, state: &AKData) -> impl Future<Item=PooledClient, Error=AkError> {
+    let state = state.clone();
+    auth::authenticate(id, &state)
+        .and_then(move |session| {
+            let pool = state.lock().unwrap().pools.get(&session.role).cloned();
+            match pool {
+                Some(pool) => Either::A(pool.get()),
+                None => Either::B(err(AkError::internal())),
+            }
+        })
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::runtime::current_thread::Runtime;
+
+    #[test]
+    fn reuse_connection() {
+        let mut runtime = Runtime::new().unwrap();
+        let pool = DbPool::new(DEFAULT_CONNECTION, 1);
+
+        let p = pool.clone();
+        let task = pool.get()
+            .and_then(|mut client| {
+                client.prepare("SELECT 1")
+                    .map_err(AkError::from)
+                    .map(|_statement| client)
+            })
+            .and_then(move |client| {
+                // the only connection is in use, so this must wait for it to be returned
+                let next = p.get();
+                drop(client);
+                next
+            })
+            .and_then(|client| {
+                let cached = client.connection.as_ref().unwrap().statements.lock().unwrap().len();
+                assert_eq!(cached, 1);
+                ok(())
+            })
+            .map_err(|e| {
+                println!("db error {:?}", e);
+                panic!("failed to reuse pooled connection");
+            });
+        runtime.block_on(task).unwrap();
+        assert_eq!(pool.inner.state.lock().unwrap().open, 1);
+    }
+
+    #[test]
+    fn failed_connect_wakes_waiters() {
+        let mut runtime = Runtime::new().unwrap();
+        // nothing listens on port 1, so every connection fails
+        let pool = DbPool::new("host=localhost port=1 user=postgres", 1);
+
+        let first = pool.get();
+        // the only connection is being made, so this must wait for it
+        let second = pool.get();
+        assert!(runtime.block_on(first).is_err());
+        assert!(runtime.block_on(second).is_err());
+        assert_eq!(pool.inner.state.lock().unwrap().open, 0);
+        assert!(pool.inner.state.lock().unwrap().waiters.is_empty());
+    }
+}
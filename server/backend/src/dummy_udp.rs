@@ -7,27 +7,28 @@ use rand::{ Rng, SeedableRng, };
 use rand::rngs::SmallRng;
 use actix::prelude::*;
 use actix::{ Actor, Context, };
-use crate::db_utils;
-use crate::models::user;
-use crate::models::beacon;
 use crate::beacon_manager::*;
+use crate::repository::Repositories;
+use simulator::{ Scenario, Simulator, SimulatorConfig, };
 use common::*;
-use std::time::Duration;
+use std::fs::{ File, OpenOptions, };
+use std::io::Write;
 use std::net::IpAddr;
 use futures::future as fut;
 use actix::fut as afut;
 
-const MESSAGE_INTERVAL: Duration = Duration::from_millis(1000);
-const MIN_DISTANCE: f64 = 1.0;
-const MAX_DISTANCE: f64 = 4.0;
 const REBOOT_AWAKE_CHANCE: f64 = 0.05;
 const REBOOT_CHANCE: f64 = 0.0;
 
 pub struct DummyUDP {
     manager: Addr<BeaconManager>,
-    data_task: SpawnHandle,
+    repositories: Repositories,
+    config: SimulatorConfig,
+    data_task: Option<SpawnHandle>,
+    ground_truth: Option<File>,
     rng: SmallRng,
     rebooting_ip: Option<IpAddr>,
+    simulator: Simulator,
 }
 
 impl Actor for DummyUDP {
@@ -43,41 +44,39 @@ impl Handler<GenTagData> for DummyUDP {
     type Result = ResponseActFuture<Self, (), ()>;
 
     fn handle(&mut self, _msg: GenTagData, _: &mut Context<Self>) -> Self::Result {
-        let b_fut = db_utils::default_connect()
-            .and_then(|client| {
-                beacon::select_beacons(client)
-            });
-        let u_fut = db_utils::default_connect()
-            .and_then(|client| {
-                user::select_user_random(client)
-            });
+        let b_fut = self.repositories.beacons.beacons();
+        let u_fut = self.repositories.users.tagged_users();
 
         let data_gen_fut = b_fut.join(u_fut)
             .into_actor(self)
-            .and_then(move |((_client1, beacons), (_client2, opt_user)), actor, _context| {
-                let time = Utc::now();
-                if let Some(user) = opt_user {
-                    for b in beacons {
-                        if let Some(ip) = actor.rebooting_ip {
-                            if ip == b.ip {
-                                // pretend to do nothing while this beacon is "rebooting"
-                                continue;
-                            }
-                            // randomly choose this beacon to be unresponsive
-                        } else if actor.rng.gen_bool(REBOOT_CHANCE) {
-                            actor.rebooting_ip = Some(b.ip);
+            .and_then(move |(beacons, users), actor, context| {
+                let tag_macs: Vec<ShortAddress> = users.iter().filter_map(|u| u.mac_address).collect();
+                let seconds = actor.config.interval_ms as f64 / 1000.0;
+                let tick = actor.simulator.tick(&tag_macs, &beacons, seconds, Utc::now());
+                actor.record_ground_truth(&tick.truth);
+
+                for range in tick.ranges {
+                    if let Some(ip) = actor.rebooting_ip {
+                        if ip == range.beacon_ip {
+                            // pretend to do nothing while this beacon is "rebooting"
                             continue;
                         }
+                        // randomly choose this beacon to be unresponsive
+                    } else if actor.rng.gen_bool(REBOOT_CHANCE) {
+                        actor.rebooting_ip = Some(range.beacon_ip);
+                        continue;
+                    }
 
-                        let user_distance = actor.rng.gen_range(MIN_DISTANCE, MAX_DISTANCE);
+                    // stamped on arrival, like the ranges from real beacons
+                    context.run_later(range.delay, move |actor, _context| {
                         actor.manager
-                            .do_send(BMResponse::TagData(b.ip, TagData {
-                                beacon_mac: b.mac_address,
-                                tag_distance: user_distance,
-                                tag_mac: user.mac_address.unwrap(),
-                                timestamp: time,
+                            .do_send(BMResponse::TagData(range.beacon_ip, TagData {
+                                beacon_mac: range.beacon_mac,
+                                tag_distance: range.distance,
+                                tag_mac: range.tag_mac,
+                                timestamp: Utc::now(),
                             }));
-                    }
+                    });
                 }
                 afut::result(Ok(()))
             })
@@ -93,25 +92,32 @@ impl Handler<BeaconCommand> for DummyUDP {
 
     fn handle(&mut self, msg: BeaconCommand, context: &mut Context<Self>) -> Self::Result {
         match msg {
-            BeaconCommand::StartEmergency(opt_ip) => {
-                self.data_task = context.run_interval(MESSAGE_INTERVAL, |_actor, context| {
+            BeaconCommand::StartEmergency(id, opt_ip) => {
+                // beacons are also started one at a time, ie when they are retried, but the tags only
+                // need to be simulated once
+                if let Some(task) = self.data_task.take() {
+                    context.cancel_future(task);
+                }
+                self.data_task = Some(context.run_interval(self.config.interval(), |_actor, context| {
                     context.notify(GenTagData);
-                });
+                }));
 
-                self.reply(context, opt_ip, |ip, mac| BMResponse::Start(ip, mac));
+                self.reply(context, opt_ip, move |ip, mac| BMResponse::Start(ip, mac, Some(id)));
             },
-            BeaconCommand::EndEmergency(opt_ip) => {
-                context.cancel_future(self.data_task);
-                self.reply(context, opt_ip, |ip, mac| BMResponse::End(ip, mac));
+            BeaconCommand::EndEmergency(id, opt_ip) => {
+                if let Some(task) = self.data_task.take() {
+                    context.cancel_future(task);
+                }
+                self.reply(context, opt_ip, move |ip, mac| BMResponse::End(ip, mac, Some(id)));
             },
-            BeaconCommand::Ping(opt_ip) => {
-                self.reply(context, opt_ip, |ip, mac| BMResponse::Ping(ip, mac));
+            BeaconCommand::Ping(id, opt_ip) => {
+                self.reply(context, opt_ip, move |ip, mac| BMResponse::Ping(ip, mac, Some(id)));
             },
-            BeaconCommand::Reboot(opt_ip) => {
-                self.reply(context, opt_ip, |ip, mac| BMResponse::Reboot(ip, mac));
+            BeaconCommand::Reboot(id, opt_ip) => {
+                self.reply(context, opt_ip, move |ip, mac| BMResponse::Reboot(ip, mac, Some(id)));
             }
-            BeaconCommand::SetIp(_ip) => {
-                self.reply(context, None, |ip, mac| BMResponse::SetIp(ip, mac));
+            BeaconCommand::SetIp(id, _ip) => {
+                self.reply(context, None, move |ip, mac| BMResponse::SetIp(ip, mac, Some(id)));
             }
         }
 
@@ -120,18 +126,56 @@ impl Handler<BeaconCommand> for DummyUDP {
 }
 
 impl DummyUDP {
-    pub fn new(manager: Addr<BeaconManager>) -> Addr<DummyUDP> {
+    pub fn new(manager: Addr<BeaconManager>, repositories: Repositories, config: SimulatorConfig) -> Addr<DummyUDP> {
         DummyUDP::create(move |_context| {
             println!("starting dummy udp actor");
+            // the config was validated at startup, but the files may have changed since
+            let scenario = match &config.scenario_file {
+                Some(file) => Scenario::from_file(file).unwrap_or_else(|e| {
+                    println!("{}, all simulated tags will walk randomly", e);
+                    Scenario::default()
+                }),
+                None => Scenario::default(),
+            };
+            let ground_truth = config.ground_truth_file.as_ref().and_then(|file| {
+                OpenOptions::new().create(true).append(true).open(file)
+                    .map_err(|e| println!("failed to open ground truth file {}: {}", file, e))
+                    .ok()
+            });
+            let sim_rng = match config.seed {
+                Some(seed) => SmallRng::seed_from_u64(seed),
+                None => SmallRng::from_entropy(),
+            };
+
             DummyUDP {
                 rebooting_ip: None,
                 manager,
+                repositories,
                 rng: SmallRng::from_entropy(),
-                data_task: Default::default(),
+                data_task: None,
+                ground_truth,
+                simulator: Simulator::new(config.clone(), scenario, sim_rng),
+                config,
             }
         })
     }
 
+    fn record_ground_truth(&mut self, truth: &[GroundTruth]) {
+        let file = match &mut self.ground_truth {
+            Some(file) => file,
+            None => return,
+        };
+        for entry in truth {
+            let written = serde_json::to_string(entry)
+                .map_err(|e| e.to_string())
+                .and_then(|line| writeln!(file, "{}", line).map_err(|e| e.to_string()));
+            if let Err(e) = written {
+                println!("failed to record ground truth: {}", e);
+                return;
+            }
+        }
+    }
+
     fn reply<F: 'static>(&mut self, context: &mut Context<Self>, opt_ip: Option<IpAddr>, msg: F)
         where F: Fn(IpAddr, MacAddress8) -> BMResponse
     {
@@ -139,24 +183,19 @@ impl DummyUDP {
             self.rebooting_ip = None;
         }
 
-        let beacons_fut = db_utils::default_connect()
-            .and_then(move |client| {
-                if let Some(ip) = opt_ip {
-                    fut::Either::B(beacon::select_beacon_by_ip(client, ip)
-                        .map(|(client, opt_beacon)| {
-                            if let Some(beacon) = opt_beacon {
-                                (client, vec![beacon])
-                            } else {
-                                (client, Vec::new())
-                            }
-                        })
-                    )
-                } else {
-                    fut::Either::A(beacon::select_beacons(client))
-                }
-            })
+        let beacons_fut = if let Some(ip) = opt_ip {
+            fut::Either::B(self.repositories.beacons.beacon_by_ip(ip)
+                .map(|opt_beacon| {
+                    opt_beacon.into_iter().collect()
+                })
+            )
+        } else {
+            fut::Either::A(self.repositories.beacons.beacons())
+        };
+
+        let beacons_fut = beacons_fut
             .into_actor(self)
-            .and_then(move |(_client, beacons), actor, _context| {
+            .and_then(move |beacons: Vec<Beacon>, actor, _context| {
                 for b in beacons {
                     // test out the retry logic by making successfully rebooting a chance.
                     if Some(b.ip) != actor.rebooting_ip {
@@ -4,7 +4,10 @@ extern crate actix_files;
 extern crate actix_identity;
 extern crate actix_session;
 extern crate actix_web;
+extern crate actix_web_actors;
+extern crate argon2;
 extern crate chrono;
+extern crate clap;
 extern crate common;
 extern crate env_logger;
 extern crate eui48;
@@ -14,38 +17,62 @@ extern crate ipc_channel;
 extern crate ipnet;
 extern crate libc;
 extern crate nalgebra as na;
+extern crate simulator;
 extern crate tokio_postgres;
+extern crate toml;
 
+mod auth;
 mod beacon_manager;
 mod beacon_udp;
+mod capture;
 mod dummy_udp;
+mod replay_udp;
+mod config;
 mod controllers;
+mod cookie_policy;
 mod data_processor;
 mod db_utils;
+mod hex;
 mod models;
+mod push;
+mod repository;
 mod conn_common;
 mod ak_error;
 
+use controllers::account_controller;
 use controllers::beacon_controller;
 use controllers::map_controller;
+use controllers::motion_controller;
 use controllers::network_interface_controller;
+use controllers::push_controller;
 use controllers::session_controller;
 use controllers::system_controller;
 use controllers::user_controller;
+use controllers::zone_controller;
 
+use models::session;
 use models::system;
 
 use actix::prelude::*;
 use actix_files as fs;
-use actix_identity::{ CookieIdentityPolicy, IdentityService, };
+use actix_identity::IdentityService;
 use actix_web::{ error, middleware, web, App, HttpRequest, HttpResponse, HttpServer, };
 use beacon_manager::*;
+use capture::Capture;
 use common::*;
+use config::Config;
+use cookie_policy::CookieConfig;
 use data_processor::*;
+use db_utils::DbPool;
+use futures::Future;
+use push::PushBroadcaster;
+use repository::Repositories;
+use repository::postgres::PgRepository;
 use ipc_channel::ipc::{ self, IpcReceiver, IpcSender, };
 use serde_derive::{ Deserialize, Serialize, };
 use std::collections::HashMap;
 use std::env;
+use std::process;
 use std::sync::*;
 
 #[derive(Copy, Clone, Serialize, Deserialize)]
@@ -59,25 +86,50 @@ pub struct AkriveiaState {
     pub tx: ipc::IpcSender<WatcherCommand>,
     pub rx: ipc::IpcReceiver<SystemCommand>,
     pub beacon_manager: Addr<BeaconManager>,
+    pub capture: Addr<Capture>,
+    // the configuration the webserver was started with
+    pub config: Config,
+    pub cookie: CookieConfig,
     pub data_processor: Addr<DataProcessor>,
-    // I would prefer this was a per user connection pool,
-    // but r2d2 does not work for tokio, and bb8 does not look very mature.
-    pub pools: HashMap<String, LoginInfo>,
+    pub push: Addr<PushBroadcaster>,
+    // shared with the actors, the caches are invalidated when beacons or users are edited
+    pub repositories: Repositories,
+    // one pool per account role, shared by every session logged in with that role
+    pub pools: HashMap<AccountRole, DbPool>,
+    // sessions that have been validated against the database, by token
+    pub sessions: HashMap<String, auth::Session>,
+    // used by the actors and for system configuration
+    pub system_pool: DbPool,
 }
 
 pub type AKData = web::Data<Arc<Mutex<AkriveiaState>>>;
 
 impl AkriveiaState {
-    pub fn new(tx: IpcSender<WatcherCommand>, rx: IpcReceiver<SystemCommand>) -> AKData {
-        let data_processor_addr =  DataProcessor::new().start();
-        let beacon_manager_addr = BeaconManager::new(data_processor_addr.clone());
+    pub fn new(tx: IpcSender<WatcherCommand>, rx: IpcReceiver<SystemCommand>, config: Config, cookie: CookieConfig) -> AKData {
+        let system_pool = DbPool::system(&config.database);
+        let pools = [AccountRole::Admin, AccountRole::Responder]
+            .iter()
+            .map(|role| (*role, DbPool::role(*role, &config.database)))
+            .collect();
+        let push_addr = PushBroadcaster::new().start();
+        let capture_addr = Capture::new(config.capture.directory.clone()).start();
+        let repositories = Repositories::cached(PgRepository::new(system_pool.clone()));
+        let data_processor_addr =  DataProcessor::new(push_addr.clone(), repositories.clone()).start();
+        let beacon_manager_addr = BeaconManager::new(data_processor_addr.clone(), push_addr.clone(), capture_addr.clone(), repositories.clone(), config.beacons.clone());
 
         beacon_manager_addr.do_send(BMCommand::ScanBeacons);
 
         web::Data::new(Arc::new(Mutex::new(AkriveiaState {
             beacon_manager: beacon_manager_addr,
+            capture: capture_addr,
+            config,
+            cookie,
             data_processor: data_processor_addr,
-            pools: HashMap::new(),
+            push: push_addr,
+            repositories,
+            pools,
+            sessions: HashMap::new(),
+            system_pool,
             tx,
             rx,
         })))
@@ -90,7 +142,7 @@ fn default_route(req: HttpRequest) -> HttpResponse {
     HttpResponse::NotFound().finish()
 }
 
-fn webserver_main(start_command: SystemCommand, tx: IpcSender<WatcherCommand>, rx: IpcReceiver<SystemCommand>) {
+fn webserver_main(start_command: SystemCommand, config: Config, tx: IpcSender<WatcherCommand>, rx: IpcReceiver<SystemCommand>) {
     let system = System::new("Akriviea");
     env::set_var("RUST_LOG", "actix_server=info,actix_web=info");
     env_logger::init();
@@ -98,27 +150,47 @@ fn webserver_main(start_command: SystemCommand, tx: IpcSender<WatcherCommand>, r
     match start_command {
         SystemCommand::StartNormal => {},
         SystemCommand::RebuildDB => {
-            let create_db_fut = system::create_db(false);
+            let create_db_fut = system::create_db(&config.database.connection, false);
             // intentionally block all further execution
             tokio::run(create_db_fut);
         },
         SystemCommand::RebuildDemoDB => {
-            let create_db_fut = system::create_db(true);
+            let create_db_fut = system::create_db(&config.database.connection, true);
             // intentionally block all further execution
             tokio::run(create_db_fut);
         },
     }
 
-    let state = AkriveiaState::new(tx, rx);
+    // the config has already been validated
+    let cookie = CookieConfig::from_config(&config.session).unwrap();
+    let key = match cookie.load_key() {
+        Ok((key, generated)) => {
+            if generated {
+                // cookies signed with any previous key can no longer be read
+                println!("generated a new session key in {}", cookie.key_file);
+                let pool = DbPool::system(&config.database);
+                Arbiter::spawn(pool.get()
+                    .and_then(|client| session::delete_all_sessions(client))
+                    .map(|(_client, _count)| {})
+                    .map_err(|e| println!("failed to clear sessions of the old key: {}", e))
+                );
+            }
+            key
+        },
+        Err(e) => {
+            println!("failed to load session key: {}", e);
+            process::exit(1);
+        },
+    };
+
+    let bind = config.server.bind.clone();
+    let static_dir = config.server.static_dir.clone();
+    let state = AkriveiaState::new(tx, rx, config, cookie.clone());
 
     // start the webserver
     HttpServer::new(move || {
         App::new()
-            .wrap(IdentityService::new(
-                CookieIdentityPolicy::new(&[0; 32])
-                    .name("session_token")
-                    .secure(false)
-            ))
+            .wrap(IdentityService::new(cookie.policy(&key)))
             .data(
                 web::JsonConfig::default()
                     .limit(4096)
@@ -151,6 +223,11 @@ fn webserver_main(start_command: SystemCommand, tx: IpcSender<WatcherCommand>, r
                 web::resource(&beacon_url(""))
                     .route(web::post().to_async(beacon_controller::post_beacon))
             )
+            .service(
+                web::resource(&beacon_key_url("{id}"))
+                    .route(web::post().to_async(beacon_controller::post_beacon_key))
+                    .route(web::delete().to_async(beacon_controller::delete_beacon_key))
+            )
             .service(
                 web::resource(&beacons_status_url())
                     .to_async(beacon_controller::beacons_status)
@@ -159,6 +236,18 @@ fn webserver_main(start_command: SystemCommand, tx: IpcSender<WatcherCommand>, r
                 web::resource(&beacon_command_url())
                     .to_async(beacon_controller::beacon_command)
             )
+            .service(
+                web::resource(&beacons_discovered_url())
+                    .route(web::get().to_async(beacon_controller::get_discovered_beacons))
+            )
+            .service(
+                web::resource(&beacons_adopt_url())
+                    .route(web::post().to_async(beacon_controller::adopt_beacon))
+            )
+            .service(
+                web::resource(&beacons_blocklist_url())
+                    .route(web::post().to_async(beacon_controller::block_beacon))
+            )
 
 
             // user
@@ -172,6 +261,10 @@ fn webserver_main(start_command: SystemCommand, tx: IpcSender<WatcherCommand>, r
                     .route(web::put().to_async(user_controller::put_user))
                     .route(web::delete().to_async(user_controller::delete_user))
             )
+            .service(
+                web::resource(&user_history_url("{id}"))
+                    .route(web::get().to_async(user_controller::get_user_history))
+            )
             .service(
                 web::resource(&user_url(""))
                     .route(web::post().to_async(user_controller::post_user))
@@ -180,6 +273,10 @@ fn webserver_main(start_command: SystemCommand, tx: IpcSender<WatcherCommand>, r
                 web::resource(&users_status_url())
                     .to_async(user_controller::users_status)
             )
+            .service(
+                web::resource(&users_floor_transitions_url())
+                    .to_async(user_controller::users_floor_transitions)
+            )
 
             // map
             .service(
@@ -201,29 +298,123 @@ fn webserver_main(start_command: SystemCommand, tx: IpcSender<WatcherCommand>, r
                     .route(web::put().to_async(map_controller::put_map_blueprint))
                     .route(web::get().to_async(map_controller::get_map_blueprint))
             )
+            .service(
+                web::resource(&map_history_url("{id}"))
+                    .route(web::get().to_async(map_controller::get_map_history))
+            )
+            .service(
+                web::resource(&map_replay_url("{id}"))
+                    .route(web::get().to_async(map_controller::get_map_replay))
+            )
             .service(
                 web::resource(&map_url(""))
                     .route(web::post().to_async(map_controller::post_map))
             )
 
+            // zone
+            .service(
+                web::resource(&zones_for_map_url("{id}"))
+                    .route(web::get().to_async(zone_controller::get_zones_for_map))
+            )
+            .service(
+                web::resource(&zone_events_url())
+                    .route(web::get().to_async(zone_controller::get_zone_events))
+            )
+            .service(
+                web::resource(&zone_url("{id}"))
+                    .route(web::put().to_async(zone_controller::put_zone))
+                    .route(web::delete().to_async(zone_controller::delete_zone))
+            )
+            .service(
+                web::resource(&zone_url(""))
+                    .route(web::post().to_async(zone_controller::post_zone))
+            )
+
             // system
             .service(
                 web::resource(&system_emergency_url())
                     .route(web::get().to_async(system_controller::get_emergency))
                     .route(web::post().to_async(system_controller::post_emergency))
             )
+            .service(
+                web::resource(&system_emergency_status_url())
+                    .route(web::get().to_async(system_controller::get_emergency_status))
+            )
+            .service(
+                web::resource(&emergency_events_url())
+                    .route(web::get().to_async(system_controller::get_emergency_events))
+            )
+            .service(
+                web::resource(&emergency_event_url("{id}"))
+                    .route(web::get().to_async(system_controller::get_emergency_event))
+            )
+            .service(
+                web::resource(&emergency_muster_url())
+                    .route(web::get().to_async(system_controller::get_muster_report))
+            )
+            .service(
+                web::resource(&emergency_muster_export_url())
+                    .route(web::get().to_async(system_controller::get_muster_export))
+            )
             .service(
                 web::resource(&system_diagnostics_url())
                     .route(web::get().to_async(system_controller::diagnostics))
             )
             .service(
                 web::resource(&system_restart_url())
-                    .route(web::post().to(system_controller::restart))
+                    .route(web::post().to_async(system_controller::restart))
             )
             .service(
                 web::resource(&system_ping_url())
                     .route(web::get().to_async(system_controller::ping))
             )
+            .service(
+                web::resource(&system_filter_url())
+                    .route(web::get().to_async(system_controller::get_filter_config))
+                    .route(web::put().to_async(system_controller::put_filter_config))
+            )
+            .service(
+                web::resource(&system_retention_url())
+                    .route(web::get().to_async(system_controller::get_retention))
+                    .route(web::put().to_async(system_controller::put_retention))
+            )
+            .service(
+                web::resource(&system_motion_url())
+                    .route(web::get().to_async(system_controller::get_motion_config))
+                    .route(web::put().to_async(system_controller::put_motion_config))
+            )
+            .service(
+                web::resource(&motion_alerts_url())
+                    .route(web::get().to_async(motion_controller::get_motion_alerts))
+            )
+            .service(
+                web::resource(&motion_alert_acknowledge_url("{id}"))
+                    .route(web::post().to_async(motion_controller::post_acknowledge))
+            )
+            .service(
+                web::resource(&system_config_url())
+                    .route(web::get().to_async(system_controller::get_config))
+            )
+            .service(
+                web::resource(&system_sessions_logout_url())
+                    .route(web::post().to_async(system_controller::logout_all_sessions))
+            )
+            .service(
+                web::resource(&system_session_key_url())
+                    .route(web::post().to_async(system_controller::rotate_session_key))
+            )
+            .service(
+                web::resource(&system_capture_url())
+                    .route(web::get().to_async(system_controller::get_capture))
+                    .route(web::post().to_async(system_controller::start_capture))
+                    .route(web::delete().to_async(system_controller::stop_capture))
+            )
+
+            // push
+            .service(
+                web::resource(&push_url())
+                    .route(web::get().to_async(push_controller::push))
+            )
 
             // network
             .service(
@@ -254,11 +445,26 @@ fn webserver_main(start_command: SystemCommand, tx: IpcSender<WatcherCommand>, r
                 web::resource(&session_logout_url())
                     .route(web::post().to_async(session_controller::logout))
             )
+
+            // account
+            .service(
+                web::resource(&accounts_url())
+                    .route(web::get().to_async(account_controller::get_accounts))
+                    .route(web::post().to_async(account_controller::post_account))
+            )
+            .service(
+                web::resource(&account_url("{id}"))
+                    .route(web::put().to_async(account_controller::put_account))
+            )
+            .service(
+                web::resource(&account_reset_url("{id}"))
+                    .route(web::post().to_async(account_controller::reset_account))
+            )
             // these two last !!
-            .service(fs::Files::new("/", "static").index_file("index.html"))
+            .service(fs::Files::new("/", &static_dir).index_file("index.html"))
             .default_service(web::resource("").to(default_route))
     })
-    .bind("0.0.0.0:8080").unwrap()
+    .bind(&bind).unwrap()
     .start();
 
     let sys_result = system.run();
@@ -297,6 +503,13 @@ fn watch(_tx: IpcSender<SystemCommand>, rx: IpcReceiver<WatcherCommand>) -> Syst
 }
 
 fn main() {
+    let config = match Config::from_args() {
+        Ok(config) => config,
+        Err(e) => {
+            println!("{}", e);
+            process::exit(1);
+        },
+    };
     let mut start_command = SystemCommand::StartNormal;
 
     loop {
@@ -306,7 +519,7 @@ fn main() {
         let pid = unsafe { libc::fork() };
         match pid {
             0 => {
-                webserver_main(start_command, child_tx, child_rx);
+                webserver_main(start_command, config.clone(), child_tx, child_rx);
                 return;
             },
             -1 => {
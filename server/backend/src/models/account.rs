@@ -0,0 +1,293 @@
+use common::*;
+use futures::{ Stream, Future, IntoFuture, };
+use tokio_postgres::row::Row;
+use tokio_postgres::types::Type;
+use crate::ak_error::AkError;
+use crate::db_utils::PooledClient;
+
+// the password hash is intentionally not part of the account, see select_account_login
+pub fn row_to_account(row: &Row) -> Account {
+    let mut entry = Account::new();
+    for (i, column) in row.columns().iter().enumerate() {
+        match column.name() {
+            "a_id" => entry.id = row.get(i),
+            "a_created" => entry.created = row.get(i),
+            "a_disabled" => entry.disabled = row.get(i),
+            "a_last_login" => entry.last_login = row.get(i),
+            "a_name" => entry.name = row.get(i),
+            "a_password_hash" => {},
+            "a_role" => entry.role = AccountRole::from(row.get::<usize, i16>(i)),
+            unhandled if unhandled.starts_with("a_") => { panic!("unhandled account column {}", unhandled); },
+            _ => {},
+        }
+    }
+    entry
+}
+
+pub fn insert_account(mut client: PooledClient, name: String, password_hash: String, role: AccountRole) -> impl Future<Item=(PooledClient, Option<Account>), Error=AkError> {
+    client
+        .prepare_typed("
+            INSERT INTO system.accounts (
+                a_created,
+                a_name,
+                a_password_hash,
+                a_role
+            )
+            VALUES( $1, $2, $3, $4 )
+            RETURNING *
+        ", &[
+            Type::TIMESTAMPTZ,
+            Type::VARCHAR,
+            Type::VARCHAR,
+            Type::INT2,
+        ])
+        .map_err(AkError::from)
+        .and_then(move |statement| {
+            client
+                .query(&statement, &[
+                    &Utc::now(),
+                    &name,
+                    &password_hash,
+                    &i16::from(role),
+                ])
+                .into_future()
+                .map_err(|(err, _next)| {
+                    AkError::from(err)
+                })
+                .map(|(row, _next)| {
+                    match row {
+                        Some(r) => (client, Some(row_to_account(&r))),
+                        _ => (client, None),
+                    }
+                })
+        })
+}
+
+pub fn select_accounts(mut client: PooledClient) -> impl Future<Item=(PooledClient, Vec<Account>), Error=AkError> {
+    client
+        .prepare("
+            SELECT * FROM system.accounts
+            ORDER BY a_name
+        ")
+        .map_err(AkError::from)
+        .and_then(move |statement| {
+            client
+                .query(&statement, &[])
+                .collect()
+                .into_future()
+                .map_err(AkError::from)
+                .map(|rows| {
+                    (client, rows.into_iter().map(|row| row_to_account(&row)).collect())
+                })
+        })
+}
+
+// the only query which returns the password hash, so that it can be verified during login
+pub fn select_account_login(mut client: PooledClient, name: String) -> impl Future<Item=(PooledClient, Option<(Account, String)>), Error=AkError> {
+    client
+        .prepare_typed("
+            SELECT * FROM system.accounts
+            WHERE a_name = $1
+        ", &[
+            Type::VARCHAR,
+        ])
+        .map_err(AkError::from)
+        .and_then(move |statement| {
+            client
+                .query(&statement, &[&name])
+                .into_future()
+                .map_err(|(err, _next)| {
+                    AkError::from(err)
+                })
+                .map(|(row, _next)| {
+                    match row {
+                        Some(r) => {
+                            let hash: String = r.get("a_password_hash");
+                            (client, Some((row_to_account(&r), hash)))
+                        },
+                        _ => (client, None),
+                    }
+                })
+        })
+}
+
+pub fn update_account(mut client: PooledClient, account: Account) -> impl Future<Item=(PooledClient, Option<Account>), Error=AkError> {
+    client
+        .prepare_typed("
+            UPDATE system.accounts
+            SET
+                a_disabled = $1,
+                a_name = $2,
+                a_role = $3
+            WHERE
+                a_id = $4
+            RETURNING *
+        ", &[
+            Type::BOOL,
+            Type::VARCHAR,
+            Type::INT2,
+            Type::INT4,
+        ])
+        .map_err(AkError::from)
+        .and_then(move |statement| {
+            client
+                .query(&statement, &[
+                    &account.disabled,
+                    &account.name,
+                    &i16::from(account.role),
+                    &account.id,
+                ])
+                .into_future()
+                .map_err(|(err, _next)| {
+                    AkError::from(err)
+                })
+                .map(|(row, _next)| {
+                    match row {
+                        Some(r) => (client, Some(row_to_account(&r))),
+                        _ => (client, None),
+                    }
+                })
+        })
+}
+
+pub fn update_account_password(mut client: PooledClient, id: i32, password_hash: String) -> impl Future<Item=(PooledClient, Option<Account>), Error=AkError> {
+    client
+        .prepare_typed("
+            UPDATE system.accounts
+            SET
+                a_password_hash = $1
+            WHERE
+                a_id = $2
+            RETURNING *
+        ", &[
+            Type::VARCHAR,
+            Type::INT4,
+        ])
+        .map_err(AkError::from)
+        .and_then(move |statement| {
+            client
+                .query(&statement, &[&password_hash, &id])
+                .into_future()
+                .map_err(|(err, _next)| {
+                    AkError::from(err)
+                })
+                .map(|(row, _next)| {
+                    match row {
+                        Some(r) => (client, Some(row_to_account(&r))),
+                        _ => (client, None),
+                    }
+                })
+        })
+}
+
+pub fn update_account_login(mut client: PooledClient, id: i32, timestamp: DateTime<Utc>) -> impl Future<Item=PooledClient, Error=AkError> {
+    client
+        .prepare_typed("
+            UPDATE system.accounts
+            SET
+                a_last_login = $1
+            WHERE
+                a_id = $2
+        ", &[
+            Type::TIMESTAMPTZ,
+            Type::INT4,
+        ])
+        .map_err(AkError::from)
+        .and_then(move |statement| {
+            client
+                .execute(&statement, &[&timestamp, &id])
+                .map_err(AkError::from)
+                .map(|_count| {
+                    client
+                })
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db_utils;
+    use tokio::runtime::current_thread::Runtime;
+
+    #[test]
+    fn insert() {
+        let mut runtime = Runtime::new().unwrap();
+        runtime.block_on(crate::system::create_db(crate::db_utils::DEFAULT_CONNECTION, true)).unwrap();
+
+        let task = db_utils::default_connect()
+            .and_then(|client| {
+                insert_account(client, "account_0".to_string(), "hash".to_string(), AccountRole::Responder)
+            })
+            .map(|(_client, opt_account)| {
+                let account = opt_account.unwrap();
+                assert!(account.name == "account_0");
+                assert!(account.role == AccountRole::Responder);
+                assert!(!account.disabled);
+                assert!(account.last_login.is_none());
+            })
+            .map_err(|e| {
+                println!("db error {:?}", e);
+                panic!("failed to insert account");
+            });
+        runtime.block_on(task).unwrap();
+    }
+
+    #[test]
+    fn select_login() {
+        let mut runtime = Runtime::new().unwrap();
+        runtime.block_on(crate::system::create_db(crate::db_utils::DEFAULT_CONNECTION, true)).unwrap();
+
+        let task = db_utils::default_connect()
+            .and_then(|client| {
+                insert_account(client, "account_0".to_string(), "hash".to_string(), AccountRole::Admin)
+            })
+            .and_then(|(client, _opt_account)| {
+                select_account_login(client, "account_0".to_string())
+            })
+            .map(|(_client, opt_login)| {
+                let (account, hash) = opt_login.unwrap();
+                assert!(account.role == AccountRole::Admin);
+                assert!(hash == "hash");
+            })
+            .map_err(|e| {
+                println!("db error {:?}", e);
+                panic!("failed to select account login");
+            });
+        runtime.block_on(task).unwrap();
+    }
+
+    #[test]
+    fn update() {
+        let mut runtime = Runtime::new().unwrap();
+        runtime.block_on(crate::system::create_db(crate::db_utils::DEFAULT_CONNECTION, true)).unwrap();
+
+        let task = db_utils::default_connect()
+            .and_then(|client| {
+                insert_account(client, "account_0".to_string(), "hash".to_string(), AccountRole::Responder)
+            })
+            .and_then(|(client, opt_account)| {
+                let mut account = opt_account.unwrap();
+                account.disabled = true;
+                account.role = AccountRole::Admin;
+                update_account(client, account)
+            })
+            .and_then(|(client, opt_account)| {
+                let account = opt_account.unwrap();
+                assert!(account.disabled);
+                assert!(account.role == AccountRole::Admin);
+                update_account_password(client, account.id, "new_hash".to_string())
+            })
+            .and_then(|(client, _opt_account)| {
+                select_account_login(client, "account_0".to_string())
+            })
+            .map(|(_client, opt_login)| {
+                let (_account, hash) = opt_login.unwrap();
+                assert!(hash == "new_hash");
+            })
+            .map_err(|e| {
+                println!("db error {:?}", e);
+                panic!("failed to update account");
+            });
+        runtime.block_on(task).unwrap();
+    }
+}
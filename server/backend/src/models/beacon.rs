@@ -7,6 +7,25 @@ use tokio_postgres::row::Row;
 use tokio_postgres::types::Type;
 use std::net::IpAddr;
 use crate::ak_error::AkError;
+use crate::db_utils::PooledClient;
+
+// the signing keys of a beacon. The previous key is still accepted until the beacon starts
+// signing with the current one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BeaconKey {
+    pub current: Vec<u8>,
+    pub previous: Option<Vec<u8>>,
+    // the highest counter accepted, so frames from before a restart are not accepted again
+    pub counter: Option<u32>,
+}
+
+fn row_to_key(row: &Row) -> BeaconKey {
+    BeaconKey {
+        current: row.get("b_key"),
+        previous: row.get("b_previous_key"),
+        counter: row.get::<_, Option<i64>>("b_counter").map(|counter| counter as u32),
+    }
+}
 
 pub fn row_to_beacon(row: &Row) -> Beacon {
     let mut b = Beacon::new();
@@ -24,6 +43,9 @@ pub fn row_to_beacon(row: &Row) -> Beacon {
             "b_name" => b.name = row.get(i),
             "b_note" => b.note = row.get(i),
             "b_state" => b.state = BeaconState::from(row.get::<usize, i16>(i)),
+            "b_key_rotated" => b.key_rotated = row.get(i),
+            // keys never leave the backend, see select_beacon_keys
+            "b_key" | "b_previous_key" => {},
             unhandled if unhandled.starts_with("b_") => { panic!("unhandled beacon column {}", unhandled); },
             _ => {},
         }
@@ -31,7 +53,7 @@ pub fn row_to_beacon(row: &Row) -> Beacon {
     b
 }
 
-pub fn select_beacons(mut client: tokio_postgres::Client) -> impl Future<Item=(tokio_postgres::Client, Vec<Beacon>), Error=AkError> {
+pub fn select_beacons(mut client: PooledClient) -> impl Future<Item=(PooledClient, Vec<Beacon>), Error=AkError> {
     // TODO paging
     client
         .prepare("
@@ -50,7 +72,7 @@ pub fn select_beacons(mut client: tokio_postgres::Client) -> impl Future<Item=(t
         })
 }
 
-pub fn select_beacons_prefetch(mut client: tokio_postgres::Client) -> impl Future<Item=(tokio_postgres::Client, Vec<(Beacon, Option<Map>)>), Error=AkError> {
+pub fn select_beacons_prefetch(mut client: PooledClient) -> impl Future<Item=(PooledClient, Vec<(Beacon, Option<Map>)>), Error=AkError> {
     // TODO paging
     client
         .prepare("
@@ -82,7 +104,7 @@ pub fn select_beacons_prefetch(mut client: tokio_postgres::Client) -> impl Futur
         })
 }
 
-pub fn select_beacon(mut client: tokio_postgres::Client, id: i32) -> impl Future<Item=(tokio_postgres::Client, Option<(Beacon, Option<Map>)>), Error=AkError> {
+pub fn select_beacon(mut client: PooledClient, id: i32) -> impl Future<Item=(PooledClient, Option<(Beacon, Option<Map>)>), Error=AkError> {
     client
         .prepare("
             SELECT * FROM runtime.beacons
@@ -105,7 +127,7 @@ pub fn select_beacon(mut client: tokio_postgres::Client, id: i32) -> impl Future
         })
 }
 
-pub fn select_beacons_for_map(mut client: tokio_postgres::Client, id: Option<i32>) -> impl Future<Item=(tokio_postgres::Client, Vec<Beacon>), Error=AkError> {
+pub fn select_beacons_for_map(mut client: PooledClient, id: Option<i32>) -> impl Future<Item=(PooledClient, Vec<Beacon>), Error=AkError> {
     let query = if id.is_some() {
         "
             SELECT * FROM runtime.beacons
@@ -137,7 +159,7 @@ pub fn select_beacons_for_map(mut client: tokio_postgres::Client, id: Option<i32
         })
 }
 
-pub fn select_beacons_by_mac(mut client: tokio_postgres::Client, macs: Vec<MacAddress8>) -> impl Future<Item=(tokio_postgres::Client, Vec<Beacon>), Error=AkError> {
+pub fn select_beacons_by_mac(mut client: PooledClient, macs: Vec<MacAddress8>) -> impl Future<Item=(PooledClient, Vec<Beacon>), Error=AkError> {
     client
         .prepare_typed("
             SELECT * FROM runtime.beacons
@@ -164,7 +186,7 @@ pub fn select_beacons_by_mac(mut client: tokio_postgres::Client, macs: Vec<MacAd
         })
 }
 
-pub fn select_beacon_by_mac(mut client: tokio_postgres::Client, mac: MacAddress8) -> impl Future<Item=(tokio_postgres::Client, Option<Beacon>), Error=AkError> {
+pub fn select_beacon_by_mac(mut client: PooledClient, mac: MacAddress8) -> impl Future<Item=(PooledClient, Option<Beacon>), Error=AkError> {
     client
         .prepare_typed("
             SELECT * FROM runtime.beacons
@@ -189,7 +211,7 @@ pub fn select_beacon_by_mac(mut client: tokio_postgres::Client, mac: MacAddress8
         })
 }
 
-pub fn select_beacon_by_ip(mut client: tokio_postgres::Client, ip: IpAddr) -> impl Future<Item=(tokio_postgres::Client, Option<Beacon>), Error=AkError> {
+pub fn select_beacon_by_ip(mut client: PooledClient, ip: IpAddr) -> impl Future<Item=(PooledClient, Option<Beacon>), Error=AkError> {
     client
         .prepare_typed("
             SELECT * FROM runtime.beacons
@@ -216,7 +238,7 @@ pub fn select_beacon_by_ip(mut client: tokio_postgres::Client, ip: IpAddr) -> im
         })
 }
 
-pub fn select_beacon_prefetch(mut client: tokio_postgres::Client, id: i32) -> impl Future<Item=(tokio_postgres::Client, Option<(Beacon, Option<Map>)>), Error=AkError> {
+pub fn select_beacon_prefetch(mut client: PooledClient, id: i32) -> impl Future<Item=(PooledClient, Option<(Beacon, Option<Map>)>), Error=AkError> {
     // TODO paging
     client
         .prepare_typed("
@@ -245,7 +267,7 @@ pub fn select_beacon_prefetch(mut client: tokio_postgres::Client, id: i32) -> im
         })
 }
 
-pub fn insert_beacon(mut client: tokio_postgres::Client, beacon: Beacon) -> impl Future<Item=(tokio_postgres::Client, Option<Beacon>), Error=AkError> {
+pub fn insert_beacon(mut client: PooledClient, beacon: Beacon) -> impl Future<Item=(PooledClient, Option<Beacon>), Error=AkError> {
     client
         .prepare_typed("
             INSERT INTO runtime.beacons (
@@ -295,7 +317,7 @@ pub fn insert_beacon(mut client: tokio_postgres::Client, beacon: Beacon) -> impl
         })
 }
 
-pub fn update_beacon(mut client: tokio_postgres::Client, beacon: Beacon) -> impl Future<Item=(tokio_postgres::Client, Option<Beacon>), Error=AkError> {
+pub fn update_beacon(mut client: PooledClient, beacon: Beacon) -> impl Future<Item=(PooledClient, Option<Beacon>), Error=AkError> {
     client
         .prepare_typed("
             UPDATE runtime.beacons
@@ -344,7 +366,7 @@ pub fn update_beacon(mut client: tokio_postgres::Client, beacon: Beacon) -> impl
         })
 }
 
-pub fn update_beacon_from_realtime(mut client: tokio_postgres::Client, realtime: RealtimeBeacon) -> impl Future<Item=(tokio_postgres::Client, Option<Beacon>), Error=AkError> {
+pub fn update_beacon_from_realtime(mut client: PooledClient, realtime: RealtimeBeacon) -> impl Future<Item=(PooledClient, Option<Beacon>), Error=AkError> {
     client
         .prepare_typed("
             UPDATE runtime.beacons
@@ -383,7 +405,7 @@ pub fn update_beacon_from_realtime(mut client: tokio_postgres::Client, realtime:
         })
 }
 
-pub fn delete_beacon(mut client: tokio_postgres::Client, id: i32) -> impl Future<Item=tokio_postgres::Client, Error=AkError> {
+pub fn delete_beacon(mut client: PooledClient, id: i32) -> impl Future<Item=PooledClient, Error=AkError> {
     client
         .prepare_typed("
             DELETE FROM runtime.beacons
@@ -407,6 +429,148 @@ pub fn delete_beacon(mut client: tokio_postgres::Client, id: i32) -> impl Future
         })
 }
 
+pub fn select_beacon_keys(mut client: PooledClient) -> impl Future<Item=(PooledClient, Vec<(MacAddress8, BeaconKey)>), Error=AkError> {
+    client
+        .prepare("
+            SELECT b_mac_address, b_key, b_previous_key, b_counter FROM runtime.beacons
+            WHERE b_key IS NOT NULL
+        ")
+        .map_err(AkError::from)
+        .and_then(move |statement| {
+            client
+                .query(&statement, &[])
+                .collect()
+                .into_future()
+                .map_err(AkError::from)
+                .map(|rows| {
+                    let keys = rows.into_iter()
+                        .map(|row| {
+                            (row.get("b_mac_address"), row_to_key(&row))
+                        })
+                        .collect();
+                    (client, keys)
+                })
+        })
+}
+
+// replaces the signing key of a beacon. The replaced key becomes the previous key, unless the
+// beacon has not been seen signing with it yet, then the beacon still has the previous key.
+pub fn update_beacon_key(mut client: PooledClient, id: i32, key: Vec<u8>, rotated: DateTime<Utc>) -> impl Future<Item=(PooledClient, Option<(MacAddress8, BeaconKey)>), Error=AkError> {
+    client
+        .prepare_typed("
+            UPDATE runtime.beacons
+            SET
+                b_previous_key = COALESCE(b_previous_key, b_key),
+                b_key = $1,
+                b_key_rotated = $2
+            WHERE
+                b_id = $3
+            RETURNING b_mac_address, b_key, b_previous_key, b_counter
+        ", &[
+            Type::BYTEA,
+            Type::TIMESTAMPTZ,
+            Type::INT4,
+        ])
+        .map_err(AkError::from)
+        .and_then(move |statement| {
+            client
+                .query(&statement, &[&key, &rotated, &id])
+                .into_future()
+                .map_err(|(err, _next)| {
+                    AkError::from(err)
+                })
+                .map(|(row, _next)| {
+                    match row {
+                        Some(r) => (client, Some((r.get("b_mac_address"), row_to_key(&r)))),
+                        _ => (client, None),
+                    }
+                })
+        })
+}
+
+// returns the mac of the beacon, None if there is no such beacon
+pub fn delete_beacon_key(mut client: PooledClient, id: i32) -> impl Future<Item=(PooledClient, Option<MacAddress8>), Error=AkError> {
+    client
+        .prepare_typed("
+            UPDATE runtime.beacons
+            SET
+                b_key = NULL,
+                b_counter = NULL,
+                b_previous_key = NULL,
+                b_key_rotated = NULL
+            WHERE
+                b_id = $1
+            RETURNING b_mac_address
+        ", &[
+            Type::INT4,
+        ])
+        .map_err(AkError::from)
+        .and_then(move |statement| {
+            client
+                .query(&statement, &[&id])
+                .into_future()
+                .map_err(|(err, _next)| {
+                    AkError::from(err)
+                })
+                .map(|(row, _next)| {
+                    match row {
+                        Some(r) => (client, Some(r.get("b_mac_address"))),
+                        _ => (client, None),
+                    }
+                })
+        })
+}
+
+// the beacon signed with its current key, the previous key is not accepted anymore. nothing is
+// cleared if the key was rotated again since.
+pub fn clear_previous_beacon_key(mut client: PooledClient, mac: MacAddress8, current: Vec<u8>) -> impl Future<Item=(PooledClient, u64), Error=AkError> {
+    client
+        .prepare_typed("
+            UPDATE runtime.beacons
+            SET b_previous_key = NULL
+            WHERE
+                b_mac_address = $1
+                AND b_key = $2
+                AND b_previous_key IS NOT NULL
+        ", &[
+            Type::MACADDR8,
+            Type::BYTEA,
+        ])
+        .map_err(AkError::from)
+        .and_then(move |statement| {
+            client
+                .execute(&statement, &[&mac, &current])
+                .map_err(AkError::from)
+                .map(|count| {
+                    (client, count)
+                })
+        })
+}
+
+// only raises the counter, so saves that arrive out of order do not lower it
+pub fn update_beacon_counter(mut client: PooledClient, mac: MacAddress8, counter: u32) -> impl Future<Item=(PooledClient, u64), Error=AkError> {
+    client
+        .prepare_typed("
+            UPDATE runtime.beacons
+            SET b_counter = GREATEST(b_counter, $1)
+            WHERE
+                b_mac_address = $2
+                AND b_key IS NOT NULL
+        ", &[
+            Type::INT8,
+            Type::MACADDR8,
+        ])
+        .map_err(AkError::from)
+        .and_then(move |statement| {
+            client
+                .execute(&statement, &[&(counter as i64), &mac])
+                .map_err(AkError::from)
+                .map(|count| {
+                    (client, count)
+                })
+        })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -418,7 +582,7 @@ mod tests {
     #[test]
     fn insert() {
         let mut runtime = Runtime::new().unwrap();
-        runtime.block_on(crate::system::create_db(true)).unwrap();
+        runtime.block_on(crate::system::create_db(crate::db_utils::DEFAULT_CONNECTION, true)).unwrap();
 
         let map = Map::new();
 
@@ -446,7 +610,7 @@ mod tests {
     #[test]
     fn delete() {
         let mut runtime = Runtime::new().unwrap();
-        runtime.block_on(crate::system::create_db(true)).unwrap();
+        runtime.block_on(crate::system::create_db(crate::db_utils::DEFAULT_CONNECTION, true)).unwrap();
 
         let map = Map::new();
 
@@ -477,7 +641,7 @@ mod tests {
     #[test]
     fn update() {
         let mut runtime = Runtime::new().unwrap();
-        runtime.block_on(crate::system::create_db(true)).unwrap();
+        runtime.block_on(crate::system::create_db(crate::db_utils::DEFAULT_CONNECTION, true)).unwrap();
 
         let map = Map::new();
 
@@ -513,7 +677,7 @@ mod tests {
     #[test]
     fn update_from_realtime() {
         let mut runtime = Runtime::new().unwrap();
-        runtime.block_on(crate::system::create_db(true)).unwrap();
+        runtime.block_on(crate::system::create_db(crate::db_utils::DEFAULT_CONNECTION, true)).unwrap();
 
         let map = Map::new();
 
@@ -548,7 +712,7 @@ mod tests {
     #[test]
     fn select() {
         let mut runtime = Runtime::new().unwrap();
-        runtime.block_on(crate::system::create_db(true)).unwrap();
+        runtime.block_on(crate::system::create_db(crate::db_utils::DEFAULT_CONNECTION, true)).unwrap();
 
         let map = Map::new();
 
@@ -579,7 +743,7 @@ mod tests {
     #[test]
     fn select_mac() {
         let mut runtime = Runtime::new().unwrap();
-        runtime.block_on(crate::system::create_db(true)).unwrap();
+        runtime.block_on(crate::system::create_db(crate::db_utils::DEFAULT_CONNECTION, true)).unwrap();
 
         let map = Map::new();
         let mac = MacAddress8::from_bytes(&[1, 0, 0, 0, 0, 0, 0, 0]).unwrap();
@@ -612,7 +776,7 @@ mod tests {
     #[test]
     fn select_for_map() {
         let mut runtime = Runtime::new().unwrap();
-        runtime.block_on(crate::system::create_db(true)).unwrap();
+        runtime.block_on(crate::system::create_db(crate::db_utils::DEFAULT_CONNECTION, true)).unwrap();
 
         let mut beacon = Beacon::new();
         beacon.name = "hello_test".to_string();
@@ -641,7 +805,7 @@ mod tests {
     #[test]
     fn select_prefetch() {
         let mut runtime = Runtime::new().unwrap();
-        runtime.block_on(crate::system::create_db(true)).unwrap();
+        runtime.block_on(crate::system::create_db(crate::db_utils::DEFAULT_CONNECTION, true)).unwrap();
 
         let map = Map::new();
 
@@ -672,7 +836,7 @@ mod tests {
     #[test]
     fn select_many() {
         let mut runtime = Runtime::new().unwrap();
-        runtime.block_on(crate::system::create_db(true)).unwrap();
+        runtime.block_on(crate::system::create_db(crate::db_utils::DEFAULT_CONNECTION, true)).unwrap();
 
         let map = Map::new();
 
@@ -703,7 +867,7 @@ mod tests {
     #[test]
     fn select_3_by_mac() {
         let mut runtime = Runtime::new().unwrap();
-        runtime.block_on(crate::system::create_db(true)).unwrap();
+        runtime.block_on(crate::system::create_db(crate::db_utils::DEFAULT_CONNECTION, true)).unwrap();
 
         let map = Map::new();
 
@@ -752,7 +916,7 @@ mod tests {
     #[test]
     fn select_many_prefetch() {
         let mut runtime = Runtime::new().unwrap();
-        runtime.block_on(crate::system::create_db(true)).unwrap();
+        runtime.block_on(crate::system::create_db(crate::db_utils::DEFAULT_CONNECTION, true)).unwrap();
 
         let map = Map::new();
 
@@ -779,4 +943,95 @@ mod tests {
             });
         runtime.block_on(task).unwrap();
     }
+
+    #[test]
+    fn keys() {
+        let mut runtime = Runtime::new().unwrap();
+        runtime.block_on(crate::system::create_db(crate::db_utils::DEFAULT_CONNECTION, true)).unwrap();
+
+        let map = Map::new();
+        let mac = MacAddress8::from_bytes(&[1, 0, 0, 0, 0, 0, 0, 0]).unwrap();
+        let now = Utc::now();
+
+        let mut beacon = Beacon::new();
+        beacon.name = "hello_test".to_string();
+        beacon.mac_address = mac;
+
+        let task = db_utils::default_connect()
+            .and_then(|client| {
+                map::insert_map(client, map)
+            })
+            .and_then(|(client, map)| {
+                beacon.map_id = Some(map.unwrap().id);
+                insert_beacon(client, beacon)
+            })
+            .and_then(move |(client, opt_beacon)| {
+                let id = opt_beacon.unwrap().id;
+                update_beacon_key(client, id, vec![1; 32], now)
+                    .and_then(move |(client, _key)| update_beacon_key(client, id, vec![2; 32], now))
+                    .map(move |(client, opt_key)| (client, id, opt_key))
+            })
+            .and_then(move |(client, id, opt_key)| {
+                let (key_mac, key) = opt_key.unwrap();
+                assert_eq!(key_mac, mac);
+                assert_eq!(key.current, vec![2; 32]);
+                assert_eq!(key.previous, Some(vec![1; 32]));
+                select_beacon_by_mac(client, mac)
+                    .map(move |(client, opt_beacon)| {
+                        assert!(opt_beacon.unwrap().key_rotated.is_some());
+                        (client, id)
+                    })
+            })
+            .and_then(move |(client, id)| {
+                // the beacon was not seen with the second key, so it still has the first
+                update_beacon_key(client, id, vec![3; 32], now)
+                    .map(move |(client, opt_key)| {
+                        assert_eq!(opt_key.unwrap().1.previous, Some(vec![1; 32]));
+                        (client, id)
+                    })
+            })
+            .and_then(move |(client, id)| {
+                clear_previous_beacon_key(client, mac, vec![2; 32])
+                    .and_then(move |(client, count)| {
+                        assert_eq!(count, 0);
+                        clear_previous_beacon_key(client, mac, vec![3; 32])
+                    })
+                    .and_then(move |(client, count)| {
+                        assert_eq!(count, 1);
+                        update_beacon_key(client, id, vec![4; 32], now)
+                    })
+                    .map(move |(client, opt_key)| {
+                        assert_eq!(opt_key.unwrap().1.previous, Some(vec![3; 32]));
+                        (client, id)
+                    })
+            })
+            .and_then(move |(client, id)| {
+                update_beacon_counter(client, mac, 7)
+                    .and_then(move |(client, _count)| update_beacon_counter(client, mac, 5))
+                    .map(move |(client, _count)| (client, id))
+            })
+            .and_then(|(client, id)| {
+                select_beacon_keys(client)
+                    .map(move |(client, keys)| {
+                        assert_eq!(keys.len(), 1);
+                        assert_eq!(keys[0].1.counter, Some(7));
+                        (client, id)
+                    })
+            })
+            .and_then(|(client, id)| {
+                delete_beacon_key(client, id)
+            })
+            .and_then(move |(client, opt_mac)| {
+                assert_eq!(opt_mac, Some(mac));
+                select_beacon_keys(client)
+            })
+            .map(|(_client, keys)| {
+                assert!(keys.is_empty());
+            })
+            .map_err(|e| {
+                println!("db error {:?}", e);
+                panic!("failed to rotate beacon keys");
+            });
+        runtime.block_on(task).unwrap();
+    }
 }
@@ -0,0 +1,111 @@
+// Beacons whose messages are ignored, kept so that a blocked beacon stays blocked after a restart.
+
+use common::*;
+use futures::{ Stream, Future, IntoFuture, };
+use tokio_postgres::types::Type;
+use crate::ak_error::AkError;
+use crate::db_utils::PooledClient;
+
+pub fn select_blocked_beacons(mut client: PooledClient) -> impl Future<Item=(PooledClient, Vec<MacAddress8>), Error=AkError> {
+    client
+        .prepare("
+            SELECT k_mac_address FROM system.beacon_blocklist
+        ")
+        .map_err(AkError::from)
+        .and_then(move |statement| {
+            client
+                .query(&statement, &[])
+                .collect()
+                .into_future()
+                .map_err(AkError::from)
+                .map(|rows| {
+                    (client, rows.into_iter().map(|row| row.get("k_mac_address")).collect())
+                })
+        })
+}
+
+// blocking a beacon that is already blocked does nothing
+pub fn insert_blocked_beacon(mut client: PooledClient, mac: MacAddress8, created: DateTime<Utc>) -> impl Future<Item=PooledClient, Error=AkError> {
+    client
+        .prepare_typed("
+            INSERT INTO system.beacon_blocklist (
+                k_mac_address,
+                k_created
+            )
+            VALUES( $1, $2 )
+            ON CONFLICT (k_mac_address) DO NOTHING
+        ", &[
+            Type::MACADDR8,
+            Type::TIMESTAMPTZ,
+        ])
+        .map_err(AkError::from)
+        .and_then(move |statement| {
+            client
+                .execute(&statement, &[&mac, &created])
+                .map_err(AkError::from)
+                .map(|_count| {
+                    client
+                })
+        })
+}
+
+pub fn delete_blocked_beacon(mut client: PooledClient, mac: MacAddress8) -> impl Future<Item=PooledClient, Error=AkError> {
+    client
+        .prepare_typed("
+            DELETE FROM system.beacon_blocklist
+            WHERE k_mac_address = $1
+        ", &[
+            Type::MACADDR8,
+        ])
+        .map_err(AkError::from)
+        .and_then(move |statement| {
+            client
+                .execute(&statement, &[&mac])
+                .map_err(AkError::from)
+                .map(|_count| {
+                    client
+                })
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db_utils;
+    use tokio::runtime::current_thread::Runtime;
+
+    #[test]
+    fn block_unblock() {
+        let mut runtime = Runtime::new().unwrap();
+        runtime.block_on(crate::system::create_db(crate::db_utils::DEFAULT_CONNECTION, true)).unwrap();
+        let mac = MacAddress8::from_bytes(&[1, 0, 0, 0, 0, 0, 0, 0]).unwrap();
+        let now = Utc::now();
+
+        let task = db_utils::default_connect()
+            .and_then(move |client| {
+                insert_blocked_beacon(client, mac, now)
+            })
+            .and_then(move |client| {
+                // blocking twice is not an error
+                insert_blocked_beacon(client, mac, now)
+            })
+            .and_then(|client| {
+                select_blocked_beacons(client)
+            })
+            .and_then(move |(client, blocked)| {
+                assert_eq!(blocked, vec![mac]);
+                delete_blocked_beacon(client, mac)
+            })
+            .and_then(|client| {
+                select_blocked_beacons(client)
+            })
+            .map(|(_client, blocked)| {
+                assert!(blocked.is_empty());
+            })
+            .map_err(|e| {
+                println!("db error {:?}", e);
+                panic!("failed to block beacon");
+            });
+        runtime.block_on(task).unwrap();
+    }
+}
@@ -0,0 +1,154 @@
+// The emergency state of the system. There is only ever one row, so that the beacon manager
+// can restore the emergency after a crash or restart.
+
+use common::*;
+use futures::{ Stream, Future, IntoFuture, };
+use tokio_postgres::row::Row;
+use tokio_postgres::types::Type;
+use crate::ak_error::AkError;
+use crate::db_utils::PooledClient;
+
+pub fn row_to_emergency(row: &Row) -> EmergencyStatus {
+    let mut status = EmergencyStatus::new();
+    for (i, column) in row.columns().iter().enumerate() {
+        match column.name() {
+            "e_id" => {},
+            "e_active" => status.active = row.get(i),
+            "e_started_at" => status.started_at = row.get(i),
+            "e_started_by" => status.started_by = row.get(i),
+            "e_ended_at" => status.ended_at = row.get(i),
+            "e_ended_by" => status.ended_by = row.get(i),
+            "e_map_ids" => status.map_ids = row.get(i),
+            unhandled if unhandled.starts_with("e_") => { panic!("unhandled emergency column {}", unhandled); },
+            _ => {},
+        }
+    }
+    status
+}
+
+// a missing row is treated as no emergency
+pub fn select_emergency(mut client: PooledClient) -> impl Future<Item=(PooledClient, EmergencyStatus), Error=AkError> {
+    client
+        .prepare("
+            SELECT *
+            FROM system.emergency
+            WHERE e_id = 1
+        ")
+        .map_err(AkError::from)
+        .and_then(move |statement| {
+            client
+                .query(&statement, &[])
+                .into_future()
+                .map_err(|(err, _next)| {
+                    AkError::from(err)
+                })
+                .map(|(row, _next)| {
+                    match row {
+                        Some(row) => (client, row_to_emergency(&row)),
+                        None => (client, EmergencyStatus::new()),
+                    }
+                })
+        })
+}
+
+pub fn update_emergency(mut client: PooledClient, status: EmergencyStatus) -> impl Future<Item=PooledClient, Error=AkError> {
+    client
+        .prepare_typed("
+            INSERT INTO system.emergency (
+                e_id,
+                e_active,
+                e_started_at,
+                e_started_by,
+                e_ended_at,
+                e_ended_by,
+                e_map_ids
+            )
+            VALUES( 1, $1, $2, $3, $4, $5, $6 )
+            ON CONFLICT (e_id) DO UPDATE SET
+                e_active = EXCLUDED.e_active,
+                e_started_at = EXCLUDED.e_started_at,
+                e_started_by = EXCLUDED.e_started_by,
+                e_ended_at = EXCLUDED.e_ended_at,
+                e_ended_by = EXCLUDED.e_ended_by,
+                e_map_ids = EXCLUDED.e_map_ids
+        ", &[
+            Type::BOOL,
+            Type::TIMESTAMPTZ,
+            Type::VARCHAR,
+            Type::TIMESTAMPTZ,
+            Type::VARCHAR,
+            Type::INT4_ARRAY,
+        ])
+        .map_err(AkError::from)
+        .and_then(move |statement| {
+            client
+                .execute(&statement, &[
+                    &status.active,
+                    &status.started_at,
+                    &status.started_by,
+                    &status.ended_at,
+                    &status.ended_by,
+                    &status.map_ids,
+                ])
+                .map_err(AkError::from)
+                .map(|_count| {
+                    client
+                })
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db_utils;
+    use tokio::runtime::current_thread::Runtime;
+
+    #[test]
+    fn start_end() {
+        let mut runtime = Runtime::new().unwrap();
+        runtime.block_on(crate::system::create_db(crate::db_utils::DEFAULT_CONNECTION, true)).unwrap();
+
+        let mut started = EmergencyStatus::new();
+        started.active = true;
+        started.started_at = Some(Utc::now());
+        started.started_by = Some("admin".to_string());
+        started.map_ids = Some(vec![1, 2]);
+        let mut ended = started.clone();
+        ended.active = false;
+        ended.ended_at = Some(Utc::now());
+
+        let task = db_utils::default_connect()
+            .and_then(|client| {
+                select_emergency(client)
+            })
+            .and_then(move |(client, status)| {
+                assert_eq!(status, EmergencyStatus::new());
+                update_emergency(client, started)
+            })
+            .and_then(|client| {
+                select_emergency(client)
+            })
+            .and_then(move |(client, status)| {
+                // postgres keeps less precision than chrono, so compare everything but the times
+                assert!(status.active);
+                assert!(status.started_at.is_some());
+                assert_eq!(status.started_by, Some("admin".to_string()));
+                assert_eq!(status.ended_at, None);
+                assert_eq!(status.map_ids, Some(vec![1, 2]));
+                update_emergency(client, ended)
+            })
+            .and_then(|client| {
+                select_emergency(client)
+            })
+            .map(|(_client, status)| {
+                assert!(!status.active);
+                assert!(status.ended_at.is_some());
+                assert_eq!(status.started_by, Some("admin".to_string()));
+            })
+            .map_err(|e| {
+                println!("db error {:?}", e);
+                panic!("failed to update emergency");
+            });
+        runtime.block_on(task).unwrap();
+    }
+}
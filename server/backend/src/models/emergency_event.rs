@@ -0,0 +1,374 @@
+// A log of every emergency, who started and ended it and why, along with every map that was
+// started or ended while the rest of it kept going.
+
+use common::*;
+use futures::{ Stream, Future, IntoFuture, };
+use tokio_postgres::row::Row;
+use tokio_postgres::types::Type;
+use crate::ak_error::AkError;
+use crate::db_utils::PooledClient;
+
+pub fn row_to_emergency_event(row: &Row) -> EmergencyEvent {
+    let mut event = EmergencyEvent::new();
+    for (i, column) in row.columns().iter().enumerate() {
+        match column.name() {
+            "v_id" => event.id = row.get(i),
+            "v_started_at" => event.started_at = row.get(i),
+            "v_started_by" => event.started_by = row.get(i),
+            "v_start_reason" => event.start_reason = row.get(i),
+            "v_ended_at" => event.ended_at = row.get(i),
+            "v_ended_by" => event.ended_by = row.get(i),
+            "v_end_reason" => event.end_reason = row.get(i),
+            "v_map_ids" => event.map_ids = row.get(i),
+            unhandled if unhandled.starts_with("v_") => { panic!("unhandled emergency event column {}", unhandled); },
+            _ => {},
+        }
+    }
+    event
+}
+
+fn row_to_scope_change(row: &Row) -> EmergencyScopeChange {
+    let mut change = EmergencyScopeChange::new();
+    for (i, column) in row.columns().iter().enumerate() {
+        match column.name() {
+            "c_id" => change.id = row.get(i),
+            "c_event_id" => change.event_id = row.get(i),
+            "c_timestamp" => change.timestamp = row.get(i),
+            "c_account" => change.account = row.get(i),
+            "c_reason" => change.reason = row.get(i),
+            "c_started" => change.started = row.get(i),
+            "c_map_ids" => change.map_ids = row.get(i),
+            unhandled if unhandled.starts_with("c_") => { panic!("unhandled emergency scope change column {}", unhandled); },
+            _ => {},
+        }
+    }
+    change
+}
+
+pub fn insert_emergency_event(mut client: PooledClient, event: EmergencyEvent) -> impl Future<Item=(PooledClient, Option<EmergencyEvent>), Error=AkError> {
+    client
+        .prepare_typed("
+            INSERT INTO runtime.emergency_events (
+                v_started_at,
+                v_started_by,
+                v_start_reason,
+                v_map_ids
+            )
+            VALUES( $1, $2, $3, $4 )
+            RETURNING *
+        ", &[
+            Type::TIMESTAMPTZ,
+            Type::VARCHAR,
+            Type::VARCHAR,
+            Type::INT4_ARRAY,
+        ])
+        .map_err(AkError::from)
+        .and_then(move |statement| {
+            client
+                .query(&statement, &[
+                    &event.started_at,
+                    &event.started_by,
+                    &event.start_reason,
+                    &event.map_ids,
+                ])
+                .into_future()
+                .map_err(|(err, _next)| {
+                    AkError::from(err)
+                })
+                .map(|(row, _next)| {
+                    match row {
+                        Some(r) => (client, Some(row_to_emergency_event(&r))),
+                        _ => (client, None),
+                    }
+                })
+        })
+}
+
+// ends every emergency that is still going, returns how many were ended
+pub fn end_emergency_events(mut client: PooledClient, ended_at: DateTime<Utc>, ended_by: Option<String>, reason: Option<String>) -> impl Future<Item=(PooledClient, u64), Error=AkError> {
+    client
+        .prepare_typed("
+            UPDATE runtime.emergency_events
+            SET
+                v_ended_at = $1,
+                v_ended_by = $2,
+                v_end_reason = $3
+            WHERE v_ended_at IS NULL
+        ", &[
+            Type::TIMESTAMPTZ,
+            Type::VARCHAR,
+            Type::VARCHAR,
+        ])
+        .map_err(AkError::from)
+        .and_then(move |statement| {
+            client
+                .execute(&statement, &[&ended_at, &ended_by, &reason])
+                .map_err(AkError::from)
+                .map(|count| {
+                    (client, count)
+                })
+        })
+}
+
+// adds maps to the emergencies that are still going. the maps are never removed, so the log
+// keeps every map an emergency covered, a site wide emergency stays site wide.
+pub fn update_emergency_events_scope(mut client: PooledClient, map_ids: Option<Vec<i32>>) -> impl Future<Item=(PooledClient, u64), Error=AkError> {
+    client
+        .prepare_typed("
+            UPDATE runtime.emergency_events
+            SET v_map_ids = CASE
+                WHEN v_map_ids IS NULL OR $1::INTEGER[] IS NULL THEN NULL
+                ELSE ARRAY(SELECT DISTINCT unnest(v_map_ids || $1::INTEGER[]) ORDER BY 1)
+            END
+            WHERE v_ended_at IS NULL
+        ", &[
+            Type::INT4_ARRAY,
+        ])
+        .map_err(AkError::from)
+        .and_then(move |statement| {
+            client
+                .execute(&statement, &[&map_ids])
+                .map_err(AkError::from)
+                .map(|count| {
+                    (client, count)
+                })
+        })
+}
+
+// logs the change against every emergency that is still going, returns how many there were
+pub fn insert_emergency_scope_change(mut client: PooledClient, change: EmergencyScopeChange) -> impl Future<Item=(PooledClient, u64), Error=AkError> {
+    client
+        .prepare_typed("
+            INSERT INTO runtime.emergency_scope_changes (
+                c_event_id,
+                c_timestamp,
+                c_account,
+                c_reason,
+                c_started,
+                c_map_ids
+            )
+            SELECT v_id, $1, $2, $3, $4, $5
+            FROM runtime.emergency_events
+            WHERE v_ended_at IS NULL
+        ", &[
+            Type::TIMESTAMPTZ,
+            Type::VARCHAR,
+            Type::VARCHAR,
+            Type::BOOL,
+            Type::INT4_ARRAY,
+        ])
+        .map_err(AkError::from)
+        .and_then(move |statement| {
+            client
+                .execute(&statement, &[
+                    &change.timestamp,
+                    &change.account,
+                    &change.reason,
+                    &change.started,
+                    &change.map_ids,
+                ])
+                .map_err(AkError::from)
+                .map(|count| {
+                    (client, count)
+                })
+        })
+}
+
+pub fn select_emergency_scope_changes(mut client: PooledClient, event_id: i32) -> impl Future<Item=(PooledClient, Vec<EmergencyScopeChange>), Error=AkError> {
+    client
+        .prepare_typed("
+            SELECT * FROM runtime.emergency_scope_changes
+            WHERE c_event_id = $1
+            ORDER BY c_timestamp, c_id
+        ", &[
+            Type::INT4,
+        ])
+        .map_err(AkError::from)
+        .and_then(move |statement| {
+            client
+                .query(&statement, &[&event_id])
+                .collect()
+                .into_future()
+                .map_err(AkError::from)
+                .map(|rows| {
+                    (client, rows.into_iter().map(|row| row_to_scope_change(&row)).collect())
+                })
+        })
+}
+
+// the most recent emergencies first
+pub fn select_emergency_events(mut client: PooledClient) -> impl Future<Item=(PooledClient, Vec<EmergencyEvent>), Error=AkError> {
+    // TODO paging
+    client
+        .prepare("
+            SELECT * FROM runtime.emergency_events
+            ORDER BY v_started_at DESC
+        ")
+        .map_err(AkError::from)
+        .and_then(move |statement| {
+            client
+                .query(&statement, &[])
+                .collect()
+                .into_future()
+                .map_err(AkError::from)
+                .map(|rows| {
+                    (client, rows.into_iter().map(|row| row_to_emergency_event(&row)).collect())
+                })
+        })
+}
+
+pub fn select_emergency_event(mut client: PooledClient, id: i32) -> impl Future<Item=(PooledClient, Option<EmergencyEvent>), Error=AkError> {
+    client
+        .prepare_typed("
+            SELECT * FROM runtime.emergency_events
+            WHERE v_id = $1
+        ", &[
+            Type::INT4,
+        ])
+        .map_err(AkError::from)
+        .and_then(move |statement| {
+            client
+                .query(&statement, &[&id])
+                .into_future()
+                .map_err(|(err, _next)| {
+                    AkError::from(err)
+                })
+                .map(|(row, _next)| {
+                    match row {
+                        Some(r) => (client, Some(row_to_emergency_event(&r))),
+                        _ => (client, None),
+                    }
+                })
+        })
+}
+
+// every user located between from and to on the given maps, or on any map when map_ids is None.
+// taken from the location history
+pub fn select_users_seen(mut client: PooledClient, from: DateTime<Utc>, to: DateTime<Utc>, map_ids: Option<Vec<i32>>) -> impl Future<Item=(PooledClient, Vec<EmergencySighting>), Error=AkError> {
+    client
+        .prepare_typed("
+            SELECT
+                u_id AS user_id,
+                COALESCE(u_name, '') AS name,
+                MIN(l_timestamp) AS first_seen,
+                MAX(l_timestamp) AS last_seen,
+                COUNT(*) AS locations
+            FROM runtime.location_history
+            INNER JOIN runtime.users ON u_id = l_user_id
+            WHERE
+                l_timestamp >= $1
+                AND l_timestamp <= $2
+                AND ($3::INTEGER[] IS NULL OR l_map_id = ANY($3))
+            GROUP BY u_id
+            ORDER BY name
+        ", &[
+            Type::TIMESTAMPTZ,
+            Type::TIMESTAMPTZ,
+            Type::INT4_ARRAY,
+        ])
+        .map_err(AkError::from)
+        .and_then(move |statement| {
+            client
+                .query(&statement, &[&from, &to, &map_ids])
+                .collect()
+                .into_future()
+                .map_err(AkError::from)
+                .map(|rows| {
+                    let users = rows.into_iter()
+                        .map(|row| EmergencySighting {
+                            user_id: row.get("user_id"),
+                            name: row.get("name"),
+                            first_seen: row.get("first_seen"),
+                            last_seen: row.get("last_seen"),
+                            locations: row.get("locations"),
+                        })
+                        .collect();
+                    (client, users)
+                })
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db_utils;
+    use tokio::runtime::current_thread::Runtime;
+
+    #[test]
+    fn start_end() {
+        let mut runtime = Runtime::new().unwrap();
+        runtime.block_on(crate::system::create_db(crate::db_utils::DEFAULT_CONNECTION, true)).unwrap();
+
+        let mut event = EmergencyEvent::new();
+        event.started_by = Some("admin".to_string());
+        event.start_reason = Some("drill".to_string());
+        event.map_ids = Some(vec![2, 4]);
+        let mut change = EmergencyScopeChange::new();
+        change.account = Some("responder".to_string());
+        change.reason = Some("floor 2 is clear".to_string());
+        change.started = false;
+        change.map_ids = Some(vec![2]);
+
+        let task = db_utils::default_connect()
+            .and_then(|client| {
+                insert_emergency_event(client, event)
+            })
+            .and_then(|(client, opt_event)| {
+                let event = opt_event.unwrap();
+                assert_eq!(event.ended_at, None);
+                update_emergency_events_scope(client, Some(vec![4]))
+                    .and_then(move |(client, count)| {
+                        assert_eq!(count, 1);
+                        insert_emergency_scope_change(client, change)
+                    })
+                    .map(move |(client, count)| {
+                        assert_eq!(count, 1);
+                        (client, event)
+                    })
+            })
+            .and_then(|(client, event)| {
+                end_emergency_events(client, Utc::now(), Some("responder".to_string()), None)
+                    .map(move |(client, count)| {
+                        assert_eq!(count, 1);
+                        (client, event.id)
+                    })
+            })
+            .and_then(|(client, id)| {
+                // events that already ended are left alone
+                end_emergency_events(client, Utc::now(), None, None)
+                    .map(move |(client, count)| {
+                        assert_eq!(count, 0);
+                        (client, id)
+                    })
+            })
+            .and_then(|(client, id)| {
+                select_emergency_event(client, id)
+            })
+            .and_then(|(client, opt_event)| {
+                let event = opt_event.unwrap();
+                assert!(event.ended_at.is_some());
+                assert_eq!(event.started_by, Some("admin".to_string()));
+                assert_eq!(event.start_reason, Some("drill".to_string()));
+                assert_eq!(event.ended_by, Some("responder".to_string()));
+                // the map that was ended is still part of the log
+                assert_eq!(event.map_ids, Some(vec![2, 4]));
+                select_emergency_scope_changes(client, event.id)
+            })
+            .and_then(|(client, changes)| {
+                assert_eq!(changes.len(), 1);
+                assert!(!changes[0].started);
+                assert_eq!(changes[0].account, Some("responder".to_string()));
+                assert_eq!(changes[0].reason, Some("floor 2 is clear".to_string()));
+                assert_eq!(changes[0].map_ids, Some(vec![2]));
+                select_emergency_events(client)
+            })
+            .map(|(_client, events)| {
+                assert_eq!(events.len(), 1);
+            })
+            .map_err(|e| {
+                println!("db error {:?}", e);
+                panic!("failed to log emergency");
+            });
+        runtime.block_on(task).unwrap();
+    }
+}
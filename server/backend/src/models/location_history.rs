@@ -0,0 +1,366 @@
+use common::*;
+use futures::{ Stream, Future, IntoFuture, };
+use na;
+use tokio_postgres::row::Row;
+use tokio_postgres::types::Type;
+use crate::ak_error::AkError;
+use crate::db_utils::PooledClient;
+use crate::models::user;
+
+fn row_to_location(row: &Row) -> LocationRecord {
+    let mut entry = LocationRecord::new();
+    for (i, column) in row.columns().iter().enumerate() {
+        match column.name() {
+            "l_id" => entry.id = row.get(i),
+            "l_beacons" => entry.beacons = row.get(i),
+            "l_beacon_coordinates" => {
+                let coords: Vec<f64> = row.get(i);
+                entry.beacon_coordinates = coords.chunks(2).map(|c| na::Vector2::new(c[0], c[1])).collect();
+            },
+            "l_coordinates" => {
+                let coords: Vec<f64> = row.get(i);
+                entry.coordinates = na::Vector2::new(coords[0], coords[1]);
+            },
+            "l_distances" => entry.distances = row.get(i),
+            "l_map_id" => entry.map_id = row.get(i),
+            "l_timestamp" => entry.timestamp = row.get(i),
+            "l_uncertainty" => entry.uncertainty = row.get(i),
+            "l_user_id" => entry.user_id = row.get(i),
+            unhandled if unhandled.starts_with("l_") => { panic!("unhandled location history column {}", unhandled); },
+            _ => {},
+        }
+    }
+    entry
+}
+
+pub fn insert_location(mut client: PooledClient, record: LocationRecord) -> impl Future<Item=(PooledClient, Option<LocationRecord>), Error=AkError> {
+    client
+        .prepare_typed("
+            INSERT INTO runtime.location_history (
+                l_beacons,
+                l_beacon_coordinates,
+                l_coordinates,
+                l_distances,
+                l_map_id,
+                l_timestamp,
+                l_uncertainty,
+                l_user_id
+            )
+            VALUES( $1, $2, $3, $4, $5, $6, $7, $8 )
+            RETURNING *
+        ", &[
+            Type::INT4_ARRAY,
+            Type::FLOAT8_ARRAY,
+            Type::FLOAT8_ARRAY,
+            Type::FLOAT8_ARRAY,
+            Type::INT4,
+            Type::TIMESTAMPTZ,
+            Type::FLOAT8,
+            Type::INT4,
+        ])
+        .map_err(AkError::from)
+        .and_then(move |statement| {
+            // stored flat, the x and y of each beacon in turn
+            let beacon_coordinates: Vec<f64> = record.beacon_coordinates
+                .iter()
+                .flat_map(|c| vec![c[0], c[1]])
+                .collect();
+            let coordinates = vec![record.coordinates[0], record.coordinates[1]];
+            client
+                .query(&statement, &[
+                    &record.beacons,
+                    &beacon_coordinates,
+                    &coordinates,
+                    &record.distances,
+                    &record.map_id,
+                    &record.timestamp,
+                    &record.uncertainty,
+                    &record.user_id,
+                ])
+                .into_future()
+                .map_err(|(err, _next)| {
+                    AkError::from(err)
+                })
+                .map(|(row, _next)| {
+                    match row {
+                        Some(r) => (client, Some(row_to_location(&r))),
+                        _ => (client, None),
+                    }
+                })
+        })
+}
+
+pub fn select_user_history(mut client: PooledClient, user_id: i32, from: DateTime<Utc>, to: DateTime<Utc>) -> impl Future<Item=(PooledClient, Vec<LocationRecord>), Error=AkError> {
+    client
+        .prepare_typed("
+            SELECT * FROM runtime.location_history
+            WHERE
+                l_user_id = $1
+                AND l_timestamp >= $2
+                AND l_timestamp <= $3
+            ORDER BY l_timestamp
+        ", &[
+            Type::INT4,
+            Type::TIMESTAMPTZ,
+            Type::TIMESTAMPTZ,
+        ])
+        .map_err(AkError::from)
+        .and_then(move |statement| {
+            client
+                .query(&statement, &[&user_id, &from, &to])
+                .collect()
+                .into_future()
+                .map_err(AkError::from)
+                .map(|rows| {
+                    (client, rows.into_iter().map(|row| row_to_location(&row)).collect())
+                })
+        })
+}
+
+pub fn select_map_history(mut client: PooledClient, map_id: i32, from: DateTime<Utc>, to: DateTime<Utc>) -> impl Future<Item=(PooledClient, Vec<LocationRecord>), Error=AkError> {
+    client
+        .prepare_typed("
+            SELECT * FROM runtime.location_history
+            WHERE
+                l_map_id = $1
+                AND l_timestamp >= $2
+                AND l_timestamp <= $3
+            ORDER BY l_timestamp
+        ", &[
+            Type::INT4,
+            Type::TIMESTAMPTZ,
+            Type::TIMESTAMPTZ,
+        ])
+        .map_err(AkError::from)
+        .and_then(move |statement| {
+            client
+                .query(&statement, &[&map_id, &from, &to])
+                .collect()
+                .into_future()
+                .map_err(AkError::from)
+                .map(|rows| {
+                    (client, rows.into_iter().map(|row| row_to_location(&row)).collect())
+                })
+        })
+}
+
+// the users are joined so that a replay can label the locations as they are drawn. a page starts
+// after the timestamp and id of the last location of the page before, and holds at most limit
+// locations
+pub fn select_map_replay(mut client: PooledClient, map_id: i32, after: (DateTime<Utc>, i64), to: DateTime<Utc>, limit: i64) -> impl Future<Item=(PooledClient, Vec<(LocationRecord, TrackedUser)>), Error=AkError> {
+    client
+        .prepare_typed("
+            SELECT * FROM runtime.location_history AS history
+            INNER JOIN runtime.users AS u ON u.u_id = history.l_user_id
+            WHERE
+                history.l_map_id = $1
+                AND (history.l_timestamp, history.l_id) > ($2, $3)
+                AND history.l_timestamp <= $4
+            ORDER BY history.l_timestamp, history.l_id
+            LIMIT $5
+        ", &[
+            Type::INT4,
+            Type::TIMESTAMPTZ,
+            Type::INT8,
+            Type::TIMESTAMPTZ,
+            Type::INT8,
+        ])
+        .map_err(AkError::from)
+        .and_then(move |statement| {
+            let (after_timestamp, after_id) = after;
+            client
+                .query(&statement, &[&map_id, &after_timestamp, &after_id, &to, &limit])
+                .collect()
+                .into_future()
+                .map_err(AkError::from)
+                .map(|rows| {
+                    (client, rows.into_iter().map(|row| (row_to_location(&row), user::row_to_user(&row))).collect())
+                })
+        })
+}
+
+// returns the number of pruned rows
+pub fn delete_history_before(mut client: PooledClient, cutoff: DateTime<Utc>) -> impl Future<Item=(PooledClient, u64), Error=AkError> {
+    client
+        .prepare_typed("
+            DELETE FROM runtime.location_history
+            WHERE l_timestamp < $1
+        ", &[
+            Type::TIMESTAMPTZ,
+        ])
+        .map_err(AkError::from)
+        .and_then(move |statement| {
+            client
+                .execute(&statement, &[&cutoff])
+                .map_err(AkError::from)
+                .map(|count| {
+                    (client, count)
+                })
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db_utils;
+    use chrono::Duration;
+    use tokio::runtime::current_thread::Runtime;
+
+    fn record_at(user_id: i32, timestamp: DateTime<Utc>) -> LocationRecord {
+        let mut record = LocationRecord::new();
+        record.user_id = user_id;
+        record.beacons = vec![100, 130, 103];
+        record.beacon_coordinates = vec![
+            na::Vector2::new(0.0, 0.0),
+            na::Vector2::new(4.0, 0.0),
+            na::Vector2::new(0.0, 4.0),
+        ];
+        record.distances = vec![1.0, 1.5, 2.0];
+        record.coordinates = na::Vector2::new(2.0, 2.0);
+        record.uncertainty = 0.25;
+        record.timestamp = timestamp;
+        record
+    }
+
+    #[test]
+    fn insert() {
+        let mut runtime = Runtime::new().unwrap();
+        runtime.block_on(crate::system::create_db(crate::db_utils::DEFAULT_CONNECTION, true)).unwrap();
+
+        let mut tracked = TrackedUser::new();
+        tracked.name = "user_0".to_string();
+
+        let task = db_utils::default_connect()
+            .and_then(|client| {
+                user::insert_user(client, tracked)
+            })
+            .and_then(|(client, opt_user)| {
+                insert_location(client, record_at(opt_user.unwrap().id, Utc::now()))
+            })
+            .map(|(_client, opt_record)| {
+                let record = opt_record.unwrap();
+                assert!(record.beacons == vec![100, 130, 103]);
+                assert!(record.beacon_coordinates[1] == na::Vector2::new(4.0, 0.0));
+                assert!(record.coordinates == na::Vector2::new(2.0, 2.0));
+            })
+            .map_err(|e| {
+                println!("db error {:?}", e);
+                panic!("failed to insert location");
+            });
+        runtime.block_on(task).unwrap();
+    }
+
+    #[test]
+    fn select_range() {
+        let mut runtime = Runtime::new().unwrap();
+        runtime.block_on(crate::system::create_db(crate::db_utils::DEFAULT_CONNECTION, true)).unwrap();
+
+        let mut tracked = TrackedUser::new();
+        tracked.name = "user_0".to_string();
+        let now = Utc::now();
+
+        let task = db_utils::default_connect()
+            .and_then(|client| {
+                user::insert_user(client, tracked)
+            })
+            .and_then(move |(client, opt_user)| {
+                let id = opt_user.unwrap().id;
+                insert_location(client, record_at(id, now - Duration::hours(2)))
+                    .and_then(move |(client, _)| insert_location(client, record_at(id, now - Duration::minutes(5))))
+                    .and_then(move |(client, _)| insert_location(client, record_at(id, now)))
+                    .and_then(move |(client, _)| select_user_history(client, id, now - Duration::hours(1), now))
+            })
+            .map(|(_client, history)| {
+                assert!(history.len() == 2);
+                assert!(history[0].timestamp < history[1].timestamp);
+            })
+            .map_err(|e| {
+                println!("db error {:?}", e);
+                panic!("failed to select location history");
+            });
+        runtime.block_on(task).unwrap();
+    }
+
+    #[test]
+    fn prune() {
+        let mut runtime = Runtime::new().unwrap();
+        runtime.block_on(crate::system::create_db(crate::db_utils::DEFAULT_CONNECTION, true)).unwrap();
+
+        let mut tracked = TrackedUser::new();
+        tracked.name = "user_0".to_string();
+        let now = Utc::now();
+
+        let task = db_utils::default_connect()
+            .and_then(|client| {
+                user::insert_user(client, tracked)
+            })
+            .and_then(move |(client, opt_user)| {
+                let id = opt_user.unwrap().id;
+                insert_location(client, record_at(id, now - Duration::days(40)))
+                    .and_then(move |(client, _)| insert_location(client, record_at(id, now)))
+                    .and_then(move |(client, _)| delete_history_before(client, now - Duration::days(30)))
+                    .map(move |(client, count)| {
+                        assert!(count == 1);
+                        (client, id)
+                    })
+            })
+            .and_then(move |(client, id)| {
+                select_user_history(client, id, Utc.timestamp(0, 0), now)
+            })
+            .map(|(_client, history)| {
+                assert!(history.len() == 1);
+            })
+            .map_err(|e| {
+                println!("db error {:?}", e);
+                panic!("failed to prune location history");
+            });
+        runtime.block_on(task).unwrap();
+    }
+
+    #[test]
+    fn select_replay() {
+        let mut runtime = Runtime::new().unwrap();
+        runtime.block_on(crate::system::create_db(crate::db_utils::DEFAULT_CONNECTION, true)).unwrap();
+
+        let mut tracked = TrackedUser::new();
+        tracked.name = "user_0".to_string();
+        let now = Utc::now();
+        let on_map = |id, timestamp| {
+            let mut record = record_at(id, timestamp);
+            record.map_id = Some(69);
+            record
+        };
+
+        let task = db_utils::default_connect()
+            .and_then(|client| {
+                user::insert_user(client, tracked)
+            })
+            .and_then(move |(client, opt_user)| {
+                let id = opt_user.unwrap().id;
+                insert_location(client, on_map(id, now - Duration::minutes(5)))
+                    .and_then(move |(client, _)| insert_location(client, on_map(id, now)))
+                    .and_then(move |(client, _)| insert_location(client, on_map(id, now)))
+                    .and_then(move |(client, _)| insert_location(client, record_at(id, now)))
+                    .and_then(move |(client, _)| select_map_replay(client, 69, (now - Duration::hours(1), -1), now, 2))
+            })
+            .and_then(move |(client, first)| {
+                assert!(first.len() == 2);
+                assert!(first[0].0.user_id == first[0].1.id);
+                assert!(first[0].1.name == "user_0");
+                // the next page carries on between the locations with the same timestamp
+                let last = &first[1].0;
+                select_map_replay(client, 69, (last.timestamp, last.id), now, 2)
+                    .map(move |(client, second)| (client, first, second))
+            })
+            .map(|(_client, first, second)| {
+                assert!(second.len() == 1);
+                assert!(second[0].0.timestamp == first[1].0.timestamp);
+                assert!(second[0].0.id > first[1].0.id);
+            })
+            .map_err(|e| {
+                println!("db error {:?}", e);
+                panic!("failed to select replay");
+            });
+        runtime.block_on(task).unwrap();
+    }
+}
@@ -6,6 +6,7 @@ use tokio_postgres::row::Row;
 use tokio_postgres::types::Type;
 use actix_web::web::{ BytesMut, };
 use crate::ak_error::AkError;
+use crate::db_utils::PooledClient;
 
 pub fn row_to_map(row: &Row) -> Map {
     let mut entry = Map::new();
@@ -49,7 +50,7 @@ pub fn maybe_row_to_map(row: &Row) -> Option<Map> {
     }
 }
 
-pub fn select_maps(mut client: tokio_postgres::Client) -> impl Future<Item=(tokio_postgres::Client, Vec<Map>), Error=AkError> {
+pub fn select_maps(mut client: PooledClient) -> impl Future<Item=(PooledClient, Vec<Map>), Error=AkError> {
     // TODO paging
     client
         .prepare("
@@ -68,7 +69,7 @@ pub fn select_maps(mut client: tokio_postgres::Client) -> impl Future<Item=(toki
         })
 }
 
-pub fn select_map(mut client: tokio_postgres::Client, id: i32) -> impl Future<Item=(tokio_postgres::Client, Option<Map>), Error=AkError> {
+pub fn select_map(mut client: PooledClient, id: i32) -> impl Future<Item=(PooledClient, Option<Map>), Error=AkError> {
     client
         .prepare("
             SELECT m_id, m_bounds, m_scale, m_name, m_note FROM runtime.maps
@@ -91,7 +92,7 @@ pub fn select_map(mut client: tokio_postgres::Client, id: i32) -> impl Future<It
         })
 }
 
-pub fn select_map_blueprint(mut client: tokio_postgres::Client, id: i32) -> impl Future<Item=(tokio_postgres::Client, Option<Vec<u8>>), Error=AkError> {
+pub fn select_map_blueprint(mut client: PooledClient, id: i32) -> impl Future<Item=(PooledClient, Option<Vec<u8>>), Error=AkError> {
     client
         .prepare_typed("
             SELECT m_id, m_blueprint FROM runtime.maps
@@ -126,7 +127,7 @@ pub fn select_map_blueprint(mut client: tokio_postgres::Client, id: i32) -> impl
         })
 }
 
-pub fn insert_map(mut client: tokio_postgres::Client, map: Map) -> impl Future<Item=(tokio_postgres::Client, Option<Map>), Error=AkError> {
+pub fn insert_map(mut client: PooledClient, map: Map) -> impl Future<Item=(PooledClient, Option<Map>), Error=AkError> {
     client
         .prepare_typed("
             INSERT INTO runtime.maps (
@@ -166,7 +167,7 @@ pub fn insert_map(mut client: tokio_postgres::Client, map: Map) -> impl Future<I
         })
 }
 
-pub fn update_map(mut client: tokio_postgres::Client, map: Map) -> impl Future<Item=(tokio_postgres::Client, Option<Map>), Error=AkError> {
+pub fn update_map(mut client: PooledClient, map: Map) -> impl Future<Item=(PooledClient, Option<Map>), Error=AkError> {
     client
         .prepare_typed("
             UPDATE runtime.maps
@@ -210,7 +211,7 @@ pub fn update_map(mut client: tokio_postgres::Client, map: Map) -> impl Future<I
         })
 }
 
-pub fn update_map_blueprint(mut client: tokio_postgres::Client, mid: i32, img: BytesMut) -> impl Future<Item=(tokio_postgres::Client), Error=AkError> {
+pub fn update_map_blueprint(mut client: PooledClient, mid: i32, img: BytesMut) -> impl Future<Item=(PooledClient), Error=AkError> {
     client
         .prepare_typed("
             UPDATE runtime.maps
@@ -239,7 +240,7 @@ pub fn update_map_blueprint(mut client: tokio_postgres::Client, mid: i32, img: B
         })
 }
 
-pub fn delete_map(mut client: tokio_postgres::Client, id: i32) -> impl Future<Item=tokio_postgres::Client, Error=AkError> {
+pub fn delete_map(mut client: PooledClient, id: i32) -> impl Future<Item=PooledClient, Error=AkError> {
     client
         .prepare_typed("
             DELETE FROM runtime.maps
@@ -272,7 +273,7 @@ mod tests {
     #[test]
     fn insert() {
         let mut runtime = Runtime::new().unwrap();
-        runtime.block_on(crate::system::create_db(true)).unwrap();
+        runtime.block_on(crate::system::create_db(crate::db_utils::DEFAULT_CONNECTION, true)).unwrap();
 
         let map = Map::new();
 
@@ -293,7 +294,7 @@ mod tests {
     #[test]
     fn update_blueprint() {
         let mut runtime = Runtime::new().unwrap();
-        runtime.block_on(crate::system::create_db(true)).unwrap();
+        runtime.block_on(crate::system::create_db(crate::db_utils::DEFAULT_CONNECTION, true)).unwrap();
 
         let mut map = Map::new();
         map.name = "map_0".to_string();
@@ -320,7 +321,7 @@ mod tests {
     #[test]
     fn update() {
         let mut runtime = Runtime::new().unwrap();
-        runtime.block_on(crate::system::create_db(true)).unwrap();
+        runtime.block_on(crate::system::create_db(crate::db_utils::DEFAULT_CONNECTION, true)).unwrap();
 
         let mut map = Map::new();
         map.name = "map_0".to_string();
@@ -347,7 +348,7 @@ mod tests {
     #[test]
     fn select() {
         let mut runtime = Runtime::new().unwrap();
-        runtime.block_on(crate::system::create_db(true)).unwrap();
+        runtime.block_on(crate::system::create_db(crate::db_utils::DEFAULT_CONNECTION, true)).unwrap();
 
         let mut map = Map::new();
         map.name = "map_0".to_string();
@@ -371,7 +372,7 @@ mod tests {
     #[test]
     fn select_blueprint() {
         let mut runtime = Runtime::new().unwrap();
-        runtime.block_on(crate::system::create_db(true)).unwrap();
+        runtime.block_on(crate::system::create_db(crate::db_utils::DEFAULT_CONNECTION, true)).unwrap();
 
         let mut map = Map::new();
         map.name = "map_0".to_string();
@@ -408,7 +409,7 @@ mod tests {
     #[test]
     fn select_many() {
         let mut runtime = Runtime::new().unwrap();
-        runtime.block_on(crate::system::create_db(true)).unwrap();
+        runtime.block_on(crate::system::create_db(crate::db_utils::DEFAULT_CONNECTION, true)).unwrap();
 
         let mut map = Map::new();
         map.name = "map_0".to_string();
@@ -432,7 +433,7 @@ mod tests {
     #[test]
     fn delete() {
         let mut runtime = Runtime::new().unwrap();
-        runtime.block_on(crate::system::create_db(true)).unwrap();
+        runtime.block_on(crate::system::create_db(crate::db_utils::DEFAULT_CONNECTION, true)).unwrap();
 
         let mut map = Map::new();
         map.name = "map_0".to_string();
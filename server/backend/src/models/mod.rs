@@ -1,6 +1,15 @@
 
+pub mod account;
 pub mod beacon;
+pub mod beacon_blocklist;
+pub mod emergency;
+pub mod emergency_event;
+pub mod location_history;
 pub mod map;
+pub mod retention;
+pub mod session;
 pub mod system;
 pub mod user;
+pub mod zone;
+pub mod zone_event;
 pub mod network_interface;
@@ -6,6 +6,7 @@ use tokio_postgres::types::Type;
 use ipnet::Ipv4Net;
 use std::net::{ IpAddr, Ipv4Addr, };
 use crate::ak_error::AkError;
+use crate::db_utils::PooledClient;
 
 // NOTE:
 // The rust std type IpAddr does not support subnet masks, but does support postgres
@@ -41,7 +42,7 @@ pub fn row_to_network_interface(row: &Row) -> NetworkInterface {
     obj
 }
 
-pub fn select_network_interfaces(mut client: tokio_postgres::Client) -> impl Future<Item=(tokio_postgres::Client, Vec<NetworkInterface>), Error=AkError> {
+pub fn select_network_interfaces(mut client: PooledClient) -> impl Future<Item=(PooledClient, Vec<NetworkInterface>), Error=AkError> {
     // TODO paging
     client
         .prepare("
@@ -61,7 +62,7 @@ pub fn select_network_interfaces(mut client: tokio_postgres::Client) -> impl Fut
         })
 }
 
-pub fn select_network_interface(mut client: tokio_postgres::Client, id: i32) -> impl Future<Item=(tokio_postgres::Client, Option<NetworkInterface>), Error=AkError> {
+pub fn select_network_interface(mut client: PooledClient, id: i32) -> impl Future<Item=(PooledClient, Option<NetworkInterface>), Error=AkError> {
     client
         .prepare("
             SELECT *
@@ -85,7 +86,7 @@ pub fn select_network_interface(mut client: tokio_postgres::Client, id: i32) ->
         })
 }
 
-pub fn insert_network_interface(mut client: tokio_postgres::Client, iface: NetworkInterface) -> impl Future<Item=(tokio_postgres::Client, Option<NetworkInterface>), Error=AkError> {
+pub fn insert_network_interface(mut client: PooledClient, iface: NetworkInterface) -> impl Future<Item=(PooledClient, Option<NetworkInterface>), Error=AkError> {
     client
         .prepare_typed("
             INSERT INTO system.network_interfaces (
@@ -132,7 +133,7 @@ pub fn insert_network_interface(mut client: tokio_postgres::Client, iface: Netwo
         })
 }
 
-pub fn update_network_interface(mut client: tokio_postgres::Client, iface: NetworkInterface) -> impl Future<Item=(tokio_postgres::Client, Option<NetworkInterface>), Error=AkError> {
+pub fn update_network_interface(mut client: PooledClient, iface: NetworkInterface) -> impl Future<Item=(PooledClient, Option<NetworkInterface>), Error=AkError> {
     client
         .prepare_typed("
             UPDATE system.network_interfaces
@@ -182,7 +183,7 @@ pub fn update_network_interface(mut client: tokio_postgres::Client, iface: Netwo
         })
 }
 
-pub fn delete_network_interface(mut client: tokio_postgres::Client, id: i32) -> impl Future<Item=tokio_postgres::Client, Error=AkError> {
+pub fn delete_network_interface(mut client: PooledClient, id: i32) -> impl Future<Item=PooledClient, Error=AkError> {
     client
         .prepare_typed("
             DELETE FROM system.network_interfaces
@@ -215,7 +216,7 @@ mod tests {
     #[test]
     fn insert() {
         let mut runtime = Runtime::new().unwrap();
-        runtime.block_on(crate::system::create_db(true)).unwrap();
+        runtime.block_on(crate::system::create_db(crate::db_utils::DEFAULT_CONNECTION, true)).unwrap();
 
         let mut iface = NetworkInterface::new();
         iface.name = "hello_test".to_string();
@@ -236,7 +237,7 @@ mod tests {
     #[test]
     fn delete() {
         let mut runtime = Runtime::new().unwrap();
-        runtime.block_on(crate::system::create_db(true)).unwrap();
+        runtime.block_on(crate::system::create_db(crate::db_utils::DEFAULT_CONNECTION, true)).unwrap();
 
         let mut iface = NetworkInterface::new();
         iface.name = "hello_test".to_string();
@@ -260,7 +261,7 @@ mod tests {
     #[test]
     fn update() {
         let mut runtime = Runtime::new().unwrap();
-        runtime.block_on(crate::system::create_db(true)).unwrap();
+        runtime.block_on(crate::system::create_db(crate::db_utils::DEFAULT_CONNECTION, true)).unwrap();
 
         let mut iface = NetworkInterface::new();
         iface.name = "hello_test".to_string();
@@ -288,7 +289,7 @@ mod tests {
     #[test]
     fn select() {
         let mut runtime = Runtime::new().unwrap();
-        runtime.block_on(crate::system::create_db(true)).unwrap();
+        runtime.block_on(crate::system::create_db(crate::db_utils::DEFAULT_CONNECTION, true)).unwrap();
 
         let mut iface = NetworkInterface::new();
         iface.name = "hello_test".to_string();
@@ -312,7 +313,7 @@ mod tests {
 #[test]
     fn select_many() {
         let mut runtime = Runtime::new().unwrap();
-        runtime.block_on(crate::system::create_db(true)).unwrap();
+        runtime.block_on(crate::system::create_db(crate::db_utils::DEFAULT_CONNECTION, true)).unwrap();
 
         let mut iface = NetworkInterface::new();
         iface.name = "hello_test".to_string();
@@ -0,0 +1,106 @@
+// How long location history is kept. There is only ever one row, so that a retention set through
+// the api is still used after a restart, before anything is pruned.
+
+use common::*;
+use futures::{ Stream, Future, IntoFuture, };
+use tokio_postgres::row::Row;
+use tokio_postgres::types::Type;
+use crate::ak_error::AkError;
+use crate::db_utils::PooledClient;
+
+pub fn row_to_retention(row: &Row) -> RetentionConfig {
+    let mut retention = RetentionConfig::new();
+    for (i, column) in row.columns().iter().enumerate() {
+        match column.name() {
+            "r_id" => {},
+            "r_location_history_days" => retention.location_history_days = row.get::<_, i32>(i) as u32,
+            unhandled if unhandled.starts_with("r_") => { panic!("unhandled retention column {}", unhandled); },
+            _ => {},
+        }
+    }
+    retention
+}
+
+// a missing row is treated as the default retention
+pub fn select_retention(mut client: PooledClient) -> impl Future<Item=(PooledClient, RetentionConfig), Error=AkError> {
+    client
+        .prepare("
+            SELECT *
+            FROM system.retention
+            WHERE r_id = 1
+        ")
+        .map_err(AkError::from)
+        .and_then(move |statement| {
+            client
+                .query(&statement, &[])
+                .into_future()
+                .map_err(|(err, _next)| {
+                    AkError::from(err)
+                })
+                .map(|(row, _next)| {
+                    match row {
+                        Some(row) => (client, row_to_retention(&row)),
+                        None => (client, RetentionConfig::new()),
+                    }
+                })
+        })
+}
+
+pub fn update_retention(mut client: PooledClient, retention: RetentionConfig) -> impl Future<Item=PooledClient, Error=AkError> {
+    client
+        .prepare_typed("
+            INSERT INTO system.retention (
+                r_id,
+                r_location_history_days
+            )
+            VALUES( 1, $1 )
+            ON CONFLICT (r_id) DO UPDATE SET
+                r_location_history_days = EXCLUDED.r_location_history_days
+        ", &[
+            Type::INT4,
+        ])
+        .map_err(AkError::from)
+        .and_then(move |statement| {
+            client
+                .execute(&statement, &[
+                    &(retention.location_history_days as i32),
+                ])
+                .map_err(AkError::from)
+                .map(|_count| {
+                    client
+                })
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db_utils;
+    use tokio::runtime::current_thread::Runtime;
+
+    #[test]
+    fn keep_forever() {
+        let mut runtime = Runtime::new().unwrap();
+        runtime.block_on(crate::system::create_db(crate::db_utils::DEFAULT_CONNECTION, true)).unwrap();
+
+        let task = db_utils::default_connect()
+            .and_then(|client| {
+                select_retention(client)
+            })
+            .and_then(|(client, retention)| {
+                assert_eq!(retention.location_history_days, RetentionConfig::new().location_history_days);
+                update_retention(client, RetentionConfig { location_history_days: 0 })
+            })
+            .and_then(|client| {
+                select_retention(client)
+            })
+            .map(|(_client, retention)| {
+                assert_eq!(retention.location_history_days, 0);
+            })
+            .map_err(|e| {
+                println!("db error {:?}", e);
+                panic!("failed to update retention");
+            });
+        runtime.block_on(task).unwrap();
+    }
+}
@@ -0,0 +1,219 @@
+use common::*;
+use futures::{ Stream, Future, };
+use tokio_postgres::types::Type;
+use crate::ak_error::AkError;
+use crate::db_utils::PooledClient;
+use crate::models::account;
+
+pub fn insert_session(mut client: PooledClient, token: String, account_id: i32, created: DateTime<Utc>, expires: DateTime<Utc>) -> impl Future<Item=PooledClient, Error=AkError> {
+    client
+        .prepare_typed("
+            INSERT INTO system.sessions (
+                s_token,
+                s_account_id,
+                s_created,
+                s_expires
+            )
+            VALUES( $1, $2, $3, $4 )
+        ", &[
+            Type::VARCHAR,
+            Type::INT4,
+            Type::TIMESTAMPTZ,
+            Type::TIMESTAMPTZ,
+        ])
+        .map_err(AkError::from)
+        .and_then(move |statement| {
+            client
+                .execute(&statement, &[&token, &account_id, &created, &expires])
+                .map_err(AkError::from)
+                .map(|_count| {
+                    client
+                })
+        })
+}
+
+// returns the account of a session, as long as the session has not expired and the account
+// is still enabled.
+pub fn select_session(mut client: PooledClient, token: String, now: DateTime<Utc>) -> impl Future<Item=(PooledClient, Option<(Account, DateTime<Utc>)>), Error=AkError> {
+    client
+        .prepare_typed("
+            SELECT * FROM system.sessions AS s
+            INNER JOIN system.accounts AS a ON a.a_id = s.s_account_id
+            WHERE
+                s.s_token = $1
+                AND s.s_expires > $2
+                AND NOT a.a_disabled
+        ", &[
+            Type::VARCHAR,
+            Type::TIMESTAMPTZ,
+        ])
+        .map_err(AkError::from)
+        .and_then(move |statement| {
+            client
+                .query(&statement, &[&token, &now])
+                .into_future()
+                .map_err(|(err, _next)| {
+                    AkError::from(err)
+                })
+                .map(|(row, _next)| {
+                    match row {
+                        Some(r) => {
+                            let expires: DateTime<Utc> = r.get("s_expires");
+                            (client, Some((account::row_to_account(&r), expires)))
+                        },
+                        _ => (client, None),
+                    }
+                })
+        })
+}
+
+pub fn delete_session(mut client: PooledClient, token: String) -> impl Future<Item=PooledClient, Error=AkError> {
+    client
+        .prepare_typed("
+            DELETE FROM system.sessions
+            WHERE s_token = $1
+        ", &[
+            Type::VARCHAR,
+        ])
+        .map_err(AkError::from)
+        .and_then(move |statement| {
+            client
+                .execute(&statement, &[&token])
+                .map_err(AkError::from)
+                .map(|_count| {
+                    client
+                })
+        })
+}
+
+// returns the number of deleted sessions
+pub fn delete_account_sessions(mut client: PooledClient, account_id: i32) -> impl Future<Item=(PooledClient, u64), Error=AkError> {
+    client
+        .prepare_typed("
+            DELETE FROM system.sessions
+            WHERE s_account_id = $1
+        ", &[
+            Type::INT4,
+        ])
+        .map_err(AkError::from)
+        .and_then(move |statement| {
+            client
+                .execute(&statement, &[&account_id])
+                .map_err(AkError::from)
+                .map(|count| {
+                    (client, count)
+                })
+        })
+}
+
+// logs out every account, returns the number of deleted sessions
+pub fn delete_all_sessions(mut client: PooledClient) -> impl Future<Item=(PooledClient, u64), Error=AkError> {
+    client
+        .prepare("
+            DELETE FROM system.sessions
+        ")
+        .map_err(AkError::from)
+        .and_then(move |statement| {
+            client
+                .execute(&statement, &[])
+                .map_err(AkError::from)
+                .map(|count| {
+                    (client, count)
+                })
+        })
+}
+
+// returns the number of deleted sessions
+pub fn delete_expired_sessions(mut client: PooledClient, now: DateTime<Utc>) -> impl Future<Item=(PooledClient, u64), Error=AkError> {
+    client
+        .prepare_typed("
+            DELETE FROM system.sessions
+            WHERE s_expires <= $1
+        ", &[
+            Type::TIMESTAMPTZ,
+        ])
+        .map_err(AkError::from)
+        .and_then(move |statement| {
+            client
+                .execute(&statement, &[&now])
+                .map_err(AkError::from)
+                .map(|count| {
+                    (client, count)
+                })
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db_utils;
+    use chrono::Duration;
+    use tokio::runtime::current_thread::Runtime;
+
+    #[test]
+    fn select_valid() {
+        let mut runtime = Runtime::new().unwrap();
+        runtime.block_on(crate::system::create_db(crate::db_utils::DEFAULT_CONNECTION, true)).unwrap();
+        let now = Utc::now();
+
+        let task = db_utils::default_connect()
+            .and_then(|client| {
+                account::insert_account(client, "account_0".to_string(), "hash".to_string(), AccountRole::Responder)
+            })
+            .and_then(move |(client, opt_account)| {
+                let id = opt_account.unwrap().id;
+                insert_session(client, "valid".to_string(), id, now, now + Duration::hours(1))
+                    .and_then(move |client| insert_session(client, "expired".to_string(), id, now - Duration::hours(2), now - Duration::hours(1)))
+            })
+            .and_then(move |client| {
+                select_session(client, "valid".to_string(), now)
+            })
+            .and_then(move |(client, opt_session)| {
+                let (account, _expires) = opt_session.unwrap();
+                assert!(account.name == "account_0");
+                select_session(client, "expired".to_string(), now)
+            })
+            .and_then(move |(client, opt_session)| {
+                assert!(opt_session.is_none());
+                delete_expired_sessions(client, now)
+            })
+            .map(|(_client, count)| {
+                assert!(count == 1);
+            })
+            .map_err(|e| {
+                println!("db error {:?}", e);
+                panic!("failed to select session");
+            });
+        runtime.block_on(task).unwrap();
+    }
+
+    #[test]
+    fn disabled_account() {
+        let mut runtime = Runtime::new().unwrap();
+        runtime.block_on(crate::system::create_db(crate::db_utils::DEFAULT_CONNECTION, true)).unwrap();
+        let now = Utc::now();
+
+        let task = db_utils::default_connect()
+            .and_then(|client| {
+                account::insert_account(client, "account_0".to_string(), "hash".to_string(), AccountRole::Responder)
+            })
+            .and_then(move |(client, opt_account)| {
+                let mut account = opt_account.unwrap();
+                let id = account.id;
+                account.disabled = true;
+                insert_session(client, "token".to_string(), id, now, now + Duration::hours(1))
+                    .and_then(move |client| account::update_account(client, account))
+            })
+            .and_then(move |(client, _opt_account)| {
+                select_session(client, "token".to_string(), now)
+            })
+            .map(|(_client, opt_session)| {
+                assert!(opt_session.is_none());
+            })
+            .map_err(|e| {
+                println!("db error {:?}", e);
+                panic!("failed to reject session of disabled account");
+            });
+        runtime.block_on(task).unwrap();
+    }
+}
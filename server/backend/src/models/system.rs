@@ -1,5 +1,9 @@
+use chrono::Utc;
+use common::AccountRole;
+use crate::auth;
 use tokio_postgres::{ NoTls, error::SqlState, };
-use futures::{ Future, future::err, future::Loop, future::ok, future::Either, future::loop_fn, };
+use tokio_postgres::types::Type;
+use futures::{ Future, future::err, future::join_all, future::Loop, future::ok, future::Either, future::loop_fn, };
 
 fn connect_db(params: &str) -> impl Future<Item=tokio_postgres::Client, Error=tokio_postgres::Error> {
     tokio_postgres::connect(params, NoTls)
@@ -19,7 +23,7 @@ const UNDO_SCHEMA: [&str; 4] = [
     "DROP ROLE ak_admin_role",
 ];
 
-const SCHEMA: [&str; 26] = [
+const SCHEMA: [&str; 43] = [
     "CREATE SCHEMA runtime",
     "CREATE SCHEMA system",
     "CREATE TABLE runtime.maps (
@@ -52,7 +56,12 @@ const SCHEMA: [&str; 26] = [
         b_map_id INTEGER REFERENCES runtime.maps(m_id) ON DELETE SET NULL,
         b_name VARCHAR(255) UNIQUE,
         b_note VARCHAR(1024),
-        b_state INT2 NOT NULL DEFAULT 0
+        b_state INT2 NOT NULL DEFAULT 0,
+        b_key BYTEA,
+        -- the highest counter accepted from a beacon signing with its key
+        b_counter BIGINT,
+        b_previous_key BYTEA,
+        b_key_rotated TIMESTAMPTZ
     );",
     "CREATE TABLE system.network_interfaces (
         n_id SERIAL PRIMARY KEY,
@@ -63,9 +72,94 @@ const SCHEMA: [&str; 26] = [
         n_name VARCHAR(255) UNIQUE,
         n_webserver_port SMALLINT
     )",
+    "CREATE TABLE runtime.location_history (
+        l_id BIGSERIAL PRIMARY KEY,
+        l_beacons INTEGER[] NOT NULL,
+        l_beacon_coordinates DOUBLE PRECISION[] NOT NULL,
+        l_coordinates DOUBLE PRECISION[2] NOT NULL,
+        l_distances DOUBLE PRECISION[] NOT NULL,
+        l_map_id INTEGER REFERENCES runtime.maps(m_id) ON DELETE SET NULL,
+        l_timestamp TIMESTAMPTZ NOT NULL,
+        l_uncertainty DOUBLE PRECISION NOT NULL,
+        l_user_id INTEGER NOT NULL REFERENCES runtime.users(u_id) ON DELETE CASCADE
+    )",
+    "CREATE TABLE system.accounts (
+        a_id SERIAL PRIMARY KEY,
+        a_created TIMESTAMPTZ NOT NULL,
+        a_disabled BOOLEAN NOT NULL DEFAULT FALSE,
+        a_last_login TIMESTAMPTZ,
+        a_name VARCHAR(256) NOT NULL UNIQUE,
+        a_password_hash VARCHAR(256) NOT NULL,
+        a_role INT2 NOT NULL
+    )",
+    "CREATE TABLE system.sessions (
+        s_token VARCHAR(64) PRIMARY KEY,
+        s_account_id INTEGER NOT NULL REFERENCES system.accounts(a_id) ON DELETE CASCADE,
+        s_created TIMESTAMPTZ NOT NULL,
+        s_expires TIMESTAMPTZ NOT NULL
+    )",
+    "CREATE TABLE system.beacon_blocklist (
+        k_mac_address MACADDR8 PRIMARY KEY,
+        k_created TIMESTAMPTZ NOT NULL
+    )",
+    "CREATE TABLE system.emergency (
+        e_id INTEGER PRIMARY KEY CHECK (e_id = 1),
+        e_active BOOLEAN NOT NULL,
+        e_started_at TIMESTAMPTZ,
+        e_started_by VARCHAR(256),
+        e_ended_at TIMESTAMPTZ,
+        e_ended_by VARCHAR(256),
+        e_map_ids INTEGER[]
+    )",
+    "CREATE TABLE system.retention (
+        r_id INTEGER PRIMARY KEY CHECK (r_id = 1),
+        r_location_history_days INTEGER NOT NULL CHECK (r_location_history_days >= 0)
+    )",
+    "CREATE TABLE runtime.emergency_events (
+        v_id SERIAL PRIMARY KEY,
+        v_started_at TIMESTAMPTZ NOT NULL,
+        v_started_by VARCHAR(256),
+        v_start_reason VARCHAR(1024),
+        v_ended_at TIMESTAMPTZ,
+        v_ended_by VARCHAR(256),
+        v_end_reason VARCHAR(1024),
+        v_map_ids INTEGER[]
+    )",
+    "CREATE TABLE runtime.emergency_scope_changes (
+        c_id SERIAL PRIMARY KEY,
+        c_event_id INTEGER NOT NULL REFERENCES runtime.emergency_events(v_id) ON DELETE CASCADE,
+        c_timestamp TIMESTAMPTZ NOT NULL,
+        c_account VARCHAR(256),
+        c_reason VARCHAR(1024),
+        c_started BOOLEAN NOT NULL,
+        c_map_ids INTEGER[]
+    )",
+    "CREATE TABLE runtime.zones (
+        z_id SERIAL PRIMARY KEY,
+        z_dwell_seconds INTEGER CHECK (z_dwell_seconds > 0),
+        z_kind INT2 NOT NULL,
+        z_map_id INTEGER NOT NULL REFERENCES runtime.maps(m_id) ON DELETE CASCADE,
+        z_name VARCHAR(256) NOT NULL,
+        z_polygon DOUBLE PRECISION[] NOT NULL
+    )",
+    "CREATE TABLE runtime.zone_events (
+        o_id BIGSERIAL PRIMARY KEY,
+        o_kind INT2 NOT NULL,
+        o_map_id INTEGER REFERENCES runtime.maps(m_id) ON DELETE SET NULL,
+        o_timestamp TIMESTAMPTZ NOT NULL,
+        o_user_id INTEGER NOT NULL REFERENCES runtime.users(u_id) ON DELETE CASCADE,
+        o_zone_id INTEGER REFERENCES runtime.zones(z_id) ON DELETE SET NULL,
+        o_zone_kind INT2 NOT NULL,
+        o_zone_name VARCHAR(256) NOT NULL
+    )",
 
     // indices
     "CREATE UNIQUE INDEX mac_address_idx ON runtime.beacons (b_mac_address)",
+    "CREATE INDEX location_history_user_idx ON runtime.location_history (l_user_id, l_timestamp)",
+    "CREATE INDEX location_history_map_idx ON runtime.location_history (l_map_id, l_timestamp, l_id)",
+    "CREATE INDEX emergency_events_started_idx ON runtime.emergency_events (v_started_at)",
+    "CREATE INDEX zones_map_idx ON runtime.zones (z_map_id)",
+    "CREATE INDEX zone_events_timestamp_idx ON runtime.zone_events (o_timestamp)",
 
     // create users
     "CREATE USER admin WITH PASSWORD 'admin' SYSID 1",
@@ -93,6 +187,8 @@ const SCHEMA: [&str; 26] = [
     "INSERT INTO system.network_interfaces(n_mac, n_beacon_port, n_webserver_port, n_mask, n_ip, n_name)
             VALUES('00:00:00:00:00:00', 9996, 8080, 24, '10.0.0.4', 'localhost')
     ",
+    "INSERT INTO system.emergency(e_id, e_active) VALUES(1, FALSE)",
+    "INSERT INTO system.retention(r_id, r_location_history_days) VALUES(1, 30)",
 ];
 
 
@@ -120,11 +216,61 @@ const DEMO_DATA: [&str; 7] = [
     ",
 ];
 
-fn ensure_ak() -> impl Future<Item=(), Error=tokio_postgres::Error> {
-    tokio_postgres::connect("dbname=ak host=localhost password=postgres user=postgres", NoTls)
-        .then(|res| {
+// The admin is given a random password when the database is rebuilt, it is printed once and should
+// be changed through the accounts api after the first login. The demo data adds a responder whose
+// password is its name.
+const ADMIN_ACCOUNT: &str = "admin";
+const DEMO_ACCOUNTS: [(&str, AccountRole); 1] = [
+    ("responder", AccountRole::Responder),
+];
+
+fn insert_default_accounts(mut client: tokio_postgres::Client, demo_data: bool) -> impl Future<Item=tokio_postgres::Client, Error=tokio_postgres::Error> {
+    let admin_password = auth::new_password();
+    println!("created account {} with password {}", ADMIN_ACCOUNT, admin_password);
+    let mut accounts = vec![(ADMIN_ACCOUNT.to_string(), admin_password, AccountRole::Admin)];
+    if demo_data {
+        accounts.extend(DEMO_ACCOUNTS.iter().map(|(name, role)| (name.to_string(), name.to_string(), *role)));
+    }
+
+    client
+        .prepare_typed("
+            INSERT INTO system.accounts(a_created, a_name, a_password_hash, a_role)
+            VALUES( $1, $2, $3, $4 )
+        ", &[
+            Type::TIMESTAMPTZ,
+            Type::VARCHAR,
+            Type::VARCHAR,
+            Type::INT2,
+        ])
+        .and_then(move |statement| {
+            let inserts: Vec<_> = accounts
+                .iter()
+                .map(|(name, password, role)| {
+                    auth::validate_password(password).expect("default password is too short");
+                    let hash = auth::hash_password(password).expect("failed to hash default password");
+                    client.execute(&statement, &[&Utc::now(), name, &hash, &i16::from(*role)])
+                })
+                .collect();
+            join_all(inserts)
+                .map(move |_counts| client)
+        })
+}
+
+// the same login without the database, so the ak database can be dropped and created from it
+fn without_dbname(connection: &str) -> String {
+    connection
+        .split_whitespace()
+        .filter(|pair| !pair.starts_with("dbname="))
+        .collect::<Vec<&str>>()
+        .join(" ")
+}
+
+fn ensure_ak(connection: &str) -> impl Future<Item=(), Error=tokio_postgres::Error> {
+    let default_connection = without_dbname(connection);
+    tokio_postgres::connect(connection, NoTls)
+        .then(move |res| {
             // make a connection to the default database to create the ak db
-            let connect = connect_db("host=localhost password=postgres user=postgres");
+            let connect = connect_db(&default_connection);
 
             // check if the ak connection was successful, and delete ak if necessary
             match &res {
@@ -218,11 +364,14 @@ fn loop_db_commands(client: tokio_postgres::Client, commands: Vec<&str>, ignore_
     })
 }
 
-pub fn create_db(demo_data: bool) -> impl Future<Item=(), Error=()> {
+// connection must log in to the ak database as a user that can create it, the schema grants the
+// admin and responder logins access to ak.
+pub fn create_db(connection: &str, demo_data: bool) -> impl Future<Item=(), Error=()> {
     println!("creating db");
-    ensure_ak()
-        .and_then(|_| {
-            connect_db("dbname=ak host=localhost password=postgres user=postgres")
+    let connection = connection.to_string();
+    ensure_ak(&connection)
+        .and_then(move |_| {
+            connect_db(&connection)
         })
         .and_then(|client| {
             loop_db_commands(client, UNDO_SCHEMA.to_vec(), true)
@@ -230,6 +379,9 @@ pub fn create_db(demo_data: bool) -> impl Future<Item=(), Error=()> {
         .and_then(|client| {
             loop_db_commands(client, SCHEMA.to_vec(), false)
         })
+        .and_then(move |client| {
+            insert_default_accounts(client, demo_data)
+        })
         .and_then(move |client| {
             if demo_data {
                 Either::A(loop_db_commands(client, DEMO_DATA.to_vec(), false))
@@ -4,8 +4,9 @@ use na;
 use tokio_postgres::row::Row;
 use tokio_postgres::types::Type;
 use crate::ak_error::AkError;
+use crate::db_utils::PooledClient;
 
-fn row_to_user(row: &Row) -> TrackedUser {
+pub fn row_to_user(row: &Row) -> TrackedUser {
     let mut entry = TrackedUser::new();
     for (i, column) in row.columns().iter().enumerate() {
         match column.name() {
@@ -33,7 +34,7 @@ fn row_to_user(row: &Row) -> TrackedUser {
     entry
 }
 
-pub fn select_users(mut client: tokio_postgres::Client, include_contacts: bool) -> impl Future<Item=(tokio_postgres::Client, Vec<TrackedUser>), Error=AkError> {
+pub fn select_users(mut client: PooledClient, include_contacts: bool) -> impl Future<Item=(PooledClient, Vec<TrackedUser>), Error=AkError> {
     // TODO paging
     let query = if include_contacts {
         "SELECT * FROM runtime.users"
@@ -59,7 +60,7 @@ pub fn select_users(mut client: tokio_postgres::Client, include_contacts: bool)
         })
 }
 
-pub fn select_user(mut client: tokio_postgres::Client, id: i32) -> impl Future<Item=(tokio_postgres::Client, Option<TrackedUser>, Option<TrackedUser>), Error=AkError> {
+pub fn select_user(mut client: PooledClient, id: i32) -> impl Future<Item=(PooledClient, Option<TrackedUser>, Option<TrackedUser>), Error=AkError> {
     client
         .prepare("
             SELECT * FROM runtime.users
@@ -82,7 +83,7 @@ pub fn select_user(mut client: tokio_postgres::Client, id: i32) -> impl Future<I
         })
 }
 
-pub fn select_user_by_short(mut client: tokio_postgres::Client, id: ShortAddress) -> impl Future<Item=(tokio_postgres::Client, Option<TrackedUser>), Error=AkError> {
+pub fn select_user_by_short(mut client: PooledClient, id: ShortAddress) -> impl Future<Item=(PooledClient, Option<TrackedUser>), Error=AkError> {
     client
         .prepare("
             SELECT * FROM runtime.users
@@ -105,7 +106,7 @@ pub fn select_user_by_short(mut client: tokio_postgres::Client, id: ShortAddress
         })
 }
 
-pub fn select_by_attached_user(mut client: tokio_postgres::Client, id: i32) -> impl Future<Item=(tokio_postgres::Client, Option<TrackedUser>), Error=AkError> {
+pub fn select_by_attached_user(mut client: PooledClient, id: i32) -> impl Future<Item=(PooledClient, Option<TrackedUser>), Error=AkError> {
     client
         .prepare("
             SELECT * FROM runtime.users
@@ -128,7 +129,7 @@ pub fn select_by_attached_user(mut client: tokio_postgres::Client, id: i32) -> i
         })
 }
 
-pub fn select_user_prefetch(client: tokio_postgres::Client, id: i32) -> impl Future<Item=(tokio_postgres::Client, Option<TrackedUser>, Option<TrackedUser>), Error=AkError> {
+pub fn select_user_prefetch(client: PooledClient, id: i32) -> impl Future<Item=(PooledClient, Option<TrackedUser>, Option<TrackedUser>), Error=AkError> {
     select_user(client, id)
         .and_then(move |(client, opt_user, _)| {
             select_by_attached_user(client, id)
@@ -138,33 +139,28 @@ pub fn select_user_prefetch(client: tokio_postgres::Client, id: i32) -> impl Fut
         })
 }
 
-pub fn select_user_random(mut client: tokio_postgres::Client) -> impl Future<Item=(tokio_postgres::Client, Option<TrackedUser>), Error=AkError> {
+// users that can be located, ie for the simulated beacons to range
+pub fn select_tagged_users(mut client: PooledClient) -> impl Future<Item=(PooledClient, Vec<TrackedUser>), Error=AkError> {
     client
         .prepare("
             SELECT *
             FROM runtime.users
             WHERE u_mac_address IS NOT NULL
-            ORDER BY random()
-            LIMIT 1
         ")
         .map_err(AkError::from)
         .and_then(move |statement| {
             client
                 .query(&statement, &[])
+                .collect()
                 .into_future()
-                .map_err(|(err, _next)| {
-                    AkError::from(err)
-                })
-                .map(|(row, _next)| {
-                    match row {
-                        Some(r) => (client, Some(row_to_user(&r))),
-                        _ => (client, None),
-                    }
+                .map_err(AkError::from)
+                .map(|rows| {
+                    (client, rows.into_iter().map(|row| row_to_user(&row)).collect())
                 })
         })
 }
 
-pub fn insert_user(mut client: tokio_postgres::Client, user: TrackedUser) -> impl Future<Item=(tokio_postgres::Client, Option<TrackedUser>), Error=AkError> {
+pub fn insert_user(mut client: PooledClient, user: TrackedUser) -> impl Future<Item=(PooledClient, Option<TrackedUser>), Error=AkError> {
     client
         .prepare_typed("
             INSERT INTO runtime.users (
@@ -222,7 +218,7 @@ pub fn insert_user(mut client: tokio_postgres::Client, user: TrackedUser) -> imp
         })
 }
 
-pub fn update_user(mut client: tokio_postgres::Client, user: TrackedUser) -> impl Future<Item=(tokio_postgres::Client, Option<TrackedUser>), Error=AkError> {
+pub fn update_user(mut client: PooledClient, user: TrackedUser) -> impl Future<Item=(PooledClient, Option<TrackedUser>), Error=AkError> {
     client
         .prepare_typed("
             UPDATE runtime.users
@@ -283,7 +279,7 @@ pub fn update_user(mut client: tokio_postgres::Client, user: TrackedUser) -> imp
         })
 }
 
-pub fn update_user_from_realtime(mut client: tokio_postgres::Client, realtime: RealtimeUserData) -> impl Future<Item=(tokio_postgres::Client, Option<TrackedUser>), Error=AkError> {
+pub fn update_user_from_realtime(mut client: PooledClient, realtime: RealtimeUserData) -> impl Future<Item=(PooledClient, Option<TrackedUser>), Error=AkError> {
     client
         .prepare_typed("
             UPDATE runtime.users
@@ -323,7 +319,7 @@ pub fn update_user_from_realtime(mut client: tokio_postgres::Client, realtime: R
         })
 }
 
-pub fn delete_user(mut client: tokio_postgres::Client, id: i32) -> impl Future<Item=tokio_postgres::Client, Error=AkError> {
+pub fn delete_user(mut client: PooledClient, id: i32) -> impl Future<Item=PooledClient, Error=AkError> {
     client
         .prepare_typed("
             DELETE FROM runtime.users
@@ -356,7 +352,7 @@ mod tests {
     #[test]
     fn insert() {
         let mut runtime = Runtime::new().unwrap();
-        runtime.block_on(crate::system::create_db(true)).unwrap();
+        runtime.block_on(crate::system::create_db(crate::db_utils::DEFAULT_CONNECTION, true)).unwrap();
 
         let user = TrackedUser::new();
 
@@ -378,7 +374,7 @@ mod tests {
     #[test]
     fn update() {
         let mut runtime = Runtime::new().unwrap();
-        runtime.block_on(crate::system::create_db(true)).unwrap();
+        runtime.block_on(crate::system::create_db(crate::db_utils::DEFAULT_CONNECTION, true)).unwrap();
 
         let mut user = TrackedUser::new();
         user.name = "user_0".to_string();
@@ -405,7 +401,7 @@ mod tests {
     #[test]
     fn update_from_realtime() {
         let mut runtime = Runtime::new().unwrap();
-        runtime.block_on(crate::system::create_db(true)).unwrap();
+        runtime.block_on(crate::system::create_db(crate::db_utils::DEFAULT_CONNECTION, true)).unwrap();
 
         let mut user = TrackedUser::new();
         user.name = "user_0".to_string();
@@ -433,7 +429,7 @@ mod tests {
     #[test]
     fn select() {
         let mut runtime = Runtime::new().unwrap();
-        runtime.block_on(crate::system::create_db(true)).unwrap();
+        runtime.block_on(crate::system::create_db(crate::db_utils::DEFAULT_CONNECTION, true)).unwrap();
 
         let mut user = TrackedUser::new();
         user.name = "user_0".to_string();
@@ -457,7 +453,7 @@ mod tests {
     #[test]
     fn select_by_short() {
         let mut runtime = Runtime::new().unwrap();
-        runtime.block_on(crate::system::create_db(true)).unwrap();
+        runtime.block_on(crate::system::create_db(crate::db_utils::DEFAULT_CONNECTION, true)).unwrap();
 
         let mut user = TrackedUser::new();
         user.name = "user_0".to_string();
@@ -482,7 +478,7 @@ mod tests {
     #[test]
     fn select_prefetch() {
         let mut runtime = Runtime::new().unwrap();
-        runtime.block_on(crate::system::create_db(true)).unwrap();
+        runtime.block_on(crate::system::create_db(crate::db_utils::DEFAULT_CONNECTION, true)).unwrap();
 
         let mut user = TrackedUser::new();
         user.name = "user_0".to_string();
@@ -513,7 +509,7 @@ mod tests {
     #[test]
     fn select_attached_user() {
         let mut runtime = Runtime::new().unwrap();
-        runtime.block_on(crate::system::create_db(true)).unwrap();
+        runtime.block_on(crate::system::create_db(crate::db_utils::DEFAULT_CONNECTION, true)).unwrap();
 
         let mut user = TrackedUser::new();
         user.name = "user_0".to_string();
@@ -548,26 +544,33 @@ mod tests {
     }
 
     #[test]
-    fn select_random() {
+    fn select_tagged() {
         let mut runtime = Runtime::new().unwrap();
-        runtime.block_on(crate::system::create_db(true)).unwrap();
+        runtime.block_on(crate::system::create_db(crate::db_utils::DEFAULT_CONNECTION, true)).unwrap();
 
         let mut user = TrackedUser::new();
         user.name = "user_0".to_string();
+        let mut tagged = TrackedUser::new();
+        tagged.name = "user_1".to_string();
+        tagged.mac_address = Some(ShortAddress::new([0, 1]));
 
         let task = db_utils::default_connect()
             .and_then(|client| {
                 insert_user(client, user)
             })
             .and_then(|(client, _opt_user)| {
-                select_user_random(client)
+                insert_user(client, tagged)
             })
-            .map(|(_client, opt_user)| {
-                assert!(opt_user.is_some());
+            .and_then(|(client, _opt_user)| {
+                select_tagged_users(client)
+            })
+            .map(|(_client, users)| {
+                assert_eq!(users.len(), 1);
+                assert_eq!(users[0].name, "user_1");
             })
             .map_err(|e| {
                 println!("db error {:?}", e);
-                panic!("failed to insert user");
+                panic!("failed to select tagged users");
             });
         runtime.block_on(task).unwrap();
     }
@@ -575,7 +578,7 @@ mod tests {
     #[test]
     fn select_many() {
         let mut runtime = Runtime::new().unwrap();
-        runtime.block_on(crate::system::create_db(true)).unwrap();
+        runtime.block_on(crate::system::create_db(crate::db_utils::DEFAULT_CONNECTION, true)).unwrap();
 
         let mut user = TrackedUser::new();
         user.name = "user_0".to_string();
@@ -599,7 +602,7 @@ mod tests {
     #[test]
     fn select_many_include_contacts() {
         let mut runtime = Runtime::new().unwrap();
-        runtime.block_on(crate::system::create_db(true)).unwrap();
+        runtime.block_on(crate::system::create_db(crate::db_utils::DEFAULT_CONNECTION, true)).unwrap();
 
         let mut user = TrackedUser::new();
         user.name = "user_0".to_string();
@@ -637,7 +640,7 @@ mod tests {
     #[test]
     fn delete() {
         let mut runtime = Runtime::new().unwrap();
-        runtime.block_on(crate::system::create_db(true)).unwrap();
+        runtime.block_on(crate::system::create_db(crate::db_utils::DEFAULT_CONNECTION, true)).unwrap();
 
         let mut user = TrackedUser::new();
         user.name = "user_0".to_string();
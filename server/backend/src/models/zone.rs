@@ -0,0 +1,257 @@
+use common::zones::{ Zone, ZoneKind, };
+use futures::{ Stream, Future, IntoFuture, };
+use na;
+use tokio_postgres::row::Row;
+use tokio_postgres::types::Type;
+use crate::ak_error::AkError;
+use crate::db_utils::PooledClient;
+
+// polygons are stored flattened, x0, y0, x1, y1, ...
+fn flatten_polygon(polygon: &Vec<na::Vector2<f64>>) -> Vec<f64> {
+    polygon.iter().flat_map(|point| vec![point.x, point.y]).collect()
+}
+
+pub fn row_to_zone(row: &Row) -> Zone {
+    let mut entry = Zone::new();
+    for (i, column) in row.columns().iter().enumerate() {
+        match column.name() {
+            "z_id" => entry.id = row.get(i),
+            "z_dwell_seconds" => entry.dwell_seconds = row.get(i),
+            "z_kind" => entry.kind = ZoneKind::from(row.get::<usize, i16>(i)),
+            "z_map_id" => entry.map_id = row.get(i),
+            "z_name" => entry.name = row.get(i),
+            "z_polygon" => {
+                let points: Vec<f64> = row.get(i);
+                entry.polygon = points
+                    .chunks(2)
+                    .filter(|point| point.len() == 2)
+                    .map(|point| na::Vector2::new(point[0], point[1]))
+                    .collect();
+            },
+            unhandled if unhandled.starts_with("z_") => { panic!("unhandled zone column {}", unhandled); },
+            _ => {},
+        }
+    }
+    entry
+}
+
+pub fn select_zones(mut client: PooledClient) -> impl Future<Item=(PooledClient, Vec<Zone>), Error=AkError> {
+    client
+        .prepare("
+            SELECT * FROM runtime.zones
+            ORDER BY z_id
+        ")
+        .map_err(AkError::from)
+        .and_then(move |statement| {
+            client
+                .query(&statement, &[])
+                .collect()
+                .into_future()
+                .map_err(AkError::from)
+                .map(|rows| {
+                    (client, rows.into_iter().map(|row| row_to_zone(&row)).collect())
+                })
+        })
+}
+
+pub fn select_zones_for_map(mut client: PooledClient, map_id: i32) -> impl Future<Item=(PooledClient, Vec<Zone>), Error=AkError> {
+    client
+        .prepare_typed("
+            SELECT * FROM runtime.zones
+            WHERE z_map_id = $1
+            ORDER BY z_id
+        ", &[
+            Type::INT4,
+        ])
+        .map_err(AkError::from)
+        .and_then(move |statement| {
+            client
+                .query(&statement, &[&map_id])
+                .collect()
+                .into_future()
+                .map_err(AkError::from)
+                .map(|rows| {
+                    (client, rows.into_iter().map(|row| row_to_zone(&row)).collect())
+                })
+        })
+}
+
+pub fn insert_zone(mut client: PooledClient, zone: Zone) -> impl Future<Item=(PooledClient, Option<Zone>), Error=AkError> {
+    client
+        .prepare_typed("
+            INSERT INTO runtime.zones (
+                z_dwell_seconds,
+                z_kind,
+                z_map_id,
+                z_name,
+                z_polygon
+            )
+            VALUES( $1, $2, $3, $4, $5 )
+            RETURNING *
+        ", &[
+            Type::INT4,
+            Type::INT2,
+            Type::INT4,
+            Type::VARCHAR,
+            Type::FLOAT8_ARRAY,
+        ])
+        .map_err(AkError::from)
+        .and_then(move |statement| {
+            let polygon = flatten_polygon(&zone.polygon);
+            client
+                .query(&statement, &[
+                    &zone.dwell_seconds,
+                    &i16::from(zone.kind),
+                    &zone.map_id,
+                    &zone.name,
+                    &polygon,
+                ])
+                .into_future()
+                .map_err(|(err, _next)| {
+                    AkError::from(err)
+                })
+                .map(|(row, _next)| {
+                    match row {
+                        Some(r) => (client, Some(row_to_zone(&r))),
+                        _ => (client, None),
+                    }
+                })
+        })
+}
+
+pub fn update_zone(mut client: PooledClient, zone: Zone) -> impl Future<Item=(PooledClient, Option<Zone>), Error=AkError> {
+    client
+        .prepare_typed("
+            UPDATE runtime.zones
+            SET
+                z_dwell_seconds = $1,
+                z_kind = $2,
+                z_map_id = $3,
+                z_name = $4,
+                z_polygon = $5
+            WHERE
+                z_id = $6
+            RETURNING *
+        ", &[
+            Type::INT4,
+            Type::INT2,
+            Type::INT4,
+            Type::VARCHAR,
+            Type::FLOAT8_ARRAY,
+            Type::INT4,
+        ])
+        .map_err(AkError::from)
+        .and_then(move |statement| {
+            let polygon = flatten_polygon(&zone.polygon);
+            client
+                .query(&statement, &[
+                    &zone.dwell_seconds,
+                    &i16::from(zone.kind),
+                    &zone.map_id,
+                    &zone.name,
+                    &polygon,
+                    &zone.id,
+                ])
+                .into_future()
+                .map_err(|(err, _next)| {
+                    AkError::from(err)
+                })
+                .map(|(row, _next)| {
+                    match row {
+                        Some(r) => (client, Some(row_to_zone(&r))),
+                        _ => (client, None),
+                    }
+                })
+        })
+}
+
+pub fn delete_zone(mut client: PooledClient, id: i32) -> impl Future<Item=PooledClient, Error=AkError> {
+    client
+        .prepare_typed("
+            DELETE FROM runtime.zones
+            WHERE z_id = $1
+        ", &[
+            Type::INT4,
+        ])
+        .map_err(AkError::from)
+        .and_then(move |statement| {
+            client
+                .execute(&statement, &[&id])
+                .map_err(AkError::from)
+                .map(|_count| {
+                    client
+                })
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::Map;
+    use crate::db_utils;
+    use crate::models::map;
+    use tokio::runtime::current_thread::Runtime;
+
+    fn triangle(map_id: i32) -> Zone {
+        let mut zone = Zone::new();
+        zone.map_id = map_id;
+        zone.name = "loading dock".to_string();
+        zone.kind = ZoneKind::Hazard;
+        zone.dwell_seconds = Some(120);
+        zone.polygon = vec![
+            na::Vector2::new(0.0, 0.0),
+            na::Vector2::new(3.0, 0.0),
+            na::Vector2::new(0.0, 2.5),
+        ];
+        zone
+    }
+
+    #[test]
+    fn insert_update_delete() {
+        let mut runtime = Runtime::new().unwrap();
+        runtime.block_on(crate::system::create_db(crate::db_utils::DEFAULT_CONNECTION, true)).unwrap();
+
+        let mut new_map = Map::new();
+        new_map.name = "map_0".to_string();
+
+        let task = db_utils::default_connect()
+            .and_then(|client| {
+                map::insert_map(client, new_map)
+            })
+            .and_then(|(client, opt_map)| {
+                insert_zone(client, triangle(opt_map.unwrap().id))
+            })
+            .and_then(|(client, opt_zone)| {
+                let mut zone = opt_zone.unwrap();
+                assert_eq!(zone.polygon.len(), 3);
+                assert_eq!(zone.polygon[2], na::Vector2::new(0.0, 2.5));
+                assert_eq!(zone.kind, ZoneKind::Hazard);
+                zone.kind = ZoneKind::MusterPoint;
+                zone.dwell_seconds = None;
+                zone.polygon.push(na::Vector2::new(-1.0, 1.0));
+                update_zone(client, zone)
+            })
+            .and_then(|(client, opt_zone)| {
+                let zone = opt_zone.unwrap();
+                select_zones_for_map(client, zone.map_id)
+            })
+            .and_then(|(client, zones)| {
+                assert_eq!(zones.len(), 1);
+                assert_eq!(zones[0].kind, ZoneKind::MusterPoint);
+                assert_eq!(zones[0].dwell_seconds, None);
+                assert_eq!(zones[0].polygon.len(), 4);
+                delete_zone(client, zones[0].id)
+            })
+            .and_then(|client| {
+                select_zones(client)
+            })
+            .map(|(_client, zones)| {
+                assert!(zones.is_empty());
+            })
+            .map_err(|e| {
+                println!("db error {:?}", e);
+                panic!("failed to update zone");
+            });
+        runtime.block_on(task).unwrap();
+    }
+}
@@ -0,0 +1,157 @@
+// Users entering, leaving, or staying too long in a zone, kept for the incident log.
+
+use common::*;
+use common::zones::{ ZoneEvent, ZoneEventKind, ZoneKind, };
+use futures::{ Stream, Future, IntoFuture, };
+use tokio_postgres::row::Row;
+use tokio_postgres::types::Type;
+use crate::ak_error::AkError;
+use crate::db_utils::PooledClient;
+
+pub fn row_to_zone_event(row: &Row) -> ZoneEvent {
+    let mut entry = ZoneEvent::new();
+    for (i, column) in row.columns().iter().enumerate() {
+        match column.name() {
+            "o_id" => entry.id = row.get(i),
+            "o_kind" => entry.kind = ZoneEventKind::from(row.get::<usize, i16>(i)),
+            "o_map_id" => entry.map_id = row.get(i),
+            "o_timestamp" => entry.timestamp = row.get(i),
+            "o_user_id" => entry.user_id = row.get(i),
+            "o_zone_id" => entry.zone_id = row.get(i),
+            "o_zone_kind" => entry.zone_kind = ZoneKind::from(row.get::<usize, i16>(i)),
+            "o_zone_name" => entry.zone_name = row.get(i),
+            "u_name" => entry.user_name = row.get::<usize, Option<String>>(i).unwrap_or(String::new()),
+            unhandled if unhandled.starts_with("o_") => { panic!("unhandled zone event column {}", unhandled); },
+            _ => {},
+        }
+    }
+    entry
+}
+
+pub fn insert_zone_event(mut client: PooledClient, event: ZoneEvent) -> impl Future<Item=(PooledClient, Option<ZoneEvent>), Error=AkError> {
+    client
+        .prepare_typed("
+            INSERT INTO runtime.zone_events (
+                o_kind,
+                o_map_id,
+                o_timestamp,
+                o_user_id,
+                o_zone_id,
+                o_zone_kind,
+                o_zone_name
+            )
+            VALUES( $1, $2, $3, $4, $5, $6, $7 )
+            RETURNING *
+        ", &[
+            Type::INT2,
+            Type::INT4,
+            Type::TIMESTAMPTZ,
+            Type::INT4,
+            Type::INT4,
+            Type::INT2,
+            Type::VARCHAR,
+        ])
+        .map_err(AkError::from)
+        .and_then(move |statement| {
+            client
+                .query(&statement, &[
+                    &i16::from(event.kind),
+                    &event.map_id,
+                    &event.timestamp,
+                    &event.user_id,
+                    &event.zone_id,
+                    &i16::from(event.zone_kind),
+                    &event.zone_name,
+                ])
+                .into_future()
+                .map_err(|(err, _next)| {
+                    AkError::from(err)
+                })
+                .map(move |(row, _next)| {
+                    match row {
+                        Some(r) => {
+                            let mut inserted = row_to_zone_event(&r);
+                            inserted.user_name = event.user_name;
+                            (client, Some(inserted))
+                        },
+                        _ => (client, None),
+                    }
+                })
+        })
+}
+
+// the most recent events first
+pub fn select_zone_events(mut client: PooledClient, from: DateTime<Utc>, to: DateTime<Utc>) -> impl Future<Item=(PooledClient, Vec<ZoneEvent>), Error=AkError> {
+    client
+        .prepare_typed("
+            SELECT runtime.zone_events.*, u_name
+            FROM runtime.zone_events
+            INNER JOIN runtime.users ON u_id = o_user_id
+            WHERE
+                o_timestamp >= $1
+                AND o_timestamp <= $2
+            ORDER BY o_timestamp DESC
+        ", &[
+            Type::TIMESTAMPTZ,
+            Type::TIMESTAMPTZ,
+        ])
+        .map_err(AkError::from)
+        .and_then(move |statement| {
+            client
+                .query(&statement, &[&from, &to])
+                .collect()
+                .into_future()
+                .map_err(AkError::from)
+                .map(|rows| {
+                    (client, rows.into_iter().map(|row| row_to_zone_event(&row)).collect())
+                })
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db_utils;
+    use crate::models::user;
+    use chrono::Duration;
+    use tokio::runtime::current_thread::Runtime;
+
+    #[test]
+    fn insert_select() {
+        let mut runtime = Runtime::new().unwrap();
+        runtime.block_on(crate::system::create_db(crate::db_utils::DEFAULT_CONNECTION, true)).unwrap();
+
+        let mut tracked = TrackedUser::new();
+        tracked.name = "user_0".to_string();
+        let now = Utc::now();
+
+        let task = db_utils::default_connect()
+            .and_then(|client| {
+                user::insert_user(client, tracked)
+            })
+            .and_then(move |(client, opt_user)| {
+                let mut event = ZoneEvent::new();
+                event.user_id = opt_user.unwrap().id;
+                event.zone_name = "boiler room".to_string();
+                event.zone_kind = ZoneKind::Restricted;
+                event.timestamp = now;
+                let mut old = event.clone();
+                old.timestamp = now - Duration::hours(2);
+                insert_zone_event(client, old)
+                    .and_then(move |(client, _)| insert_zone_event(client, event))
+                    .and_then(move |(client, _)| select_zone_events(client, now - Duration::hours(1), now + Duration::hours(1)))
+            })
+            .map(|(_client, events)| {
+                assert_eq!(events.len(), 1);
+                assert_eq!(events[0].user_name, "user_0");
+                assert_eq!(events[0].zone_name, "boiler room");
+                assert_eq!(events[0].zone_kind, ZoneKind::Restricted);
+                assert_eq!(events[0].kind, ZoneEventKind::Enter);
+            })
+            .map_err(|e| {
+                println!("db error {:?}", e);
+                panic!("failed to log zone events");
+            });
+        runtime.block_on(task).unwrap();
+    }
+}
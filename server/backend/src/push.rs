@@ -0,0 +1,345 @@
+// Pushes changes from the beacon manager and data processor to the frontend over websockets,
+// so that pages do not need to poll the REST endpoints. Every websocket is a PushSession, which
+// registers itself with the PushBroadcaster. The manager and processor publish to the
+// broadcaster, which forwards each message to the sessions subscribed to it.
+
+use actix::prelude::*;
+use actix_web_actors::ws;
+use common::*;
+use std::collections::BTreeMap;
+use std::time::{ Duration, Instant, };
+
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(15);
+
+struct Subscriber {
+    recipient: Recipient<PushText>,
+    subscription: PushSubscription,
+}
+
+pub struct PushBroadcaster {
+    next_id: usize,
+    subscribers: BTreeMap<usize, Subscriber>,
+    // the map each user was last pushed on, so the map they leave hears about it
+    user_maps: BTreeMap<i32, Option<i32>>,
+}
+
+impl PushBroadcaster {
+    pub fn new() -> PushBroadcaster {
+        PushBroadcaster {
+            next_id: 0,
+            subscribers: BTreeMap::new(),
+            user_maps: BTreeMap::new(),
+        }
+    }
+}
+
+impl Actor for PushBroadcaster {
+    type Context = Context<Self>;
+}
+
+// a serialized push message, ready to be written to a websocket
+#[derive(Message)]
+pub struct PushText(pub String);
+
+pub struct Connect(pub Recipient<PushText>);
+
+impl Message for Connect {
+    type Result = usize;
+}
+
+impl Handler<Connect> for PushBroadcaster {
+    type Result = usize;
+
+    fn handle(&mut self, msg: Connect, _context: &mut Context<Self>) -> Self::Result {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.subscribers.insert(id, Subscriber {
+            recipient: msg.0,
+            // nothing is pushed until the client says what it is interested in
+            subscription: PushSubscription {
+                diagnostics: false,
+                map_ids: Some(Vec::new()),
+            },
+        });
+        id
+    }
+}
+
+#[derive(Message)]
+pub struct Disconnect(pub usize);
+
+impl Handler<Disconnect> for PushBroadcaster {
+    type Result = ();
+
+    fn handle(&mut self, msg: Disconnect, _context: &mut Context<Self>) {
+        self.subscribers.remove(&msg.0);
+    }
+}
+
+#[derive(Message)]
+pub struct Subscribe(pub usize, pub PushSubscription);
+
+impl Handler<Subscribe> for PushBroadcaster {
+    type Result = ();
+
+    fn handle(&mut self, msg: Subscribe, _context: &mut Context<Self>) {
+        if let Some(subscriber) = self.subscribers.get_mut(&msg.0) {
+            subscriber.subscription = msg.1;
+        }
+    }
+}
+
+#[derive(Message)]
+pub struct Publish(pub PushMessage);
+
+impl Handler<Publish> for PushBroadcaster {
+    type Result = ();
+
+    fn handle(&mut self, msg: Publish, _context: &mut Context<Self>) {
+        // a user moving off a map is also sent to the subscribers of that map, so they can remove it
+        let left_map = match &msg.0 {
+            PushMessage::UserMoved(user) => {
+                match self.user_maps.insert(user.id, user.map_id) {
+                    Some(previous) if previous != user.map_id => previous,
+                    _ => None,
+                }
+            },
+            _ => None,
+        };
+        let wants = |subscription: &PushSubscription| {
+            subscription.wants(&msg.0) || (left_map.is_some() && subscription.wants_map(left_map))
+        };
+
+        if !self.subscribers.values().any(|s| wants(&s.subscription)) {
+            return;
+        }
+
+        let text = match serde_json::to_string(&msg.0) {
+            Ok(text) => text,
+            Err(e) => {
+                println!("failed to serialize push message: {}", e);
+                return;
+            },
+        };
+
+        let mut closed = Vec::new();
+        for (id, subscriber) in &self.subscribers {
+            if wants(&subscriber.subscription) {
+                if subscriber.recipient.do_send(PushText(text.clone())).is_err() {
+                    closed.push(*id);
+                }
+            }
+        }
+        for id in closed {
+            self.subscribers.remove(&id);
+        }
+    }
+}
+
+pub struct PushSession {
+    broadcaster: Addr<PushBroadcaster>,
+    heartbeat: Instant,
+    id: Option<usize>,
+}
+
+impl PushSession {
+    pub fn new(broadcaster: Addr<PushBroadcaster>) -> PushSession {
+        PushSession {
+            broadcaster,
+            heartbeat: Instant::now(),
+            id: None,
+        }
+    }
+
+    fn check_heartbeat(&self, context: &mut ws::WebsocketContext<Self>) {
+        context.run_interval(HEARTBEAT_INTERVAL, |actor, context| {
+            if Instant::now().duration_since(actor.heartbeat) > CLIENT_TIMEOUT {
+                context.stop();
+            } else {
+                context.ping("");
+            }
+        });
+    }
+}
+
+impl Actor for PushSession {
+    type Context = ws::WebsocketContext<Self>;
+
+    fn started(&mut self, context: &mut Self::Context) {
+        self.check_heartbeat(context);
+
+        self.broadcaster
+            .send(Connect(context.address().recipient()))
+            .into_actor(self)
+            .then(|res, actor, context| {
+                match res {
+                    Ok(id) => actor.id = Some(id),
+                    Err(_) => context.stop(),
+                }
+                fut::ok(())
+            })
+            .wait(context);
+    }
+
+    fn stopping(&mut self, _context: &mut Self::Context) -> Running {
+        if let Some(id) = self.id {
+            self.broadcaster.do_send(Disconnect(id));
+        }
+        Running::Stop
+    }
+}
+
+impl Handler<PushText> for PushSession {
+    type Result = ();
+
+    fn handle(&mut self, msg: PushText, context: &mut Self::Context) {
+        context.text(msg.0);
+    }
+}
+
+impl StreamHandler<ws::Message, ws::ProtocolError> for PushSession {
+    fn handle(&mut self, msg: ws::Message, context: &mut Self::Context) {
+        match msg {
+            ws::Message::Ping(msg) => {
+                self.heartbeat = Instant::now();
+                context.pong(&msg);
+            },
+            ws::Message::Pong(_) => {
+                self.heartbeat = Instant::now();
+            },
+            ws::Message::Text(text) => {
+                // the only message clients send is their subscription
+                match serde_json::from_str::<PushSubscription>(&text) {
+                    Ok(subscription) => {
+                        if let Some(id) = self.id {
+                            self.broadcaster.do_send(Subscribe(id, subscription));
+                        }
+                    },
+                    Err(e) => {
+                        println!("invalid push subscription {}: {}", text, e);
+                    },
+                }
+            },
+            ws::Message::Close(_) => {
+                context.stop();
+            },
+            ws::Message::Binary(_) | ws::Message::Nop => {},
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix::System;
+    use futures::Future;
+
+    struct Collector(Vec<String>);
+
+    impl Actor for Collector {
+        type Context = Context<Self>;
+    }
+
+    impl Handler<PushText> for Collector {
+        type Result = ();
+
+        fn handle(&mut self, msg: PushText, _context: &mut Context<Self>) {
+            self.0.push(msg.0);
+        }
+    }
+
+    struct Count;
+
+    impl Message for Count {
+        type Result = usize;
+    }
+
+    impl Handler<Count> for Collector {
+        type Result = usize;
+
+        fn handle(&mut self, _msg: Count, _context: &mut Context<Self>) -> usize {
+            self.0.len()
+        }
+    }
+
+    #[test]
+    fn only_subscribed_maps() {
+        System::run(|| {
+            let broadcaster = PushBroadcaster::new().start();
+            let collector = Collector(Vec::new()).start();
+
+            let mut user = RealtimeUserData::from({
+                let mut u = TrackedUser::new();
+                u.mac_address = Some(ShortAddress::from_bytes(&[0, 1]).unwrap());
+                u
+            });
+
+            let b = broadcaster.clone();
+            let c = collector.clone();
+            let task = broadcaster
+                .send(Connect(collector.clone().recipient()))
+                .and_then(move |id| {
+                    b.do_send(Subscribe(id, PushSubscription {
+                        diagnostics: false,
+                        map_ids: Some(vec![1]),
+                    }));
+                    user.map_id = Some(2);
+                    b.do_send(Publish(PushMessage::UserMoved(user.clone())));
+                    user.map_id = Some(1);
+                    b.do_send(Publish(PushMessage::UserMoved(user.clone())));
+                    b.do_send(Publish(PushMessage::Emergency(EmergencyStatus::new())));
+                    // publish is handled in order, so this is sent after the messages above
+                    b.send(Subscribe(id, PushSubscription::new()))
+                })
+                .and_then(move |_| c.send(Count))
+                .map(|count| {
+                    assert_eq!(count, 2);
+                    System::current().stop();
+                })
+                .map_err(|_| panic!("broadcaster mailbox closed"));
+            Arbiter::spawn(task);
+        }).unwrap();
+    }
+
+    #[test]
+    fn users_leaving_a_map() {
+        System::run(|| {
+            let broadcaster = PushBroadcaster::new().start();
+            let collector = Collector(Vec::new()).start();
+
+            let mut user = RealtimeUserData::from({
+                let mut u = TrackedUser::new();
+                u.id = 1;
+                u.mac_address = Some(ShortAddress::from_bytes(&[0, 1]).unwrap());
+                u
+            });
+
+            let b = broadcaster.clone();
+            let c = collector.clone();
+            let task = broadcaster
+                .send(Connect(collector.clone().recipient()))
+                .and_then(move |id| {
+                    b.do_send(Subscribe(id, PushSubscription {
+                        diagnostics: false,
+                        map_ids: Some(vec![1]),
+                    }));
+                    user.map_id = Some(1);
+                    b.do_send(Publish(PushMessage::UserMoved(user.clone())));
+                    // leaves the subscribed map, then moves between maps that are not subscribed
+                    user.map_id = Some(2);
+                    b.do_send(Publish(PushMessage::UserMoved(user.clone())));
+                    user.map_id = Some(3);
+                    b.do_send(Publish(PushMessage::UserMoved(user.clone())));
+                    b.send(Subscribe(id, PushSubscription::new()))
+                })
+                .and_then(move |_| c.send(Count))
+                .map(|count| {
+                    assert_eq!(count, 2);
+                    System::current().stop();
+                })
+                .map_err(|_| panic!("broadcaster mailbox closed"));
+            Arbiter::spawn(task);
+        }).unwrap();
+    }
+}
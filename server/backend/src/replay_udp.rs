@@ -0,0 +1,140 @@
+// Plays a capture of udp beacons back to the manager, in place of the simulated tags of the dummy
+// beacons, at the speed the frames were received or faster. Frames are parsed the same way the
+// udp connection parses them, but are not authenticated again, they were captured before they
+// were checked. Acknowledgements in the capture answered the requests of the recorded session, so
+// only ranges are played, and the requests of this session are answered for every beacon that
+// appears in the capture instead.
+
+use actix::prelude::*;
+use crate::beacon_manager::*;
+use crate::capture::{ self, CapturedFrame, };
+use crate::config::ReplayConfig;
+use crate::conn_common;
+use common::*;
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+use std::time::{ Duration, Instant, };
+
+pub struct ReplayUDP {
+    // the beacons that sent frames in the capture, and where from
+    beacons: BTreeMap<MacAddress8, IpAddr>,
+    config: ReplayConfig,
+    frames: Vec<CapturedFrame>,
+    manager: Addr<BeaconManager>,
+    next: usize,
+    // when the first frame was played, the rest are played relative to it
+    started: Instant,
+}
+
+impl Actor for ReplayUDP {
+    type Context = Context<Self>;
+
+    fn started(&mut self, context: &mut Context<Self>) {
+        self.started = Instant::now();
+        self.play(context);
+    }
+}
+
+impl Handler<BeaconCommand> for ReplayUDP {
+    type Result = Result<(), ()>;
+
+    fn handle(&mut self, msg: BeaconCommand, _context: &mut Context<Self>) -> Self::Result {
+        match msg {
+            BeaconCommand::StartEmergency(id, opt_ip) => self.reply(opt_ip, |ip, mac| BMResponse::Start(ip, mac, Some(id))),
+            BeaconCommand::EndEmergency(id, opt_ip) => self.reply(opt_ip, |ip, mac| BMResponse::End(ip, mac, Some(id))),
+            BeaconCommand::Ping(id, opt_ip) => self.reply(opt_ip, |ip, mac| BMResponse::Ping(ip, mac, Some(id))),
+            BeaconCommand::Reboot(id, opt_ip) => self.reply(opt_ip, |ip, mac| BMResponse::Reboot(ip, mac, Some(id))),
+            BeaconCommand::SetIp(id, _ip) => self.reply(None, |ip, mac| BMResponse::SetIp(ip, mac, Some(id))),
+        }
+        Ok(())
+    }
+}
+
+impl ReplayUDP {
+    pub fn new(manager: Addr<BeaconManager>, config: ReplayConfig) -> Addr<ReplayUDP> {
+        // the capture was read when the config was validated, but it may have changed since
+        let frames = match &config.file {
+            Some(file) => capture::read_capture(file).unwrap_or_else(|e| {
+                println!("{}, nothing will be replayed", e);
+                Vec::new()
+            }),
+            None => Vec::new(),
+        };
+        println!("replaying {} frames from {} at {}x", frames.len(), config.file.as_ref().map_or("", |f| f.as_str()), config.speed);
+
+        let mut beacons = BTreeMap::new();
+        for frame in &frames {
+            let mac = frame.bytes()
+                .and_then(|data| conn_common::parse_message(&data).ok())
+                .and_then(|received| received.message.beacon_mac());
+            if let Some(mac) = mac {
+                beacons.insert(mac, frame.ip);
+            }
+        }
+
+        ReplayUDP::create(move |_context| {
+            ReplayUDP {
+                beacons,
+                config,
+                frames,
+                manager,
+                next: 0,
+                started: Instant::now(),
+            }
+        })
+    }
+
+    // when the frame is played, relative to the first frame
+    fn offset(&self, frame: &CapturedFrame) -> Duration {
+        let elapsed = (frame.timestamp - self.frames[0].timestamp).num_microseconds().unwrap_or(0).max(0);
+        Duration::from_micros((elapsed as f64 / self.config.speed) as u64)
+    }
+
+    // plays every frame that is due, then waits for the next one
+    fn play(&mut self, context: &mut Context<Self>) {
+        if self.frames.is_empty() {
+            return;
+        }
+        loop {
+            if self.next >= self.frames.len() {
+                if !self.config.looped {
+                    println!("finished replaying {} frames", self.frames.len());
+                    return;
+                }
+                self.next = 0;
+                self.started = Instant::now();
+                // let other messages through before starting over, even if the first frames are due
+                context.run_later(Duration::from_millis(0), |actor, context| actor.play(context));
+                return;
+            }
+
+            let due = self.started + self.offset(&self.frames[self.next]);
+            let now = Instant::now();
+            if due > now {
+                context.run_later(due - now, |actor, context| actor.play(context));
+                return;
+            }
+
+            let frame = &self.frames[self.next];
+            self.next += 1;
+            let received = match frame.bytes().map(|data| conn_common::parse_message(&data)) {
+                Some(Ok(received)) => received,
+                // garbage is captured too, the udp connection would have rejected it
+                _ => continue,
+            };
+            if let Some(response @ BMResponse::TagData(..)) = conn_common::to_response(received.message, received.sequence, frame.ip) {
+                self.manager.do_send(response);
+            }
+        }
+    }
+
+    fn reply<F>(&self, opt_ip: Option<IpAddr>, msg: F)
+        where F: Fn(IpAddr, MacAddress8) -> BMResponse
+    {
+        for (mac, ip) in &self.beacons {
+            if opt_ip.map_or(true, |opt_ip| opt_ip == *ip) {
+                self.manager.do_send(msg(*ip, *mac));
+            }
+        }
+    }
+}
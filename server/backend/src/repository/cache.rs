@@ -0,0 +1,258 @@
+// Beacons and users are few, and rarely change, so all of them are loaded on the first lookup and
+// every lookup after is answered from memory until the cache is invalidated. Updates from the
+// realtime data are written through to the repository, and the cached copy is replaced with what
+// the repository returned.
+
+use common::*;
+use crate::ak_error::AkError;
+use crate::models::beacon::BeaconKey;
+use futures::future::ok;
+use futures::Future;
+use std::net::IpAddr;
+use std::sync::{ Arc, Mutex, };
+use super::{ BeaconRepository, RepoFuture, UserRepository, };
+
+struct CacheState<T> {
+    entries: Option<Vec<T>>,
+    // bumped when invalidated, so a load that started before is not kept
+    generation: u64,
+}
+
+#[derive(Clone)]
+struct Cache<T> {
+    state: Arc<Mutex<CacheState<T>>>,
+}
+
+impl<T: Clone + 'static> Cache<T> {
+    fn new() -> Cache<T> {
+        Cache {
+            state: Arc::new(Mutex::new(CacheState {
+                entries: None,
+                generation: 0,
+            })),
+        }
+    }
+
+    fn get<F>(&self, load: F) -> RepoFuture<Vec<T>>
+        where F: FnOnce() -> RepoFuture<Vec<T>>
+    {
+        let generation = {
+            let state = self.state.lock().unwrap();
+            if let Some(entries) = &state.entries {
+                return Box::new(ok::<_, AkError>(entries.clone()));
+            }
+            state.generation
+        };
+
+        let state = self.state.clone();
+        Box::new(load()
+            .map(move |entries| {
+                let mut state = state.lock().unwrap();
+                if state.generation == generation {
+                    state.entries = Some(entries.clone());
+                }
+                entries
+            })
+        )
+    }
+
+    // replaces the cached entry that matches, if the entries are loaded
+    fn replace<F>(&self, entry: T, matches: F)
+        where F: Fn(&T) -> bool
+    {
+        if let Some(entries) = &mut self.state.lock().unwrap().entries {
+            match entries.iter_mut().find(|e| matches(e)) {
+                Some(cached) => *cached = entry,
+                None => entries.push(entry),
+            }
+        }
+    }
+
+    fn invalidate(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.entries = None;
+        state.generation += 1;
+    }
+}
+
+pub struct CachedBeacons {
+    cache: Cache<Beacon>,
+    inner: Arc<dyn BeaconRepository>,
+}
+
+impl CachedBeacons {
+    pub fn new(inner: Arc<dyn BeaconRepository>) -> CachedBeacons {
+        CachedBeacons {
+            cache: Cache::new(),
+            inner,
+        }
+    }
+}
+
+impl BeaconRepository for CachedBeacons {
+    fn beacons(&self) -> RepoFuture<Vec<Beacon>> {
+        self.cache.get(|| self.inner.beacons())
+    }
+
+    fn beacon_by_mac(&self, mac: MacAddress8) -> RepoFuture<Option<Beacon>> {
+        Box::new(self.beacons()
+            .map(move |beacons| {
+                beacons.into_iter().find(|b| b.mac_address == mac)
+            })
+        )
+    }
+
+    fn beacons_by_mac(&self, macs: Vec<MacAddress8>) -> RepoFuture<Vec<Beacon>> {
+        Box::new(self.beacons()
+            .map(move |beacons| {
+                beacons.into_iter().filter(|b| macs.contains(&b.mac_address)).collect()
+            })
+        )
+    }
+
+    fn beacon_by_ip(&self, ip: IpAddr) -> RepoFuture<Option<Beacon>> {
+        Box::new(self.beacons()
+            .map(move |beacons| {
+                beacons.into_iter().find(|b| b.ip == ip)
+            })
+        )
+    }
+
+    fn update_beacon_from_realtime(&self, realtime: RealtimeBeacon) -> RepoFuture<Option<Beacon>> {
+        let cache = self.cache.clone();
+        Box::new(self.inner.update_beacon_from_realtime(realtime)
+            .map(move |opt_beacon| {
+                if let Some(beacon) = &opt_beacon {
+                    let id = beacon.id;
+                    cache.replace(beacon.clone(), |b| b.id == id);
+                }
+                opt_beacon
+            })
+        )
+    }
+
+    // only read when the manager starts, or the udp connections are created
+    fn blocked_beacons(&self) -> RepoFuture<Vec<MacAddress8>> {
+        self.inner.blocked_beacons()
+    }
+
+    fn beacon_keys(&self) -> RepoFuture<Vec<(MacAddress8, BeaconKey)>> {
+        self.inner.beacon_keys()
+    }
+
+    fn update_beacon_counter(&self, mac: MacAddress8, counter: u32) -> RepoFuture<()> {
+        self.inner.update_beacon_counter(mac, counter)
+    }
+
+    fn clear_previous_beacon_key(&self, mac: MacAddress8, current: Vec<u8>) -> RepoFuture<()> {
+        self.inner.clear_previous_beacon_key(mac, current)
+    }
+
+    fn invalidate(&self) {
+        self.cache.invalidate();
+    }
+}
+
+pub struct CachedUsers {
+    cache: Cache<TrackedUser>,
+    inner: Arc<dyn UserRepository>,
+}
+
+impl CachedUsers {
+    pub fn new(inner: Arc<dyn UserRepository>) -> CachedUsers {
+        CachedUsers {
+            cache: Cache::new(),
+            inner,
+        }
+    }
+}
+
+impl UserRepository for CachedUsers {
+    // only users with a tag can be looked up by it, so every user is found in the tagged users
+    fn user_by_short(&self, id: ShortAddress) -> RepoFuture<Option<TrackedUser>> {
+        Box::new(self.tagged_users()
+            .map(move |users| {
+                users.into_iter().find(|u| u.mac_address == Some(id))
+            })
+        )
+    }
+
+    fn tagged_users(&self) -> RepoFuture<Vec<TrackedUser>> {
+        self.cache.get(|| self.inner.tagged_users())
+    }
+
+    fn update_user_from_realtime(&self, realtime: RealtimeUserData) -> RepoFuture<Option<TrackedUser>> {
+        let cache = self.cache.clone();
+        Box::new(self.inner.update_user_from_realtime(realtime)
+            .map(move |opt_user| {
+                if let Some(user) = &opt_user {
+                    let id = user.id;
+                    cache.replace(user.clone(), |u| u.id == id);
+                }
+                opt_user
+            })
+        )
+    }
+
+    fn insert_location(&self, record: LocationRecord) -> RepoFuture<()> {
+        self.inner.insert_location(record)
+    }
+
+    fn delete_history_before(&self, cutoff: DateTime<Utc>) -> RepoFuture<u64> {
+        self.inner.delete_history_before(cutoff)
+    }
+
+    fn invalidate(&self) {
+        self.cache.invalidate();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repository::memory::MemoryRepository;
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn cached_until_invalidated() {
+        let memory = MemoryRepository::new();
+        let tag = ShortAddress::from_bytes(&[0, 1]).unwrap();
+        let mut user = TrackedUser::new();
+        user.id = 1;
+        user.mac_address = Some(tag);
+        memory.data.lock().unwrap().users = vec![user];
+        let users = CachedUsers::new(Arc::new(memory.clone()));
+
+        assert_eq!(users.user_by_short(tag).wait().unwrap().map(|u| u.id), Some(1));
+        // the tag is given to someone else behind the cache's back
+        memory.data.lock().unwrap().users[0].mac_address = Some(ShortAddress::from_bytes(&[0, 2]).unwrap());
+        assert!(users.user_by_short(tag).wait().unwrap().is_some());
+        assert_eq!(users.tagged_users().wait().unwrap().len(), 1);
+        assert_eq!(memory.data.lock().unwrap().user_reads, 1);
+
+        users.invalidate();
+        assert!(users.user_by_short(tag).wait().unwrap().is_none());
+        assert_eq!(memory.data.lock().unwrap().user_reads, 2);
+    }
+
+    #[test]
+    fn realtime_written_through() {
+        let memory = MemoryRepository::new();
+        let mut beacon = Beacon::new();
+        beacon.id = 1;
+        beacon.mac_address = MacAddress8::from_bytes(&[1, 0, 0, 0, 0, 0, 0, 0]).unwrap();
+        memory.data.lock().unwrap().beacons = vec![beacon.clone()];
+        let beacons = CachedBeacons::new(Arc::new(memory.clone()));
+        assert_eq!(beacons.beacons().wait().unwrap().len(), 1);
+
+        let ip = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2));
+        let mut realtime = RealtimeBeacon::from(beacon.clone());
+        realtime.ip = ip;
+        beacons.update_beacon_from_realtime(realtime).wait().unwrap();
+        assert_eq!(beacons.beacon_by_ip(ip).wait().unwrap().map(|b| b.id), Some(1));
+        assert_eq!(beacons.beacon_by_mac(beacon.mac_address).wait().unwrap().map(|b| b.ip), Some(ip));
+        assert_eq!(beacons.beacons_by_mac(vec![beacon.mac_address]).wait().unwrap().len(), 1);
+        assert_eq!(memory.data.lock().unwrap().beacon_reads, 1);
+        assert_eq!(memory.data.lock().unwrap().beacons[0].ip, ip);
+    }
+}
@@ -0,0 +1,242 @@
+// Keeps everything in memory, clones share the same data so a test can look at what the actors
+// stored, or change the beacons and users while the actors are running.
+
+use common::*;
+use common::zones::{ Zone, ZoneEvent, };
+use crate::ak_error::AkError;
+use crate::models::beacon::BeaconKey;
+use futures::future::ok;
+use std::net::IpAddr;
+use std::sync::{ Arc, Mutex, };
+use super::{ BeaconRepository, MapRepository, RepoFuture, SystemRepository, UserRepository, };
+
+#[derive(Default)]
+pub struct MemoryData {
+    pub beacon_keys: Vec<(MacAddress8, BeaconKey)>,
+    pub beacons: Vec<Beacon>,
+    pub blocked: Vec<MacAddress8>,
+    pub emergency: EmergencyStatus,
+    pub emergency_events: Vec<EmergencyEvent>,
+    pub emergency_scope_changes: Vec<EmergencyScopeChange>,
+    pub locations: Vec<LocationRecord>,
+    pub network_interfaces: Vec<NetworkInterface>,
+    pub retention: Option<RetentionConfig>,
+    pub users: Vec<TrackedUser>,
+    pub zone_events: Vec<ZoneEvent>,
+    pub zones: Vec<Zone>,
+    // how many times the beacons and users were read, to check what was cached
+    pub beacon_reads: usize,
+    pub user_reads: usize,
+}
+
+#[derive(Clone, Default)]
+pub struct MemoryRepository {
+    pub data: Arc<Mutex<MemoryData>>,
+}
+
+impl MemoryRepository {
+    pub fn new() -> MemoryRepository {
+        MemoryRepository::default()
+    }
+
+    fn with<T, F>(&self, f: F) -> RepoFuture<T>
+        where F: FnOnce(&mut MemoryData) -> T,
+              T: 'static,
+    {
+        Box::new(ok::<_, AkError>(f(&mut self.data.lock().unwrap())))
+    }
+}
+
+impl BeaconRepository for MemoryRepository {
+    fn beacons(&self) -> RepoFuture<Vec<Beacon>> {
+        self.with(|data| {
+            data.beacon_reads += 1;
+            data.beacons.clone()
+        })
+    }
+
+    fn beacon_by_mac(&self, mac: MacAddress8) -> RepoFuture<Option<Beacon>> {
+        self.with(move |data| {
+            data.beacon_reads += 1;
+            data.beacons.iter().find(|b| b.mac_address == mac).cloned()
+        })
+    }
+
+    fn beacons_by_mac(&self, macs: Vec<MacAddress8>) -> RepoFuture<Vec<Beacon>> {
+        self.with(move |data| {
+            data.beacon_reads += 1;
+            data.beacons.iter().filter(|b| macs.contains(&b.mac_address)).cloned().collect()
+        })
+    }
+
+    fn beacon_by_ip(&self, ip: IpAddr) -> RepoFuture<Option<Beacon>> {
+        self.with(move |data| {
+            data.beacon_reads += 1;
+            data.beacons.iter().find(|b| b.ip == ip).cloned()
+        })
+    }
+
+    fn update_beacon_from_realtime(&self, realtime: RealtimeBeacon) -> RepoFuture<Option<Beacon>> {
+        self.with(move |data| {
+            data.beacons.iter_mut().find(|b| b.id == realtime.id).map(|b| {
+                b.ip = realtime.ip;
+                b.last_active = realtime.last_active;
+                b.state = realtime.state;
+                b.clone()
+            })
+        })
+    }
+
+    fn blocked_beacons(&self) -> RepoFuture<Vec<MacAddress8>> {
+        self.with(|data| data.blocked.clone())
+    }
+
+    fn beacon_keys(&self) -> RepoFuture<Vec<(MacAddress8, BeaconKey)>> {
+        self.with(|data| data.beacon_keys.clone())
+    }
+
+    fn update_beacon_counter(&self, mac: MacAddress8, counter: u32) -> RepoFuture<()> {
+        self.with(move |data| {
+            if let Some((_mac, key)) = data.beacon_keys.iter_mut().find(|(m, _key)| *m == mac) {
+                key.counter = Some(key.counter.map_or(counter, |c| c.max(counter)));
+            }
+        })
+    }
+
+    fn clear_previous_beacon_key(&self, mac: MacAddress8, current: Vec<u8>) -> RepoFuture<()> {
+        self.with(move |data| {
+            if let Some((_mac, key)) = data.beacon_keys.iter_mut().find(|(m, key)| *m == mac && key.current == current) {
+                key.previous = None;
+            }
+        })
+    }
+}
+
+impl UserRepository for MemoryRepository {
+    fn user_by_short(&self, id: ShortAddress) -> RepoFuture<Option<TrackedUser>> {
+        self.with(move |data| {
+            data.user_reads += 1;
+            data.users.iter().find(|u| u.mac_address == Some(id)).cloned()
+        })
+    }
+
+    fn tagged_users(&self) -> RepoFuture<Vec<TrackedUser>> {
+        self.with(|data| {
+            data.user_reads += 1;
+            data.users.iter().filter(|u| u.mac_address.is_some()).cloned().collect()
+        })
+    }
+
+    fn update_user_from_realtime(&self, realtime: RealtimeUserData) -> RepoFuture<Option<TrackedUser>> {
+        self.with(move |data| {
+            data.users.iter_mut().find(|u| u.mac_address == Some(realtime.addr)).map(|u| {
+                u.coordinates = realtime.coordinates;
+                u.last_active = realtime.last_active;
+                u.map_id = realtime.map_id;
+                u.clone()
+            })
+        })
+    }
+
+    fn insert_location(&self, record: LocationRecord) -> RepoFuture<()> {
+        self.with(move |data| {
+            let mut record = record;
+            record.id = data.locations.len() as i64 + 1;
+            data.locations.push(record);
+        })
+    }
+
+    fn delete_history_before(&self, cutoff: DateTime<Utc>) -> RepoFuture<u64> {
+        self.with(move |data| {
+            let before = data.locations.len();
+            data.locations.retain(|record| record.timestamp >= cutoff);
+            (before - data.locations.len()) as u64
+        })
+    }
+}
+
+impl MapRepository for MemoryRepository {
+    fn zones(&self) -> RepoFuture<Vec<Zone>> {
+        self.with(|data| data.zones.clone())
+    }
+
+    fn insert_zone_events(&self, events: Vec<ZoneEvent>) -> RepoFuture<()> {
+        self.with(move |data| {
+            for mut event in events {
+                event.id = data.zone_events.len() as i64 + 1;
+                data.zone_events.push(event);
+            }
+        })
+    }
+}
+
+impl SystemRepository for MemoryRepository {
+    fn emergency(&self) -> RepoFuture<EmergencyStatus> {
+        self.with(|data| data.emergency.clone())
+    }
+
+    fn update_emergency(&self, status: EmergencyStatus) -> RepoFuture<()> {
+        self.with(move |data| data.emergency = status)
+    }
+
+    fn insert_emergency_event(&self, event: EmergencyEvent) -> RepoFuture<()> {
+        self.with(move |data| {
+            let mut event = event;
+            event.id = data.emergency_events.len() as i32 + 1;
+            data.emergency_events.push(event);
+        })
+    }
+
+    fn update_emergency_events_scope(&self, map_ids: Option<Vec<i32>>) -> RepoFuture<u64> {
+        self.with(move |data| {
+            let mut count = 0;
+            for event in data.emergency_events.iter_mut().filter(|e| e.ended_at.is_none()) {
+                event.map_ids = match (event.map_ids.take(), &map_ids) {
+                    (Some(mut ids), Some(added)) => {
+                        ids.extend(added);
+                        ids.sort();
+                        ids.dedup();
+                        Some(ids)
+                    },
+                    _ => None,
+                };
+                count += 1;
+            }
+            count
+        })
+    }
+
+    fn insert_emergency_scope_change(&self, change: EmergencyScopeChange) -> RepoFuture<u64> {
+        self.with(move |data| {
+            let open: Vec<i32> = data.emergency_events.iter().filter(|e| e.ended_at.is_none()).map(|e| e.id).collect();
+            for event_id in open.iter() {
+                let mut change = change.clone();
+                change.id = data.emergency_scope_changes.len() as i32 + 1;
+                change.event_id = *event_id;
+                data.emergency_scope_changes.push(change);
+            }
+            open.len() as u64
+        })
+    }
+
+    fn end_emergency_events(&self, ended_at: DateTime<Utc>, ended_by: Option<String>, reason: Option<String>) -> RepoFuture<u64> {
+        self.with(move |data| {
+            let mut count = 0;
+            for event in data.emergency_events.iter_mut().filter(|e| e.ended_at.is_none()) {
+                event.ended_at = Some(ended_at);
+                event.ended_by = ended_by.clone();
+                event.end_reason = reason.clone();
+                count += 1;
+            }
+            count
+        })
+    }
+
+    fn network_interfaces(&self) -> RepoFuture<Vec<NetworkInterface>> {
+        self.with(|data| data.network_interfaces.clone())
+    }
+
+    fn retention(&self) -> RepoFuture<RetentionConfig> {
+        self.with(|data| data.retention.clone().unwrap_or_else(RetentionConfig::new))
+    }
+}
@@ -0,0 +1,93 @@
+// The data the positioning needs from the database, behind traits so the data processor, beacon
+// manager and dummy beacons can run against something other than postgres, ie in tests. Beacons
+// and users are looked up for every range a beacon sends, so they are cached, and the caches are
+// invalidated when beacons or users are edited.
+
+pub mod cache;
+// only used to run the actors without a database
+#[cfg(test)]
+pub mod memory;
+pub mod postgres;
+
+use common::*;
+use common::zones::{ Zone, ZoneEvent, };
+use crate::ak_error::AkError;
+use crate::models::beacon::BeaconKey;
+use futures::Future;
+use std::net::IpAddr;
+use std::sync::Arc;
+use self::cache::{ CachedBeacons, CachedUsers, };
+
+pub type RepoFuture<T> = Box<dyn Future<Item=T, Error=AkError>>;
+
+pub trait BeaconRepository: Send + Sync {
+    fn beacons(&self) -> RepoFuture<Vec<Beacon>>;
+    fn beacon_by_mac(&self, mac: MacAddress8) -> RepoFuture<Option<Beacon>>;
+    fn beacons_by_mac(&self, macs: Vec<MacAddress8>) -> RepoFuture<Vec<Beacon>>;
+    fn beacon_by_ip(&self, ip: IpAddr) -> RepoFuture<Option<Beacon>>;
+    fn update_beacon_from_realtime(&self, realtime: RealtimeBeacon) -> RepoFuture<Option<Beacon>>;
+    fn blocked_beacons(&self) -> RepoFuture<Vec<MacAddress8>>;
+    fn beacon_keys(&self) -> RepoFuture<Vec<(MacAddress8, BeaconKey)>>;
+    fn update_beacon_counter(&self, mac: MacAddress8, counter: u32) -> RepoFuture<()>;
+    // the beacon signed with current, so its previous key is not accepted anymore
+    fn clear_previous_beacon_key(&self, mac: MacAddress8, current: Vec<u8>) -> RepoFuture<()>;
+
+    // the beacons were changed elsewhere, anything kept from before is stale
+    fn invalidate(&self) {}
+}
+
+pub trait UserRepository: Send + Sync {
+    fn user_by_short(&self, id: ShortAddress) -> RepoFuture<Option<TrackedUser>>;
+    fn tagged_users(&self) -> RepoFuture<Vec<TrackedUser>>;
+    fn update_user_from_realtime(&self, realtime: RealtimeUserData) -> RepoFuture<Option<TrackedUser>>;
+    fn insert_location(&self, record: LocationRecord) -> RepoFuture<()>;
+    fn delete_history_before(&self, cutoff: DateTime<Utc>) -> RepoFuture<u64>;
+
+    // the users were changed elsewhere, anything kept from before is stale
+    fn invalidate(&self) {}
+}
+
+pub trait MapRepository: Send + Sync {
+    fn zones(&self) -> RepoFuture<Vec<Zone>>;
+    fn insert_zone_events(&self, events: Vec<ZoneEvent>) -> RepoFuture<()>;
+}
+
+// the emergency and its log, the interfaces the beacons are listened for on, and how long the
+// location history is kept
+pub trait SystemRepository: Send + Sync {
+    fn emergency(&self) -> RepoFuture<EmergencyStatus>;
+    fn update_emergency(&self, status: EmergencyStatus) -> RepoFuture<()>;
+    fn insert_emergency_event(&self, event: EmergencyEvent) -> RepoFuture<()>;
+    // adds maps to the emergencies still going, returns how many there were
+    fn update_emergency_events_scope(&self, map_ids: Option<Vec<i32>>) -> RepoFuture<u64>;
+    // logs maps that were started or ended against the emergencies still going
+    fn insert_emergency_scope_change(&self, change: EmergencyScopeChange) -> RepoFuture<u64>;
+    // ends the emergencies still going, returns how many there were
+    fn end_emergency_events(&self, ended_at: DateTime<Utc>, ended_by: Option<String>, reason: Option<String>) -> RepoFuture<u64>;
+    fn network_interfaces(&self) -> RepoFuture<Vec<NetworkInterface>>;
+    fn retention(&self) -> RepoFuture<RetentionConfig>;
+}
+
+#[derive(Clone)]
+pub struct Repositories {
+    pub beacons: Arc<dyn BeaconRepository>,
+    pub maps: Arc<dyn MapRepository>,
+    pub system: Arc<dyn SystemRepository>,
+    pub users: Arc<dyn UserRepository>,
+}
+
+impl Repositories {
+    // beacons and users are cached in front of the repository, maps are only read when the zones
+    // are reloaded, and the system state when it changes
+    pub fn cached<R>(repository: R) -> Repositories
+        where R: BeaconRepository + MapRepository + SystemRepository + UserRepository + 'static
+    {
+        let repository = Arc::new(repository);
+        Repositories {
+            beacons: Arc::new(CachedBeacons::new(repository.clone())),
+            maps: repository.clone(),
+            system: repository.clone(),
+            users: Arc::new(CachedUsers::new(repository)),
+        }
+    }
+}
@@ -0,0 +1,259 @@
+use common::*;
+use common::zones::{ Zone, ZoneEvent, };
+use crate::ak_error::AkError;
+use crate::db_utils::DbPool;
+use crate::models::beacon::{ self, BeaconKey, };
+use crate::models::beacon_blocklist;
+use crate::models::emergency;
+use crate::models::emergency_event;
+use crate::models::location_history;
+use crate::models::network_interface;
+use crate::models::retention;
+use crate::models::user;
+use crate::models::zone;
+use crate::models::zone_event;
+use futures::{ stream, Future, Stream, };
+use std::net::IpAddr;
+use super::{ BeaconRepository, MapRepository, RepoFuture, SystemRepository, UserRepository, };
+
+// every lookup checks out a connection from the pool, and returns it when done
+pub struct PgRepository {
+    pool: DbPool,
+}
+
+impl PgRepository {
+    pub fn new(pool: DbPool) -> PgRepository {
+        PgRepository {
+            pool,
+        }
+    }
+}
+
+impl BeaconRepository for PgRepository {
+    fn beacons(&self) -> RepoFuture<Vec<Beacon>> {
+        Box::new(self.pool.get()
+            .and_then(|client| {
+                beacon::select_beacons(client)
+            })
+            .map(|(_client, beacons)| beacons)
+        )
+    }
+
+    fn beacon_by_mac(&self, mac: MacAddress8) -> RepoFuture<Option<Beacon>> {
+        Box::new(self.pool.get()
+            .and_then(move |client| {
+                beacon::select_beacon_by_mac(client, mac)
+            })
+            .map(|(_client, opt_beacon)| opt_beacon)
+        )
+    }
+
+    fn beacons_by_mac(&self, macs: Vec<MacAddress8>) -> RepoFuture<Vec<Beacon>> {
+        Box::new(self.pool.get()
+            .and_then(move |client| {
+                beacon::select_beacons_by_mac(client, macs)
+            })
+            .map(|(_client, beacons)| beacons)
+        )
+    }
+
+    fn beacon_by_ip(&self, ip: IpAddr) -> RepoFuture<Option<Beacon>> {
+        Box::new(self.pool.get()
+            .and_then(move |client| {
+                beacon::select_beacon_by_ip(client, ip)
+            })
+            .map(|(_client, opt_beacon)| opt_beacon)
+        )
+    }
+
+    fn update_beacon_from_realtime(&self, realtime: RealtimeBeacon) -> RepoFuture<Option<Beacon>> {
+        Box::new(self.pool.get()
+            .and_then(move |client| {
+                beacon::update_beacon_from_realtime(client, realtime)
+            })
+            .map(|(_client, opt_beacon)| opt_beacon)
+        )
+    }
+
+    fn blocked_beacons(&self) -> RepoFuture<Vec<MacAddress8>> {
+        Box::new(self.pool.get()
+            .and_then(|client| {
+                beacon_blocklist::select_blocked_beacons(client)
+            })
+            .map(|(_client, blocked)| blocked)
+        )
+    }
+
+    fn beacon_keys(&self) -> RepoFuture<Vec<(MacAddress8, BeaconKey)>> {
+        Box::new(self.pool.get()
+            .and_then(|client| {
+                beacon::select_beacon_keys(client)
+            })
+            .map(|(_client, keys)| keys)
+        )
+    }
+
+    fn update_beacon_counter(&self, mac: MacAddress8, counter: u32) -> RepoFuture<()> {
+        Box::new(self.pool.get()
+            .and_then(move |client| {
+                beacon::update_beacon_counter(client, mac, counter)
+            })
+            .map(|(_client, _count)| {})
+        )
+    }
+
+    fn clear_previous_beacon_key(&self, mac: MacAddress8, current: Vec<u8>) -> RepoFuture<()> {
+        Box::new(self.pool.get()
+            .and_then(move |client| {
+                beacon::clear_previous_beacon_key(client, mac, current)
+            })
+            .map(|(_client, _count)| {})
+        )
+    }
+}
+
+impl UserRepository for PgRepository {
+    fn user_by_short(&self, id: ShortAddress) -> RepoFuture<Option<TrackedUser>> {
+        Box::new(self.pool.get()
+            .and_then(move |client| {
+                user::select_user_by_short(client, id)
+            })
+            .map(|(_client, opt_user)| opt_user)
+        )
+    }
+
+    fn tagged_users(&self) -> RepoFuture<Vec<TrackedUser>> {
+        Box::new(self.pool.get()
+            .and_then(|client| {
+                user::select_tagged_users(client)
+            })
+            .map(|(_client, users)| users)
+        )
+    }
+
+    fn update_user_from_realtime(&self, realtime: RealtimeUserData) -> RepoFuture<Option<TrackedUser>> {
+        Box::new(self.pool.get()
+            .and_then(move |client| {
+                user::update_user_from_realtime(client, realtime)
+            })
+            .map(|(_client, opt_user)| opt_user)
+        )
+    }
+
+    fn insert_location(&self, record: LocationRecord) -> RepoFuture<()> {
+        Box::new(self.pool.get()
+            .and_then(move |client| {
+                location_history::insert_location(client, record)
+            })
+            .map(|(_client, _opt_record)| {})
+        )
+    }
+
+    fn delete_history_before(&self, cutoff: DateTime<Utc>) -> RepoFuture<u64> {
+        Box::new(self.pool.get()
+            .and_then(move |client| {
+                location_history::delete_history_before(client, cutoff)
+            })
+            .map(|(_client, count)| count)
+        )
+    }
+}
+
+impl MapRepository for PgRepository {
+    fn zones(&self) -> RepoFuture<Vec<Zone>> {
+        Box::new(self.pool.get()
+            .and_then(|client| {
+                zone::select_zones(client)
+            })
+            .map(|(_client, zones)| zones)
+        )
+    }
+
+    // the events are inserted in order, on one connection
+    fn insert_zone_events(&self, events: Vec<ZoneEvent>) -> RepoFuture<()> {
+        Box::new(self.pool.get()
+            .and_then(move |client| {
+                stream::iter_ok::<_, AkError>(events)
+                    .fold(client, |client, event| {
+                        zone_event::insert_zone_event(client, event)
+                            .map(|(client, _opt_event)| client)
+                    })
+            })
+            .map(|_client| {})
+        )
+    }
+}
+
+impl SystemRepository for PgRepository {
+    fn emergency(&self) -> RepoFuture<EmergencyStatus> {
+        Box::new(self.pool.get()
+            .and_then(|client| {
+                emergency::select_emergency(client)
+            })
+            .map(|(_client, status)| status)
+        )
+    }
+
+    fn update_emergency(&self, status: EmergencyStatus) -> RepoFuture<()> {
+        Box::new(self.pool.get()
+            .and_then(move |client| {
+                emergency::update_emergency(client, status)
+            })
+            .map(|_client| {})
+        )
+    }
+
+    fn insert_emergency_event(&self, event: EmergencyEvent) -> RepoFuture<()> {
+        Box::new(self.pool.get()
+            .and_then(move |client| {
+                emergency_event::insert_emergency_event(client, event)
+            })
+            .map(|(_client, _opt_event)| {})
+        )
+    }
+
+    fn update_emergency_events_scope(&self, map_ids: Option<Vec<i32>>) -> RepoFuture<u64> {
+        Box::new(self.pool.get()
+            .and_then(move |client| {
+                emergency_event::update_emergency_events_scope(client, map_ids)
+            })
+            .map(|(_client, count)| count)
+        )
+    }
+
+    fn insert_emergency_scope_change(&self, change: EmergencyScopeChange) -> RepoFuture<u64> {
+        Box::new(self.pool.get()
+            .and_then(move |client| {
+                emergency_event::insert_emergency_scope_change(client, change)
+            })
+            .map(|(_client, count)| count)
+        )
+    }
+
+    fn end_emergency_events(&self, ended_at: DateTime<Utc>, ended_by: Option<String>, reason: Option<String>) -> RepoFuture<u64> {
+        Box::new(self.pool.get()
+            .and_then(move |client| {
+                emergency_event::end_emergency_events(client, ended_at, ended_by, reason)
+            })
+            .map(|(_client, count)| count)
+        )
+    }
+
+    fn network_interfaces(&self) -> RepoFuture<Vec<NetworkInterface>> {
+        Box::new(self.pool.get()
+            .and_then(|client| {
+                network_interface::select_network_interfaces(client)
+            })
+            .map(|(_client, ifaces)| ifaces)
+        )
+    }
+
+    fn retention(&self) -> RepoFuture<RetentionConfig> {
+        Box::new(self.pool.get()
+            .and_then(|client| {
+                retention::select_retention(client)
+            })
+            .map(|(_client, retention)| retention)
+        )
+    }
+}
@@ -0,0 +1,421 @@
+// One emulated anchor, a DWM1000 anchor behind the ESP32 wifi relay. It answers the manager's
+// commands the way the firmware does, and ranges every tag while an emergency is on. Frames are
+// returned rather than sent, along with when they should be sent, so a failing anchor can be
+// slow without holding up the others.
+
+use common::{ MacAddress8, ShortAddress, };
+use common::beacon_protocol::{ self, Message, Packet, WireFormat, };
+use rand::Rng;
+use std::collections::HashMap;
+use std::net::Ipv4Addr;
+use std::str::FromStr;
+use std::time::{ Duration, Instant, };
+
+// the relay holds the anchor in reset this long before it acknowledges a reboot
+const REBOOT_ACK_DELAY: Duration = Duration::from_millis(3000);
+// then restarts, and reconnects to the wifi
+const REBOOT_DOWNTIME: Duration = Duration::from_millis(4000);
+// the firmware clamps its ranges to these, in meters
+const MIN_RANGE: f64 = 0.01;
+const MAX_RANGE: f64 = 300.0;
+// where a tag starts, and how far it can move between ranges, in meters
+const START_RANGE: (f64, f64) = (1.0, 10.0);
+const RANGE_DRIFT: f64 = 0.1;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Failure {
+    None,
+    // hears everything and sends nothing
+    Silent,
+    // every frame is sent this late
+    Slow(Duration),
+    // restarts on its own this often
+    RebootLoop(Duration),
+    // this chance of each frame being corrupted
+    Malformed(f64),
+}
+
+impl FromStr for Failure {
+    type Err = String;
+
+    // none, silent, slow:<ms>, reboot-loop:<seconds> or malformed:<chance>
+    fn from_str(s: &str) -> Result<Failure, String> {
+        let mut split = s.splitn(2, ':');
+        let (kind, value) = (split.next().unwrap_or(""), split.next());
+        let number = |value: Option<&str>| -> Result<f64, String> {
+            value
+                .and_then(|v| v.parse::<f64>().ok())
+                .filter(|v| v.is_finite() && *v >= 0.0)
+                .ok_or_else(|| format!("{} needs a number, ie {}:1", kind, kind))
+        };
+        match kind {
+            "none" => Ok(Failure::None),
+            "silent" => Ok(Failure::Silent),
+            "slow" => Ok(Failure::Slow(Duration::from_millis(number(value)? as u64))),
+            "reboot-loop" => match number(value)? {
+                seconds if seconds >= 1.0 => Ok(Failure::RebootLoop(Duration::from_secs(seconds as u64))),
+                _ => Err("reboot-loop needs at least 1 second between reboots".to_string()),
+            },
+            "malformed" => match number(value)? {
+                chance if chance <= 1.0 => Ok(Failure::Malformed(chance)),
+                _ => Err("malformed needs a chance between 0 and 1".to_string()),
+            },
+            _ => Err(format!("unknown failure {}, expected none, silent, slow, reboot-loop or malformed", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Outgoing {
+    pub at: Instant,
+    pub data: Vec<u8>,
+    // the relay sends everything to its host, which can be changed with setip
+    pub to: Ipv4Addr,
+}
+
+pub struct Anchor {
+    pub ip: Ipv4Addr,
+    pub mac: MacAddress8,
+    // answer hellos, like firmware that speaks the binary format. The relay in this tree does not.
+    pub binary: bool,
+    pub failure: Failure,
+    // sign binary frames with this key
+    pub key: Option<Vec<u8>>,
+    // signed frames are counted across reboots
+    counter: u32,
+    distances: HashMap<ShortAddress, f64>,
+    down_until: Option<Instant>,
+    format: WireFormat,
+    host: Ipv4Addr,
+    next_reboot: Option<Instant>,
+    // the sequence of frames that do not answer a request
+    sequence: u16,
+    // the anchor keeps this in eeprom, so ranging carries on after a reboot
+    started: bool,
+}
+
+impl Anchor {
+    pub fn new(ip: Ipv4Addr, mac: MacAddress8, host: Ipv4Addr) -> Anchor {
+        Anchor {
+            ip,
+            mac,
+            binary: false,
+            failure: Failure::None,
+            key: None,
+            counter: 0,
+            distances: HashMap::new(),
+            down_until: None,
+            format: WireFormat::Legacy,
+            host,
+            next_reboot: None,
+            sequence: 0,
+            started: false,
+        }
+    }
+
+    // a frame from the manager, answered with the frames to send back
+    pub fn receive<R: Rng>(&mut self, data: &[u8], now: Instant, rng: &mut R) -> Vec<Outgoing> {
+        if !self.is_up(now) || self.failure == Failure::Silent {
+            return Vec::new();
+        }
+
+        let (message, sequence) = if beacon_protocol::is_binary(data) {
+            if !self.binary {
+                return Vec::new();
+            }
+            match beacon_protocol::decode(data) {
+                Ok(packet) => {
+                    // answered in the format it was asked in, ie by a manager that did not see
+                    // the beacon reboot
+                    self.format = WireFormat::Binary(packet.version);
+                    (packet.message, Some(packet.sequence))
+                },
+                Err(_e) => return Vec::new(),
+            }
+        } else if self.format != WireFormat::Legacy {
+            // a beacon that negotiated the binary format ignores the legacy copy of a broadcast
+            return Vec::new();
+        } else {
+            // the relay only looks for the command in the text, anything malformed is ignored.
+            // it looks for "set_ip", the emulator answers the "setip" the manager sends.
+            match beacon_protocol::decode_legacy(&String::from_utf8_lossy(data)) {
+                Ok(message) => (message, None),
+                Err(_e) => return Vec::new(),
+            }
+        };
+
+        let reply = match message {
+            Message::Hello(versions) => {
+                let version = match beacon_protocol::negotiate(&versions) {
+                    Some(version) if self.binary => version,
+                    // older firmware does not know the hello
+                    _ => return Vec::new(),
+                };
+                self.format = WireFormat::Binary(version);
+                Message::HelloAck(self.mac, version)
+            },
+            Message::Start => {
+                self.started = true;
+                Message::StartAck(self.mac)
+            },
+            Message::End => {
+                self.started = false;
+                Message::EndAck(self.mac)
+            },
+            Message::Ping => Message::PingAck(self.mac),
+            Message::SetIp(host) => {
+                self.host = host;
+                Message::SetIpAck(self.mac)
+            },
+            Message::Reboot => {
+                let ack = self.frame(Message::RebootAck(self.mac), sequence);
+                let sent = self.send(ack, now + REBOOT_ACK_DELAY, rng);
+                self.reboot(now, REBOOT_ACK_DELAY + REBOOT_DOWNTIME);
+                return sent;
+            },
+            // replies from other beacons
+            _ => return Vec::new(),
+        };
+        let frame = self.frame(reply, sequence);
+        self.send(frame, now, rng)
+    }
+
+    // the ranges to each tag, sent while an emergency is on
+    pub fn range<R: Rng>(&mut self, tags: &[ShortAddress], now: Instant, rng: &mut R) -> Vec<Outgoing> {
+        if !self.is_up(now) || self.failure == Failure::Silent || !self.started {
+            return Vec::new();
+        }
+
+        let mut sent = Vec::new();
+        for tag in tags {
+            let distance = self.distances.entry(*tag).or_insert_with(|| rng.gen_range(START_RANGE.0, START_RANGE.1));
+            *distance = (*distance + rng.gen_range(-RANGE_DRIFT, RANGE_DRIFT)).max(MIN_RANGE).min(MAX_RANGE);
+            let message = Message::Range(self.mac, *tag, *distance);
+            let frame = self.frame(message, None);
+            sent.extend(self.send(frame, now, rng));
+        }
+        sent
+    }
+
+    pub fn is_up(&mut self, now: Instant) -> bool {
+        if let Failure::RebootLoop(period) = self.failure {
+            let next = *self.next_reboot.get_or_insert(now + period);
+            if now >= next {
+                self.next_reboot = Some(now + period);
+                self.reboot(now, REBOOT_DOWNTIME);
+            }
+        }
+        self.down_until.map_or(true, |until| now >= until)
+    }
+
+    fn reboot(&mut self, now: Instant, downtime: Duration) {
+        self.down_until = Some(now + downtime);
+        // the format is negotiated again after every restart
+        self.format = WireFormat::Legacy;
+    }
+
+    // encodes the message in the negotiated format, replies carry the sequence of their request
+    fn frame(&mut self, message: Message, sequence: Option<u16>) -> Vec<u8> {
+        let version = match self.format {
+            WireFormat::Binary(version) => version,
+            // the relay ends every line it forwards
+            WireFormat::Legacy => return beacon_protocol::encode_legacy(&message)
+                .map(|text| format!("{}\n", text).into_bytes())
+                .unwrap_or_default(),
+        };
+        let sequence = sequence.unwrap_or_else(|| {
+            self.sequence = self.sequence.wrapping_add(1);
+            self.sequence
+        });
+        let mut packet = Packet::new(sequence, message);
+        packet.version = version;
+        match &self.key {
+            Some(key) => {
+                self.counter += 1;
+                beacon_protocol::encode_signed(&packet, self.counter, key)
+            },
+            None => beacon_protocol::encode(&packet),
+        }
+    }
+
+    fn send<R: Rng>(&self, mut data: Vec<u8>, at: Instant, rng: &mut R) -> Vec<Outgoing> {
+        if data.is_empty() {
+            return Vec::new();
+        }
+        let at = match self.failure {
+            Failure::Slow(delay) => at + delay,
+            _ => at,
+        };
+        if let Failure::Malformed(chance) = self.failure {
+            if rng.gen_bool(chance) {
+                corrupt(&mut data, rng);
+            }
+        }
+        vec![Outgoing {
+            at,
+            data,
+            to: self.host,
+        }]
+    }
+}
+
+// breaks the crc of a binary frame, or the brackets of a legacy one
+fn corrupt<R: Rng>(data: &mut Vec<u8>, rng: &mut R) {
+    if beacon_protocol::is_binary(data) {
+        let index = rng.gen_range(beacon_protocol::HEADER_LENGTH, data.len());
+        data[index] ^= 0xff;
+    } else {
+        let half = data.len() / 2;
+        data.truncate(half);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand::rngs::SmallRng;
+
+    fn anchor() -> Anchor {
+        let mac = MacAddress8::from_bytes(&[0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff, 0, 1]).unwrap();
+        Anchor::new(Ipv4Addr::new(127, 0, 0, 2), mac, Ipv4Addr::new(127, 0, 0, 1))
+    }
+
+    fn rng() -> SmallRng {
+        SmallRng::seed_from_u64(1)
+    }
+
+    fn legacy(message: Message) -> Vec<u8> {
+        beacon_protocol::encode_legacy(&message).unwrap().into_bytes()
+    }
+
+    fn decode_legacy(sent: &[Outgoing]) -> Vec<Message> {
+        sent.iter().map(|o| beacon_protocol::decode_legacy(&String::from_utf8_lossy(&o.data)).unwrap()).collect()
+    }
+
+    #[test]
+    fn legacy_commands() {
+        let mut anchor = anchor();
+        let mut rng = rng();
+        let now = Instant::now();
+        let tag = ShortAddress::new([0, 1]);
+
+        let sent = anchor.receive(&legacy(Message::Ping), now, &mut rng);
+        assert_eq!(decode_legacy(&sent), vec![Message::PingAck(anchor.mac)]);
+        assert!(sent[0].data.ends_with(b"]\n"));
+        assert_eq!(sent[0].at, now);
+
+        // ranges only while started
+        assert!(anchor.range(&[tag], now, &mut rng).is_empty());
+        anchor.receive(&legacy(Message::Start), now, &mut rng);
+        match decode_legacy(&anchor.range(&[tag], now, &mut rng)).as_slice() {
+            [Message::Range(mac, range_tag, distance)] => {
+                assert_eq!(*mac, anchor.mac);
+                assert_eq!(*range_tag, tag);
+                assert!(*distance >= START_RANGE.0 - RANGE_DRIFT && *distance <= START_RANGE.1 + RANGE_DRIFT);
+            },
+            other => panic!("expected a range, got {:?}", other),
+        }
+
+        let host = Ipv4Addr::new(10, 0, 0, 1);
+        let sent = anchor.receive(&legacy(Message::SetIp(host)), now, &mut rng);
+        assert_eq!(decode_legacy(&sent), vec![Message::SetIpAck(anchor.mac)]);
+        assert_eq!(sent[0].to, host);
+
+        // the legacy firmware does not negotiate
+        let hello = beacon_protocol::encode(&Packet::new(0, Message::Hello(vec![beacon_protocol::VERSION])));
+        assert!(anchor.receive(&hello, now, &mut rng).is_empty());
+        assert!(anchor.receive(b"[garbage", now, &mut rng).is_empty());
+    }
+
+    #[test]
+    fn binary_commands() {
+        let mut anchor = anchor();
+        anchor.binary = true;
+        anchor.key = Some(vec![7; beacon_protocol::KEY_LENGTH]);
+        let mut rng = rng();
+        let now = Instant::now();
+
+        let hello = beacon_protocol::encode(&Packet::new(0, Message::Hello(vec![beacon_protocol::VERSION])));
+        let sent = anchor.receive(&hello, now, &mut rng);
+        assert_eq!(beacon_protocol::decode(&sent[0].data).unwrap().message, Message::HelloAck(anchor.mac, beacon_protocol::VERSION));
+
+        // replies echo the request's sequence, and are signed
+        let ping = beacon_protocol::encode(&Packet::new(42, Message::Ping));
+        let sent = anchor.receive(&ping, now, &mut rng);
+        let (packet, signature) = beacon_protocol::decode_signed(&sent[0].data).unwrap();
+        assert_eq!(packet.sequence, 42);
+        assert_eq!(packet.message, Message::PingAck(anchor.mac));
+        assert!(beacon_protocol::verify(&sent[0].data, &signature.unwrap(), &[7; beacon_protocol::KEY_LENGTH]));
+
+        // the legacy copy of a broadcast is ignored
+        assert!(anchor.receive(&legacy(Message::Ping), now, &mut rng).is_empty());
+    }
+
+    #[test]
+    fn reboot() {
+        let mut anchor = anchor();
+        anchor.binary = true;
+        let mut rng = rng();
+        let now = Instant::now();
+        let hello = beacon_protocol::encode(&Packet::new(0, Message::Hello(vec![beacon_protocol::VERSION])));
+        anchor.receive(&hello, now, &mut rng);
+
+        let reboot = beacon_protocol::encode(&Packet::new(5, Message::Reboot));
+        let sent = anchor.receive(&reboot, now, &mut rng);
+        assert_eq!(sent[0].at, now + REBOOT_ACK_DELAY);
+        assert_eq!(beacon_protocol::decode(&sent[0].data).unwrap().message, Message::RebootAck(anchor.mac));
+
+        let ping = beacon_protocol::encode(&Packet::new(6, Message::Ping));
+        assert!(anchor.receive(&ping, now + REBOOT_ACK_DELAY, &mut rng).is_empty());
+
+        // back up, and answering legacy broadcasts until it is addressed in binary again
+        let later = now + REBOOT_ACK_DELAY + REBOOT_DOWNTIME;
+        assert_eq!(decode_legacy(&anchor.receive(&legacy(Message::Ping), later, &mut rng)), vec![Message::PingAck(anchor.mac)]);
+        let sent = anchor.receive(&ping, later, &mut rng);
+        assert_eq!(beacon_protocol::decode(&sent[0].data).unwrap().sequence, 6);
+        assert!(anchor.receive(&legacy(Message::Ping), later, &mut rng).is_empty());
+    }
+
+    #[test]
+    fn failures() {
+        let mut rng = rng();
+        let now = Instant::now();
+        let ping = legacy(Message::Ping);
+
+        let mut silent = anchor();
+        silent.failure = Failure::Silent;
+        assert!(silent.receive(&ping, now, &mut rng).is_empty());
+
+        let mut slow = anchor();
+        slow.failure = Failure::Slow(Duration::from_millis(500));
+        assert_eq!(slow.receive(&ping, now, &mut rng)[0].at, now + Duration::from_millis(500));
+
+        let mut looping = anchor();
+        looping.failure = Failure::RebootLoop(Duration::from_secs(10));
+        assert_eq!(looping.receive(&ping, now, &mut rng).len(), 1);
+        assert!(looping.receive(&ping, now + Duration::from_secs(10), &mut rng).is_empty());
+        assert_eq!(looping.receive(&ping, now + Duration::from_secs(10) + REBOOT_DOWNTIME, &mut rng).len(), 1);
+
+        let mut malformed = anchor();
+        malformed.failure = Failure::Malformed(1.0);
+        let sent = malformed.receive(&ping, now, &mut rng);
+        assert!(beacon_protocol::decode_legacy(&String::from_utf8_lossy(&sent[0].data)).is_err());
+        malformed.binary = true;
+        let hello = beacon_protocol::encode(&Packet::new(0, Message::Hello(vec![beacon_protocol::VERSION])));
+        let sent = malformed.receive(&hello, now, &mut rng);
+        assert!(beacon_protocol::decode(&sent[0].data).is_err());
+    }
+
+    #[test]
+    fn parse_failure() {
+        assert_eq!("silent".parse::<Failure>(), Ok(Failure::Silent));
+        assert_eq!("slow:250".parse::<Failure>(), Ok(Failure::Slow(Duration::from_millis(250))));
+        assert_eq!("reboot-loop:30".parse::<Failure>(), Ok(Failure::RebootLoop(Duration::from_secs(30))));
+        assert_eq!("malformed:0.5".parse::<Failure>(), Ok(Failure::Malformed(0.5)));
+        assert!("slow".parse::<Failure>().is_err());
+        assert!("malformed:2".parse::<Failure>().is_err());
+        assert!("reboot-loop:0".parse::<Failure>().is_err());
+        assert!("sometimes".parse::<Failure>().is_err());
+    }
+}
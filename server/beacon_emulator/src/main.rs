@@ -0,0 +1,257 @@
+// Emulates anchor beacons on localhost, so the backend's udp connection can be run against
+// something other than real hardware. Each anchor gets its own loopback address, ie 127.0.0.2,
+// 127.0.0.3 and so on, and listens on the beacon port the way the relay firmware does. Add a
+// network interface in the backend with an address on 127.0.0.0/8 and the same beacon port, and
+// the anchors answer its broadcasts and show up as discovered beacons. Their messages are only
+// acted on once they are registered and given the key passed with --key, or when the backend
+// accepts unsigned messages with beacons.allow_unsigned.
+//
+// The backend listens on every address, so the anchors share the port with it. Linux delivers
+// frames sent to an anchor's address to that anchor, and broadcasts to every socket on the port.
+
+extern crate clap;
+extern crate common;
+extern crate net2;
+extern crate rand;
+
+mod anchor;
+
+use anchor::{ Anchor, Failure, Outgoing, };
+use clap::{ App, Arg, ArgMatches, };
+use common::{ MacAddress8, ShortAddress, };
+use common::beacon_protocol::KEY_LENGTH;
+use net2::UdpBuilder;
+use rand::SeedableRng;
+use rand::rngs::SmallRng;
+use std::collections::HashMap;
+use std::io;
+use std::net::{ Ipv4Addr, SocketAddr, UdpSocket, };
+use std::process;
+use std::str::FromStr;
+use std::sync::mpsc::{ self, Receiver, RecvTimeoutError, Sender, };
+use std::thread;
+use std::time::{ Duration, Instant, };
+
+const MAX_FRAME_LENGTH: usize = 1024;
+
+#[derive(Clone)]
+struct Options {
+    anchors: u8,
+    binary: bool,
+    broadcast: Ipv4Addr,
+    failures: HashMap<u8, Failure>,
+    first_ip: Ipv4Addr,
+    host: Ipv4Addr,
+    key: Option<Vec<u8>>,
+    port: u16,
+    range_interval: Duration,
+    seed: Option<u64>,
+    tags: Vec<ShortAddress>,
+}
+
+fn cli<'a, 'b>() -> App<'a, 'b> {
+    let value = |name: &'a str, help: &'a str| {
+        Arg::with_name(name)
+            .long(name)
+            .takes_value(true)
+            .help(help)
+    };
+
+    App::new("beacon_emulator")
+        .about("Emulates Akriveia anchor beacons on localhost udp")
+        .arg(value("anchors", "number of anchors").default_value("4"))
+        .arg(value("first-ip", "address of the first anchor, the rest count up from it").default_value("127.0.0.2"))
+        .arg(value("port", "beacon port of the backend's network interface").default_value("9996"))
+        .arg(value("host", "where the anchors send their replies and ranges, until a setip").default_value("127.0.0.1"))
+        .arg(value("broadcast", "broadcast address the backend sends to").default_value("127.255.255.255"))
+        .arg(value("tags", "comma separated tags that are ranged during an emergency").default_value("00:01"))
+        .arg(value("range-interval", "milliseconds between the ranges to each tag").default_value("1000"))
+        .arg(Arg::with_name("binary")
+            .long("binary")
+            .help("answer hellos and speak the binary format, like newer firmware"))
+        .arg(value("key", "hex encoded key the anchors sign binary frames with"))
+        .arg(value("fail", "failure of an anchor, ie 2=slow:500. none, silent, slow:<ms>, reboot-loop:<seconds>, malformed:<chance>")
+            .multiple(true)
+            .number_of_values(1))
+        .arg(value("seed", "seed of the ranges, random when not given"))
+}
+
+fn parse<T: FromStr>(matches: &ArgMatches, name: &str) -> Result<T, String> {
+    let raw = matches.value_of(name).unwrap_or("");
+    raw.parse::<T>().map_err(|_e| format!("invalid value for --{}: {}", name, raw))
+}
+
+fn decode_hex(value: &str) -> Option<Vec<u8>> {
+    if value.len() % 2 != 0 || !value.is_ascii() {
+        return None;
+    }
+    (0..value.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&value[i..i + 2], 16).ok())
+        .collect()
+}
+
+impl Options {
+    fn from_args(matches: &ArgMatches) -> Result<Options, String> {
+        let anchors: u8 = parse(matches, "anchors")?;
+        let first_ip: Ipv4Addr = parse(matches, "first-ip")?;
+        if anchors == 0 || first_ip.octets()[3] as usize + anchors as usize > 255 {
+            return Err(format!("{} anchors do not fit after {}", anchors, first_ip));
+        }
+
+        let tags = matches.value_of("tags").unwrap_or("")
+            .split(',')
+            .filter(|tag| !tag.trim().is_empty())
+            .map(|tag| ShortAddress::parse_str(tag.trim()).map_err(|e| format!("invalid tag {}: {}", tag, e)))
+            .collect::<Result<Vec<ShortAddress>, String>>()?;
+
+        let mut failures = HashMap::new();
+        for fail in matches.values_of("fail").into_iter().flatten() {
+            let mut split = fail.splitn(2, '=');
+            let index = split.next().and_then(|index| index.parse::<u8>().ok())
+                .filter(|index| *index >= 1 && *index <= anchors)
+                .ok_or_else(|| format!("--fail needs an anchor between 1 and {}, ie 1=silent, not {}", anchors, fail))?;
+            let failure = split.next().unwrap_or("").parse::<Failure>()?;
+            failures.insert(index, failure);
+        }
+
+        let range_interval: u64 = parse(matches, "range-interval")?;
+        if range_interval == 0 {
+            return Err("--range-interval must be greater than 0".to_string());
+        }
+        let seed = match matches.value_of("seed") {
+            Some(_seed) => Some(parse(matches, "seed")?),
+            None => None,
+        };
+
+        let key = match matches.value_of("key") {
+            Some(hex) => match decode_hex(hex.trim()) {
+                Some(key) if key.len() == KEY_LENGTH => Some(key),
+                _ => return Err(format!("--key must be {} hex encoded bytes", KEY_LENGTH)),
+            },
+            None => None,
+        };
+
+        Ok(Options {
+            anchors,
+            binary: matches.is_present("binary") || key.is_some(),
+            broadcast: parse(matches, "broadcast")?,
+            failures,
+            first_ip,
+            host: parse(matches, "host")?,
+            key,
+            port: parse(matches, "port")?,
+            range_interval: Duration::from_millis(range_interval),
+            seed,
+            tags,
+        })
+    }
+}
+
+// the port is shared with the backend, and with the other anchors for broadcasts
+fn bind(ip: Ipv4Addr, port: u16) -> io::Result<UdpSocket> {
+    UdpBuilder::new_v4()?
+        .reuse_address(true)?
+        .bind(SocketAddr::new(ip.into(), port))
+}
+
+// hands every frame received on the socket to the anchors listening to it
+fn listen(socket: UdpSocket, anchors: Vec<Sender<Vec<u8>>>) {
+    thread::spawn(move || {
+        let mut buffer = [0; MAX_FRAME_LENGTH];
+        loop {
+            match socket.recv_from(&mut buffer) {
+                Ok((length, _from)) => {
+                    for anchor in &anchors {
+                        let _ = anchor.send(buffer[..length].to_vec());
+                    }
+                },
+                Err(e) => {
+                    println!("failed to receive: {}", e);
+                    return;
+                },
+            }
+        }
+    });
+}
+
+fn run(mut anchor: Anchor, socket: UdpSocket, frames: Receiver<Vec<u8>>, options: &Options, mut rng: SmallRng) {
+    let mut pending: Vec<Outgoing> = Vec::new();
+    let mut next_range = Instant::now() + options.range_interval;
+    loop {
+        let now = Instant::now();
+        let deadline = pending.iter().map(|o| o.at).fold(next_range, |a, b| a.min(b));
+        let wait = if deadline > now { deadline - now } else { Duration::from_millis(0) };
+        match frames.recv_timeout(wait) {
+            Ok(frame) => pending.extend(anchor.receive(&frame, Instant::now(), &mut rng)),
+            Err(RecvTimeoutError::Timeout) => {},
+            Err(RecvTimeoutError::Disconnected) => return,
+        }
+
+        let now = Instant::now();
+        if now >= next_range {
+            pending.extend(anchor.range(&options.tags, now, &mut rng));
+            next_range += options.range_interval;
+        }
+
+        let (due, later): (Vec<Outgoing>, Vec<Outgoing>) = pending.into_iter().partition(|o| o.at <= now);
+        pending = later;
+        for outgoing in due {
+            if let Err(e) = socket.send_to(&outgoing.data, SocketAddr::new(outgoing.to.into(), options.port)) {
+                println!("anchor {} failed to send to {}: {}", anchor.ip, outgoing.to, e);
+            }
+        }
+    }
+}
+
+fn main() {
+    let matches = cli().get_matches();
+    let options = match Options::from_args(&matches) {
+        Ok(options) => options,
+        Err(e) => {
+            println!("{}", e);
+            process::exit(1);
+        },
+    };
+
+    let mut broadcast_listeners = Vec::new();
+    let mut threads = Vec::new();
+    for index in 1..=options.anchors {
+        let [a, b, c, d] = options.first_ip.octets();
+        let ip = Ipv4Addr::new(a, b, c, d + index - 1);
+        // the default eui of the relay firmware, numbered
+        let mac = MacAddress8::from_bytes(&[0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff, 0x00, index]).unwrap();
+        let mut anchor = Anchor::new(ip, mac, options.host);
+        anchor.binary = options.binary;
+        anchor.failure = options.failures.get(&index).cloned().unwrap_or(Failure::None);
+        anchor.key = options.key.clone();
+
+        let socket = match bind(ip, options.port) {
+            Ok(socket) => socket,
+            Err(e) => {
+                println!("failed to bind anchor {} to {}:{}: {}", index, ip, options.port, e);
+                process::exit(1);
+            },
+        };
+        let (sender, frames) = mpsc::channel();
+        listen(socket.try_clone().unwrap(), vec![sender.clone()]);
+        broadcast_listeners.push(sender);
+
+        println!("anchor {} at {} with mac {}, {:?}", index, ip, mac.to_hex_string(), anchor.failure);
+        let rng = match options.seed {
+            Some(seed) => SmallRng::seed_from_u64(seed.wrapping_add(index as u64)),
+            None => SmallRng::from_entropy(),
+        };
+        let anchor_options = options.clone();
+        threads.push(thread::spawn(move || run(anchor, socket, frames, &anchor_options, rng)));
+    }
+
+    match bind(options.broadcast, options.port) {
+        Ok(socket) => listen(socket, broadcast_listeners),
+        Err(e) => println!("failed to listen for broadcasts on {}:{}, only direct commands will be answered: {}", options.broadcast, options.port, e),
+    }
+
+    for thread in threads {
+        let _ = thread.join();
+    }
+}
@@ -0,0 +1,767 @@
+// The messages exchanged between the beacon manager and the anchor beacons.
+//
+// Two wire formats are understood. The legacy format is the bracketed text sent by the original
+// firmware, ie "[start]" or "<[mac|range_ack|0x00ab|1.25]>". The binary format is versioned and
+// framed, all integers are big endian:
+//
+//   offset  size  field
+//   0       2     magic, "AK"
+//   2       1     protocol version
+//   3       1     message type
+//   4       2     sequence number
+//   6       2     payload length, n
+//   8       n     payload, a list of fields. each field is a type byte, a length byte, and a value
+//   8 + n   4     crc32 (ieee) of every preceding byte
+//
+// Replies carry the sequence number of the request they answer, messages sent unprompted by a
+// beacon (ranges) use the beacon's own counter. Unknown field types are skipped, so newer beacons
+// can add fields without breaking older managers.
+//
+// The format is negotiated per beacon. The manager broadcasts a Hello listing the versions it
+// supports, and a beacon that understands the binary format replies with a HelloAck in the
+// version it picked. From then on that beacon is addressed in binary, and ignores bracketed
+// commands. Beacons that never reply to the Hello keep using the legacy format.
+//
+// Beacons provisioned with a key sign every frame they send. A signed payload ends with a counter
+// field, which the beacon increments for every frame and keeps across reboots, and a hmac field
+// holding the first 16 bytes of a hmac-sha256 over every byte of the frame before the hmac value.
+// The crc still follows. The manager drops frames with a counter it has already accepted, see
+// ReplayWindow. The legacy format can not be signed.
+
+use crate::ShortAddress;
+use eui64::MacAddress8;
+use hmac::{ Hmac, Mac, };
+use sha2::Sha256;
+use std::error::Error;
+use std::fmt;
+use std::net::Ipv4Addr;
+
+pub const MAGIC: [u8; 2] = *b"AK";
+pub const VERSION: u8 = 1;
+pub const SUPPORTED_VERSIONS: &[u8] = &[VERSION];
+pub const HEADER_LENGTH: usize = 8;
+pub const CRC_LENGTH: usize = 4;
+pub const MAX_PAYLOAD_LENGTH: usize = 512;
+pub const KEY_LENGTH: usize = 32;
+pub const HMAC_LENGTH: usize = 16;
+
+// message types, a reply is always its request with the high bit set
+const MSG_HELLO: u8 = 0x01;
+const MSG_START: u8 = 0x02;
+const MSG_END: u8 = 0x03;
+const MSG_PING: u8 = 0x04;
+const MSG_REBOOT: u8 = 0x05;
+const MSG_SET_IP: u8 = 0x06;
+const MSG_ACK: u8 = 0x80;
+const MSG_RANGE: u8 = 0x90;
+
+// field types
+const FIELD_BEACON_MAC: u8 = 0x01;
+const FIELD_TAG_MAC: u8 = 0x02;
+// unsigned millimeters
+const FIELD_DISTANCE: u8 = 0x03;
+const FIELD_IP: u8 = 0x04;
+const FIELD_VERSIONS: u8 = 0x05;
+const FIELD_VERSION: u8 = 0x06;
+const FIELD_COUNTER: u8 = 0x07;
+// always the last field
+const FIELD_HMAC: u8 = 0x08;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WireFormat {
+    Legacy,
+    Binary(u8),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Message {
+    // manager to beacon
+    Hello(Vec<u8>),
+    Start,
+    End,
+    Ping,
+    Reboot,
+    SetIp(Ipv4Addr),
+
+    // beacon to manager
+    HelloAck(MacAddress8, u8),
+    StartAck(MacAddress8),
+    EndAck(MacAddress8),
+    PingAck(MacAddress8),
+    RebootAck(MacAddress8),
+    SetIpAck(MacAddress8),
+    Range(MacAddress8, ShortAddress, f64),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Packet {
+    pub version: u8,
+    pub sequence: u16,
+    pub message: Message,
+}
+
+// the authentication of a signed frame, checked against the beacon's key with verify
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Signature {
+    pub counter: u32,
+    tag: [u8; HMAC_LENGTH],
+    // the number of bytes at the start of the frame covered by the tag
+    signed_length: usize,
+}
+
+// Tracks the counters of the last 64 frames accepted from a beacon. Frames may arrive out of
+// order, but each counter is only accepted once, and counters too old to track are refused.
+#[derive(Debug, Clone, Default)]
+pub struct ReplayWindow {
+    highest: Option<u32>,
+    // bit n is set when highest - n was accepted
+    seen: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProtocolError {
+    Truncated,
+    BadMagic,
+    BadLength,
+    BadCrc,
+    UnsupportedVersion(u8),
+    UnknownMessage(u8),
+    MissingField(u8),
+    BadField(u8),
+    // the legacy text could not be parsed
+    Format,
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProtocolError::Truncated => write!(f, "Frame is shorter than its header"),
+            ProtocolError::BadMagic => write!(f, "Frame does not start with the protocol magic"),
+            ProtocolError::BadLength => write!(f, "Frame length does not match its payload length"),
+            ProtocolError::BadCrc => write!(f, "Frame crc does not match its contents"),
+            ProtocolError::UnsupportedVersion(v) => write!(f, "Unsupported protocol version {}", v),
+            ProtocolError::UnknownMessage(t) => write!(f, "Unknown message type {:#04x}", t),
+            ProtocolError::MissingField(t) => write!(f, "Missing field {:#04x}", t),
+            ProtocolError::BadField(t) => write!(f, "Invalid field {:#04x}", t),
+            ProtocolError::Format => write!(f, "Invalid message format"),
+        }
+    }
+}
+
+impl Error for ProtocolError {}
+
+impl Message {
+    // the beacon that sent the message, None for messages sent by the manager
+    pub fn beacon_mac(&self) -> Option<MacAddress8> {
+        match self {
+            Message::HelloAck(mac, _)
+                | Message::StartAck(mac)
+                | Message::EndAck(mac)
+                | Message::PingAck(mac)
+                | Message::RebootAck(mac)
+                | Message::SetIpAck(mac)
+                | Message::Range(mac, _, _) => Some(*mac),
+            Message::Hello(_)
+                | Message::Start
+                | Message::End
+                | Message::Ping
+                | Message::Reboot
+                | Message::SetIp(_) => None,
+        }
+    }
+}
+
+impl Packet {
+    pub fn new(sequence: u16, message: Message) -> Packet {
+        Packet {
+            version: VERSION,
+            sequence,
+            message,
+        }
+    }
+}
+
+// whether the bytes are meant to be a binary frame, rather than legacy text
+pub fn is_binary(data: &[u8]) -> bool {
+    data.starts_with(&MAGIC)
+}
+
+// picks the newest version both sides support
+pub fn negotiate(offered: &[u8]) -> Option<u8> {
+    offered.iter().filter(|v| SUPPORTED_VERSIONS.contains(v)).max().cloned()
+}
+
+pub fn encode(packet: &Packet) -> Vec<u8> {
+    let (message_type, fields) = message_fields(&packet.message);
+    build_frame(packet, message_type, fields)
+}
+
+// encodes the packet signed with the key, the counter must never be reused with the same key
+pub fn encode_signed(packet: &Packet, counter: u32, key: &[u8]) -> Vec<u8> {
+    let (message_type, mut fields) = message_fields(&packet.message);
+    fields.push((FIELD_COUNTER, counter.to_be_bytes().to_vec()));
+    fields.push((FIELD_HMAC, vec![0; HMAC_LENGTH]));
+    let mut frame = build_frame(packet, message_type, fields);
+
+    let crc_start = frame.len() - CRC_LENGTH;
+    let tag_start = crc_start - HMAC_LENGTH;
+    let tag = hmac_tag(key, &frame[..tag_start]);
+    frame[tag_start..crc_start].copy_from_slice(&tag);
+    let crc = crc32(&frame[..crc_start]);
+    frame[crc_start..].copy_from_slice(&crc.to_be_bytes());
+    frame
+}
+
+fn build_frame(packet: &Packet, message_type: u8, fields: Vec<(u8, Vec<u8>)>) -> Vec<u8> {
+    let mut payload = Vec::new();
+    for (field_type, value) in fields {
+        assert!(value.len() <= u8::max_value() as usize);
+        payload.push(field_type);
+        payload.push(value.len() as u8);
+        payload.extend_from_slice(&value);
+    }
+    assert!(payload.len() <= MAX_PAYLOAD_LENGTH);
+
+    let mut frame = Vec::with_capacity(HEADER_LENGTH + payload.len() + CRC_LENGTH);
+    frame.extend_from_slice(&MAGIC);
+    frame.push(packet.version);
+    frame.push(message_type);
+    frame.extend_from_slice(&packet.sequence.to_be_bytes());
+    frame.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+    frame.extend_from_slice(&payload);
+    let crc = crc32(&frame);
+    frame.extend_from_slice(&crc.to_be_bytes());
+    frame
+}
+
+pub fn decode(data: &[u8]) -> Result<Packet, ProtocolError> {
+    decode_signed(data).map(|(packet, _signature)| packet)
+}
+
+// decodes the frame along with its signature, if it was signed. The signature is not verified.
+pub fn decode_signed(data: &[u8]) -> Result<(Packet, Option<Signature>), ProtocolError> {
+    if data.len() < HEADER_LENGTH + CRC_LENGTH {
+        return Err(ProtocolError::Truncated);
+    }
+    if !is_binary(data) {
+        return Err(ProtocolError::BadMagic);
+    }
+    let payload_length = u16::from_be_bytes([data[6], data[7]]) as usize;
+    if payload_length > MAX_PAYLOAD_LENGTH || data.len() != HEADER_LENGTH + payload_length + CRC_LENGTH {
+        return Err(ProtocolError::BadLength);
+    }
+    let crc_start = HEADER_LENGTH + payload_length;
+    let crc = u32::from_be_bytes([data[crc_start], data[crc_start + 1], data[crc_start + 2], data[crc_start + 3]]);
+    if crc != crc32(&data[..crc_start]) {
+        return Err(ProtocolError::BadCrc);
+    }
+
+    let version = data[2];
+    if !SUPPORTED_VERSIONS.contains(&version) {
+        return Err(ProtocolError::UnsupportedVersion(version));
+    }
+    let message_type = data[3];
+    let sequence = u16::from_be_bytes([data[4], data[5]]);
+    let fields = Fields::parse(&data[HEADER_LENGTH..crc_start])?;
+
+    let message = match message_type {
+        MSG_HELLO => Message::Hello(fields.get(FIELD_VERSIONS)?.to_vec()),
+        MSG_START => Message::Start,
+        MSG_END => Message::End,
+        MSG_PING => Message::Ping,
+        MSG_REBOOT => Message::Reboot,
+        MSG_SET_IP => Message::SetIp(fields.ip(FIELD_IP)?),
+        t if t == MSG_HELLO | MSG_ACK => Message::HelloAck(fields.mac(FIELD_BEACON_MAC)?, fields.byte(FIELD_VERSION)?),
+        t if t == MSG_START | MSG_ACK => Message::StartAck(fields.mac(FIELD_BEACON_MAC)?),
+        t if t == MSG_END | MSG_ACK => Message::EndAck(fields.mac(FIELD_BEACON_MAC)?),
+        t if t == MSG_PING | MSG_ACK => Message::PingAck(fields.mac(FIELD_BEACON_MAC)?),
+        t if t == MSG_REBOOT | MSG_ACK => Message::RebootAck(fields.mac(FIELD_BEACON_MAC)?),
+        t if t == MSG_SET_IP | MSG_ACK => Message::SetIpAck(fields.mac(FIELD_BEACON_MAC)?),
+        MSG_RANGE => Message::Range(
+            fields.mac(FIELD_BEACON_MAC)?,
+            fields.short(FIELD_TAG_MAC)?,
+            fields.u32(FIELD_DISTANCE)? as f64 / 1000.0,
+        ),
+        t => return Err(ProtocolError::UnknownMessage(t)),
+    };
+
+    let signature = match fields.fields.last() {
+        Some((FIELD_HMAC, offset, value)) => {
+            if value.len() != HMAC_LENGTH {
+                return Err(ProtocolError::BadField(FIELD_HMAC));
+            }
+            let mut tag = [0; HMAC_LENGTH];
+            tag.copy_from_slice(value);
+            Some(Signature {
+                counter: fields.u32(FIELD_COUNTER)?,
+                tag,
+                signed_length: HEADER_LENGTH + offset,
+            })
+        },
+        // the hmac can not cover the fields after it
+        _ if fields.get(FIELD_HMAC).is_ok() => return Err(ProtocolError::BadField(FIELD_HMAC)),
+        _ => None,
+    };
+
+    Ok((Packet {
+        version,
+        sequence,
+        message,
+    }, signature))
+}
+
+// whether the frame the signature was decoded from was signed with the key
+pub fn verify(data: &[u8], signature: &Signature, key: &[u8]) -> bool {
+    if data.len() < signature.signed_length {
+        return false;
+    }
+    let expected = hmac_tag(key, &data[..signature.signed_length]);
+    // compare every byte, so the time taken does not reveal how much of the tag matched
+    expected.iter()
+        .zip(signature.tag.iter())
+        .fold(0u8, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+fn hmac_tag(key: &[u8], data: &[u8]) -> [u8; HMAC_LENGTH] {
+    let mut mac = Hmac::<Sha256>::new_varkey(key).expect("hmac accepts keys of any length");
+    mac.input(data);
+    let code = mac.result().code();
+    let mut tag = [0; HMAC_LENGTH];
+    tag.copy_from_slice(&code[..HMAC_LENGTH]);
+    tag
+}
+
+impl ReplayWindow {
+    pub const SIZE: u32 = 64;
+
+    pub fn new() -> ReplayWindow {
+        ReplayWindow::default()
+    }
+
+    // continues after the highest counter accepted before, ie before a restart. whatever was
+    // accepted below it is not known, so none of it is accepted again.
+    pub fn resume(highest: u32) -> ReplayWindow {
+        ReplayWindow {
+            highest: Some(highest),
+            seen: u64::max_value(),
+        }
+    }
+
+    pub fn highest(&self) -> Option<u32> {
+        self.highest
+    }
+
+    // records the counter, returns false if it was already accepted or is too old to tell
+    pub fn accept(&mut self, counter: u32) -> bool {
+        match self.highest {
+            Some(highest) if counter <= highest => {
+                let age = highest - counter;
+                if age >= ReplayWindow::SIZE {
+                    return false;
+                }
+                let bit = 1u64 << age;
+                if self.seen & bit != 0 {
+                    return false;
+                }
+                self.seen |= bit;
+            },
+            Some(highest) => {
+                let shift = counter - highest;
+                self.seen = if shift >= ReplayWindow::SIZE { 0 } else { self.seen << shift };
+                self.seen |= 1;
+                self.highest = Some(counter);
+            },
+            None => {
+                self.seen = 1;
+                self.highest = Some(counter);
+            },
+        }
+        true
+    }
+}
+
+fn message_fields(message: &Message) -> (u8, Vec<(u8, Vec<u8>)>) {
+    let mac = |mac: &MacAddress8| (FIELD_BEACON_MAC, mac.as_bytes().to_vec());
+    match message {
+        Message::Hello(versions) => (MSG_HELLO, vec![(FIELD_VERSIONS, versions.clone())]),
+        Message::Start => (MSG_START, Vec::new()),
+        Message::End => (MSG_END, Vec::new()),
+        Message::Ping => (MSG_PING, Vec::new()),
+        Message::Reboot => (MSG_REBOOT, Vec::new()),
+        Message::SetIp(ip) => (MSG_SET_IP, vec![(FIELD_IP, ip.octets().to_vec())]),
+        Message::HelloAck(m, version) => (MSG_HELLO | MSG_ACK, vec![mac(m), (FIELD_VERSION, vec![*version])]),
+        Message::StartAck(m) => (MSG_START | MSG_ACK, vec![mac(m)]),
+        Message::EndAck(m) => (MSG_END | MSG_ACK, vec![mac(m)]),
+        Message::PingAck(m) => (MSG_PING | MSG_ACK, vec![mac(m)]),
+        Message::RebootAck(m) => (MSG_REBOOT | MSG_ACK, vec![mac(m)]),
+        Message::SetIpAck(m) => (MSG_SET_IP | MSG_ACK, vec![mac(m)]),
+        Message::Range(m, tag, distance) => (MSG_RANGE, vec![
+            mac(m),
+            (FIELD_TAG_MAC, tag.as_bytes().to_vec()),
+            (FIELD_DISTANCE, to_millimeters(*distance).to_be_bytes().to_vec()),
+        ]),
+    }
+}
+
+fn to_millimeters(distance: f64) -> u32 {
+    let mm = (distance * 1000.0).round();
+    if mm.is_nan() || mm <= 0.0 {
+        0
+    } else if mm >= u32::max_value() as f64 {
+        u32::max_value()
+    } else {
+        mm as u32
+    }
+}
+
+struct Fields<'a> {
+    // the type, the offset of the value in the payload, and the value of each field
+    fields: Vec<(u8, usize, &'a [u8])>,
+}
+
+impl<'a> Fields<'a> {
+    fn parse(payload: &'a [u8]) -> Result<Fields<'a>, ProtocolError> {
+        let mut fields = Vec::new();
+        let mut offset = 0;
+        while offset < payload.len() {
+            if payload.len() < offset + 2 {
+                return Err(ProtocolError::BadLength);
+            }
+            let (field_type, length) = (payload[offset], payload[offset + 1] as usize);
+            let start = offset + 2;
+            if payload.len() < start + length {
+                return Err(ProtocolError::BadLength);
+            }
+            fields.push((field_type, start, &payload[start..start + length]));
+            offset = start + length;
+        }
+        Ok(Fields { fields })
+    }
+
+    fn get(&self, field_type: u8) -> Result<&'a [u8], ProtocolError> {
+        self.fields.iter()
+            .find(|(t, _offset, _value)| *t == field_type)
+            .map(|(_t, _offset, value)| *value)
+            .ok_or(ProtocolError::MissingField(field_type))
+    }
+
+    fn sized(&self, field_type: u8, size: usize) -> Result<&'a [u8], ProtocolError> {
+        let value = self.get(field_type)?;
+        if value.len() == size {
+            Ok(value)
+        } else {
+            Err(ProtocolError::BadField(field_type))
+        }
+    }
+
+    fn byte(&self, field_type: u8) -> Result<u8, ProtocolError> {
+        self.sized(field_type, 1).map(|v| v[0])
+    }
+
+    fn u32(&self, field_type: u8) -> Result<u32, ProtocolError> {
+        self.sized(field_type, 4).map(|v| u32::from_be_bytes([v[0], v[1], v[2], v[3]]))
+    }
+
+    fn mac(&self, field_type: u8) -> Result<MacAddress8, ProtocolError> {
+        MacAddress8::from_bytes(self.sized(field_type, 8)?)
+            .map_err(|_e| ProtocolError::BadField(field_type))
+    }
+
+    fn short(&self, field_type: u8) -> Result<ShortAddress, ProtocolError> {
+        ShortAddress::from_bytes(self.sized(field_type, 2)?)
+            .map_err(|_e| ProtocolError::BadField(field_type))
+    }
+
+    fn ip(&self, field_type: u8) -> Result<Ipv4Addr, ProtocolError> {
+        self.sized(field_type, 4).map(|v| Ipv4Addr::new(v[0], v[1], v[2], v[3]))
+    }
+}
+
+// bitwise crc32 (ieee 802.3), small enough to match on the firmware without a table
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffffu32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            let mask = (!(crc & 1)).wrapping_add(1);
+            crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+// The legacy text format. Hello has no legacy form, legacy beacons do not negotiate.
+pub fn encode_legacy(message: &Message) -> Option<String> {
+    let text = match message {
+        Message::Hello(_) => return None,
+        Message::Start => "[start]".to_string(),
+        Message::End => "[end]".to_string(),
+        Message::Ping => "[ping]".to_string(),
+        Message::Reboot => "[reboot]".to_string(),
+        Message::SetIp(ip) => format!("[setip|{}]", ip),
+        Message::HelloAck(..) => return None,
+        Message::StartAck(mac) => format!("[{}|start_ack]", mac.to_hex_string()),
+        Message::EndAck(mac) => format!("[{}|end_ack]", mac.to_hex_string()),
+        Message::PingAck(mac) => format!("[{}|ping_ack]", mac.to_hex_string()),
+        Message::RebootAck(mac) => format!("[{}|reboot_ack]", mac.to_hex_string()),
+        Message::SetIpAck(mac) => format!("[{}|setip_ack]", mac.to_hex_string()),
+        Message::Range(mac, tag, distance) => format!("[{}|range_ack|{}|{:.2}]", mac.to_hex_string(), tag, distance),
+    };
+    Some(text)
+}
+
+// Parses the first bracketed message in the text, anything around the brackets (the firmware's
+// "<" and ">" markers, line endings) is ignored.
+pub fn decode_legacy(text: &str) -> Result<Message, ProtocolError> {
+    let start = text.find('[').ok_or(ProtocolError::Format)?;
+    let end = text[start..].find(']').ok_or(ProtocolError::Format)? + start;
+    let split: Vec<&str> = text[start + 1..end].split('|').map(|s| s.trim()).collect();
+
+    match (split.len(), split[0]) {
+        (1, "start") => return Ok(Message::Start),
+        (1, "end") => return Ok(Message::End),
+        (1, "ping") => return Ok(Message::Ping),
+        (1, "reboot") => return Ok(Message::Reboot),
+        (2, "setip") => return split[1].parse().map(Message::SetIp).map_err(|_e| ProtocolError::Format),
+        (1, _) => return Err(ProtocolError::Format),
+        _ => {},
+    }
+
+    // every reply starts with the mac of the beacon
+    let mac = MacAddress8::parse_str(split[0]).map_err(|_e| ProtocolError::Format)?;
+    match (split.len(), split[1]) {
+        (2, "start_ack") => Ok(Message::StartAck(mac)),
+        (2, "end_ack") => Ok(Message::EndAck(mac)),
+        (2, "ping_ack") => Ok(Message::PingAck(mac)),
+        (2, "reboot_ack") => Ok(Message::RebootAck(mac)),
+        (2, "setip_ack") => Ok(Message::SetIpAck(mac)),
+        (4, "range_ack") => {
+            let tag = ShortAddress::parse_str(split[2]).map_err(|_e| ProtocolError::Format)?;
+            Ok(Message::Range(mac, tag, parse_distance(split[3])?))
+        },
+        _ => Err(ProtocolError::Format),
+    }
+}
+
+// the firmware prints distances with arduino's String(double), keep only the number
+fn parse_distance(raw: &str) -> Result<f64, ProtocolError> {
+    let numeric: String = raw.chars()
+        .filter(|c| c.is_ascii_digit() || *c == '.' || *c == '-')
+        .collect();
+    match numeric.parse::<f64>() {
+        Ok(d) if d.is_finite() => Ok(d),
+        _ => Err(ProtocolError::Format),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn mac(n: u8) -> MacAddress8 {
+        MacAddress8::from_bytes(&[n, 1, 2, 3, 4, 5, 6, 7]).unwrap()
+    }
+
+    #[test]
+    fn crc_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn legacy_firmware() {
+        let m = mac(0x0a);
+        let text = format!("<[{}|range_ack|0x00ab|1.25]>\r\n", m.to_hex_string());
+        assert_eq!(decode_legacy(&text), Ok(Message::Range(m, ShortAddress::new([0x00, 0xab]), 1.25)));
+
+        let text = format!("<[{}|ping_ack]>\r\n", m.to_hex_string());
+        assert_eq!(decode_legacy(&text), Ok(Message::PingAck(m)));
+
+        assert_eq!(decode_legacy("[setip|10.0.0.2]"), Ok(Message::SetIp(Ipv4Addr::new(10, 0, 0, 2))));
+        assert_eq!(decode_legacy("[start"), Err(ProtocolError::Format));
+        assert_eq!(decode_legacy("[start_ack]"), Err(ProtocolError::Format));
+    }
+
+    #[test]
+    fn corrupt_frames() {
+        let frame = encode(&Packet::new(7, Message::PingAck(mac(1))));
+        assert!(decode(&frame).is_ok());
+
+        let mut flipped = frame.clone();
+        flipped[HEADER_LENGTH] ^= 0x01;
+        assert_eq!(decode(&flipped), Err(ProtocolError::BadCrc));
+
+        assert_eq!(decode(&frame[..frame.len() - 1]), Err(ProtocolError::BadLength));
+        assert_eq!(decode(&frame[..HEADER_LENGTH]), Err(ProtocolError::Truncated));
+
+        let mut future = Packet::new(7, Message::Ping);
+        future.version = VERSION + 1;
+        assert_eq!(decode(&encode(&future)), Err(ProtocolError::UnsupportedVersion(VERSION + 1)));
+    }
+
+    #[test]
+    fn skips_unknown_fields() {
+        let mut frame = encode(&Packet::new(1, Message::StartAck(mac(2))));
+        frame.truncate(frame.len() - CRC_LENGTH);
+        frame.extend_from_slice(&[0x7f, 2, 0xaa, 0xbb]);
+        let payload_length = (frame.len() - HEADER_LENGTH) as u16;
+        frame[6..8].copy_from_slice(&payload_length.to_be_bytes());
+        let crc = crc32(&frame);
+        frame.extend_from_slice(&crc.to_be_bytes());
+
+        assert_eq!(decode(&frame).unwrap().message, Message::StartAck(mac(2)));
+    }
+
+    #[test]
+    fn signed_frames() {
+        let key = [7u8; KEY_LENGTH];
+        let packet = Packet::new(3, Message::Range(mac(4), ShortAddress::new([0, 1]), 2.5));
+        let frame = encode_signed(&packet, 42, &key);
+
+        let (decoded, signature) = decode_signed(&frame).unwrap();
+        let signature = signature.unwrap();
+        assert_eq!(decoded, packet);
+        assert_eq!(signature.counter, 42);
+        assert!(verify(&frame, &signature, &key));
+        assert!(!verify(&frame, &signature, &[8u8; KEY_LENGTH]));
+        // managers that do not check signatures can still read the frame
+        assert_eq!(decode(&frame), Ok(packet.clone()));
+
+        let (_packet, signature) = decode_signed(&encode(&packet)).unwrap();
+        assert_eq!(signature, None);
+
+        // changing the counter, with a valid crc, breaks the signature
+        let mut replayed = frame.clone();
+        let crc_start = replayed.len() - CRC_LENGTH;
+        let counter_start = crc_start - HMAC_LENGTH - 2 - 4;
+        replayed[counter_start + 3] ^= 0x01;
+        let crc = crc32(&replayed[..crc_start]);
+        replayed[crc_start..].copy_from_slice(&crc.to_be_bytes());
+        let (_packet, signature) = decode_signed(&replayed).unwrap();
+        assert_eq!(signature.as_ref().unwrap().counter, 43);
+        assert!(!verify(&replayed, &signature.unwrap(), &key));
+    }
+
+    #[test]
+    fn replay_window() {
+        let mut window = ReplayWindow::new();
+        assert!(window.accept(10));
+        assert!(!window.accept(10));
+        assert!(window.accept(12));
+        // out of order, but not seen yet
+        assert!(window.accept(11));
+        assert!(!window.accept(11));
+        assert!(window.accept(12 + ReplayWindow::SIZE - 1));
+        // 12 is now the oldest counter the window remembers
+        assert!(!window.accept(12));
+        assert!(!window.accept(11));
+        assert!(window.accept(1000));
+        assert!(!window.accept(1000 - ReplayWindow::SIZE));
+        assert!(window.accept(1000 - ReplayWindow::SIZE + 1));
+
+        let mut resumed = ReplayWindow::resume(1000);
+        assert!(!resumed.accept(1000));
+        assert!(!resumed.accept(999));
+        assert!(resumed.accept(1001));
+        assert_eq!(resumed.highest(), Some(1001));
+    }
+
+    #[test]
+    fn negotiation() {
+        assert_eq!(negotiate(&[VERSION, VERSION + 1]), Some(VERSION));
+        assert_eq!(negotiate(&[VERSION + 1]), None);
+    }
+
+    fn arb_message() -> impl Strategy<Value = Message> {
+        let arb_mac = any::<[u8; 8]>().prop_map(|b| MacAddress8::from_bytes(&b).unwrap());
+        let arb_short = any::<[u8; 2]>().prop_map(ShortAddress::new);
+        prop_oneof![
+            prop::collection::vec(any::<u8>(), 0..8).prop_map(Message::Hello),
+            Just(Message::Start),
+            Just(Message::End),
+            Just(Message::Ping),
+            Just(Message::Reboot),
+            any::<[u8; 4]>().prop_map(|b| Message::SetIp(Ipv4Addr::from(b))),
+            (arb_mac.clone(), any::<u8>()).prop_map(|(m, v)| Message::HelloAck(m, v)),
+            arb_mac.clone().prop_map(Message::StartAck),
+            arb_mac.clone().prop_map(Message::EndAck),
+            arb_mac.clone().prop_map(Message::PingAck),
+            arb_mac.clone().prop_map(Message::RebootAck),
+            arb_mac.clone().prop_map(Message::SetIpAck),
+            // distances are sent in whole millimeters
+            (arb_mac, arb_short, 0u32..1_000_000).prop_map(|(m, t, mm)| Message::Range(m, t, mm as f64 / 1000.0)),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn binary_round_trip(sequence in any::<u16>(), message in arb_message()) {
+            let packet = Packet::new(sequence, message);
+            prop_assert_eq!(decode(&encode(&packet)), Ok(packet));
+        }
+
+        #[test]
+        fn signed_round_trip(sequence in any::<u16>(), counter in any::<u32>(), message in arb_message(), key in prop::collection::vec(any::<u8>(), 1..64)) {
+            let packet = Packet::new(sequence, message);
+            let frame = encode_signed(&packet, counter, &key);
+            let (decoded, signature) = decode_signed(&frame).unwrap();
+            let signature = signature.unwrap();
+            prop_assert_eq!(decoded, packet);
+            prop_assert_eq!(signature.counter, counter);
+            prop_assert!(verify(&frame, &signature, &key));
+        }
+
+        #[test]
+        fn replay_accepts_once(counters in prop::collection::vec(0u32..200, 0..100)) {
+            let mut window = ReplayWindow::new();
+            let mut accepted = std::collections::BTreeSet::new();
+            for counter in counters {
+                if window.accept(counter) {
+                    prop_assert!(accepted.insert(counter));
+                }
+            }
+        }
+
+        #[test]
+        fn legacy_round_trip(message in arb_message()) {
+            if let Some(text) = encode_legacy(&message) {
+                let decoded = decode_legacy(&text).unwrap();
+                match (&message, &decoded) {
+                    (Message::Range(m0, t0, d0), Message::Range(m1, t1, d1)) => {
+                        prop_assert_eq!(m0, m1);
+                        prop_assert_eq!(t0, t1);
+                        prop_assert!((d0 - d1).abs() < 0.01);
+                    },
+                    _ => prop_assert_eq!(&message, &decoded),
+                }
+            }
+        }
+
+        // random input must be rejected without panicking
+        #[test]
+        fn fuzz_decode(data in prop::collection::vec(any::<u8>(), 0..64)) {
+            let _ = decode(&data);
+            let _ = decode_legacy(&String::from_utf8_lossy(&data));
+        }
+
+        #[test]
+        fn fuzz_decode_framed(version in any::<u8>(), message_type in any::<u8>(), payload in prop::collection::vec(any::<u8>(), 0..32)) {
+            // frames with a valid header and crc, to get past the framing checks
+            let mut frame = MAGIC.to_vec();
+            frame.extend_from_slice(&[version, message_type, 0, 0]);
+            frame.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+            frame.extend_from_slice(&payload);
+            let crc = crc32(&frame);
+            frame.extend_from_slice(&crc.to_be_bytes());
+            let _ = decode(&frame);
+        }
+
+        #[test]
+        fn any_corruption_detected(sequence in any::<u16>(), message in arb_message(), index in any::<prop::sample::Index>(), bit in 0u8..8) {
+            let mut frame = encode(&Packet::new(sequence, message));
+            let i = index.index(frame.len());
+            frame[i] ^= 1 << bit;
+            prop_assert!(decode(&frame).is_err());
+        }
+    }
+}
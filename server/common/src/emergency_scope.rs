@@ -0,0 +1,165 @@
+// An emergency covers either the whole site, or only some of its maps (a wing or a floor).
+// These decide which state a beacon should be in, and what an emergency covers after part of
+// it is started or ended.
+
+use crate::{ BeaconState, EmergencyStatus, };
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ScopeChange {
+    // the emergency covers these maps, or the whole site when None
+    Active(Option<Vec<i32>>),
+    Ended,
+}
+
+pub fn covers(status: &EmergencyStatus, map_id: Option<i32>) -> bool {
+    if !status.active {
+        return false;
+    }
+    match (&status.map_ids, map_id) {
+        (None, _) => true,
+        (Some(ids), Some(id)) => ids.contains(&id),
+        // beacons that are not on a map are only part of site wide emergencies
+        (Some(_), None) => false,
+    }
+}
+
+// the state a beacon on the map should be in
+pub fn beacon_state(status: &EmergencyStatus, map_id: Option<i32>) -> BeaconState {
+    if covers(status, map_id) {
+        BeaconState::Active
+    } else {
+        BeaconState::Idle
+    }
+}
+
+fn normalize(mut ids: Vec<i32>) -> Vec<i32> {
+    ids.sort();
+    ids.dedup();
+    ids
+}
+
+// starting an emergency over maps that are already covered changes nothing, and starting a
+// site wide emergency replaces any maps.
+pub fn start(status: &EmergencyStatus, map_ids: Option<Vec<i32>>) -> Result<ScopeChange, &'static str> {
+    if map_ids.as_ref().map_or(false, |ids| ids.is_empty()) {
+        return Err("an emergency must cover at least one map");
+    }
+
+    let current = if status.active { status.map_ids.clone() } else { Some(Vec::new()) };
+    let map_ids = match (current, map_ids) {
+        (None, _) | (_, None) => None,
+        (Some(mut current), Some(added)) => {
+            current.extend(added);
+            Some(normalize(current))
+        },
+    };
+    Ok(ScopeChange::Active(map_ids))
+}
+
+// ending the emergency on every map it covers ends the emergency. a site wide emergency can
+// only be ended for the whole site.
+pub fn end(status: &EmergencyStatus, map_ids: Option<Vec<i32>>) -> Result<ScopeChange, &'static str> {
+    if !status.active {
+        return Ok(ScopeChange::Ended);
+    }
+
+    match (&status.map_ids, map_ids) {
+        (_, None) => Ok(ScopeChange::Ended),
+        (None, Some(_)) => Err("a site wide emergency can only be ended for the whole site"),
+        (Some(current), Some(removed)) => {
+            let remaining: Vec<i32> = current.iter()
+                .filter(|id| !removed.contains(id))
+                .cloned()
+                .collect();
+            if remaining.is_empty() {
+                Ok(ScopeChange::Ended)
+            } else {
+                Ok(ScopeChange::Active(Some(remaining)))
+            }
+        },
+    }
+}
+
+// what a change to the maps of an active emergency did, true and the maps that were started, or
+// false and the maps that were ended. None is the whole site.
+pub fn rescoped(before: &Option<Vec<i32>>, after: &Option<Vec<i32>>) -> (bool, Option<Vec<i32>>) {
+    match (before, after) {
+        (_, None) => (true, None),
+        // a site wide emergency is only ever ended as a whole
+        (None, Some(_)) => (false, None),
+        (Some(before), Some(after)) => {
+            let started: Vec<i32> = after.iter().filter(|id| !before.contains(id)).cloned().collect();
+            if started.is_empty() {
+                (false, Some(before.iter().filter(|id| !after.contains(id)).cloned().collect()))
+            } else {
+                (true, Some(started))
+            }
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn active(map_ids: Option<Vec<i32>>) -> EmergencyStatus {
+        let mut status = EmergencyStatus::new();
+        status.active = true;
+        status.map_ids = map_ids;
+        status
+    }
+
+    #[test]
+    fn beacon_states() {
+        let idle = EmergencyStatus::new();
+        assert_eq!(beacon_state(&idle, Some(1)), BeaconState::Idle);
+
+        let site = active(None);
+        assert_eq!(beacon_state(&site, Some(1)), BeaconState::Active);
+        assert_eq!(beacon_state(&site, None), BeaconState::Active);
+
+        let zone = active(Some(vec![1, 3]));
+        assert_eq!(beacon_state(&zone, Some(1)), BeaconState::Active);
+        assert_eq!(beacon_state(&zone, Some(2)), BeaconState::Idle);
+        assert_eq!(beacon_state(&zone, None), BeaconState::Idle);
+    }
+
+    #[test]
+    fn start_scopes() {
+        let idle = EmergencyStatus::new();
+        assert_eq!(start(&idle, Some(vec![3, 1, 3])), Ok(ScopeChange::Active(Some(vec![1, 3]))));
+        assert_eq!(start(&idle, None), Ok(ScopeChange::Active(None)));
+        assert!(start(&idle, Some(Vec::new())).is_err());
+
+        // maps are added to a zoned emergency
+        let zone = active(Some(vec![1]));
+        assert_eq!(start(&zone, Some(vec![2])), Ok(ScopeChange::Active(Some(vec![1, 2]))));
+        assert_eq!(start(&zone, None), Ok(ScopeChange::Active(None)));
+
+        // a site wide emergency already covers every map
+        let site = active(None);
+        assert_eq!(start(&site, Some(vec![2])), Ok(ScopeChange::Active(None)));
+    }
+
+    #[test]
+    fn end_scopes() {
+        let zone = active(Some(vec![1, 2]));
+        assert_eq!(end(&zone, Some(vec![1])), Ok(ScopeChange::Active(Some(vec![2]))));
+        assert_eq!(end(&zone, Some(vec![1, 2])), Ok(ScopeChange::Ended));
+        assert_eq!(end(&zone, Some(vec![5])), Ok(ScopeChange::Active(Some(vec![1, 2]))));
+        assert_eq!(end(&zone, None), Ok(ScopeChange::Ended));
+
+        let site = active(None);
+        assert!(end(&site, Some(vec![1])).is_err());
+        assert_eq!(end(&site, None), Ok(ScopeChange::Ended));
+
+        assert_eq!(end(&EmergencyStatus::new(), Some(vec![1])), Ok(ScopeChange::Ended));
+    }
+
+    #[test]
+    fn rescopes() {
+        assert_eq!(rescoped(&Some(vec![1]), &Some(vec![1, 2])), (true, Some(vec![2])));
+        assert_eq!(rescoped(&Some(vec![1, 2]), &Some(vec![2])), (false, Some(vec![1])));
+        assert_eq!(rescoped(&Some(vec![1]), &None), (true, None));
+    }
+}
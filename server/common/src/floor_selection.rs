@@ -0,0 +1,120 @@
+// Chooses which map (floor) a tag is most likely on, given the anchors that heard it.
+// Anchors on other floors will often still range a tag through the ceiling, so the
+// decision considers how many anchors on each floor heard the tag, how good those ranges
+// are, and which floor the tag was on previously.
+
+use chrono::{ DateTime, Utc, };
+use serde_derive::{ Deserialize, Serialize, };
+use crate::ShortAddress;
+
+// minimum anchors on a single floor to resolve a 2d position
+pub const MIN_FLOOR_ANCHORS: usize = 3;
+// ranges shorter than this are considered to be close to full quality, in meters
+const REFERENCE_DISTANCE: f64 = 5.0;
+// a range weight of this value is considered to be half quality
+const REFERENCE_WEIGHT: f64 = 10.0;
+// score multiplier for the floor the tag is currently on, this keeps the tag from
+// flickering between floors when the scores are close.
+const HYSTERESIS: f64 = 1.25;
+
+#[derive(Debug, Clone)]
+pub struct FloorCandidate {
+    pub map_id: i32,
+    // distance and weight of every range from an anchor on this floor
+    pub ranges: Vec<(f64, f64)>,
+}
+
+impl FloorCandidate {
+    pub fn new(map_id: i32) -> FloorCandidate {
+        FloorCandidate {
+            map_id,
+            ranges: Vec::new(),
+        }
+    }
+
+    // each anchor contributes at most 1 to the score, less when the range is long or noisy
+    pub fn score(&self) -> f64 {
+        self.ranges.iter().map(|(distance, weight)| {
+            let closeness = 1.0 / (1.0 + distance.max(0.0) / REFERENCE_DISTANCE);
+            let consistency = weight / (weight + REFERENCE_WEIGHT);
+            (closeness + consistency) / 2.0
+        }).sum()
+    }
+}
+
+pub fn select_floor(candidates: &[FloorCandidate], current: Option<i32>) -> Option<i32> {
+    candidates
+        .iter()
+        .filter(|c| c.ranges.len() >= MIN_FLOOR_ANCHORS)
+        .map(|c| {
+            let bonus = if Some(c.map_id) == current { HYSTERESIS } else { 1.0 };
+            (c.map_id, c.score() * bonus)
+        })
+        .fold(None, |best: Option<(i32, f64)>, (map_id, score)| {
+            match best {
+                Some((_, best_score)) if best_score >= score => best,
+                _ => Some((map_id, score)),
+            }
+        })
+        .map(|(map_id, _score)| map_id)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FloorTransition {
+    pub addr: ShortAddress,
+    pub from_map_id: Option<i32>,
+    pub to_map_id: Option<i32>,
+    pub timestamp: DateTime<Utc>,
+    pub user_id: i32,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidate(map_id: i32, ranges: &[f64]) -> FloorCandidate {
+        FloorCandidate {
+            map_id,
+            ranges: ranges.iter().map(|d| (*d, 20.0)).collect(),
+        }
+    }
+
+    #[test]
+    fn prefers_more_anchors() {
+        let candidates = [
+            candidate(1, &[3.0, 3.0, 3.0]),
+            candidate(2, &[3.0, 3.0, 3.0, 3.0]),
+        ];
+        assert_eq!(select_floor(&candidates, None), Some(2));
+    }
+
+    #[test]
+    fn prefers_shorter_ranges() {
+        let candidates = [
+            candidate(1, &[8.0, 9.0, 10.0]),
+            candidate(2, &[2.0, 3.0, 4.0]),
+        ];
+        assert_eq!(select_floor(&candidates, None), Some(2));
+    }
+
+    #[test]
+    fn stays_on_current_floor_when_close() {
+        let candidates = [
+            candidate(1, &[3.0, 3.0, 3.5]),
+            candidate(2, &[3.0, 3.0, 3.0]),
+        ];
+        assert_eq!(select_floor(&candidates, None), Some(2));
+        assert_eq!(select_floor(&candidates, Some(1)), Some(1));
+    }
+
+    #[test]
+    fn requires_enough_anchors() {
+        let candidates = [
+            candidate(1, &[1.0, 1.0]),
+            candidate(2, &[9.0, 9.0, 9.0]),
+        ];
+        assert_eq!(select_floor(&candidates, Some(1)), Some(2));
+        assert_eq!(select_floor(&candidates[..1], None), None);
+        assert_eq!(select_floor(&[], None), None);
+    }
+}
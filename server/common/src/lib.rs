@@ -4,7 +4,16 @@ extern crate eui48;
 extern crate eui64;
 extern crate ipnet;
 
+pub mod beacon_protocol;
+pub mod emergency_scope;
+pub mod floor_selection;
+pub mod motion;
+pub mod multilateration;
+pub mod muster;
+pub mod positioning;
 pub mod short_address;
+pub mod tag_filter;
+pub mod zones;
 
 pub use chrono::offset::TimeZone;
 pub use chrono::{ DateTime, Utc, format::DelayedFormat, format::StrftimeItems, };
@@ -22,12 +31,24 @@ pub fn beacon_url(id: &str) -> String {
 pub fn beacon_command_url() -> String {
     return String::from("/beacons/command");
 }
+pub fn beacon_key_url(id: &str) -> String {
+    return format!("/beacon/{}/key", id);
+}
 pub fn beacons_url() -> String {
     return String::from("/beacons");
 }
 pub fn beacons_status_url() -> String {
     return String::from("/beacons/status");
 }
+pub fn beacons_discovered_url() -> String {
+    return String::from("/beacons/discovered");
+}
+pub fn beacons_adopt_url() -> String {
+    return String::from("/beacons/discovered/adopt");
+}
+pub fn beacons_blocklist_url() -> String {
+    return String::from("/beacons/discovered/blocklist");
+}
 pub fn beacons_for_map_url(id: &str) -> String {
     return format!("/map/{}/beacons", id);
 }
@@ -41,6 +62,12 @@ pub fn users_url() -> String {
 pub fn users_status_url() -> String {
     return String::from("/users/status");
 }
+pub fn user_history_url(id: &str) -> String {
+    return format!("/user/{}/history", id);
+}
+pub fn users_floor_transitions_url() -> String {
+    return String::from("/users/floor_transitions");
+}
 
 pub fn map_url(id: &str) -> String {
     return format!("/map/{}", id);
@@ -48,10 +75,33 @@ pub fn map_url(id: &str) -> String {
 pub fn map_blueprint_url(id: &str) -> String {
     return format!("/map/{}/blueprint", id);
 }
+pub fn map_history_url(id: &str) -> String {
+    return format!("/map/{}/history", id);
+}
+pub fn map_replay_url(id: &str) -> String {
+    return format!("/map/{}/replay", id);
+}
 pub fn maps_url() -> String {
     return String::from("/maps");
 }
 
+pub fn zone_url(id: &str) -> String {
+    return format!("/zone/{}", id);
+}
+pub fn zones_for_map_url(id: &str) -> String {
+    return format!("/map/{}/zones", id);
+}
+pub fn zone_events_url() -> String {
+    return String::from("/zones/events");
+}
+
+pub fn motion_alerts_url() -> String {
+    return String::from("/motion/alerts");
+}
+pub fn motion_alert_acknowledge_url(id: &str) -> String {
+    return format!("/motion/alert/{}/acknowledge", id);
+}
+
 pub fn network_url(id: &str) -> String {
     return format!("/network/{}", id);
 }
@@ -62,6 +112,21 @@ pub fn networks_url() -> String {
 pub fn system_emergency_url() -> String {
     return String::from("/system/emergency");
 }
+pub fn system_emergency_status_url() -> String {
+    return String::from("/system/emergency/status");
+}
+pub fn emergency_events_url() -> String {
+    return String::from("/system/emergency/events");
+}
+pub fn emergency_event_url(id: &str) -> String {
+    return format!("/system/emergency/event/{}", id);
+}
+pub fn emergency_muster_url() -> String {
+    return String::from("/system/emergency/muster");
+}
+pub fn emergency_muster_export_url() -> String {
+    return String::from("/system/emergency/muster/export");
+}
 
 pub fn system_diagnostics_url() -> String {
     return String::from("/system/diagnostics");
@@ -72,6 +137,31 @@ pub fn system_restart_url() -> String {
 pub fn system_ping_url() -> String {
     return String::from("/system/ping");
 }
+pub fn system_filter_url() -> String {
+    return String::from("/system/filter");
+}
+pub fn system_retention_url() -> String {
+    return String::from("/system/retention");
+}
+pub fn system_motion_url() -> String {
+    return String::from("/system/motion");
+}
+pub fn system_config_url() -> String {
+    return String::from("/system/config");
+}
+pub fn system_sessions_logout_url() -> String {
+    return String::from("/system/sessions/logout");
+}
+pub fn system_session_key_url() -> String {
+    return String::from("/system/sessions/key");
+}
+pub fn system_capture_url() -> String {
+    return String::from("/system/capture");
+}
+
+pub fn push_url() -> String {
+    return String::from("/push");
+}
 
 pub fn session_login_url() -> String {
     return String::from("/session/login");
@@ -85,15 +175,187 @@ pub fn session_check_url() -> String {
     return String::from("/session/check");
 }
 
+pub fn account_url(id: &str) -> String {
+    return format!("/account/{}", id);
+}
+pub fn account_reset_url(id: &str) -> String {
+    return format!("/account/{}/reset", id);
+}
+pub fn accounts_url() -> String {
+    return String::from("/accounts");
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SystemCommandResponse {
     pub emergency: bool,
+    // why the emergency was started or ended, kept in the emergency log
+    #[serde(default)]
+    pub reason: Option<String>,
+    // the maps to start or end the emergency on, None for the whole site
+    #[serde(default)]
+    pub map_ids: Option<Vec<i32>>,
 }
 
 impl SystemCommandResponse {
     pub fn new(emergency: bool) -> SystemCommandResponse {
         SystemCommandResponse {
             emergency,
+            reason: None,
+            map_ids: None,
+        }
+    }
+}
+
+// who started or ended the current emergency, and what it covers.
+// the manager keeps this in the database so an emergency survives a restart.
+#[derive(Default, Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct EmergencyStatus {
+    pub active: bool,
+    pub started_at: Option<DateTime<Utc>>,
+    // None when the emergency was not started by an account
+    pub started_by: Option<String>,
+    pub ended_at: Option<DateTime<Utc>>,
+    pub ended_by: Option<String>,
+    // the maps the emergency covers, None when it covers the whole site
+    pub map_ids: Option<Vec<i32>>,
+}
+
+impl EmergencyStatus {
+    pub fn new() -> EmergencyStatus {
+        EmergencyStatus {
+            active: false,
+            started_at: None,
+            started_by: None,
+            ended_at: None,
+            ended_by: None,
+            map_ids: None,
+        }
+    }
+}
+
+// a single emergency from start to end, ended_at is None while the emergency is going
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct EmergencyEvent {
+    pub id: i32,
+    pub started_at: DateTime<Utc>,
+    pub started_by: Option<String>,
+    pub start_reason: Option<String>,
+    pub ended_at: Option<DateTime<Utc>>,
+    pub ended_by: Option<String>,
+    pub end_reason: Option<String>,
+    // every map the emergency covered at some point, None when it covered the whole site
+    pub map_ids: Option<Vec<i32>>,
+}
+
+impl EmergencyEvent {
+    pub fn new() -> EmergencyEvent {
+        EmergencyEvent {
+            id: -1,
+            started_at: Utc::now(),
+            started_by: None,
+            start_reason: None,
+            ended_at: None,
+            ended_by: None,
+            end_reason: None,
+            map_ids: None,
+        }
+    }
+
+    // how long the emergency lasted, or has lasted so far
+    pub fn duration(&self, now: DateTime<Utc>) -> chrono::Duration {
+        self.ended_at.unwrap_or(now).signed_duration_since(self.started_at)
+    }
+}
+
+// a user that was located while an emergency was going
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct EmergencySighting {
+    pub user_id: i32,
+    pub name: String,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+    pub locations: i64,
+}
+
+// maps of an emergency that were started or ended while the rest of it kept going
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct EmergencyScopeChange {
+    pub id: i32,
+    pub event_id: i32,
+    pub timestamp: DateTime<Utc>,
+    pub account: Option<String>,
+    pub reason: Option<String>,
+    // false when the maps were ended
+    pub started: bool,
+    // the maps that were started or ended, None for the whole site
+    pub map_ids: Option<Vec<i32>>,
+}
+
+impl EmergencyScopeChange {
+    pub fn new() -> EmergencyScopeChange {
+        EmergencyScopeChange {
+            id: -1,
+            event_id: -1,
+            timestamp: Utc::now(),
+            account: None,
+            reason: None,
+            started: true,
+            map_ids: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EmergencyEventDetail {
+    pub event: EmergencyEvent,
+    pub scope_changes: Vec<EmergencyScopeChange>,
+    pub users: Vec<EmergencySighting>,
+    pub zone_events: Vec<zones::ZoneEvent>,
+}
+
+// changes pushed to the frontend as they happen
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum PushMessage {
+    BeaconChanged(RealtimeBeacon),
+    BeaconAlert(BeaconAlert),
+    Emergency(EmergencyStatus),
+    MotionAlert(motion::MotionAlert),
+    Range(TagData),
+    UserMoved(RealtimeUserData),
+    ZoneEvent(zones::ZoneEvent),
+}
+
+// sent by the frontend to choose which push messages it receives
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PushSubscription {
+    pub diagnostics: bool,
+    pub map_ids: Option<Vec<i32>>, // None subscribes to every map
+}
+
+impl PushSubscription {
+    pub fn new() -> PushSubscription {
+        PushSubscription {
+            diagnostics: false,
+            map_ids: None,
+        }
+    }
+
+    pub fn wants_map(&self, map_id: Option<i32>) -> bool {
+        match &self.map_ids {
+            Some(ids) => map_id.map_or(false, |id| ids.contains(&id)),
+            None => true,
+        }
+    }
+
+    pub fn wants(&self, msg: &PushMessage) -> bool {
+        match msg {
+            PushMessage::BeaconChanged(beacon) => self.wants_map(beacon.map_id),
+            PushMessage::BeaconAlert(_) => self.diagnostics,
+            PushMessage::Emergency(_) => true,
+            PushMessage::MotionAlert(alert) => self.wants_map(alert.map_id),
+            PushMessage::Range(_) => self.diagnostics,
+            PushMessage::UserMoved(user) => self.wants_map(user.map_id),
+            PushMessage::ZoneEvent(event) => self.wants_map(event.map_id),
         }
     }
 }
@@ -106,9 +368,103 @@ pub struct TagData {
     pub timestamp: DateTime<Utc>,
 }
 
+// where a simulated tag really was, to score the locations calculated from its ranges
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GroundTruth {
+    pub coordinates: na::Vector2<f64>,
+    pub map_id: i32,
+    pub tag_mac: ShortAddress,
+    pub timestamp: DateTime<Utc>,
+}
+
+// a recording of the frames received from udp beacons, for replaying into another backend
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct CaptureStatus {
+    // the file being written, or the last file written when the capture is stopped
+    pub file: Option<String>,
+    pub active: bool,
+    pub frames: u64,
+    pub started_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct CaptureRequest {
+    // the file name in the capture directory, without an extension. the time the capture
+    // started when not given
+    #[serde(default)]
+    pub name: Option<String>,
+}
+
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
 pub struct DiagnosticData {
     pub tag_data: Vec<TagData>,
+    // how quickly beacons acknowledge each kind of command, since the server started
+    pub command_latency: Vec<CommandLatency>,
+    // the most recent rejected beacon messages, newest last
+    pub alerts: Vec<BeaconAlert>,
+}
+
+// a message claiming to be from a beacon that was rejected, ie it was unsigned or forged
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BeaconAlert {
+    pub ip: IpAddr,
+    // the beacon the message claimed to be from, if it could be read
+    pub mac: Option<MacAddress8>,
+    pub reason: String,
+    pub timestamp: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum BeaconCommandKind {
+    StartEmergency,
+    EndEmergency,
+    Ping,
+    Reboot,
+    SetIp,
+}
+
+impl fmt::Display for BeaconCommandKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BeaconCommandKind::StartEmergency => write!(f, "Start Emergency"),
+            BeaconCommandKind::EndEmergency => write!(f, "End Emergency"),
+            BeaconCommandKind::Ping => write!(f, "Ping"),
+            BeaconCommandKind::Reboot => write!(f, "Reboot"),
+            BeaconCommandKind::SetIp => write!(f, "Set IP"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CommandLatency {
+    pub command: BeaconCommandKind,
+    // acknowledgements received
+    pub acks: u64,
+    // beacons that never acknowledged a request
+    pub timeouts: u64,
+    pub last_ms: f64,
+    pub mean_ms: f64,
+    pub max_ms: f64,
+}
+
+impl CommandLatency {
+    pub fn new(command: BeaconCommandKind) -> CommandLatency {
+        CommandLatency {
+            command,
+            acks: 0,
+            timeouts: 0,
+            last_ms: 0.0,
+            mean_ms: 0.0,
+            max_ms: 0.0,
+        }
+    }
+
+    pub fn record(&mut self, ms: f64) {
+        self.acks += 1;
+        self.last_ms = ms;
+        self.mean_ms += (ms - self.mean_ms) / self.acks as f64;
+        self.max_ms = self.max_ms.max(ms);
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -130,6 +486,8 @@ impl DiagnosticData {
     pub fn new() -> DiagnosticData {
         DiagnosticData {
             tag_data: Vec::new(),
+            command_latency: Vec::new(),
+            alerts: Vec::new(),
         }
     }
 }
@@ -146,10 +504,13 @@ pub struct RealtimeUserData {
     pub addr: ShortAddress,
     pub beacon_tofs: Vec<BeaconTOFToUser>,
     pub coordinates: na::Vector2<f64>,
+    pub error_ellipse: tag_filter::CovarianceEllipse,
     pub id: i32,
     pub last_active: DateTime<Utc>,
     pub map_id: Option<i32>,
     pub name: String,
+    pub uncertainty: f64, // meters, estimated by the position solver
+    pub velocity: na::Vector2<f64>, // meters per second
 }
 
 impl From<TrackedUser> for RealtimeUserData {
@@ -158,10 +519,13 @@ impl From<TrackedUser> for RealtimeUserData {
             addr: user.mac_address.unwrap(), // user must have a mac address to be tracked
             beacon_tofs: Vec::new(),
             coordinates: user.coordinates,
+            error_ellipse: tag_filter::CovarianceEllipse::new(),
             id: user.id,
             last_active: user.last_active,
             map_id: user.map_id,
             name: user.name,
+            uncertainty: 0.0,
+            velocity: na::Vector2::new(0.0, 0.0),
         }
     }
 }
@@ -210,6 +574,80 @@ impl TrackedUser {
     }
 }
 
+// a single located position of a user, kept so that movements can be reviewed after the fact
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LocationRecord {
+    pub id: i64, // primary key
+    pub beacons: Vec<i32>, // ids of the beacons that contributed to the location
+    pub beacon_coordinates: Vec<na::Vector2<f64>>, // where each contributing beacon was at the time
+    pub coordinates: na::Vector2<f64>,
+    pub distances: Vec<f64>, // range from each contributing beacon, in meters
+    pub map_id: Option<i32>,
+    pub timestamp: DateTime<Utc>,
+    pub uncertainty: f64, // meters
+    pub user_id: i32,
+}
+
+impl LocationRecord {
+    pub fn new() -> LocationRecord {
+        LocationRecord {
+            id: -1,
+            beacons: Vec::new(),
+            beacon_coordinates: Vec::new(),
+            coordinates: na::Vector2::new(0.0, 0.0),
+            distances: Vec::new(),
+            map_id: None,
+            timestamp: Utc.timestamp(0, 0),
+            uncertainty: 0.0,
+            user_id: -1,
+        }
+    }
+}
+
+// time window of a location history query, open ends are unbounded
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryParams {
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+}
+
+impl HistoryParams {
+    pub fn range(&self) -> (DateTime<Utc>, DateTime<Utc>) {
+        (
+            self.from.unwrap_or(Utc.timestamp(0, 0)),
+            self.to.unwrap_or(Utc::now()),
+        )
+    }
+}
+
+// the timestamp and id of the last location in a page of a replay, the next page starts after it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReplayCursor {
+    pub after: DateTime<Utc>,
+    pub after_id: i64,
+}
+
+// a replay is sent a page at a time, next is none once the window has been sent
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReplayPage {
+    pub frames: Vec<RealtimeUserData>,
+    pub next: Option<ReplayCursor>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RetentionConfig {
+    // location history older than this is pruned, 0 keeps history forever
+    pub location_history_days: u32,
+}
+
+impl RetentionConfig {
+    pub fn new() -> RetentionConfig {
+        RetentionConfig {
+            location_history_days: 30,
+        }
+    }
+}
+
 #[derive(Copy, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum BeaconState {
     Unknown,
@@ -281,6 +719,8 @@ pub struct Beacon {
     pub name: String,
     pub note: Option<String>,
     pub state: BeaconState,
+    // when the signing key was provisioned, None when the beacon has no key
+    pub key_rotated: Option<DateTime<Utc>>,
 }
 
 impl Beacon {
@@ -295,6 +735,7 @@ impl Beacon {
             name: String::new(),
             note: None,
             state: BeaconState::Unknown,
+            key_rotated: None,
         }
     }
 
@@ -307,6 +748,37 @@ impl Beacon {
     }
 }
 
+// a beacon that has messaged the server without being registered, or that has been blocked
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DiscoveredBeacon {
+    pub mac_address: MacAddress8,
+    // the fields below are None for blocked beacons not heard from since the server started
+    pub ip: Option<IpAddr>,
+    pub first_seen: Option<DateTime<Utc>>,
+    pub last_seen: Option<DateTime<Utc>>,
+    pub messages: u64,
+    pub blocked: bool,
+}
+
+impl DiscoveredBeacon {
+    pub fn new(mac_address: MacAddress8) -> DiscoveredBeacon {
+        DiscoveredBeacon {
+            mac_address,
+            ip: None,
+            first_seen: None,
+            last_seen: None,
+            messages: 0,
+            blocked: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlockBeaconRequest {
+    pub mac_address: MacAddress8,
+    pub blocked: bool,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RealtimeBeacon {
     pub id: i32, // primary key
@@ -408,6 +880,84 @@ impl LoginInfo {
     }
 }
 
+#[derive(Copy, Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum AccountRole {
+    Admin,
+    Responder,
+}
+
+impl From<AccountRole> for i16 {
+    fn from(r: AccountRole) -> Self {
+        match r {
+            AccountRole::Admin => 0,
+            AccountRole::Responder => 1,
+        }
+    }
+}
+
+impl From<i16> for AccountRole {
+    fn from(r: i16) -> Self {
+        match r {
+            0 => AccountRole::Admin,
+            1 => AccountRole::Responder,
+            _ => panic!("unexpected account role"),
+        }
+    }
+}
+
+impl fmt::Display for AccountRole {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            AccountRole::Admin => write!(f, "Admin"),
+            AccountRole::Responder => write!(f, "Responder"),
+        }
+    }
+}
+
+// an application login, the password hash never leaves the server
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Account {
+    pub id: i32, // primary key
+    pub created: DateTime<Utc>,
+    pub disabled: bool,
+    pub last_login: Option<DateTime<Utc>>,
+    pub name: String,
+    pub role: AccountRole,
+}
+
+impl Account {
+    pub fn new() -> Account {
+        Account {
+            id: -1,
+            created: Utc.timestamp(0, 0),
+            disabled: false,
+            last_login: None,
+            name: String::new(),
+            role: AccountRole::Responder,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NewAccount {
+    pub name: String,
+    pub pw: String,
+    pub role: AccountRole,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PasswordReset {
+    pub pw: String,
+}
+
+// returned on login, and when checking the current session
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionInfo {
+    pub expires: DateTime<Utc>,
+    pub name: String,
+    pub role: AccountRole,
+}
+
 #[derive(Copy, Clone, Serialize, Deserialize)]
 pub enum SystemCommand {
     StartNormal,
@@ -0,0 +1,297 @@
+// Man down detection, a user whose location has barely changed for too long may be hurt, and a
+// tag that has stopped ranging may be broken, shielded, or left behind.
+
+use chrono::{ DateTime, Duration, Utc, };
+use na;
+use serde_derive::{ Deserialize, Serialize, };
+use std::collections::VecDeque;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MotionConfig {
+    // how long a user must stay put before they are reported
+    pub stationary_seconds: u32,
+    // users whose locations spread less than this, in meters, are not moving. locations jitter
+    // by a fraction of a meter even when a tag is sitting on a desk.
+    pub stationary_radius: f64,
+    // how long a tag can go without a location before it is reported lost
+    pub tag_lost_seconds: u32,
+}
+
+impl MotionConfig {
+    pub fn new() -> MotionConfig {
+        MotionConfig {
+            stationary_seconds: 120,
+            stationary_radius: 0.75,
+            tag_lost_seconds: 30,
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), &'static str> {
+        if self.stationary_seconds == 0 || self.tag_lost_seconds == 0 {
+            return Err("motion thresholds must be at least one second");
+        }
+        if self.stationary_radius.is_nan() || self.stationary_radius <= 0.0 {
+            return Err("the stationary radius must be greater than zero");
+        }
+        Ok(())
+    }
+}
+
+#[derive(Copy, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MotionAlertKind {
+    Stationary,
+    TagLost,
+}
+
+impl fmt::Display for MotionAlertKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            MotionAlertKind::Stationary => write!(f, "Stationary Too Long"),
+            MotionAlertKind::TagLost => write!(f, "Tag Lost"),
+        }
+    }
+}
+
+// alerts stay listed until a responder acknowledges them, even when the user starts moving again
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MotionAlert {
+    pub id: u64,
+    pub acknowledged_at: Option<DateTime<Utc>>,
+    pub acknowledged_by: Option<String>,
+    pub cleared_at: Option<DateTime<Utc>>, // when the user moved again, or the tag came back
+    pub coordinates: na::Vector2<f64>,
+    pub kind: MotionAlertKind,
+    pub last_active: DateTime<Utc>,
+    pub map_id: Option<i32>,
+    pub raised_at: DateTime<Utc>,
+    pub user_id: i32,
+    pub user_name: String,
+}
+
+impl MotionAlert {
+    pub fn new() -> MotionAlert {
+        MotionAlert {
+            id: 0,
+            acknowledged_at: None,
+            acknowledged_by: None,
+            cleared_at: None,
+            coordinates: na::Vector2::new(0.0, 0.0),
+            kind: MotionAlertKind::Stationary,
+            last_active: Utc::now(),
+            map_id: None,
+            raised_at: Utc::now(),
+            user_id: -1,
+            user_name: String::new(),
+        }
+    }
+
+    // nobody needs to see an alert that was acknowledged and is no longer happening
+    pub fn is_done(&self) -> bool {
+        self.acknowledged_at.is_some() && self.cleared_at.is_some()
+    }
+}
+
+// applies a changed alert to a list of alerts, dropping the alerts that are done
+pub fn merge_alert(alerts: &mut Vec<MotionAlert>, alert: MotionAlert) {
+    match alerts.iter().position(|a| a.id == alert.id) {
+        Some(index) if alert.is_done() => { alerts.remove(index); },
+        Some(index) => alerts[index] = alert,
+        None if alert.is_done() => {},
+        None => alerts.push(alert),
+    }
+}
+
+#[derive(Copy, Debug, Clone, PartialEq)]
+pub enum MotionChange {
+    Raised(MotionAlertKind),
+    Cleared(MotionAlertKind),
+}
+
+#[derive(Debug)]
+pub struct MotionTracker {
+    condition: Option<MotionAlertKind>,
+    map_id: Option<i32>,
+    // locations within the stationary window, oldest first
+    samples: VecDeque<(DateTime<Utc>, na::Vector2<f64>)>,
+    // the first location since the tracker was reset, a user cannot have been still for longer
+    // than they have been watched.
+    since: Option<DateTime<Utc>>,
+}
+
+impl MotionTracker {
+    pub fn new() -> MotionTracker {
+        MotionTracker {
+            condition: None,
+            map_id: None,
+            samples: VecDeque::new(),
+            since: None,
+        }
+    }
+
+    // forgets every location, ie when an emergency starts nobody has been still during it yet
+    pub fn reset(&mut self) {
+        self.samples.clear();
+        self.since = None;
+    }
+
+    pub fn update(&mut self, config: &MotionConfig, map_id: Option<i32>, point: &na::Vector2<f64>, timestamp: DateTime<Utc>) {
+        if map_id != self.map_id {
+            // a user who changed floors has moved
+            self.reset();
+            self.map_id = map_id;
+        }
+        self.samples.push_back((timestamp, *point));
+        self.since.get_or_insert(timestamp);
+
+        let window_start = timestamp - Duration::seconds(config.stationary_seconds as i64);
+        while self.samples.front().map_or(false, |(stamp, _point)| *stamp < window_start) {
+            self.samples.pop_front();
+        }
+    }
+
+    fn is_stationary(&self, config: &MotionConfig, now: DateTime<Utc>) -> bool {
+        let watched_since = match self.since {
+            Some(since) => since,
+            None => return false,
+        };
+        if now - watched_since < Duration::seconds(config.stationary_seconds as i64) || self.samples.len() < 2 {
+            return false;
+        }
+
+        let count = self.samples.len() as f64;
+        let mean = self.samples.iter().fold(na::Vector2::new(0.0, 0.0), |sum, (_stamp, point)| sum + point) / count;
+        let variance = self.samples.iter().map(|(_stamp, point)| (point - mean).norm_squared()).sum::<f64>() / count;
+        variance <= config.stationary_radius * config.stationary_radius
+    }
+
+    // the alerts to raise or clear, watching is false for users outside of an emergency
+    pub fn check(&mut self, config: &MotionConfig, watching: bool, now: DateTime<Utc>, last_active: DateTime<Utc>) -> Vec<MotionChange> {
+        let current = if !watching {
+            None
+        } else if now - last_active > Duration::seconds(config.tag_lost_seconds as i64) {
+            Some(MotionAlertKind::TagLost)
+        } else if self.is_stationary(config, now) {
+            Some(MotionAlertKind::Stationary)
+        } else {
+            None
+        };
+
+        if current == self.condition {
+            return Vec::new();
+        }
+        let mut changes = Vec::new();
+        if let Some(previous) = self.condition {
+            changes.push(MotionChange::Cleared(previous));
+        }
+        if let Some(kind) = current {
+            changes.push(MotionChange::Raised(kind));
+        }
+        self.condition = current;
+        changes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn seconds(s: i64) -> DateTime<Utc> {
+        Utc.timestamp(1000 + s, 0)
+    }
+
+    fn config() -> MotionConfig {
+        let mut config = MotionConfig::new();
+        config.stationary_seconds = 10;
+        config.stationary_radius = 0.5;
+        config.tag_lost_seconds = 5;
+        config
+    }
+
+    #[test]
+    fn stationary() {
+        let config = config();
+        let mut tracker = MotionTracker::new();
+        for s in 0..10 {
+            // jitter well within the radius
+            let jitter = if s % 2 == 0 { 0.1 } else { -0.1 };
+            tracker.update(&config, Some(1), &na::Vector2::new(3.0 + jitter, 4.0), seconds(s));
+            assert!(tracker.check(&config, true, seconds(s), seconds(s)).is_empty());
+        }
+        tracker.update(&config, Some(1), &na::Vector2::new(3.0, 4.0), seconds(10));
+        assert_eq!(tracker.check(&config, true, seconds(10), seconds(10)), vec![MotionChange::Raised(MotionAlertKind::Stationary)]);
+        // raised once
+        assert!(tracker.check(&config, true, seconds(10), seconds(10)).is_empty());
+
+        tracker.update(&config, Some(1), &na::Vector2::new(9.0, 4.0), seconds(11));
+        assert_eq!(tracker.check(&config, true, seconds(11), seconds(11)), vec![MotionChange::Cleared(MotionAlertKind::Stationary)]);
+    }
+
+    #[test]
+    fn moving() {
+        let config = config();
+        let mut tracker = MotionTracker::new();
+        for s in 0..30 {
+            tracker.update(&config, Some(1), &na::Vector2::new(s as f64 * 0.5, 0.0), seconds(s));
+            assert!(tracker.check(&config, true, seconds(s), seconds(s)).is_empty());
+        }
+    }
+
+    #[test]
+    fn tag_lost() {
+        let config = config();
+        let mut tracker = MotionTracker::new();
+        tracker.update(&config, Some(1), &na::Vector2::new(0.0, 0.0), seconds(0));
+        assert!(tracker.check(&config, true, seconds(5), seconds(0)).is_empty());
+        assert_eq!(tracker.check(&config, true, seconds(6), seconds(0)), vec![MotionChange::Raised(MotionAlertKind::TagLost)]);
+
+        tracker.update(&config, Some(1), &na::Vector2::new(0.0, 0.0), seconds(12));
+        assert_eq!(tracker.check(&config, true, seconds(12), seconds(12)), vec![MotionChange::Cleared(MotionAlertKind::TagLost)]);
+
+        // back in the same place it was lost, long enough ago to be stationary
+        tracker.update(&config, Some(1), &na::Vector2::new(0.1, 0.0), seconds(13));
+        assert_eq!(tracker.check(&config, true, seconds(13), seconds(13)), vec![MotionChange::Raised(MotionAlertKind::Stationary)]);
+    }
+
+    #[test]
+    fn not_watching() {
+        let config = config();
+        let mut tracker = MotionTracker::new();
+        tracker.update(&config, Some(1), &na::Vector2::new(0.0, 0.0), seconds(0));
+        assert!(tracker.check(&config, false, seconds(60), seconds(0)).is_empty());
+        assert_eq!(tracker.check(&config, true, seconds(60), seconds(0)), vec![MotionChange::Raised(MotionAlertKind::TagLost)]);
+        assert_eq!(tracker.check(&config, false, seconds(60), seconds(0)), vec![MotionChange::Cleared(MotionAlertKind::TagLost)]);
+    }
+
+    #[test]
+    fn floor_change() {
+        let config = config();
+        let mut tracker = MotionTracker::new();
+        for s in 0..9 {
+            tracker.update(&config, Some(1), &na::Vector2::new(0.0, 0.0), seconds(s));
+        }
+        // the same coordinates on another floor are somewhere else
+        tracker.update(&config, Some(2), &na::Vector2::new(0.0, 0.0), seconds(9));
+        tracker.update(&config, Some(2), &na::Vector2::new(0.0, 0.0), seconds(10));
+        assert!(tracker.check(&config, true, seconds(10), seconds(10)).is_empty());
+    }
+
+    #[test]
+    fn merge() {
+        let mut alerts = Vec::new();
+        let mut alert = MotionAlert::new();
+        alert.id = 3;
+        merge_alert(&mut alerts, alert.clone());
+        assert_eq!(alerts.len(), 1);
+
+        alert.acknowledged_at = Some(seconds(0));
+        merge_alert(&mut alerts, alert.clone());
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].acknowledged_at, Some(seconds(0)));
+
+        alert.cleared_at = Some(seconds(1));
+        merge_alert(&mut alerts, alert);
+        assert!(alerts.is_empty());
+    }
+}
@@ -0,0 +1,273 @@
+// Position solver for a tag given ranges to any number of anchors.
+// The solution is seeded with a linearized least squares estimate, which is then refined
+// with a weighted Gauss-Newton iteration on the true (non-linear) range equations. Every
+// anchor that reports a range contributes, rather than only the first three.
+
+use na;
+use std::fmt;
+
+const MIN_MEASUREMENTS: usize = 3;
+const MAX_ITERATIONS: usize = 20;
+const CONVERGENCE_THRESHOLD: f64 = 1e-6; // meters
+// determinants below this are considered singular, which happens when all anchors
+// are (nearly) collinear and the position cannot be resolved.
+const SINGULAR_THRESHOLD: f64 = 1e-9;
+// avoid dividing by zero when the estimate sits directly on top of an anchor.
+const MIN_ANCHOR_DISTANCE: f64 = 1e-9;
+
+#[derive(Debug, Clone)]
+pub struct RangeMeasurement {
+    pub anchor: na::Vector2<f64>,
+    pub distance: f64,
+    // relative confidence in this range, typically the inverse of its variance.
+    pub weight: f64,
+}
+
+impl RangeMeasurement {
+    pub fn new(anchor: na::Vector2<f64>, distance: f64) -> RangeMeasurement {
+        RangeMeasurement {
+            anchor,
+            distance,
+            weight: 1.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Solution {
+    pub coordinates: na::Vector2<f64>,
+    // covariance of the coordinates, in square meters.
+    pub covariance: na::Matrix2<f64>,
+    // root mean square of the range residuals at the solution, in meters.
+    pub residual: f64,
+    // distance root mean square (sqrt of the covariance trace), in meters.
+    pub uncertainty: f64,
+    pub iterations: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SolveError {
+    // fewer anchors than required to resolve a 2d position.
+    NotEnoughAnchors(usize),
+    // the anchors are collinear or coincident.
+    DegenerateGeometry,
+    // a range or anchor position was not a finite number.
+    InvalidMeasurement,
+}
+
+impl fmt::Display for SolveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SolveError::NotEnoughAnchors(count) => write!(f, "not enough anchors to multilaterate, found {}", count),
+            SolveError::DegenerateGeometry => write!(f, "anchor geometry is degenerate"),
+            SolveError::InvalidMeasurement => write!(f, "measurement is not a finite number"),
+        }
+    }
+}
+
+// Linearize by subtracting the first range equation from the others, then solve the
+// resulting overdetermined system with weighted least squares.
+fn linear_seed(measurements: &[RangeMeasurement]) -> Result<na::Vector2<f64>, SolveError> {
+    let first = &measurements[0];
+    let mut ata = na::Matrix2::zeros();
+    let mut atb = na::Vector2::zeros();
+
+    for m in &measurements[1..] {
+        let row = na::Vector2::new(
+            2.0 * (m.anchor.x - first.anchor.x),
+            2.0 * (m.anchor.y - first.anchor.y),
+        );
+        let b = first.distance * first.distance - m.distance * m.distance
+            + m.anchor.norm_squared() - first.anchor.norm_squared();
+        let w = m.weight.min(first.weight);
+
+        ata += row * row.transpose() * w;
+        atb += row * b * w;
+    }
+
+    if ata.determinant().abs() < SINGULAR_THRESHOLD {
+        return Err(SolveError::DegenerateGeometry);
+    }
+
+    match ata.try_inverse() {
+        Some(inv) => Ok(inv * atb),
+        None => Err(SolveError::DegenerateGeometry),
+    }
+}
+
+// returns the weighted normal matrix, the weighted gradient and the weighted sum of square residuals
+fn normal_equations(measurements: &[RangeMeasurement], position: &na::Vector2<f64>) -> (na::Matrix2<f64>, na::Vector2<f64>, f64) {
+    let mut jtj = na::Matrix2::zeros();
+    let mut jtr = na::Vector2::zeros();
+    let mut sum_sq = 0.0;
+
+    for m in measurements {
+        let diff = position - m.anchor;
+        let range = diff.norm().max(MIN_ANCHOR_DISTANCE);
+        let residual = range - m.distance;
+        let jacobian = diff / range;
+
+        jtj += jacobian * jacobian.transpose() * m.weight;
+        jtr += jacobian * residual * m.weight;
+        sum_sq += residual * residual * m.weight;
+    }
+
+    (jtj, jtr, sum_sq)
+}
+
+pub fn solve(measurements: &[RangeMeasurement]) -> Result<Solution, SolveError> {
+    if measurements.len() < MIN_MEASUREMENTS {
+        return Err(SolveError::NotEnoughAnchors(measurements.len()));
+    }
+
+    let valid = measurements.iter().all(|m| {
+        m.anchor.x.is_finite() && m.anchor.y.is_finite()
+            && m.distance.is_finite() && m.weight.is_finite() && m.weight > 0.0
+    });
+    if !valid {
+        return Err(SolveError::InvalidMeasurement);
+    }
+
+    let mut position = linear_seed(measurements)?;
+    let mut iterations = 0;
+
+    while iterations < MAX_ITERATIONS {
+        iterations += 1;
+        let (jtj, jtr, _) = normal_equations(measurements, &position);
+        let step = match jtj.try_inverse() {
+            Some(inv) => inv * jtr,
+            // the linear seed is still a usable answer
+            None => break,
+        };
+
+        position -= step;
+        if step.norm() < CONVERGENCE_THRESHOLD {
+            break;
+        }
+    }
+
+    let (jtj, _, sum_sq) = normal_equations(measurements, &position);
+    let weight_sum: f64 = measurements.iter().map(|m| m.weight).sum();
+    let residual = (sum_sq / weight_sum).sqrt();
+
+    // scale the inverse normal matrix by the variance of unit weight.
+    // the variance is only defined with redundant measurements, which is guaranteed here.
+    let dof = (measurements.len() - 2) as f64;
+    let covariance = match jtj.try_inverse() {
+        Some(inv) => inv * (sum_sq / dof),
+        None => return Err(SolveError::DegenerateGeometry),
+    };
+
+    if !position.x.is_finite() || !position.y.is_finite() {
+        return Err(SolveError::DegenerateGeometry);
+    }
+
+    Ok(Solution {
+        coordinates: position,
+        covariance,
+        residual,
+        uncertainty: covariance.trace().max(0.0).sqrt(),
+        iterations,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ranges_to(point: na::Vector2<f64>, anchors: &[na::Vector2<f64>]) -> Vec<RangeMeasurement> {
+        anchors.iter().map(|a| RangeMeasurement::new(*a, (point - a).norm())).collect()
+    }
+
+    #[test]
+    fn exact_three_anchors() {
+        let anchors = [
+            na::Vector2::new(1.0, 3.0),
+            na::Vector2::new(1.0, 1.0),
+            na::Vector2::new(3.5, 1.0),
+        ];
+        let truth = na::Vector2::new(2.0, 2.0);
+        let solution = solve(&ranges_to(truth, &anchors)).unwrap();
+
+        assert!((solution.coordinates - truth).norm() < 1e-6);
+        assert!(solution.residual < 1e-6);
+    }
+
+    #[test]
+    fn uses_every_anchor() {
+        let anchors = [
+            na::Vector2::new(0.0, 0.0),
+            na::Vector2::new(10.0, 0.0),
+            na::Vector2::new(0.0, 10.0),
+            na::Vector2::new(10.0, 10.0),
+            na::Vector2::new(5.0, 12.0),
+        ];
+        let truth = na::Vector2::new(4.0, 6.0);
+        let mut measurements = ranges_to(truth, &anchors);
+        // corrupt a single range, the remaining anchors should outvote it
+        measurements[0].distance += 0.5;
+
+        let solution = solve(&measurements).unwrap();
+        let three_only = solve(&measurements[..3]).unwrap();
+
+        assert!((solution.coordinates - truth).norm() < (three_only.coordinates - truth).norm());
+        assert!(solution.residual > 0.0);
+        assert!(solution.uncertainty > 0.0);
+    }
+
+    #[test]
+    fn weights_favour_confident_ranges() {
+        let anchors = [
+            na::Vector2::new(0.0, 0.0),
+            na::Vector2::new(10.0, 0.0),
+            na::Vector2::new(0.0, 10.0),
+            na::Vector2::new(10.0, 10.0),
+        ];
+        let truth = na::Vector2::new(3.0, 3.0);
+        let mut measurements = ranges_to(truth, &anchors);
+        measurements[3].distance += 1.0;
+
+        let unweighted = solve(&measurements).unwrap();
+        measurements[3].weight = 0.01;
+        let weighted = solve(&measurements).unwrap();
+
+        assert!((weighted.coordinates - truth).norm() < (unweighted.coordinates - truth).norm());
+    }
+
+    #[test]
+    fn not_enough_anchors() {
+        let anchors = [
+            na::Vector2::new(0.0, 0.0),
+            na::Vector2::new(10.0, 0.0),
+        ];
+        let measurements = ranges_to(na::Vector2::new(1.0, 1.0), &anchors);
+
+        assert_eq!(solve(&measurements).unwrap_err(), SolveError::NotEnoughAnchors(2));
+        assert_eq!(solve(&[]).unwrap_err(), SolveError::NotEnoughAnchors(0));
+    }
+
+    #[test]
+    fn collinear_anchors() {
+        let anchors = [
+            na::Vector2::new(0.0, 0.0),
+            na::Vector2::new(5.0, 0.0),
+            na::Vector2::new(10.0, 0.0),
+        ];
+        let measurements = ranges_to(na::Vector2::new(3.0, 4.0), &anchors);
+
+        assert_eq!(solve(&measurements).unwrap_err(), SolveError::DegenerateGeometry);
+    }
+
+    #[test]
+    fn invalid_range() {
+        let anchors = [
+            na::Vector2::new(0.0, 0.0),
+            na::Vector2::new(10.0, 0.0),
+            na::Vector2::new(0.0, 10.0),
+        ];
+        let mut measurements = ranges_to(na::Vector2::new(1.0, 1.0), &anchors);
+        measurements[1].distance = std::f64::NAN;
+
+        assert_eq!(solve(&measurements).unwrap_err(), SolveError::InvalidMeasurement);
+    }
+}
@@ -0,0 +1,235 @@
+// Accountability during an emergency, sorting every user into those who have reached a muster
+// point, those last located somewhere else, and those who have never been located at all.
+
+use chrono::{ DateTime, Utc, };
+use crate::{ EmergencyStatus, Map, RealtimeUserData, TrackedUser, };
+use crate::zones::{ Zone, ZoneKind, };
+use na;
+use serde_derive::{ Deserialize, Serialize, };
+use std::fmt;
+
+#[derive(Copy, Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum MusterStatus {
+    // ordered by how urgently the user needs to be found
+    Missing,
+    NeverSeen,
+    Mustered,
+}
+
+impl fmt::Display for MusterStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            MusterStatus::Missing => write!(f, "Last Seen Inside"),
+            MusterStatus::NeverSeen => write!(f, "Never Seen"),
+            MusterStatus::Mustered => write!(f, "Mustered"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MusterEntry {
+    pub coordinates: Option<na::Vector2<f64>>,
+    pub employee_id: Option<String>,
+    pub last_seen: Option<DateTime<Utc>>,
+    pub map_id: Option<i32>,
+    pub map_name: Option<String>,
+    pub muster_point: Option<String>, // name of the muster zone the user is in
+    pub name: String,
+    pub status: MusterStatus,
+    pub user_id: i32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MusterReport {
+    pub emergency: EmergencyStatus,
+    pub entries: Vec<MusterEntry>,
+    pub generated_at: DateTime<Utc>,
+    pub missing: usize,
+    pub mustered: usize,
+    pub never_seen: usize,
+}
+
+// the most recent known location of a user, the realtime data is newer than the database
+fn location(user: &TrackedUser, realtime: &[RealtimeUserData]) -> Option<(i32, na::Vector2<f64>, DateTime<Utc>)> {
+    match realtime.iter().find(|rt| rt.id == user.id) {
+        Some(rt) => rt.map_id.map(|map_id| (map_id, rt.coordinates, rt.last_active)),
+        None => user.map_id.map(|map_id| (map_id, user.coordinates, user.last_active)),
+    }
+}
+
+// users only count as mustered when they were located in a muster zone after the emergency
+// started, a location from before then says nothing about where they are now. This still holds
+// once the emergency has ended, the report is then about the last emergency.
+pub fn report(users: Vec<TrackedUser>, realtime: &[RealtimeUserData], zones: &[Zone], maps: &[Map], emergency: EmergencyStatus, now: DateTime<Utc>) -> MusterReport {
+    let since = emergency.started_at;
+    let mut entries: Vec<MusterEntry> = users
+        .into_iter()
+        .map(|user| {
+            let located = location(&user, realtime);
+            let muster_point = located.and_then(|(map_id, point, timestamp)| {
+                if since.map_or(false, |since| timestamp < since) {
+                    return None;
+                }
+                zones.iter()
+                    .find(|zone| zone.kind == ZoneKind::MusterPoint && zone.map_id == map_id && zone.contains(&point))
+                    .map(|zone| zone.name.clone())
+            });
+            let status = match (&located, &muster_point) {
+                (None, _) => MusterStatus::NeverSeen,
+                (Some(_), Some(_)) => MusterStatus::Mustered,
+                (Some(_), None) => MusterStatus::Missing,
+            };
+            let map_id = located.map(|(map_id, _point, _timestamp)| map_id);
+
+            MusterEntry {
+                coordinates: located.map(|(_map_id, point, _timestamp)| point),
+                employee_id: user.employee_id,
+                last_seen: located.map(|(_map_id, _point, timestamp)| timestamp),
+                map_id,
+                map_name: map_id.and_then(|id| maps.iter().find(|map| map.id == id)).map(|map| map.name.clone()),
+                muster_point,
+                name: user.name,
+                status,
+                user_id: user.id,
+            }
+        })
+        .collect();
+    entries.sort_by(|a, b| a.status.cmp(&b.status).then_with(|| a.name.cmp(&b.name)));
+
+    let count = |status| entries.iter().filter(|entry| entry.status == status).count();
+    MusterReport {
+        missing: count(MusterStatus::Missing),
+        mustered: count(MusterStatus::Mustered),
+        never_seen: count(MusterStatus::NeverSeen),
+        emergency,
+        entries,
+        generated_at: now,
+    }
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains(|c: char| c == ',' || c == '"' || c == '\n') {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+pub fn to_csv(report: &MusterReport) -> String {
+    let mut csv = String::from("name,employee_id,status,map,x,y,last_seen,age_seconds,muster_point\n");
+    for entry in &report.entries {
+        let fields = vec![
+            entry.name.clone(),
+            entry.employee_id.clone().unwrap_or(String::new()),
+            entry.status.to_string(),
+            entry.map_name.clone().unwrap_or(String::new()),
+            entry.coordinates.map_or(String::new(), |point| format!("{:.2}", point.x)),
+            entry.coordinates.map_or(String::new(), |point| format!("{:.2}", point.y)),
+            entry.last_seen.map_or(String::new(), |stamp| stamp.to_rfc3339()),
+            entry.last_seen.map_or(String::new(), |stamp| (report.generated_at - stamp).num_seconds().to_string()),
+            entry.muster_point.clone().unwrap_or(String::new()),
+        ];
+        let line: Vec<String> = fields.iter().map(|field| csv_field(field)).collect();
+        csv.push_str(&line.join(","));
+        csv.push('\n');
+    }
+    csv
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{ Duration, TimeZone, };
+    use crate::ShortAddress;
+
+    fn user(id: i32, name: &str, map_id: Option<i32>, x: f64, last_active: DateTime<Utc>) -> TrackedUser {
+        let mut user = TrackedUser::new();
+        user.id = id;
+        user.name = name.to_string();
+        user.map_id = map_id;
+        user.coordinates = na::Vector2::new(x, 1.0);
+        user.last_active = last_active;
+        user
+    }
+
+    fn muster_zone() -> Zone {
+        let mut zone = Zone::new();
+        zone.id = 1;
+        zone.map_id = 1;
+        zone.kind = ZoneKind::MusterPoint;
+        zone.name = "parking lot".to_string();
+        zone.polygon = vec![
+            na::Vector2::new(0.0, 0.0),
+            na::Vector2::new(2.0, 0.0),
+            na::Vector2::new(2.0, 2.0),
+            na::Vector2::new(0.0, 2.0),
+        ];
+        zone
+    }
+
+    #[test]
+    fn statuses() {
+        let start = Utc.timestamp(1000, 0);
+        let mut emergency = EmergencyStatus::new();
+        emergency.active = true;
+        emergency.started_at = Some(start);
+
+        let users = vec![
+            user(1, "inside", Some(1), 1.0, start + Duration::seconds(5)),
+            user(2, "outside", Some(1), 5.0, start + Duration::seconds(5)),
+            user(3, "nobody", None, 0.0, Utc.timestamp(0, 0)),
+            // at the muster point, but only before the emergency
+            user(4, "stale", Some(1), 1.0, start - Duration::seconds(5)),
+            // the database says inside, the realtime data is newer
+            user(5, "walked out", Some(1), 5.0, start),
+        ];
+        let mut tagged = user(5, "walked out", Some(1), 1.5, start + Duration::seconds(9));
+        tagged.mac_address = Some(ShortAddress::nil());
+        let moved = RealtimeUserData::from(tagged);
+
+        let mut map = Map::new();
+        map.id = 1;
+        map.name = "ground".to_string();
+
+        let report = report(users, &[moved], &[muster_zone()], &[map], emergency, start + Duration::seconds(10));
+        assert_eq!(report.mustered, 2);
+        assert_eq!(report.missing, 2);
+        assert_eq!(report.never_seen, 1);
+
+        let names: Vec<&str> = report.entries.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, vec!["outside", "stale", "nobody", "inside", "walked out"]);
+        assert_eq!(report.entries[0].map_name, Some("ground".to_string()));
+        assert_eq!(report.entries[2].last_seen, None);
+        assert_eq!(report.entries[3].muster_point, Some("parking lot".to_string()));
+    }
+
+    #[test]
+    fn ended() {
+        let start = Utc.timestamp(1000, 0);
+        let mut emergency = EmergencyStatus::new();
+        emergency.started_at = Some(start);
+        emergency.ended_at = Some(start + Duration::seconds(60));
+
+        let users = vec![
+            user(1, "inside", Some(1), 1.0, start + Duration::seconds(5)),
+            user(2, "stale", Some(1), 1.0, start - Duration::seconds(5)),
+        ];
+        let report = report(users, &[], &[muster_zone()], &[], emergency, start + Duration::seconds(90));
+        assert_eq!(report.mustered, 1);
+        assert_eq!(report.missing, 1);
+    }
+
+    #[test]
+    fn csv() {
+        let now = Utc.timestamp(1000, 0);
+        let users = vec![
+            user(1, "Smith, Jane", Some(1), 1.0, now - Duration::seconds(30)),
+        ];
+        let report = report(users, &[], &[muster_zone()], &[], EmergencyStatus::new(), now);
+        let csv = to_csv(&report);
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[1].starts_with("\"Smith, Jane\",,Mustered,,1.00,1.00,"));
+        assert!(lines[1].ends_with(",30,parking lot"));
+    }
+}
@@ -0,0 +1,236 @@
+// The steps from ranges to a location that do not need the database, shared by the data
+// processor and the accuracy benchmark. The latest ranges from each beacon are kept and averaged,
+// grouped by the floor their beacon is placed on, and the tag is located on the most likely floor.
+
+use chrono::{ DateTime, Duration, TimeZone, Utc, };
+use crate::{ Beacon, MacAddress8, ShortAddress, TagData, };
+use crate::floor_selection::{ self, FloorCandidate, };
+use crate::multilateration::{ self, RangeMeasurement, Solution, SolveError, };
+use crate::tag_filter::FilterType;
+use std::collections::{ BTreeMap, VecDeque, };
+use std::fmt;
+
+pub const LOCATION_HISTORY_SIZE: usize = 5;
+// assumed variance of a single averaged range in square meters, this keeps a beacon with
+// a perfectly steady history from completely dominating the solution.
+const RANGE_VARIANCE_FLOOR: f64 = 0.01;
+// ranges from beacons that have not heard a tag in this long are forgotten, otherwise
+// anchors on a floor the tag has left keep contributing old ranges.
+const STALE_RANGE_SECONDS: i64 = 10;
+
+#[derive(Debug)]
+pub struct RangeHistory {
+    pub ranges: VecDeque<f64>,
+    pub last_seen: DateTime<Utc>,
+}
+
+// the recent ranges from every beacon that heard one tag
+#[derive(Debug, Default)]
+pub struct TagRanges {
+    pub beacons: BTreeMap<MacAddress8, RangeHistory>,
+}
+
+impl TagRanges {
+    pub fn new() -> TagRanges {
+        TagRanges::default()
+    }
+
+    pub fn append(&mut self, tag_data: &TagData) {
+        let entry = self.beacons.entry(tag_data.beacon_mac).or_insert_with(|| RangeHistory {
+            ranges: VecDeque::new(),
+            last_seen: tag_data.timestamp,
+        });
+        entry.ranges.push_back(tag_data.tag_distance);
+        entry.last_seen = tag_data.timestamp;
+        if entry.ranges.len() > LOCATION_HISTORY_SIZE {
+            entry.ranges.pop_front();
+        }
+
+        let stale_time = tag_data.timestamp - Duration::seconds(STALE_RANGE_SECONDS);
+        self.beacons.retain(|_mac, hist| hist.last_seen >= stale_time);
+    }
+
+    // one range per beacon, weighted by how consistent its history has been. None until enough
+    // beacons have heard the tag to locate it. when a state filter is active, it does the
+    // smoothing, so the latest range is used rather than adding the lag of a moving average.
+    pub fn averages(&self, tag_mac: ShortAddress, filter_type: FilterType, timestamp: DateTime<Utc>) -> Option<Vec<(TagData, f64)>> {
+        if self.beacons.len() < floor_selection::MIN_FLOOR_ANCHORS {
+            return None;
+        }
+
+        let averages = self.beacons.iter().map(|(beacon_mac, hist)| {
+            let hist_vec = &hist.ranges;
+            let count = hist_vec.len() as f64;
+            let mean = hist_vec.iter().sum::<f64>() / count;
+            let variance = hist_vec.iter().map(|d| (d - mean) * (d - mean)).sum::<f64>() / count;
+            let distance = match filter_type {
+                FilterType::Passthrough => mean,
+                FilterType::Kalman => *hist_vec.back().unwrap_or(&mean),
+            };
+            let data = TagData {
+                tag_mac,
+                beacon_mac: *beacon_mac,
+                tag_distance: distance,
+                timestamp,
+            };
+            (data, 1.0 / (RANGE_VARIANCE_FLOOR + variance / count))
+        }).collect();
+        Some(averages)
+    }
+}
+
+// a location before it is smoothed by the tag's filter
+#[derive(Debug, Clone)]
+pub struct Fix {
+    pub map_id: i32,
+    // the beacons on the map that ranged the tag, and their ranges in the same order
+    pub beacons: Vec<Beacon>,
+    pub ranges: Vec<(TagData, f64)>,
+    pub solution: Solution,
+    // of the latest range
+    pub timestamp: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LocateError {
+    // no single floor has enough beacons to locate the tag
+    NoFloor,
+    Solve(SolveError),
+}
+
+impl fmt::Display for LocateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LocateError::NoFloor => write!(f, "no floor has enough beacons to locate the tag"),
+            LocateError::Solve(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+// locates the tag from the averaged ranges, the beacons are those that sent the ranges in any
+// order. the current map is preferred when the floors are close.
+pub fn locate(beacons: Vec<Beacon>, mut averages: Vec<(TagData, f64)>, current_map_id: Option<i32>) -> Result<Fix, LocateError> {
+    // beacons without a map cannot contribute to a location
+    let mut floors: BTreeMap<i32, (Vec<Beacon>, Vec<(TagData, f64)>)> = BTreeMap::new();
+    for beacon in beacons {
+        let map_id = match beacon.map_id {
+            Some(map_id) => map_id,
+            None => continue,
+        };
+        if let Some(index) = averages.iter().position(|(t, _weight)| t.beacon_mac == beacon.mac_address) {
+            let data = averages.swap_remove(index);
+            let floor = floors.entry(map_id).or_insert_with(|| (Vec::new(), Vec::new()));
+            floor.0.push(beacon);
+            floor.1.push(data);
+        }
+    }
+
+    let candidates: Vec<FloorCandidate> = floors
+        .iter()
+        .map(|(map_id, (_beacons, data))| {
+            FloorCandidate {
+                map_id: *map_id,
+                ranges: data.iter().map(|(t, weight)| (t.tag_distance, *weight)).collect(),
+            }
+        })
+        .collect();
+    let map_id = floor_selection::select_floor(&candidates, current_map_id).ok_or(LocateError::NoFloor)?;
+    let (beacons, ranges) = floors.remove(&map_id).unwrap_or_default();
+
+    let measurements: Vec<RangeMeasurement> = beacons
+        .iter()
+        .zip(ranges.iter())
+        .map(|(beacon, (data, weight))| {
+            RangeMeasurement {
+                anchor: beacon.coordinates,
+                distance: data.tag_distance,
+                weight: *weight,
+            }
+        })
+        .collect();
+    let solution = multilateration::solve(&measurements).map_err(LocateError::Solve)?;
+    let timestamp = ranges.iter().fold(Utc.timestamp(0, 0), |max, (t, _weight)| max.max(t.timestamp));
+
+    Ok(Fix {
+        map_id,
+        beacons,
+        ranges,
+        solution,
+        timestamp,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use na;
+
+    fn mac(n: u8) -> MacAddress8 {
+        MacAddress8::from_bytes(&[n, 0, 0, 0, 0, 0, 0, 0]).unwrap()
+    }
+
+    fn range(beacon: u8, distance: f64, seconds: i64) -> TagData {
+        TagData {
+            beacon_mac: mac(beacon),
+            tag_distance: distance,
+            tag_mac: ShortAddress::new([0, 1]),
+            timestamp: Utc.timestamp(1000 + seconds, 0),
+        }
+    }
+
+    fn beacon(n: u8, map_id: i32, x: f64, y: f64) -> Beacon {
+        let mut beacon = Beacon::new();
+        beacon.coordinates = na::Vector2::new(x, y);
+        beacon.mac_address = mac(n);
+        beacon.map_id = Some(map_id);
+        beacon
+    }
+
+    #[test]
+    fn averages() {
+        let mut ranges = TagRanges::new();
+        let tag = ShortAddress::new([0, 1]);
+        ranges.append(&range(1, 2.0, 0));
+        ranges.append(&range(2, 3.0, 0));
+        assert!(ranges.averages(tag, FilterType::Passthrough, Utc.timestamp(1000, 0)).is_none());
+
+        ranges.append(&range(3, 4.0, 0));
+        ranges.append(&range(1, 4.0, 1));
+        let averages = ranges.averages(tag, FilterType::Passthrough, Utc.timestamp(1001, 0)).unwrap();
+        assert_eq!(averages.len(), 3);
+        assert_eq!(averages[0].0.tag_distance, 3.0);
+        // the steady beacons are trusted more
+        assert!(averages[0].1 < averages[1].1);
+
+        let latest = ranges.averages(tag, FilterType::Kalman, Utc.timestamp(1001, 0)).unwrap();
+        assert_eq!(latest[0].0.tag_distance, 4.0);
+
+        // old ranges are forgotten
+        ranges.append(&range(1, 4.0, STALE_RANGE_SECONDS + 1));
+        assert_eq!(ranges.beacons.len(), 1);
+    }
+
+    #[test]
+    fn locates_on_best_floor() {
+        let beacons = vec![
+            beacon(1, 1, 0.0, 0.0),
+            beacon(2, 1, 10.0, 0.0),
+            beacon(3, 1, 0.0, 10.0),
+            // a single beacon heard through the ceiling
+            beacon(4, 2, 5.0, 5.0),
+        ];
+        let point = na::Vector2::new(3.0, 4.0);
+        let averages: Vec<(TagData, f64)> = beacons.iter().enumerate()
+            .map(|(i, b)| (range(b.mac_address.as_bytes()[0], (b.coordinates - point).norm(), i as i64), 10.0))
+            .collect();
+
+        let fix = locate(beacons, averages, Some(2)).unwrap();
+        assert_eq!(fix.map_id, 1);
+        assert_eq!(fix.beacons.len(), 3);
+        assert!((fix.solution.coordinates - point).norm() < 1e-6);
+        assert_eq!(fix.timestamp, Utc.timestamp(1002, 0));
+
+        let unplaced = vec![beacon(1, 1, 0.0, 0.0)];
+        assert_eq!(locate(unplaced, vec![(range(1, 1.0, 0), 1.0)], None).unwrap_err(), LocateError::NoFloor);
+    }
+}
@@ -0,0 +1,339 @@
+// Per tag state estimation, smoothing the positions produced by the multilateration solver.
+// The kalman filter models the tag as moving with a constant velocity, with the changes in
+// velocity (someone starting or stopping walking) treated as process noise.
+
+use chrono::{ DateTime, Utc, };
+use crate::multilateration::Solution;
+use na;
+use serde_derive::{ Deserialize, Serialize, };
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum FilterType {
+    // use the solver output directly
+    Passthrough,
+    // constant velocity kalman filter
+    Kalman,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FilterConfig {
+    pub filter_type: FilterType,
+    // acceleration noise spectral density, in (m/s^2)^2 / Hz. larger values follow
+    // changes in direction faster, smaller values give smoother tracks.
+    pub process_noise: f64,
+    // lower bound on the variance of a solver position, in square meters.
+    pub measurement_noise: f64,
+    // variance of the initial velocity guess, in (m/s)^2.
+    pub initial_velocity_variance: f64,
+    // the filter is restarted when a tag has not been seen for this long, in seconds.
+    pub reset_timeout: f64,
+}
+
+impl FilterConfig {
+    pub fn new() -> FilterConfig {
+        FilterConfig {
+            filter_type: FilterType::Kalman,
+            process_noise: 0.5,
+            measurement_noise: 0.05,
+            initial_velocity_variance: 4.0,
+            reset_timeout: 10.0,
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), &'static str> {
+        let noises = [self.process_noise, self.measurement_noise, self.initial_velocity_variance];
+        if noises.iter().any(|noise| !noise.is_finite() || *noise <= 0.0) {
+            return Err("filter noise values must be greater than zero");
+        }
+        if !self.reset_timeout.is_finite() || self.reset_timeout <= 0.0 {
+            return Err("the filter reset timeout must be greater than zero");
+        }
+        Ok(())
+    }
+}
+
+impl Default for FilterConfig {
+    fn default() -> Self {
+        FilterConfig::new()
+    }
+}
+
+// one standard deviation error ellipse of a position estimate
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct CovarianceEllipse {
+    pub semi_major: f64, // meters
+    pub semi_minor: f64, // meters
+    pub angle: f64, // radians, counter clockwise from the x axis to the major axis
+}
+
+impl CovarianceEllipse {
+    pub fn new() -> CovarianceEllipse {
+        CovarianceEllipse {
+            semi_major: 0.0,
+            semi_minor: 0.0,
+            angle: 0.0,
+        }
+    }
+
+    pub fn from_covariance(cov: &na::Matrix2<f64>) -> CovarianceEllipse {
+        let a = cov[(0, 0)];
+        let b = (cov[(0, 1)] + cov[(1, 0)]) / 2.0;
+        let c = cov[(1, 1)];
+
+        let mean = (a + c) / 2.0;
+        let spread = (((a - c) / 2.0).powi(2) + b * b).sqrt();
+
+        CovarianceEllipse {
+            semi_major: (mean + spread).max(0.0).sqrt(),
+            semi_minor: (mean - spread).max(0.0).sqrt(),
+            angle: 0.5 * (2.0 * b).atan2(a - c),
+        }
+    }
+}
+
+impl Default for CovarianceEllipse {
+    fn default() -> Self {
+        CovarianceEllipse::new()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FilteredState {
+    pub coordinates: na::Vector2<f64>,
+    pub velocity: na::Vector2<f64>,
+    pub covariance: na::Matrix2<f64>,
+}
+
+impl FilteredState {
+    pub fn ellipse(&self) -> CovarianceEllipse {
+        CovarianceEllipse::from_covariance(&self.covariance)
+    }
+
+    pub fn uncertainty(&self) -> f64 {
+        self.covariance.trace().max(0.0).sqrt()
+    }
+}
+
+#[derive(Debug, Clone)]
+struct KalmanState {
+    // x, y, vx, vy
+    state: na::Vector4<f64>,
+    covariance: na::Matrix4<f64>,
+    last_update: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone)]
+pub struct TagFilter {
+    config: FilterConfig,
+    kalman: Option<KalmanState>,
+}
+
+impl TagFilter {
+    pub fn new(config: FilterConfig) -> TagFilter {
+        TagFilter {
+            config,
+            kalman: None,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.kalman = None;
+    }
+
+    pub fn update(&mut self, measurement: &Solution, timestamp: DateTime<Utc>) -> FilteredState {
+        match self.config.filter_type {
+            FilterType::Passthrough => {
+                FilteredState {
+                    coordinates: measurement.coordinates,
+                    velocity: na::Vector2::zeros(),
+                    covariance: measurement.covariance,
+                }
+            },
+            FilterType::Kalman => self.update_kalman(measurement, timestamp),
+        }
+    }
+
+    fn measurement_covariance(&self, measurement: &Solution) -> na::Matrix2<f64> {
+        measurement.covariance + na::Matrix2::identity() * self.config.measurement_noise
+    }
+
+    fn initial_state(&self, measurement: &Solution, timestamp: DateTime<Utc>) -> KalmanState {
+        let r = self.measurement_covariance(measurement);
+        let mut covariance = na::Matrix4::zeros();
+        covariance.fixed_slice_mut::<na::U2, na::U2>(0, 0).copy_from(&r);
+        covariance[(2, 2)] = self.config.initial_velocity_variance;
+        covariance[(3, 3)] = self.config.initial_velocity_variance;
+
+        KalmanState {
+            state: na::Vector4::new(measurement.coordinates.x, measurement.coordinates.y, 0.0, 0.0),
+            covariance,
+            last_update: timestamp,
+        }
+    }
+
+    fn update_kalman(&mut self, measurement: &Solution, timestamp: DateTime<Utc>) -> FilteredState {
+        let r = self.measurement_covariance(measurement);
+        let next = match self.kalman.take() {
+            Some(prev) => {
+                let dt = (timestamp - prev.last_update).num_milliseconds() as f64 / 1000.0;
+                if dt > self.config.reset_timeout || dt < 0.0 {
+                    self.initial_state(measurement, timestamp)
+                } else {
+                    // predict
+                    let f = na::Matrix4::new(
+                        1.0, 0.0, dt, 0.0,
+                        0.0, 1.0, 0.0, dt,
+                        0.0, 0.0, 1.0, 0.0,
+                        0.0, 0.0, 0.0, 1.0,
+                    );
+                    let q = self.config.process_noise;
+                    let dt2 = dt * dt / 2.0 * q;
+                    let dt3 = dt * dt * dt / 3.0 * q;
+                    let process = na::Matrix4::new(
+                        dt3, 0.0, dt2, 0.0,
+                        0.0, dt3, 0.0, dt2,
+                        dt2, 0.0, dt * q, 0.0,
+                        0.0, dt2, 0.0, dt * q,
+                    );
+                    let state = f * prev.state;
+                    let covariance = f * prev.covariance * f.transpose() + process;
+
+                    // correct with the solver position
+                    let h = na::Matrix2x4::new(
+                        1.0, 0.0, 0.0, 0.0,
+                        0.0, 1.0, 0.0, 0.0,
+                    );
+                    let innovation = measurement.coordinates - h * state;
+                    let s = h * covariance * h.transpose() + r;
+                    match s.try_inverse() {
+                        Some(s_inv) => {
+                            let gain = covariance * h.transpose() * s_inv;
+                            let identity = na::Matrix4::<f64>::identity();
+                            let corrected = (identity - gain * h) * covariance;
+                            KalmanState {
+                                state: state + gain * innovation,
+                                // keep the covariance symmetric despite rounding
+                                covariance: (corrected + corrected.transpose()) / 2.0,
+                                last_update: timestamp,
+                            }
+                        },
+                        None => self.initial_state(measurement, timestamp),
+                    }
+                }
+            },
+            None => self.initial_state(measurement, timestamp),
+        };
+
+        let result = FilteredState {
+            coordinates: na::Vector2::new(next.state[0], next.state[1]),
+            velocity: na::Vector2::new(next.state[2], next.state[3]),
+            covariance: next.covariance.fixed_slice::<na::U2, na::U2>(0, 0).into_owned(),
+        };
+        self.kalman = Some(next);
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    fn measurement(x: f64, y: f64, variance: f64) -> Solution {
+        Solution {
+            coordinates: na::Vector2::new(x, y),
+            covariance: na::Matrix2::identity() * variance,
+            residual: 0.0,
+            uncertainty: (2.0 * variance).sqrt(),
+            iterations: 1,
+        }
+    }
+
+    #[test]
+    fn validate() {
+        assert!(FilterConfig::new().validate().is_ok());
+        let mut config = FilterConfig::new();
+        config.process_noise = std::f64::NAN;
+        assert!(config.validate().is_err());
+        let mut config = FilterConfig::new();
+        config.reset_timeout = 0.0;
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn passthrough() {
+        let mut config = FilterConfig::new();
+        config.filter_type = FilterType::Passthrough;
+        let mut filter = TagFilter::new(config);
+
+        let state = filter.update(&measurement(1.0, 2.0, 0.1), Utc::now());
+        assert_eq!(state.coordinates, na::Vector2::new(1.0, 2.0));
+        assert_eq!(state.velocity, na::Vector2::zeros());
+    }
+
+    #[test]
+    fn tracks_constant_velocity() {
+        let mut filter = TagFilter::new(FilterConfig::new());
+        let start = Utc::now();
+        let mut state = filter.update(&measurement(0.0, 0.0, 0.05), start);
+
+        for i in 1..30 {
+            let t = start + Duration::milliseconds(i * 500);
+            state = filter.update(&measurement(i as f64 * 0.5, 0.0, 0.05), t);
+        }
+
+        // walking at 1 m/s along x
+        assert!((state.velocity.x - 1.0).abs() < 0.1);
+        assert!(state.velocity.y.abs() < 0.1);
+        assert!((state.coordinates.x - 14.5).abs() < 0.2);
+    }
+
+    #[test]
+    fn smooths_noise() {
+        let mut filter = TagFilter::new(FilterConfig::new());
+        let start = Utc::now();
+        let mut state = filter.update(&measurement(5.0, 5.0, 0.1), start);
+
+        for i in 1..20 {
+            let t = start + Duration::milliseconds(i * 1000);
+            let jitter = if i % 2 == 0 { 0.5 } else { -0.5 };
+            state = filter.update(&measurement(5.0 + jitter, 5.0 - jitter, 0.1), t);
+        }
+
+        assert!((state.coordinates - na::Vector2::new(5.0, 5.0)).norm() < 0.5);
+        assert!(state.uncertainty() < (2.0 * 0.15f64).sqrt());
+    }
+
+    #[test]
+    fn resets_after_timeout() {
+        let config = FilterConfig::new();
+        let timeout = config.reset_timeout;
+        let mut filter = TagFilter::new(config);
+        let start = Utc::now();
+        filter.update(&measurement(0.0, 0.0, 0.05), start);
+        filter.update(&measurement(1.0, 0.0, 0.05), start + Duration::seconds(1));
+
+        let later = start + Duration::seconds(timeout as i64 + 5);
+        let state = filter.update(&measurement(20.0, 20.0, 0.05), later);
+        assert_eq!(state.coordinates, na::Vector2::new(20.0, 20.0));
+        assert_eq!(state.velocity, na::Vector2::zeros());
+    }
+
+    #[test]
+    fn ellipse_axes() {
+        let cov = na::Matrix2::new(
+            4.0, 0.0,
+            0.0, 1.0,
+        );
+        let ellipse = CovarianceEllipse::from_covariance(&cov);
+        assert!((ellipse.semi_major - 2.0).abs() < 1e-9);
+        assert!((ellipse.semi_minor - 1.0).abs() < 1e-9);
+        assert!(ellipse.angle.abs() < 1e-9);
+
+        let rotated = CovarianceEllipse::from_covariance(&na::Matrix2::new(
+            1.0, 4.0 - 1.0,
+            4.0 - 1.0, 1.0,
+        ));
+        assert!((rotated.angle - std::f64::consts::FRAC_PI_4).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,362 @@
+// Areas drawn on a map (hazards, muster points, restricted rooms). Every located position is
+// checked against the zones of its map, and a user entering, leaving, or staying too long in a
+// zone produces an event for the incident log.
+
+use chrono::{ DateTime, Duration, TimeZone, Utc, };
+use na;
+use serde_derive::{ Deserialize, Serialize, };
+use std::collections::BTreeMap;
+use std::fmt;
+
+#[derive(Copy, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ZoneKind {
+    Hazard,
+    MusterPoint,
+    Restricted,
+    Other,
+}
+
+impl ZoneKind {
+    pub fn all() -> [ZoneKind; 4] {
+        [ZoneKind::Hazard, ZoneKind::MusterPoint, ZoneKind::Restricted, ZoneKind::Other]
+    }
+}
+
+impl fmt::Display for ZoneKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            ZoneKind::Hazard => write!(f, "Hazard"),
+            ZoneKind::MusterPoint => write!(f, "Muster Point"),
+            ZoneKind::Restricted => write!(f, "Restricted"),
+            ZoneKind::Other => write!(f, "Other"),
+        }
+    }
+}
+
+impl From<ZoneKind> for i16 {
+    fn from(kind: ZoneKind) -> Self {
+        match kind {
+            ZoneKind::Hazard => 0,
+            ZoneKind::MusterPoint => 1,
+            ZoneKind::Restricted => 2,
+            ZoneKind::Other => 3,
+        }
+    }
+}
+
+impl From<i16> for ZoneKind {
+    fn from(kind: i16) -> Self {
+        match kind {
+            0 => ZoneKind::Hazard,
+            1 => ZoneKind::MusterPoint,
+            2 => ZoneKind::Restricted,
+            3 => ZoneKind::Other,
+            _ => panic!("unexpected zone kind"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Zone {
+    pub id: i32, // primary key
+    pub dwell_seconds: Option<i32>, // alert when a user stays inside longer than this
+    pub kind: ZoneKind,
+    pub map_id: i32,
+    pub name: String,
+    pub polygon: Vec<na::Vector2<f64>>, // vertices in meters, in the same space as beacons
+}
+
+impl Zone {
+    pub fn new() -> Zone {
+        Zone {
+            id: -1,
+            dwell_seconds: None,
+            kind: ZoneKind::Other,
+            map_id: -1,
+            name: String::new(),
+            polygon: Vec::new(),
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), &'static str> {
+        if self.name.trim().is_empty() {
+            return Err("a zone must have a name");
+        }
+        if self.polygon.len() < 3 {
+            return Err("a zone needs at least 3 points");
+        }
+        if self.dwell_seconds.map_or(false, |seconds| seconds <= 0) {
+            return Err("the dwell time must be positive");
+        }
+        Ok(())
+    }
+
+    // ray casting, points exactly on an edge may land on either side
+    pub fn contains(&self, point: &na::Vector2<f64>) -> bool {
+        if self.polygon.len() < 3 {
+            return false;
+        }
+
+        let mut inside = false;
+        let mut j = self.polygon.len() - 1;
+        for i in 0..self.polygon.len() {
+            let a = &self.polygon[i];
+            let b = &self.polygon[j];
+            if (a.y > point.y) != (b.y > point.y)
+                && point.x < (b.x - a.x) * (point.y - a.y) / (b.y - a.y) + a.x {
+                inside = !inside;
+            }
+            j = i;
+        }
+        inside
+    }
+}
+
+#[derive(Copy, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ZoneEventKind {
+    Enter,
+    Exit,
+    DwellExceeded,
+}
+
+impl fmt::Display for ZoneEventKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            ZoneEventKind::Enter => write!(f, "Entered"),
+            ZoneEventKind::Exit => write!(f, "Left"),
+            ZoneEventKind::DwellExceeded => write!(f, "Stayed Too Long"),
+        }
+    }
+}
+
+impl From<ZoneEventKind> for i16 {
+    fn from(kind: ZoneEventKind) -> Self {
+        match kind {
+            ZoneEventKind::Enter => 0,
+            ZoneEventKind::Exit => 1,
+            ZoneEventKind::DwellExceeded => 2,
+        }
+    }
+}
+
+impl From<i16> for ZoneEventKind {
+    fn from(kind: i16) -> Self {
+        match kind {
+            0 => ZoneEventKind::Enter,
+            1 => ZoneEventKind::Exit,
+            2 => ZoneEventKind::DwellExceeded,
+            _ => panic!("unexpected zone event kind"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ZoneEvent {
+    pub id: i64, // primary key
+    pub kind: ZoneEventKind,
+    pub map_id: Option<i32>,
+    pub timestamp: DateTime<Utc>,
+    pub user_id: i32,
+    pub user_name: String,
+    pub zone_id: Option<i32>, // None once the zone has been deleted
+    pub zone_kind: ZoneKind,
+    pub zone_name: String, // kept so the log still reads after the zone is deleted
+}
+
+impl ZoneEvent {
+    pub fn new() -> ZoneEvent {
+        ZoneEvent {
+            id: -1,
+            kind: ZoneEventKind::Enter,
+            map_id: None,
+            timestamp: Utc.timestamp(0, 0),
+            user_id: -1,
+            user_name: String::new(),
+            zone_id: None,
+            zone_kind: ZoneKind::Other,
+            zone_name: String::new(),
+        }
+    }
+
+    fn for_zone(zone: &Zone, kind: ZoneEventKind, timestamp: DateTime<Utc>) -> ZoneEvent {
+        ZoneEvent {
+            kind,
+            map_id: Some(zone.map_id),
+            timestamp,
+            zone_id: Some(zone.id),
+            zone_kind: zone.kind,
+            zone_name: zone.name.clone(),
+            ..ZoneEvent::new()
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Occupancy {
+    entered: DateTime<Utc>,
+    dwell_alerted: bool,
+}
+
+// which zones a single user is inside of
+#[derive(Debug, Clone)]
+pub struct ZoneTracker {
+    occupied: BTreeMap<i32, Occupancy>,
+}
+
+impl ZoneTracker {
+    pub fn new() -> ZoneTracker {
+        ZoneTracker {
+            occupied: BTreeMap::new(),
+        }
+    }
+
+    pub fn is_inside(&self, zone_id: i32) -> bool {
+        self.occupied.contains_key(&zone_id)
+    }
+
+    // check a new position against every zone. the events are missing the user, the caller
+    // knows who moved. zones that no longer exist are forgotten without an event.
+    pub fn update(&mut self, zones: &[Zone], map_id: Option<i32>, point: &na::Vector2<f64>, timestamp: DateTime<Utc>) -> Vec<ZoneEvent> {
+        let mut events = Vec::new();
+        self.occupied.retain(|zone_id, _| zones.iter().any(|zone| zone.id == *zone_id));
+
+        for zone in zones {
+            let inside = Some(zone.map_id) == map_id && zone.contains(point);
+            match (inside, self.occupied.get_mut(&zone.id)) {
+                (true, None) => {
+                    self.occupied.insert(zone.id, Occupancy {
+                        entered: timestamp,
+                        dwell_alerted: false,
+                    });
+                    events.push(ZoneEvent::for_zone(zone, ZoneEventKind::Enter, timestamp));
+                },
+                (true, Some(occupancy)) => {
+                    let exceeded = zone.dwell_seconds.map_or(false, |seconds| {
+                        timestamp - occupancy.entered >= Duration::seconds(seconds as i64)
+                    });
+                    if exceeded && !occupancy.dwell_alerted {
+                        occupancy.dwell_alerted = true;
+                        events.push(ZoneEvent::for_zone(zone, ZoneEventKind::DwellExceeded, timestamp));
+                    }
+                },
+                (false, Some(_)) => {
+                    self.occupied.remove(&zone.id);
+                    events.push(ZoneEvent::for_zone(zone, ZoneEventKind::Exit, timestamp));
+                },
+                (false, None) => {},
+            }
+        }
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn square(id: i32, map_id: i32, dwell_seconds: Option<i32>) -> Zone {
+        Zone {
+            id,
+            dwell_seconds,
+            kind: ZoneKind::Hazard,
+            map_id,
+            name: format!("zone {}", id),
+            polygon: vec![
+                na::Vector2::new(0.0, 0.0),
+                na::Vector2::new(4.0, 0.0),
+                na::Vector2::new(4.0, 4.0),
+                na::Vector2::new(0.0, 4.0),
+            ],
+        }
+    }
+
+    fn kinds(events: &Vec<ZoneEvent>) -> Vec<ZoneEventKind> {
+        events.iter().map(|e| e.kind).collect()
+    }
+
+    #[test]
+    fn contains() {
+        let zone = square(1, 1, None);
+        assert!(zone.contains(&na::Vector2::new(2.0, 2.0)));
+        assert!(!zone.contains(&na::Vector2::new(5.0, 2.0)));
+        assert!(!zone.contains(&na::Vector2::new(2.0, -1.0)));
+
+        // concave, an L shape missing its top right corner
+        let mut l_shape = square(2, 1, None);
+        l_shape.polygon = vec![
+            na::Vector2::new(0.0, 0.0),
+            na::Vector2::new(4.0, 0.0),
+            na::Vector2::new(4.0, 2.0),
+            na::Vector2::new(2.0, 2.0),
+            na::Vector2::new(2.0, 4.0),
+            na::Vector2::new(0.0, 4.0),
+        ];
+        assert!(l_shape.contains(&na::Vector2::new(1.0, 3.0)));
+        assert!(l_shape.contains(&na::Vector2::new(3.0, 1.0)));
+        assert!(!l_shape.contains(&na::Vector2::new(3.0, 3.0)));
+
+        l_shape.polygon.truncate(2);
+        assert!(!l_shape.contains(&na::Vector2::new(1.0, 0.5)));
+    }
+
+    #[test]
+    fn validate() {
+        assert!(square(1, 1, Some(60)).validate().is_ok());
+        assert!(square(1, 1, Some(0)).validate().is_err());
+        let mut zone = square(1, 1, None);
+        zone.name = " ".to_string();
+        assert!(zone.validate().is_err());
+        let mut zone = square(1, 1, None);
+        zone.polygon.pop();
+        zone.polygon.pop();
+        assert!(zone.validate().is_err());
+    }
+
+    #[test]
+    fn enter_dwell_exit() {
+        let zones = vec![square(1, 1, Some(10))];
+        let mut tracker = ZoneTracker::new();
+        let start = Utc.timestamp(100, 0);
+        let inside = na::Vector2::new(1.0, 1.0);
+
+        assert!(tracker.update(&zones, Some(1), &na::Vector2::new(6.0, 1.0), start).is_empty());
+
+        let events = tracker.update(&zones, Some(1), &inside, start);
+        assert_eq!(kinds(&events), vec![ZoneEventKind::Enter]);
+        assert_eq!(events[0].zone_id, Some(1));
+        assert_eq!(events[0].zone_name, "zone 1");
+        assert!(tracker.is_inside(1));
+
+        assert!(tracker.update(&zones, Some(1), &inside, start + Duration::seconds(5)).is_empty());
+        let events = tracker.update(&zones, Some(1), &inside, start + Duration::seconds(10));
+        assert_eq!(kinds(&events), vec![ZoneEventKind::DwellExceeded]);
+        // only alerted once per visit
+        assert!(tracker.update(&zones, Some(1), &inside, start + Duration::seconds(20)).is_empty());
+
+        let events = tracker.update(&zones, Some(1), &na::Vector2::new(6.0, 1.0), start + Duration::seconds(21));
+        assert_eq!(kinds(&events), vec![ZoneEventKind::Exit]);
+        assert!(!tracker.is_inside(1));
+    }
+
+    #[test]
+    fn other_maps() {
+        let zones = vec![square(1, 1, None), square(2, 2, None)];
+        let mut tracker = ZoneTracker::new();
+        let now = Utc.timestamp(100, 0);
+        let point = na::Vector2::new(1.0, 1.0);
+
+        let events = tracker.update(&zones, Some(1), &point, now);
+        assert_eq!(kinds(&events), vec![ZoneEventKind::Enter]);
+        assert_eq!(events[0].zone_id, Some(1));
+
+        // the same coordinates on another floor leave the first zone
+        let events = tracker.update(&zones, Some(2), &point, now);
+        assert_eq!(kinds(&events), vec![ZoneEventKind::Exit, ZoneEventKind::Enter]);
+        assert_eq!(events[0].zone_id, Some(1));
+        assert_eq!(events[1].zone_id, Some(2));
+
+        // deleted zones are forgotten quietly
+        assert!(tracker.update(&zones[..1], Some(1), &point, now).len() == 1);
+        assert!(!tracker.is_inside(2));
+    }
+}
@@ -1,4 +1,6 @@
 use common::*;
+use common::motion::{ MotionAlert, MotionAlertKind, };
+use common::zones::{ Zone, ZoneKind, };
 use stdweb::traits::*;
 use na;
 use stdweb::web::event::{ ClickEvent, };
@@ -10,6 +12,8 @@ use crate::util::WebUserType;
 
 const USER_RADIUS: f64 = 5.0;
 const BEACON_RADIUS: f64 = 8.0;
+const ZONE_VERTEX_RADIUS: f64 = 3.0;
+const ALERT_RADIUS: f64 = 12.0;
 const MAX_TIME: f64 = 30000.0; // milliseconds
 
 struct GradColor {
@@ -41,6 +45,15 @@ pub fn screen_space(map: &Map, x: f64, y: f64) -> na::Vector2<f64> {
     na::Vector2::new(x, map.bounds.y as f64 - y)
 }
 
+fn zone_color(kind: ZoneKind) -> &'static str {
+    match kind {
+        ZoneKind::Hazard => "#FF0000",
+        ZoneKind::MusterPoint => "#00AA00",
+        ZoneKind::Restricted => "#FF8800",
+        ZoneKind::Other => "#0000FF",
+    }
+}
+
 fn color_to_hex(c: &LinSrgb<f64>) -> String {
     let comps = c.into_components();
     let color_string = format!(
@@ -227,6 +240,80 @@ impl Canvas {
         }
     }
 
+    fn zone_path(&mut self, map: &Map, zone: &Zone) {
+        self.context.begin_path();
+        for (i, point) in zone.polygon.iter().enumerate() {
+            let pos = screen_space(map, point.x * map.scale, point.y * map.scale);
+            if i == 0 {
+                self.context.move_to(pos.x, pos.y);
+            } else {
+                self.context.line_to(pos.x, pos.y);
+            }
+        }
+        self.context.close_path();
+    }
+
+    pub fn draw_zones(&mut self, map: &Map, zones: &Vec<&Zone>) {
+        self.context.save();
+        for zone in zones {
+            if zone.polygon.len() < 3 {
+                continue;
+            }
+            let color = zone_color(zone.kind);
+            self.zone_path(map, zone);
+            self.context.set_fill_style_color(&format!("{}33", color));
+            self.context.fill(FillRule::NonZero);
+            self.context.set_stroke_style_color(color);
+            self.context.stroke();
+
+            // label the zone at the average of its vertices
+            let sum = zone.polygon.iter().fold(na::Vector2::new(0.0, 0.0), |sum, point| sum + point);
+            let center = sum / zone.polygon.len() as f64;
+            let pos = screen_space(map, center.x * map.scale, center.y * map.scale);
+            self.context.set_fill_style_color(color);
+            self.context.set_text_align(TextAlign::Center);
+            self.context.set_font("12px sans-serif");
+            self.context.fill_text(&zone.name, pos.x, pos.y, None);
+        }
+        self.context.restore();
+    }
+
+    // a zone that is still being drawn, it may not have enough points to be closed yet
+    pub fn draw_zone_outline(&mut self, map: &Map, zone: &Zone) {
+        self.context.save();
+        let color = zone_color(zone.kind);
+        self.context.set_line_dash(vec![4.0, 4.0]);
+        self.context.set_stroke_style_color(color);
+        self.zone_path(map, zone);
+        self.context.stroke();
+        self.context.set_fill_style_color(color);
+        for point in &zone.polygon {
+            let pos = screen_space(map, point.x * map.scale, point.y * map.scale);
+            self.context.begin_path();
+            self.context.arc(pos.x, pos.y, ZONE_VERTEX_RADIUS, 0.0, std::f64::consts::PI * 2.0, true);
+            self.context.fill(FillRule::NonZero);
+        }
+        self.context.restore();
+    }
+
+    // rings the last known location of users that need checking on
+    pub fn draw_motion_alerts(&mut self, map: &Map, alerts: &Vec<&MotionAlert>) {
+        self.context.save();
+        self.context.set_line_width(3.0);
+        for alert in alerts {
+            let pos = screen_space(map, alert.coordinates.x * map.scale, alert.coordinates.y * map.scale);
+            match alert.kind {
+                MotionAlertKind::Stationary => self.context.set_line_dash(vec![]),
+                MotionAlertKind::TagLost => self.context.set_line_dash(vec![4.0, 4.0]),
+            }
+            self.context.set_stroke_style_color("#dc3545");
+            self.context.begin_path();
+            self.context.arc(pos.x, pos.y, ALERT_RADIUS, 0.0, std::f64::consts::PI * 2.0, true);
+            self.context.stroke();
+        }
+        self.context.restore();
+    }
+
     pub fn draw_beacons(&mut self, map: &Map, beacons: &Vec<&Beacon>) {
         self.context.save();
         for beacon in beacons {
@@ -251,7 +338,8 @@ impl Canvas {
         self.context.restore();
     }
 
-    pub fn draw_users(&mut self, map: &Map, users: &Vec<RealtimeUserData>, show_distance: Option<ShortAddress>) {
+    // now is the time in milliseconds that the users are shown at, used to fade out stale locations
+    pub fn draw_users(&mut self, map: &Map, users: &Vec<RealtimeUserData>, show_distance: Option<ShortAddress>, now: f64) {
         self.context.save();
         for user in users.iter() {
             let user_pos = screen_space(
@@ -260,10 +348,25 @@ impl Canvas {
                 user.coordinates.y as f64 * map.scale,
             );
 
-            let diff = stdweb::web::Date::now() - user.last_active.timestamp_millis() as f64;
+            let diff = now - user.last_active.timestamp_millis() as f64;
             let freshness = GRAD_COLOR.grad.get(num::clamp(diff / MAX_TIME, 0.0, 1.0));
             let color_string = color_to_hex(&freshness);
 
+            // draw the estimated error of the location
+            let ellipse = &user.error_ellipse;
+            if ellipse.semi_major > 0.0 && ellipse.semi_minor > 0.0 {
+                self.context.save();
+                self.context.set_fill_style_color(&format!("{}33", color_string));
+                self.context.translate(user_pos.x, user_pos.y);
+                // screen space has y pointing down, so the rotation is reversed.
+                self.context.rotate(-ellipse.angle);
+                self.context.scale(ellipse.semi_major * map.scale, ellipse.semi_minor * map.scale);
+                self.context.begin_path();
+                self.context.arc(0.0, 0.0, 1.0, 0.0, std::f64::consts::PI * 2.0, true);
+                self.context.fill(FillRule::NonZero);
+                self.context.restore();
+            }
+
             // draw the user icon
             self.context.set_fill_style_color(&color_string);
             self.context.begin_path();
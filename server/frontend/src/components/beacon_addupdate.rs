@@ -3,6 +3,7 @@ use crate::util::{ self, WebUserType, JsonResponseHandler, };
 use super::root;
 use super::user_message::UserMessage;
 use super::status::{ self };
+use stdweb::web;
 use yew::Callback;
 use yew::prelude::*;
 use yew::services::fetch::{ FetchService, FetchTask, };
@@ -19,10 +20,14 @@ pub enum Msg {
     RequestAddUpdateBeacon,
     RequestGetAvailMaps,
     RequestGetBeacon(i32),
+    RequestRemoveKey,
+    RequestRotateKey,
 
     ResponseAddBeacon(util::JsonResponse<Beacon>),
     ResponseGetAvailMaps(util::JsonResponse<Vec<Map>>),
     ResponseGetBeacon(util::JsonResponse<Beacon>),
+    ResponseRemoveKey(util::JsonResponse<()>),
+    ResponseRotateKey(util::JsonResponse<String>),
     ResponseUpdateBeacon(util::JsonResponse<Beacon>),
 }
 
@@ -34,6 +39,8 @@ struct Data {
     // keep the raw string from the user in case the parsing fails.
     pub avail_floors: Vec<Map>,
     pub id: Option<i32>,
+    // a newly generated signing key, it can not be fetched again
+    pub new_key: Option<String>,
     pub raw_coord0: String,
     pub raw_coord1: String,
     pub raw_mac: String,
@@ -45,6 +52,7 @@ impl Data {
             beacon: Beacon::new(),
             avail_floors: Vec::new(),
             id: None,
+            new_key: None,
             raw_coord0: "0".to_string(),
             raw_coord1: "0".to_string(),
             raw_mac: MacAddress8::nil().to_hex_string(),
@@ -210,6 +218,60 @@ impl Component for BeaconAddUpdate {
                     _ => {},
                 }
             },
+            Msg::RequestRotateKey => {
+                if let Some(id) = self.data.id {
+                    self.user_msg.reset();
+                    self.fetch_task = post_request!(
+                        self.fetch_service,
+                        &beacon_key_url(&id.to_string()),
+                        (),
+                        self.self_link,
+                        Msg::ResponseRotateKey
+                    );
+                }
+            },
+            Msg::RequestRemoveKey => {
+                match self.data.id {
+                    Some(id) if web::window().confirm("Are you sure you wish to remove the signing key? Messages from this beacon will be rejected unless unsigned messages are allowed.") => {
+                        self.user_msg.reset();
+                        self.fetch_task = delete_request!(
+                            self.fetch_service,
+                            &beacon_key_url(&id.to_string()),
+                            self.self_link,
+                            Msg::ResponseRemoveKey
+                        );
+                    },
+                    _ => {},
+                }
+            },
+            Msg::ResponseRotateKey(response) => {
+                self.handle_response(
+                    response,
+                    |s, key| {
+                        s.user_msg.success_message = Some("successfully generated a signing key".to_string());
+                        s.data.new_key = Some(key);
+                        if let Some(id) = s.data.id {
+                            s.self_link.send_self(Msg::RequestGetBeacon(id));
+                        }
+                    },
+                    |s, e| {
+                        s.user_msg.error_messages.push(format!("failed to generate signing key, reason: {}", e));
+                    },
+                );
+            },
+            Msg::ResponseRemoveKey(response) => {
+                self.handle_response(
+                    response,
+                    |s, _| {
+                        s.user_msg.success_message = Some("successfully removed the signing key".to_string());
+                        s.data.new_key = None;
+                        s.data.beacon.key_rotated = None;
+                    },
+                    |s, e| {
+                        s.user_msg.error_messages.push(format!("failed to remove signing key, reason: {}", e));
+                    },
+                );
+            },
             Msg::ResponseGetAvailMaps(response) => {
                 self.handle_response(
                     response,
@@ -272,6 +334,66 @@ impl Component for BeaconAddUpdate {
     }
 }
 
+impl BeaconAddUpdate {
+    fn key_view(&self) -> Html<Self> {
+        match (self.user_type, self.data.id) {
+            (WebUserType::Admin, Some(_id)) => {},
+            _ => return html! { <></> },
+        }
+
+        let (status, rotate_label) = match self.data.beacon.key_rotated {
+            Some(rotated) => (format!("Provisioned {}", util::format_timestamp(&rotated)), "Rotate Key"),
+            None => ("Not provisioned".to_string(), "Provision Key"),
+        };
+        let new_key = match &self.data.new_key {
+            Some(key) => html! {
+                <tr>
+                    <td class="formLabel">{ "New Key: " }</td>
+                    <td>
+                        <code>{ key }</code>
+                        <p>{ "Flash this key onto the beacon, it will not be shown again. The previous key is accepted until the beacon uses this one." }</p>
+                    </td>
+                </tr>
+            },
+            None => html! { <></> },
+        };
+
+        html! {
+            <>
+                <tr>
+                    <td class="formLabel">{ "Signing Key: " }</td>
+                    <td>
+                        { status }
+                        <button
+                            type="button",
+                            class="btn btn-sm btn-primary",
+                            onclick=|_| Msg::RequestRotateKey,
+                        >
+                            { rotate_label }
+                        </button>
+                        {
+                            if self.data.beacon.key_rotated.is_some() {
+                                html! {
+                                    <button
+                                        type="button",
+                                        class="btn btn-sm btn-danger",
+                                        onclick=|_| Msg::RequestRemoveKey,
+                                    >
+                                        { "Remove Key" }
+                                    </button>
+                                }
+                            } else {
+                                html! { <></> }
+                            }
+                        }
+                    </td>
+                </tr>
+                { new_key }
+            </>
+        }
+    }
+}
+
 impl Renderable<BeaconAddUpdate> for BeaconAddUpdate {
     fn view(&self) -> Html<Self> {
         let title_name = match self.data.id {
@@ -401,6 +523,7 @@ impl Renderable<BeaconAddUpdate> for BeaconAddUpdate {
                                     />
                                 </td>
                             </tr>
+                            { self.key_view() }
                         </table>
                         <div class="formButtons">
                             {
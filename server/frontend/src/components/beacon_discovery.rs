@@ -0,0 +1,450 @@
+use common::*;
+use crate::util::*;
+use std::time::Duration;
+use super::root;
+use super::user_message::UserMessage;
+use super::value_button::DisplayButton;
+use yew::prelude::*;
+use yew::services::fetch::{ FetchService, FetchTask, };
+use yew::services::interval::{ IntervalService, IntervalTask, };
+
+const DISCOVERY_POLLING_RATE: Duration = Duration::from_millis(5000);
+
+pub enum Msg {
+    ChangeRootPage(root::Page),
+    CancelAdopt,
+    InputCoordinate(usize, String),
+    InputFloorName(Option<i32>),
+    InputName(String),
+    SelectAdopt(String),
+
+    RequestAdoptBeacon,
+    RequestBlockBeacon(String),
+    RequestGetAvailMaps,
+    RequestGetDiscovered,
+    RequestUnblockBeacon(String),
+
+    ResponseAdoptBeacon(JsonResponse<Beacon>),
+    ResponseBlockBeacon(JsonResponse<()>),
+    ResponseGetAvailMaps(JsonResponse<Vec<Map>>),
+    ResponseGetDiscovered(JsonResponse<Vec<DiscoveredBeacon>>),
+}
+
+// the beacon being adopted, the coordinates are kept as typed until they are parsed
+struct Adoption {
+    beacon: Beacon,
+    raw_coord0: String,
+    raw_coord1: String,
+}
+
+pub struct BeaconDiscovery {
+    adoption: Option<Adoption>,
+    avail_floors: Vec<Map>,
+    change_page: Callback<root::Page>,
+    fetch_service: FetchService,
+    fetch_task: Option<FetchTask>,
+    // TODO more robust way of handling concurrent requests
+    get_fetch_task: Option<FetchTask>,
+    interval_service: IntervalService,
+    interval_service_task: Option<IntervalTask>,
+    list: Vec<DiscoveredBeacon>,
+    self_link: ComponentLink<Self>,
+    user_msg: UserMessage<Self>,
+}
+
+impl JsonResponseHandler for BeaconDiscovery {}
+
+#[derive(Properties)]
+pub struct BeaconDiscoveryProps {
+    #[props(required)]
+    pub change_page: Callback<root::Page>,
+}
+
+impl BeaconDiscovery {
+    fn validate(&mut self) -> bool {
+        let adoption = match &mut self.adoption {
+            Some(adoption) => adoption,
+            None => return false,
+        };
+
+        if adoption.beacon.name.is_empty() {
+            self.user_msg.error_messages.push("a name is required".to_string());
+            return false;
+        }
+
+        match (adoption.raw_coord0.parse::<f64>(), adoption.raw_coord1.parse::<f64>()) {
+            (Ok(x), Ok(y)) => {
+                adoption.beacon.coordinates[0] = x;
+                adoption.beacon.coordinates[1] = y;
+                true
+            },
+            (Err(e), _) | (_, Err(e)) => {
+                self.user_msg.error_messages.push(format!("failed to parse coordinates: {}", e));
+                false
+            },
+        }
+    }
+
+    fn request_block(&mut self, mac: &str, blocked: bool) {
+        self.user_msg.reset();
+        if let Ok(mac_address) = MacAddress8::parse_str(mac) {
+            let request = BlockBeaconRequest {
+                mac_address,
+                blocked,
+            };
+            self.fetch_task = post_request!(
+                self.fetch_service,
+                &beacons_blocklist_url(),
+                request,
+                self.self_link,
+                Msg::ResponseBlockBeacon
+            );
+        }
+    }
+
+    fn adoption_view(&self) -> Html<Self> {
+        let adoption = match &self.adoption {
+            Some(adoption) => adoption,
+            None => return html! { <></> },
+        };
+
+        let mut floor_options = self.avail_floors.iter().map(|floor| {
+            let floor_id = floor.id;
+            html! {
+                <option
+                    onclick=|_| Msg::InputFloorName(Some(floor_id)),
+                    selected={ Some(floor_id) == adoption.beacon.map_id },
+                >
+                    { &floor.name }
+                </option>
+            }
+        });
+
+        html! {
+            <div class="boxedForm">
+                <h3>{ format!("Adopt {}", adoption.beacon.mac_address.to_hex_string()) }</h3>
+                <table>
+                    <tr>
+                        <td class="formLabel">{ "Name: " }</td>
+                        <td>
+                            <input
+                                type="text",
+                                value=&adoption.beacon.name,
+                                oninput=|e| Msg::InputName(e.value),
+                            />
+                        </td>
+                    </tr>
+                    <tr>
+                        <td class="formLabel">{ "Assign to Map: " }</td>
+                        <td>
+                            <select class="formAlign">
+                                <option
+                                    onclick=|_| Msg::InputFloorName(None),
+                                    selected={ None == adoption.beacon.map_id },
+                                >
+                                    { "None" }
+                                </option>
+                                { for floor_options }
+                            </select>
+                        </td>
+                    </tr>
+                    <tr>
+                        <td class="formLabel">{ "Coordinates: " }</td>
+                        <td>
+                            <input
+                                type="text",
+                                class="coordinates",
+                                value=&adoption.raw_coord0,
+                                oninput=|e| Msg::InputCoordinate(0, e.value),
+                            />
+                            <input
+                                type="text",
+                                class="coordinates"
+                                value=&adoption.raw_coord1,
+                                oninput=|e| Msg::InputCoordinate(1, e.value),
+                            />
+                        </td>
+                    </tr>
+                </table>
+                <div class="formButtons">
+                    <button
+                        type="button",
+                        class="btn btn-lg btn-success align",
+                        onclick=|_| Msg::RequestAdoptBeacon,
+                    >
+                        { "Adopt Beacon" }
+                    </button>
+                    <button
+                        type="button",
+                        class="btn btn-lg btn-danger align",
+                        onclick=|_| Msg::CancelAdopt,
+                    >
+                        { "Cancel" }
+                    </button>
+                </div>
+            </div>
+        }
+    }
+}
+
+impl Component for BeaconDiscovery {
+    type Message = Msg;
+    type Properties = BeaconDiscoveryProps;
+
+    fn create(props: Self::Properties, mut link: ComponentLink<Self>) -> Self {
+        link.send_self(Msg::RequestGetDiscovered);
+        link.send_self(Msg::RequestGetAvailMaps);
+        let mut result = BeaconDiscovery {
+            adoption: None,
+            avail_floors: Vec::new(),
+            change_page: props.change_page,
+            fetch_service: FetchService::new(),
+            fetch_task: None,
+            get_fetch_task: None,
+            interval_service: IntervalService::new(),
+            interval_service_task: None,
+            list: Vec::new(),
+            self_link: link,
+            user_msg: UserMessage::new(),
+        };
+        result.interval_service_task = Some(result.interval_service.spawn(
+            DISCOVERY_POLLING_RATE,
+            result.self_link.send_back(|_| Msg::RequestGetDiscovered)
+        ));
+        result
+    }
+
+    fn update(&mut self, msg: Self::Message) -> ShouldRender {
+        match msg {
+            Msg::ChangeRootPage(page) => {
+                self.change_page.emit(page);
+            },
+            Msg::CancelAdopt => {
+                self.adoption = None;
+            },
+            Msg::InputName(name) => {
+                if let Some(adoption) = &mut self.adoption {
+                    adoption.beacon.name = name;
+                }
+            },
+            Msg::InputFloorName(map_id) => {
+                if let Some(adoption) = &mut self.adoption {
+                    adoption.beacon.map_id = map_id;
+                }
+            },
+            Msg::InputCoordinate(index, value) => {
+                if let Some(adoption) = &mut self.adoption {
+                    match index {
+                        0 => { adoption.raw_coord0 = value; },
+                        1 => { adoption.raw_coord1 = value; },
+                        _ => panic!("invalid coordinate index specified"),
+                    };
+                }
+            },
+            Msg::SelectAdopt(mac) => {
+                self.user_msg.reset();
+                let discovered = self.list.iter().find(|d| d.mac_address.to_hex_string() == mac);
+                if let Some(discovered) = discovered {
+                    let mut beacon = Beacon::new();
+                    beacon.mac_address = discovered.mac_address;
+                    if let Some(ip) = discovered.ip {
+                        beacon.ip = ip;
+                    }
+                    if let Some(last_seen) = discovered.last_seen {
+                        beacon.last_active = last_seen;
+                    }
+                    self.adoption = Some(Adoption {
+                        beacon,
+                        raw_coord0: "0".to_string(),
+                        raw_coord1: "0".to_string(),
+                    });
+                }
+            },
+            Msg::RequestGetDiscovered => {
+                self.get_fetch_task = get_request!(
+                    self.fetch_service,
+                    &beacons_discovered_url(),
+                    self.self_link,
+                    Msg::ResponseGetDiscovered
+                );
+            },
+            Msg::RequestGetAvailMaps => {
+                self.fetch_task = get_request!(
+                    self.fetch_service,
+                    &maps_url(),
+                    self.self_link,
+                    Msg::ResponseGetAvailMaps
+                );
+            },
+            Msg::RequestAdoptBeacon => {
+                self.user_msg.reset();
+                if self.validate() {
+                    if let Some(adoption) = &self.adoption {
+                        self.fetch_task = post_request!(
+                            self.fetch_service,
+                            &beacons_adopt_url(),
+                            adoption.beacon,
+                            self.self_link,
+                            Msg::ResponseAdoptBeacon
+                        );
+                    }
+                }
+            },
+            Msg::RequestBlockBeacon(mac) => {
+                self.request_block(&mac, true);
+            },
+            Msg::RequestUnblockBeacon(mac) => {
+                self.request_block(&mac, false);
+            },
+            Msg::ResponseGetDiscovered(response) => {
+                self.handle_response(
+                    response,
+                    |s, mut discovered| {
+                        // the most recently heard from first, quiet blocked beacons last
+                        discovered.sort_by(|a, b| b.last_seen.cmp(&a.last_seen));
+                        s.list = discovered;
+                    },
+                    |s, e| {
+                        s.user_msg.error_messages.push(format!("failed to obtain discovered beacons, reason: {}", e));
+                    },
+                );
+            },
+            Msg::ResponseGetAvailMaps(response) => {
+                self.handle_response(
+                    response,
+                    |s, maps| {
+                        s.avail_floors = maps;
+                    },
+                    |s, e| {
+                        s.user_msg.error_messages.push(format!("failed to obtain available floors list, reason: {}", e));
+                    },
+                );
+            },
+            Msg::ResponseAdoptBeacon(response) => {
+                self.handle_response(
+                    response,
+                    |s, beacon| {
+                        s.user_msg.success_message = Some(format!("successfully adopted beacon {}", beacon.name));
+                        s.adoption = None;
+                    },
+                    |s, e| {
+                        s.user_msg.error_messages.push(format!("failed to adopt beacon, reason: {}", e));
+                    },
+                );
+                self.self_link.send_self(Msg::RequestGetDiscovered);
+            },
+            Msg::ResponseBlockBeacon(response) => {
+                self.handle_response(
+                    response,
+                    |s, _| {
+                        s.user_msg.success_message = Some("successfully updated the blocklist".to_string());
+                    },
+                    |s, e| {
+                        s.user_msg.error_messages.push(format!("failed to update the blocklist, reason: {}", e));
+                    },
+                );
+                self.self_link.send_self(Msg::RequestGetDiscovered);
+            },
+        }
+        true
+    }
+
+    fn change(&mut self, _props: Self::Properties) -> ShouldRender {
+        self.self_link.send_self(Msg::RequestGetDiscovered);
+        true
+    }
+}
+
+impl Renderable<BeaconDiscovery> for BeaconDiscovery {
+    fn view(&self) -> Html<Self> {
+        let format_seen = |seen: &Option<DateTime<Utc>>| {
+            seen.map_or(String::new(), |stamp| format_timestamp(&stamp).to_string())
+        };
+
+        let mut rows = self.list.iter().map(|discovered| {
+            let mac = discovered.mac_address.to_hex_string();
+            let actions = if discovered.blocked {
+                html! {
+                    <DisplayButton<String>
+                        display="Unblock".to_owned(),
+                        on_click=|value| Msg::RequestUnblockBeacon(value),
+                        border=false,
+                        value=mac.clone(),
+                        icon="fa fa-check",
+                        style="btn btn-sm btn-secondary",
+                    />
+                }
+            } else {
+                html! {
+                    <>
+                        <DisplayButton<String>
+                            display="Adopt".to_owned(),
+                            on_click=|value| Msg::SelectAdopt(value),
+                            border=false,
+                            value=mac.clone(),
+                            icon="fa fa-plus",
+                            style="btn btn-sm btn-primary",
+                        />
+                        <DisplayButton<String>
+                            display="Block".to_owned(),
+                            on_click=|value| Msg::RequestBlockBeacon(value),
+                            border=false,
+                            value=mac.clone(),
+                            icon="fa fa-ban",
+                            style="btn btn-sm btn-danger",
+                        />
+                    </>
+                }
+            };
+
+            html! {
+                <tr>
+                    <td>{ &mac }</td>
+                    <td>{ discovered.ip.map_or(String::new(), |ip| ip.to_string()) }</td>
+                    <td>{ format_seen(&discovered.first_seen) }</td>
+                    <td>{ format_seen(&discovered.last_seen) }</td>
+                    <td>{ discovered.messages }</td>
+                    <td>{ if discovered.blocked { "Blocked" } else { "" } }</td>
+                    <td>{ actions }</td>
+                </tr>
+            }
+        });
+
+        html! {
+            <>
+                { self.user_msg.view() }
+                <div class="content-wrapper">
+                    { self.adoption_view() }
+                    <div class="boxedForm">
+                        <div class="d-flex justify-content-between">
+                            <h2>{ "Discovered Beacons" }</h2>
+                            <button
+                                class="btn btn-secondary logoutPlacement my-1",
+                                onclick=|_| Msg::ChangeRootPage(root::Page::BeaconList),
+                            >
+                                { "Beacon List" }
+                            </button>
+                        </div>
+                        <p>{ "Beacons that have messaged the server without being registered." }</p>
+                        <table class="table table-striped">
+                            <thead>
+                                <tr>
+                                    <th>{ "Mac" }</th>
+                                    <th>{ "IP" }</th>
+                                    <th>{ "First Seen" }</th>
+                                    <th>{ "Last Seen" }</th>
+                                    <th>{ "Messages" }</th>
+                                    <th>{ "Status" }</th>
+                                    <th>{ "Actions" }</th>
+                                </tr>
+                            </thead>
+                            <tbody>
+                                { for rows }
+                            </tbody>
+                        </table>
+                    </div>
+                </div>
+            </>
+        }
+    }
+}
@@ -1,20 +1,28 @@
 use common::*;
+use crate::push::{ PushChannel, PushResponse, };
 use crate::util::*;
 use std::collections::{ VecDeque, BTreeSet };
 use std::time::Duration;
 use super::user_message::UserMessage;
 use super::value_button::ValueButton;
+use yew::format::Json;
 use yew::prelude::*;
 use yew::services::fetch::{ FetchService, FetchTask, };
 use yew::services::interval::{ IntervalTask, IntervalService, };
+use yew::services::websocket::WebSocketStatus;
 
 const DIAGNOSTIC_POLLING_RATE: Duration = Duration::from_millis(1000);
 const MAX_BUFFER_SIZE: usize = 0x50;
+const MAX_ALERTS: usize = 0x20;
 
 pub enum Msg {
     ClearBuffer,
     ToggleBeaconSelected(MacAddress8),
 
+    PushConnect,
+    PushReceived(PushResponse),
+    PushStatus(WebSocketStatus),
+
     RequestDiagnostics,
 
     ResponseDiagnostics(JsonResponse<common::DiagnosticData>),
@@ -22,12 +30,15 @@ pub enum Msg {
 
 pub struct Diagnostics {
     active_beacons: BTreeSet<MacAddress8>,
+    alerts: VecDeque<BeaconAlert>,
+    command_latency: Vec<CommandLatency>,
     diagnostic_data: VecDeque<common::TagData>,
     emergency: bool,
     fetch_service: FetchService,
     fetch_task: Option<FetchTask>,
     interval_service: Option<IntervalService>,
     interval_service_task: Option<IntervalTask>,
+    push: PushChannel,
     selected_beacons: BTreeSet<MacAddress8>,
     self_link: ComponentLink<Diagnostics>,
     user_msg: UserMessage<Self>,
@@ -41,7 +52,21 @@ pub struct DiagnosticsProps {
 }
 
 impl Diagnostics {
+    fn add_point(&mut self, point: TagData) {
+        if !self.active_beacons.contains(&point.beacon_mac) {
+            self.active_beacons.insert(point.beacon_mac.clone());
+            self.selected_beacons.insert(point.beacon_mac.clone());
+        }
+        self.diagnostic_data.push_front(point);
+    }
+
     fn start_service(&mut self) {
+        // ranges are pushed while the socket is up
+        if self.push.is_connected() {
+            self.end_service();
+            return;
+        }
+
         let mut interval_service = IntervalService::new();
         self.interval_service_task = Some(interval_service.spawn(DIAGNOSTIC_POLLING_RATE, self.self_link.send_back(|_| Msg::RequestDiagnostics)));
         self.interval_service = Some(interval_service);
@@ -51,21 +76,103 @@ impl Diagnostics {
         self.interval_service = None;
         self.interval_service_task = None;
     }
+
+    fn alerts_view(&self) -> Html<Self> {
+        if self.alerts.is_empty() {
+            return html! { <></> };
+        }
+
+        let mut alert_rows = self.alerts.iter().map(|alert| {
+            html! {
+                <tr>
+                    <td>{ format_timestamp(&alert.timestamp) }</td>
+                    <td>{ &alert.ip }</td>
+                    <td>{ alert.mac.map_or(String::new(), |mac| mac.to_hex_string()) }</td>
+                    <td>{ &alert.reason }</td>
+                </tr>
+            }
+        });
+
+        html! {
+            <>
+                <h4>{ "Rejected Beacon Messages" }</h4>
+                <table class="table table-striped">
+                    <thead>
+                        <tr>
+                            <th>{ "Timestamp" }</th>
+                            <th>{ "IP" }</th>
+                            <th>{ "Beacon Mac" }</th>
+                            <th>{ "Reason" }</th>
+                        </tr>
+                    </thead>
+                    <tbody>
+                        { for alert_rows }
+                    </tbody>
+                </table>
+            </>
+        }
+    }
+
+    fn latency_view(&self) -> Html<Self> {
+        if self.command_latency.is_empty() {
+            return html! { <></> };
+        }
+
+        let mut latency_rows = self.command_latency.iter().map(|row| {
+            html! {
+                <tr>
+                    <td>{ &row.command }</td>
+                    <td>{ &row.acks }</td>
+                    <td>{ &row.timeouts }</td>
+                    <td>{ format!("{:.1}", row.last_ms) }</td>
+                    <td>{ format!("{:.1}", row.mean_ms) }</td>
+                    <td>{ format!("{:.1}", row.max_ms) }</td>
+                </tr>
+            }
+        });
+
+        html! {
+            <table class="table table-striped">
+                <thead>
+                    <tr>
+                        <th>{ "Command" }</th>
+                        <th>{ "Acks" }</th>
+                        <th>{ "Timeouts" }</th>
+                        <th>{ "Last (ms)" }</th>
+                        <th>{ "Mean (ms)" }</th>
+                        <th>{ "Max (ms)" }</th>
+                    </tr>
+                </thead>
+                <tbody>
+                    { for latency_rows }
+                </tbody>
+            </table>
+        }
+    }
 }
 
 impl Component for Diagnostics {
     type Message = Msg;
     type Properties = DiagnosticsProps;
 
-    fn create(props: Self::Properties, link: ComponentLink<Self>) -> Self {
+    fn create(props: Self::Properties, mut link: ComponentLink<Self>) -> Self {
+        link.send_self(Msg::PushConnect);
+        // ranges are pushed, but the command latency is only fetched
+        link.send_self(Msg::RequestDiagnostics);
         let mut result = Diagnostics {
             active_beacons: BTreeSet::new(),
+            alerts: VecDeque::new(),
+            command_latency: Vec::new(),
             diagnostic_data: VecDeque::new(),
             emergency: props.emergency,
             fetch_service: FetchService::new(),
             fetch_task: None,
             interval_service: None,
             interval_service_task: None,
+            push: PushChannel::new(PushSubscription {
+                diagnostics: true,
+                map_ids: Some(Vec::new()),
+            }),
             selected_beacons: BTreeSet::new(),
             self_link: link,
             user_msg: UserMessage::new(),
@@ -89,6 +196,37 @@ impl Component for Diagnostics {
                     self.selected_beacons.insert(b_mac.clone());
                 }
             },
+            Msg::PushConnect => {
+                self.push.connect(
+                    self.self_link.send_back(Msg::PushReceived),
+                    self.self_link.send_back(Msg::PushStatus),
+                );
+            },
+            Msg::PushStatus(status) => {
+                self.push.status(status, self.self_link.send_back(|_| Msg::PushConnect));
+                if self.emergency {
+                    self.start_service();
+                }
+            },
+            Msg::PushReceived(Json(response)) => {
+                match response {
+                    Ok(PushMessage::Range(point)) if self.emergency => {
+                        self.add_point(point);
+                        self.diagnostic_data.truncate(MAX_BUFFER_SIZE);
+                    },
+                    Ok(PushMessage::BeaconAlert(alert)) => {
+                        self.alerts.push_front(alert);
+                        self.alerts.truncate(MAX_ALERTS);
+                    },
+                    Ok(_) => {
+                        return false;
+                    },
+                    Err(e) => {
+                        Log!("failed to parse push message, {}", e);
+                        return false;
+                    },
+                }
+            },
             Msg::RequestDiagnostics => {
                 self.user_msg.reset();
                 self.fetch_task = get_request!(
@@ -102,12 +240,11 @@ impl Component for Diagnostics {
                 self.handle_response(
                     response,
                     |s, diagnostics_data| {
+                        s.command_latency = diagnostics_data.command_latency;
+                        // the server sends the oldest alert first
+                        s.alerts = diagnostics_data.alerts.into_iter().rev().take(MAX_ALERTS).collect();
                         for point in diagnostics_data.tag_data.into_iter() {
-                            if !s.active_beacons.contains(&point.beacon_mac) {
-                                s.active_beacons.insert(point.beacon_mac.clone());
-                                s.selected_beacons.insert(point.beacon_mac.clone());
-                            }
-                            s.diagnostic_data.push_front(point);
+                            s.add_point(point);
                         }
                         s.diagnostic_data.truncate(MAX_BUFFER_SIZE);
                     },
@@ -177,6 +314,8 @@ impl Renderable<Diagnostics> for Diagnostics {
                                 <h2>{ "Diagnostics" }</h2>
                                 <tr>{ for beacon_selections }</tr>
                             </div>
+                            { self.alerts_view() }
+                            { self.latency_view() }
                             <table class="table table-striped">
                                 <thead>
                                     <tr>
@@ -201,6 +340,8 @@ impl Renderable<Diagnostics> for Diagnostics {
                         <div class="boxedForm">
                             <h2>{ "Diagnostics" }</h2>
                             <h4>{ "No diagnostics yet..." }</h4>
+                            { self.alerts_view() }
+                            { self.latency_view() }
                         </div>
                     </div>
                 </>
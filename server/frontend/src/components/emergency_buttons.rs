@@ -1,34 +1,137 @@
+use common::*;
+use common::emergency_scope;
+use crate::util::*;
+use std::collections::BTreeSet;
 use yew::prelude::*;
 use yew::Component;
+use yew::services::fetch::{ FetchService, FetchTask, };
 use super::value_button::DisplayButton;
 
+// what to start or end the emergency on, no maps means the whole site
+pub struct EmergencyRequest {
+    pub reason: Option<String>,
+    pub map_ids: Option<Vec<i32>>,
+}
+
 pub enum Msg {
+    InputReason(String),
+    ToggleMap(i32),
+    SelectSite,
     RequestEmergency,
     RequestEndEmergency,
+
+    RequestGetMaps,
+    ResponseGetMaps(JsonResponse<Vec<Map>>),
 }
 
 pub struct EmergencyButtons {
-    is_emergency: bool,
-    on_emergency: Callback<()>,
-    on_end_emergency: Callback<()>,
+    status: EmergencyStatus,
+    // an optional note kept in the emergency log
+    reason: String,
+    maps: Vec<Map>,
+    selected_maps: BTreeSet<i32>,
+    fetch_service: FetchService,
+    fetch_task: Option<FetchTask>,
+    self_link: ComponentLink<Self>,
+    on_emergency: Callback<EmergencyRequest>,
+    on_end_emergency: Callback<EmergencyRequest>,
 }
 
+impl JsonResponseHandler for EmergencyButtons {}
+
 #[derive(Properties)]
 pub struct EmergencyButtonsProps {
-    pub is_emergency: bool,
+    pub status: EmergencyStatus,
     #[props(required)]
-    pub on_emergency: Callback<()>,
+    pub on_emergency: Callback<EmergencyRequest>,
     #[props(required)]
-    pub on_end_emergency: Callback<()>,
+    pub on_end_emergency: Callback<EmergencyRequest>,
+}
+
+impl EmergencyButtons {
+    fn take_request(&mut self) -> EmergencyRequest {
+        let reason = self.reason.trim().to_string();
+        self.reason = String::new();
+        EmergencyRequest {
+            reason: if reason.is_empty() { None } else { Some(reason) },
+            map_ids: self.selected_scope(),
+        }
+    }
+
+    fn selected_scope(&self) -> Option<Vec<i32>> {
+        if self.selected_maps.is_empty() {
+            None
+        } else {
+            Some(self.selected_maps.iter().cloned().collect())
+        }
+    }
+
+    fn map_name(&self, id: i32) -> String {
+        self.maps.iter()
+            .find(|map| map.id == id)
+            .map_or(format!("map {}", id), |map| map.name.clone())
+    }
+
+    fn status_view(&self) -> Html<Self> {
+        if !self.status.active {
+            return html! { <></> };
+        }
+        let scope = match &self.status.map_ids {
+            Some(ids) => ids.iter().map(|id| self.map_name(*id)).collect::<Vec<String>>().join(", "),
+            None => "the whole site".to_string(),
+        };
+        html! {
+            <span class="e-status">{ format!("Tracking {}", scope) }</span>
+        }
+    }
+
+    fn scope_view(&self) -> Html<Self> {
+        let mut maps = self.maps.iter().map(|map| {
+            let id = map.id;
+            // maps already in the emergency are marked so the user can tell what is covered
+            let covered = self.status.active && emergency_scope::covers(&self.status, Some(id));
+            html! {
+                <label class="e-scope">
+                    <input
+                        type="checkbox",
+                        checked={ self.selected_maps.contains(&id) },
+                        onclick=|_| Msg::ToggleMap(id),
+                    />
+                    { if covered { format!(" {} (tracking)", map.name) } else { format!(" {}", map.name) } }
+                </label>
+            }
+        });
+
+        html! {
+            <div>
+                <label class="e-scope">
+                    <input
+                        type="checkbox",
+                        checked={ self.selected_maps.is_empty() },
+                        onclick=|_| Msg::SelectSite,
+                    />
+                    { " Whole Site" }
+                </label>
+                { for maps }
+            </div>
+        }
+    }
 }
 
 impl Component for EmergencyButtons {
     type Message = Msg;
     type Properties = EmergencyButtonsProps;
 
-    fn create(props: Self::Properties, _link: ComponentLink<Self>) -> Self {
+    fn create(props: Self::Properties, mut link: ComponentLink<Self>) -> Self {
+        link.send_self(Msg::RequestGetMaps);
         let result = EmergencyButtons {
-            is_emergency: props.is_emergency,
+            status: props.status,
+            reason: String::new(),
+            maps: Vec::new(),
+            selected_maps: BTreeSet::new(),
+            fetch_service: FetchService::new(),
+            fetch_task: None,
+            self_link: link,
             on_emergency: props.on_emergency,
             on_end_emergency: props.on_end_emergency,
         };
@@ -38,16 +141,50 @@ impl Component for EmergencyButtons {
 
     fn update(&mut self, msg: Self::Message) -> ShouldRender {
         match msg {
+            Msg::InputReason(reason) => {
+                self.reason = reason;
+            },
+            Msg::ToggleMap(id) => {
+                if !self.selected_maps.remove(&id) {
+                    self.selected_maps.insert(id);
+                }
+            },
+            Msg::SelectSite => {
+                self.selected_maps.clear();
+            },
             Msg::RequestEmergency => {
-                self.on_emergency.emit(())
+                let request = self.take_request();
+                self.on_emergency.emit(request)
+            },
+            Msg::RequestEndEmergency => {
+                let request = self.take_request();
+                self.on_end_emergency.emit(request)
+            },
+            Msg::RequestGetMaps => {
+                self.fetch_task = get_request!(
+                    self.fetch_service,
+                    &maps_url(),
+                    self.self_link,
+                    Msg::ResponseGetMaps
+                );
+            },
+            Msg::ResponseGetMaps(response) => {
+                self.handle_response(
+                    response,
+                    |s, maps| {
+                        s.maps = maps;
+                    },
+                    |_s, e| {
+                        Log!("response - failed to obtain maps for the emergency scope, {}", e);
+                    },
+                );
             },
-            Msg::RequestEndEmergency => self.on_end_emergency.emit(()),
         }
         true
     }
 
     fn change(&mut self, props: Self::Properties) -> ShouldRender {
-        self.is_emergency = props.is_emergency;
+        self.status = props.status;
         self.on_emergency = props.on_emergency;
         self.on_end_emergency = props.on_end_emergency;
         true
@@ -56,24 +193,43 @@ impl Component for EmergencyButtons {
 
 impl Renderable<EmergencyButtons> for EmergencyButtons {
     fn view(&self) -> Html<Self> {
+        let scope = self.selected_scope();
+        // nothing to start when everything selected is already being tracked
+        let start_disabled = match &scope {
+            Some(ids) => ids.iter().all(|id| emergency_scope::covers(&self.status, Some(*id))),
+            None => self.status.active && self.status.map_ids.is_none(),
+        };
+        // a site wide emergency can only be ended for the whole site
+        let end_disabled = !self.status.active || (scope.is_some() && self.status.map_ids.is_none());
+        let is_emergency = self.status.active;
+
         html! {
             <div>
                 <DisplayButton<()>
                     value=(),
                     style="btn btn-lg btn-success e-buttons"
                     on_click=|_| Msg::RequestEmergency,
-                    disabled={self.is_emergency},
-                    icon={ if self.is_emergency {"fa fa-refresh fa-spin"} else {"fa fa-hourglass-start"} },
+                    disabled={start_disabled},
+                    icon={ if is_emergency {"fa fa-refresh fa-spin"} else {"fa fa-hourglass-start"} },
                     display="Start Tracking",
                 />
                 <DisplayButton<()>
                     value=(),
                     style="btn btn-lg btn-danger e-buttons"
                     on_click=|_| Msg::RequestEndEmergency,
-                    disabled={!self.is_emergency},
+                    disabled={end_disabled},
                     icon="fa fa-hourglass-end",
                     display="End Tracking",
                 />
+                <input
+                    type="text",
+                    class="e-reason",
+                    placeholder="Reason (optional)",
+                    value=&self.reason,
+                    oninput=|e| Msg::InputReason(e.value),
+                />
+                { self.status_view() }
+                { self.scope_view() }
             </div>
         }
     }
@@ -0,0 +1,303 @@
+use common::*;
+use crate::util::*;
+use super::user_message::UserMessage;
+use super::value_button::DisplayButton;
+use yew::prelude::*;
+use yew::services::fetch::{ FetchService, FetchTask, };
+
+pub enum Msg {
+    CloseDetail,
+
+    RequestGetEvent(i32),
+    RequestGetEvents,
+
+    ResponseGetEvent(JsonResponse<EmergencyEventDetail>),
+    ResponseGetEvents(JsonResponse<Vec<EmergencyEvent>>),
+}
+
+pub struct EmergencyHistory {
+    detail: Option<EmergencyEventDetail>,
+    events: Vec<EmergencyEvent>,
+    fetch_service: FetchService,
+    fetch_task: Option<FetchTask>,
+    self_link: ComponentLink<Self>,
+    user_msg: UserMessage<Self>,
+}
+
+impl JsonResponseHandler for EmergencyHistory {}
+
+#[derive(Properties)]
+pub struct EmergencyHistoryProps {
+    // refresh the list when an emergency starts or ends
+    pub emergency: bool,
+}
+
+fn format_account(account: &Option<String>) -> String {
+    account.clone().unwrap_or("system".to_string())
+}
+
+fn format_scope(map_ids: &Option<Vec<i32>>) -> String {
+    match map_ids {
+        Some(map_ids) => format!("maps {:?}", map_ids),
+        None => "site".to_string(),
+    }
+}
+
+impl EmergencyHistory {
+    fn detail_view(&self) -> Html<Self> {
+        let detail = match &self.detail {
+            Some(detail) => detail,
+            None => return html! { <></> },
+        };
+        let event = &detail.event;
+
+        let mut rows = detail.users.iter().map(|user| {
+            html! {
+                <tr>
+                    <td>{ &user.name }</td>
+                    <td>{ format_timestamp(&user.first_seen) }</td>
+                    <td>{ format_timestamp(&user.last_seen) }</td>
+                    <td>{ user.locations }</td>
+                </tr>
+            }
+        });
+
+        let mut scope_rows = detail.scope_changes.iter().map(|change| {
+            html! {
+                <tr>
+                    <td>{ format_timestamp(&change.timestamp) }</td>
+                    <td>{ if change.started { "Started" } else { "Ended" } }</td>
+                    <td>{ format_scope(&change.map_ids) }</td>
+                    <td>{ format_account(&change.account) }</td>
+                    <td>{ change.reason.clone().unwrap_or(String::new()) }</td>
+                </tr>
+            }
+        });
+
+        let mut zone_rows = detail.zone_events.iter().map(|event| {
+            html! {
+                <tr>
+                    <td>{ format_timestamp(&event.timestamp) }</td>
+                    <td>{ &event.user_name }</td>
+                    <td>{ event.kind.to_string() }</td>
+                    <td>{ &event.zone_name }</td>
+                    <td>{ event.zone_kind.to_string() }</td>
+                </tr>
+            }
+        });
+
+        html! {
+            <div class="boxedForm">
+                <div class="d-flex justify-content-between">
+                    <h3>{ format!("Emergency started {}", format_timestamp(&event.started_at)) }</h3>
+                    <button
+                        class="btn btn-secondary logoutPlacement my-1",
+                        onclick=|_| Msg::CloseDetail,
+                    >
+                        { "Close" }
+                    </button>
+                </div>
+                <table>
+                    <tr>
+                        <td class="formLabel">{ "Started By: " }</td>
+                        <td>{ format_account(&event.started_by) }</td>
+                    </tr>
+                    <tr>
+                        <td class="formLabel">{ "Start Reason: " }</td>
+                        <td>{ event.start_reason.clone().unwrap_or(String::new()) }</td>
+                    </tr>
+                    <tr>
+                        <td class="formLabel">{ "Ended: " }</td>
+                        <td>{ event.ended_at.map_or("ongoing".to_string(), |stamp| format_timestamp(&stamp).to_string()) }</td>
+                    </tr>
+                    <tr>
+                        <td class="formLabel">{ "Ended By: " }</td>
+                        <td>{ event.ended_at.map_or(String::new(), |_| format_account(&event.ended_by)) }</td>
+                    </tr>
+                    <tr>
+                        <td class="formLabel">{ "End Reason: " }</td>
+                        <td>{ event.end_reason.clone().unwrap_or(String::new()) }</td>
+                    </tr>
+                    <tr>
+                        <td class="formLabel">{ "Duration: " }</td>
+                        <td>{ format_duration(event.duration(Utc::now())) }</td>
+                    </tr>
+                    <tr>
+                        <td class="formLabel">{ "Scope: " }</td>
+                        <td>{ format_scope(&event.map_ids) }</td>
+                    </tr>
+                </table>
+                <h4>{ format!("Scope Changes ({})", detail.scope_changes.len()) }</h4>
+                <table class="table table-striped">
+                    <thead>
+                        <tr>
+                            <th>{ "Time" }</th>
+                            <th>{ "Change" }</th>
+                            <th>{ "Scope" }</th>
+                            <th>{ "By" }</th>
+                            <th>{ "Reason" }</th>
+                        </tr>
+                    </thead>
+                    <tbody>
+                        { for scope_rows }
+                    </tbody>
+                </table>
+                <h4>{ format!("Users Seen ({})", detail.users.len()) }</h4>
+                <table class="table table-striped">
+                    <thead>
+                        <tr>
+                            <th>{ "Name" }</th>
+                            <th>{ "First Seen" }</th>
+                            <th>{ "Last Seen" }</th>
+                            <th>{ "Locations" }</th>
+                        </tr>
+                    </thead>
+                    <tbody>
+                        { for rows }
+                    </tbody>
+                </table>
+                <h4>{ format!("Zone Events ({})", detail.zone_events.len()) }</h4>
+                <table class="table table-striped">
+                    <thead>
+                        <tr>
+                            <th>{ "Time" }</th>
+                            <th>{ "Name" }</th>
+                            <th>{ "Event" }</th>
+                            <th>{ "Zone" }</th>
+                            <th>{ "Kind" }</th>
+                        </tr>
+                    </thead>
+                    <tbody>
+                        { for zone_rows }
+                    </tbody>
+                </table>
+            </div>
+        }
+    }
+}
+
+impl Component for EmergencyHistory {
+    type Message = Msg;
+    type Properties = EmergencyHistoryProps;
+
+    fn create(_props: Self::Properties, mut link: ComponentLink<Self>) -> Self {
+        link.send_self(Msg::RequestGetEvents);
+        EmergencyHistory {
+            detail: None,
+            events: Vec::new(),
+            fetch_service: FetchService::new(),
+            fetch_task: None,
+            self_link: link,
+            user_msg: UserMessage::new(),
+        }
+    }
+
+    fn update(&mut self, msg: Self::Message) -> ShouldRender {
+        match msg {
+            Msg::CloseDetail => {
+                self.detail = None;
+            },
+            Msg::RequestGetEvent(id) => {
+                self.user_msg.reset();
+                self.fetch_task = get_request!(
+                    self.fetch_service,
+                    &emergency_event_url(&id.to_string()),
+                    self.self_link,
+                    Msg::ResponseGetEvent
+                );
+            },
+            Msg::RequestGetEvents => {
+                self.fetch_task = get_request!(
+                    self.fetch_service,
+                    &emergency_events_url(),
+                    self.self_link,
+                    Msg::ResponseGetEvents
+                );
+            },
+            Msg::ResponseGetEvent(response) => {
+                self.handle_response(
+                    response,
+                    |s, detail| {
+                        s.detail = Some(detail);
+                    },
+                    |s, e| {
+                        s.user_msg.error_messages.push(format!("failed to obtain emergency, reason: {}", e));
+                    },
+                );
+            },
+            Msg::ResponseGetEvents(response) => {
+                self.handle_response(
+                    response,
+                    |s, events| {
+                        s.events = events;
+                    },
+                    |s, e| {
+                        s.user_msg.error_messages.push(format!("failed to obtain emergency history, reason: {}", e));
+                    },
+                );
+            },
+        }
+        true
+    }
+
+    fn change(&mut self, _props: Self::Properties) -> ShouldRender {
+        self.self_link.send_self(Msg::RequestGetEvents);
+        true
+    }
+}
+
+impl Renderable<EmergencyHistory> for EmergencyHistory {
+    fn view(&self) -> Html<Self> {
+        let now = Utc::now();
+        let mut rows = self.events.iter().map(|event| {
+            html! {
+                <tr>
+                    <td>{ format_timestamp(&event.started_at) }</td>
+                    <td>{ format_duration(event.duration(now)) }</td>
+                    <td>{ format_account(&event.started_by) }</td>
+                    <td>{ event.start_reason.clone().unwrap_or(String::new()) }</td>
+                    <td>{ event.ended_at.map_or("ongoing".to_string(), |_| format_account(&event.ended_by)) }</td>
+                    <td>{ format_scope(&event.map_ids) }</td>
+                    <td>
+                        <DisplayButton<i32>
+                            display="Details".to_owned(),
+                            on_click=|id| Msg::RequestGetEvent(id),
+                            border=false,
+                            value=event.id,
+                            icon="fa fa-info-circle",
+                            style="btn btn-sm btn-primary",
+                        />
+                    </td>
+                </tr>
+            }
+        });
+
+        html! {
+            <>
+                { self.user_msg.view() }
+                <div class="content-wrapper">
+                    { self.detail_view() }
+                    <div class="boxedForm">
+                        <h2>{ "Emergency History" }</h2>
+                        <table class="table table-striped">
+                            <thead>
+                                <tr>
+                                    <th>{ "Started" }</th>
+                                    <th>{ "Duration" }</th>
+                                    <th>{ "Started By" }</th>
+                                    <th>{ "Reason" }</th>
+                                    <th>{ "Ended By" }</th>
+                                    <th>{ "Scope" }</th>
+                                    <th>{ "Details" }</th>
+                                </tr>
+                            </thead>
+                            <tbody>
+                                { for rows }
+                            </tbody>
+                        </table>
+                    </div>
+                </div>
+            </>
+        }
+    }
+}
@@ -1,5 +1,6 @@
 use common::*;
 use crate::util::*;
+use yew::format::Json;
 use yew::services::fetch::{ FetchService, FetchTask, StatusCode, };
 use yew::prelude::*;
 use super::root;
@@ -27,11 +28,12 @@ pub enum Msg {
     InputName(String),
     InputPassword(String),
 
+    RequestCheck,
     RequestLogin,
-    RequestLoginAnon,
     RequestLogout,
 
-    ResponseLogin(JsonResponse<()>),
+    ResponseCheck(JsonResponse<SessionInfo>),
+    ResponseLogin(JsonResponse<SessionInfo>),
     ResponseLogout(JsonResponse<()>),
 }
 
@@ -78,7 +80,7 @@ impl Component for Login {
     fn create(props: Self::Properties, mut link: ComponentLink<Self>) -> Self {
         match props.auto_action {
             AutoAction::Nothing => {},
-            AutoAction::Login => link.send_self(Msg::RequestLoginAnon),
+            AutoAction::Login => link.send_self(Msg::RequestCheck),
             AutoAction::Logout => link.send_self(Msg::RequestLogout),
         }
 
@@ -116,19 +118,14 @@ impl Component for Login {
             Msg::InputPassword(pw) => {
                 self.data.login.pw = pw;
             },
-            Msg::RequestLoginAnon => {
-                self.user_msg.error_messages = Vec::new();
-                self.user_msg.success_message = None;
-                let mut info = LoginInfo::new();
-                info.name = String::from("responder");
-                self.fetch_task = post_request!(
+            Msg::RequestCheck => {
+                self.user_msg.reset();
+                self.fetch_task = get_request!(
                     self.fetch_service,
-                    &session_login_url(),
-                    info,
+                    &session_check_url(),
                     self.self_link,
-                    Msg::ResponseLogin
+                    Msg::ResponseCheck
                 );
-                self.change_user_type.emit(WebUserType::Responder);
             },
             Msg::RequestLogin => {
                 self.user_msg.reset();
@@ -140,7 +137,6 @@ impl Component for Login {
                     Msg::ResponseLogin
                 );
                 self.data.login.reset_pw(); // ensure the password is deleted asap
-                self.change_user_type.emit(WebUserType::Admin);
             },
             Msg::RequestLogout => {
                 self.user_msg.reset();
@@ -153,14 +149,24 @@ impl Component for Login {
                 );
                 self.data.login.reset_pw(); // ensure the password is deleted asap
             },
+            Msg::ResponseCheck(response) => {
+                // without a valid session, the login form is shown
+                let (_meta, Json(body)) = response.into_parts();
+                if let Ok(Ok(session)) = body {
+                    self.change_user_type.emit(WebUserType::from(session.role));
+                    self.self_link.send_self(Msg::ChangeRootPage(root::Page::MapView(None)));
+                }
+                self.auto_action = AutoAction::Nothing;
+            },
             Msg::ResponseLogin(response) => {
-                let (meta, _body) = response.into_parts();
-                match meta.status {
-                    StatusCode::OK => {
+                let (meta, Json(body)) = response.into_parts();
+                match (meta.status, body) {
+                    (StatusCode::OK, Ok(Ok(session))) => {
                         self.user_msg.success_message = Some("Successfully logged in.".to_string());
+                        self.change_user_type.emit(WebUserType::from(session.role));
                         self.self_link.send_self(Msg::ChangeRootPage(root::Page::MapView(None)));
                     },
-                    StatusCode::UNAUTHORIZED => {
+                    (StatusCode::UNAUTHORIZED, _) => {
                         self.user_msg.error_messages.push("Failed to login, username or password is incorrect.".to_string());
                     },
                     _ => {
@@ -188,7 +194,7 @@ impl Component for Login {
         if props.auto_action != self.auto_action {
             match props.auto_action {
                 AutoAction::Nothing => {},
-                AutoAction::Login => self.self_link.send_self(Msg::RequestLoginAnon),
+                AutoAction::Login => self.self_link.send_self(Msg::RequestCheck),
                 AutoAction::Logout => self.self_link.send_self(Msg::RequestLogout),
             }
         }
@@ -235,11 +241,6 @@ impl Login {
                                 value="Login",
                                 onclick=|_| Msg::RequestLogin,
                             />
-                            <input
-                                type="submit",
-                                value="Cancel",
-                                onclick=|_| Msg::RequestLoginAnon,
-                            />
                         </div>
                     </div>
                 </div>
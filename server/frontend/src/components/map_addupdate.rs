@@ -1,4 +1,5 @@
 use common::*;
+use common::zones::{ Zone, ZoneKind, };
 use crate::canvas::{ Canvas, screen_space };
 use crate::util::{ self, WebUserType, JsonResponseHandler, };
 use std::time::Duration;
@@ -36,11 +37,22 @@ pub enum Msg {
     ToggleBeaconPlacement(i32),
     ToggleGrid,
 
+    CancelZone,
+    EditZone(i32),
+    InputZoneDwell(String),
+    InputZoneKind(ZoneKind),
+    InputZoneName(String),
+    NewZone,
+    UndoZonePoint,
+
     RequestAddUpdateMap,
     RequestGetMap(i32),
     RequestGetBeaconsForMap(i32),
     RequestGetUnattachedBeacons,
     RequestPutBeacon(i32),
+    RequestDeleteZone(i32),
+    RequestGetZones(i32),
+    RequestSaveZone,
 
     ResponseAddMap(util::JsonResponse<Map>),
     ResponseGetBeaconsForMap(util::JsonResponse<Vec<Beacon>>),
@@ -50,6 +62,9 @@ pub enum Msg {
     ResponseUpdateMap(util::JsonResponse<Map>),
     ResponsePutBeacon(util::JsonResponse<Beacon>),
     ResponseUpdateBlueprint(util::BinResponse<()>),
+    ResponseDeleteZone(i32, util::JsonResponse<()>),
+    ResponseGetZones(util::JsonResponse<Vec<Zone>>),
+    ResponseSaveZone(util::JsonResponse<Zone>),
 }
 
 struct BeaconData {
@@ -68,6 +83,10 @@ struct Data {
     pub raw_scale: String,
     pub current_beacon: Option<i32>,
     pub blueprint: Option<FileData>,
+    pub zones: Vec<Zone>,
+    // the zone being drawn, clicking the map adds a point to it
+    pub current_zone: Option<Zone>,
+    pub raw_dwell: String,
 }
 
 impl Data {
@@ -81,6 +100,9 @@ impl Data {
             raw_scale: "1".to_string(),
             current_beacon: None,
             blueprint: None,
+            zones: Vec::new(),
+            current_zone: None,
+            raw_dwell: String::new(),
         }
     }
 }
@@ -154,6 +176,46 @@ impl MapAddUpdate {
         success
     }
 
+    fn validate_zone(&mut self) -> bool {
+        let dwell = self.data.raw_dwell.trim();
+        let dwell_seconds = if dwell.is_empty() {
+            None
+        } else {
+            match dwell.parse::<i32>() {
+                Ok(seconds) => Some(seconds),
+                Err(e) => {
+                    self.user_msg.error_messages.push(format!("failed to parse dwell time: {}", e));
+                    return false;
+                },
+            }
+        };
+
+        match &mut self.data.current_zone {
+            Some(zone) => {
+                zone.dwell_seconds = dwell_seconds;
+                match zone.validate() {
+                    Ok(()) => true,
+                    Err(e) => {
+                        self.user_msg.error_messages.push(e.to_string());
+                        false
+                    },
+                }
+            },
+            None => false,
+        }
+    }
+
+    fn redraw(&mut self) {
+        self.canvas.reset(&self.data.map, &self.map_img, self.show_grid);
+        let current_id = self.data.current_zone.as_ref().map(|zone| zone.id);
+        // the zone being edited is drawn from the draft instead
+        self.canvas.draw_zones(&self.data.map, &self.data.zones.iter().filter(|zone| Some(zone.id) != current_id).collect());
+        if let Some(zone) = &self.data.current_zone {
+            self.canvas.draw_zone_outline(&self.data.map, zone);
+        }
+        self.canvas.draw_beacons(&self.data.map, &self.data.attached_beacons.iter().map(|(b, _bdata)| b).collect());
+    }
+
     fn load_img(&mut self) {
         if let Some(img) = &self.map_img {
             // use the date to force reload
@@ -190,6 +252,7 @@ pub struct MapAddUpdate {
     fetch_task_binary: Option<FetchTask>,
     fetch_task_map: Option<FetchTask>,
     fetch_task_unattached_beacons: Option<FetchTask>,
+    fetch_task_zones: Option<FetchTask>,
 }
 
 impl JsonResponseHandler for MapAddUpdate {}
@@ -212,6 +275,7 @@ impl Component for MapAddUpdate {
             link.send_self(Msg::RequestGetMap(id));
             link.send_self(Msg::RequestGetBeaconsForMap(id));
             link.send_self(Msg::RequestGetUnattachedBeacons);
+            link.send_self(Msg::RequestGetZones(id));
         }
         let data = Data::new();
 
@@ -236,11 +300,10 @@ impl Component for MapAddUpdate {
             fetch_task_binary: None,
             fetch_task_map: None,
             fetch_task_unattached_beacons: None,
-
+            fetch_task_zones: None,
         };
 
-        result.canvas.reset(&result.data.map, &result.map_img, result.show_grid);
-        result.canvas.draw_beacons(&result.data.map, &result.data.attached_beacons.iter().map(|(b, _d)| b).collect());
+        result.redraw();
         result.data.opt_id = props.opt_id;
         result
     }
@@ -290,6 +353,43 @@ impl Component for MapAddUpdate {
             Msg::InputScale(value) => {
                 self.data.raw_scale = value;
             },
+            Msg::NewZone => {
+                let mut zone = Zone::new();
+                zone.map_id = self.data.map.id;
+                zone.kind = ZoneKind::Hazard;
+                self.data.current_zone = Some(zone);
+                self.data.current_beacon = None;
+                self.data.raw_dwell = String::new();
+            },
+            Msg::EditZone(id) => {
+                self.data.current_zone = self.data.zones.iter().find(|zone| zone.id == id).cloned();
+                self.data.current_beacon = None;
+                self.data.raw_dwell = self.data.current_zone
+                    .as_ref()
+                    .and_then(|zone| zone.dwell_seconds)
+                    .map_or(String::new(), |seconds| seconds.to_string());
+            },
+            Msg::CancelZone => {
+                self.data.current_zone = None;
+            },
+            Msg::InputZoneName(name) => {
+                if let Some(zone) = &mut self.data.current_zone {
+                    zone.name = name;
+                }
+            },
+            Msg::InputZoneKind(kind) => {
+                if let Some(zone) = &mut self.data.current_zone {
+                    zone.kind = kind;
+                }
+            },
+            Msg::InputZoneDwell(dwell) => {
+                self.data.raw_dwell = dwell;
+            },
+            Msg::UndoZonePoint => {
+                if let Some(zone) = &mut self.data.current_zone {
+                    zone.polygon.pop();
+                }
+            },
             Msg::ToggleBeaconPlacement(beacon_id) => {
                 match self.data.current_beacon {
                     Some(id) if beacon_id == id => {
@@ -348,16 +448,23 @@ impl Component for MapAddUpdate {
                                 self.data.attached_beacons[index].1.raw_x = coords.x.to_string();
                                 self.data.attached_beacons[index].1.raw_y = coords.y.to_string();
                                 self.data.attached_beacons[index].0.coordinates = coords;
-                                self.canvas.reset(&self.data.map, &self.map_img, self.show_grid);
-                                self.canvas.draw_beacons(&self.data.map, &self.data.attached_beacons.iter().map(|(b, _bdata)| b).collect());
                             },
                             _ => {
                                 Log!("invalid current beacon");
                             },
                         }
                     },
-                    _ => {
-                        Log!("ignoring input location because a beacon has not been selected");
+                    None => {
+                        match &mut self.data.current_zone {
+                            Some(zone) => {
+                                let pix_coords = na::Vector2::new(event.client_x() - canvas_bound.get_left() as i32, event.client_y() - canvas_bound.get_top() as i32);
+                                let world_coords = screen_space(&self.data.map, pix_coords.x as f64, pix_coords.y as f64);
+                                zone.polygon.push(na::Vector2::new(world_coords.x / self.data.map.scale, world_coords.y / self.data.map.scale));
+                            },
+                            None => {
+                                Log!("ignoring input location because a beacon or zone has not been selected");
+                            },
+                        }
                     }
                 }
             },
@@ -389,6 +496,49 @@ impl Component for MapAddUpdate {
                     Msg::ResponseGetBeaconsForMap
                 );
             },
+            Msg::RequestGetZones(id) => {
+                self.fetch_task_zones = get_request!(
+                    self.fetch_service,
+                    &zones_for_map_url(&id.to_string()),
+                    self.self_link,
+                    Msg::ResponseGetZones
+                );
+            },
+            Msg::RequestSaveZone => {
+                self.user_msg.reset();
+                if self.validate_zone() {
+                    match &self.data.current_zone {
+                        Some(zone) if zone.id < 0 => {
+                            self.fetch_task_zones = post_request!(
+                                self.fetch_service,
+                                &zone_url(""),
+                                zone,
+                                self.self_link,
+                                Msg::ResponseSaveZone
+                            );
+                        },
+                        Some(zone) => {
+                            self.fetch_task_zones = put_request!(
+                                self.fetch_service,
+                                &zone_url(&zone.id.to_string()),
+                                zone,
+                                self.self_link,
+                                Msg::ResponseSaveZone
+                            );
+                        },
+                        None => { },
+                    }
+                }
+            },
+            Msg::RequestDeleteZone(id) => {
+                self.user_msg.reset();
+                self.fetch_task_zones = delete_request!(
+                    self.fetch_service,
+                    &zone_url(&id.to_string()),
+                    self.self_link,
+                    move |response| Msg::ResponseDeleteZone(id, response)
+                );
+            },
             Msg::RequestGetUnattachedBeacons => {
                 self.user_msg.error_messages = Vec::new();
                 self.fetch_task_unattached_beacons = get_request!(
@@ -560,6 +710,48 @@ impl Component for MapAddUpdate {
                     self.user_msg.error_messages.push("failed to find map".to_owned());
                 }
             },
+            Msg::ResponseGetZones(response) => {
+                self.handle_response(
+                    response,
+                    |s, zones| {
+                        s.data.zones = zones;
+                    },
+                    |s, e| {
+                        s.user_msg.error_messages.push(format!("failed to obtain zones, reason: {}", e));
+                    },
+                );
+            },
+            Msg::ResponseSaveZone(response) => {
+                self.handle_response(
+                    response,
+                    |s, zone| {
+                        s.user_msg.success_message = Some("successfully saved zone".to_owned());
+                        match s.data.zones.iter().position(|z| z.id == zone.id) {
+                            Some(index) => s.data.zones[index] = zone,
+                            None => s.data.zones.push(zone),
+                        }
+                        s.data.current_zone = None;
+                    },
+                    |s, e| {
+                        s.user_msg.error_messages.push(format!("failed to save zone, reason: {}", e));
+                    },
+                );
+            },
+            Msg::ResponseDeleteZone(id, response) => {
+                self.handle_response(
+                    response,
+                    |s, _| {
+                        s.user_msg.success_message = Some("successfully deleted zone".to_owned());
+                        s.data.zones.retain(|zone| zone.id != id);
+                        if s.data.current_zone.as_ref().map_or(false, |zone| zone.id == id) {
+                            s.data.current_zone = None;
+                        }
+                    },
+                    |s, e| {
+                        s.user_msg.error_messages.push(format!("failed to delete zone, reason: {}", e));
+                    },
+                );
+            },
             Msg::ResponseAddMap(response) => {
                 self.handle_response(
                     response,
@@ -585,8 +777,7 @@ impl Component for MapAddUpdate {
             },
         }
 
-        self.canvas.reset(&self.data.map, &self.map_img, self.show_grid);
-        self.canvas.draw_beacons(&self.data.map, &self.data.attached_beacons.iter().map(|(b, _bdata)| b).collect());
+        self.redraw();
         true
     }
 
@@ -596,6 +787,7 @@ impl Component for MapAddUpdate {
         if let Some(id) = props.opt_id {
             self.self_link.send_self(Msg::RequestGetMap(id));
             self.self_link.send_self(Msg::RequestGetBeaconsForMap(id));
+            self.self_link.send_self(Msg::RequestGetZones(id));
         }
         true
     }
@@ -736,6 +928,160 @@ impl MapAddUpdate {
     }
 }
 
+impl MapAddUpdate {
+    fn render_current_zone(&self) -> Html<Self> {
+        let zone = match &self.data.current_zone {
+            Some(zone) => zone,
+            None => return html! { },
+        };
+
+        let mut kind_options = ZoneKind::all().iter().map(|kind| {
+            let kind = *kind;
+            html! {
+                <option
+                    onclick=|_| Msg::InputZoneKind(kind),
+                    selected={ kind == zone.kind },
+                >
+                    { kind.to_string() }
+                </option>
+            }
+        }).collect::<Vec<Html<Self>>>();
+
+        html! {
+            <table>
+                <tr>
+                    <td class="formLabel">{ "Zone Name:" }</td>
+                    <td>
+                        <input
+                            type="text",
+                            value=&zone.name,
+                            oninput=|e| Msg::InputZoneName(e.value),
+                        />
+                    </td>
+                </tr>
+                <tr>
+                    <td class="formLabel">{ "Kind:" }</td>
+                    <td>
+                        <select class="formAlign">
+                            { for kind_options.drain(..) }
+                        </select>
+                    </td>
+                </tr>
+                <tr>
+                    <td class="formLabel">{ "Dwell Alert(s):" }</td>
+                    <td>
+                        <input
+                            type="text",
+                            value=&self.data.raw_dwell,
+                            placeholder="No Limit",
+                            oninput=|e| Msg::InputZoneDwell(e.value),
+                        />
+                    </td>
+                </tr>
+                <tr>
+                    <td class="formLabel">{ "Points:" }</td>
+                    <td>{ format!("{}, click the map to add a point", zone.polygon.len()) }</td>
+                </tr>
+                <tr>
+                    <td></td>
+                    <td>
+                        <button
+                            class="btn btn-sm btn-success mx-1",
+                            onclick=|_| Msg::RequestSaveZone,
+                        >
+                            { "Save Zone" }
+                        </button>
+                        <button
+                            class="btn btn-sm btn-warning mx-1",
+                            onclick=|_| Msg::UndoZonePoint,
+                        >
+                            { "Undo Point" }
+                        </button>
+                        <button
+                            class="btn btn-sm btn-secondary mx-1",
+                            onclick=|_| Msg::CancelZone,
+                        >
+                            { "Cancel" }
+                        </button>
+                    </td>
+                </tr>
+            </table>
+        }
+    }
+
+    fn render_zones(&self) -> Html<Self> {
+        if self.data.opt_id.is_none() {
+            return html! { };
+        }
+        let is_admin = self.user_type == WebUserType::Admin;
+
+        let mut zone_rows = self.data.zones.iter().map(|zone| {
+            let zone_id = zone.id;
+            html! {
+                <tr>
+                    <td class="formLabel">{ &zone.name }</td>
+                    <td>{ zone.kind.to_string() }</td>
+                    <td>{ zone.dwell_seconds.map_or("-".to_string(), |seconds| format!("{}s", seconds)) }</td>
+                    <td>
+                        {
+                            if is_admin {
+                                html! {
+                                    <>
+                                        <button
+                                            class="btn btn-sm btn-warning mx-1",
+                                            onclick=|_| Msg::EditZone(zone_id),
+                                        >
+                                            { "Edit" }
+                                        </button>
+                                        <button
+                                            class="btn btn-sm btn-danger mx-1",
+                                            onclick=|_| Msg::RequestDeleteZone(zone_id),
+                                        >
+                                            { "Delete" }
+                                        </button>
+                                    </>
+                                }
+                            } else {
+                                html! { }
+                            }
+                        }
+                    </td>
+                </tr>
+            }
+        });
+
+        html! {
+            <>
+                <h3>{ "Zones" }</h3>
+                <table>
+                    <tr>
+                        <td class="formLabel">{ "Name" }</td>
+                        <td class="formLabel">{ "Kind" }</td>
+                        <td class="formLabel">{ "Dwell Alert" }</td>
+                        <td class="formLabel">{ "Actions" }</td>
+                    </tr>
+                    { for zone_rows }
+                </table>
+                {
+                    if is_admin && self.data.current_zone.is_none() {
+                        html! {
+                            <button
+                                class="btn btn-sm btn-success mx-1",
+                                onclick=|_| Msg::NewZone,
+                            >
+                                { "Add Zone" }
+                            </button>
+                        }
+                    } else {
+                        html! { }
+                    }
+                }
+                { self.render_current_zone() }
+            </>
+        }
+    }
+}
+
 impl Renderable<MapAddUpdate> for MapAddUpdate {
     fn view(&self) -> Html<Self> {
         let title_name = match self.data.opt_id {
@@ -840,6 +1186,7 @@ impl Renderable<MapAddUpdate> for MapAddUpdate {
                             </tr>
                         </table>
                     { self.render_beacon_placement() }
+                    { self.render_zones() }
                     <div>
                         <input
                             type="checkbox",
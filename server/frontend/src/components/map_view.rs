@@ -1,33 +1,108 @@
 use common::*;
+use common::motion::{ self, MotionAlert, };
+use common::zones::{ Zone, ZoneEvent, };
 use crate::canvas::{ Canvas, };
+use crate::push::{ PushChannel, PushResponse, };
 use crate::util::*;
+use std::collections::{ BTreeMap, VecDeque, };
 use std::time::Duration;
 use stdweb::web::{ Node, html_element::ImageElement, Date, };
+use super::motion_alerts::MotionAlerts;
 use super::user_message::UserMessage;
 use super::value_button::{ ValueButton, DisplayButton, };
+use yew::format::Json;
 use yew::prelude::*;
 use yew::services::fetch::{ FetchService, FetchTask, };
 use yew::services::interval::{ IntervalService, IntervalTask, };
+use yew::services::websocket::WebSocketStatus;
 use yew::virtual_dom::vnode::VNode;
 
 const REALTIME_USER_POLL_RATE: Duration = Duration::from_millis(1000);
+const REPLAY_TICK_RATE: Duration = Duration::from_millis(100);
+const REPLAY_SPEEDS: [u32; 5] = [1, 2, 5, 10, 30];
+const DEFAULT_REPLAY_MINUTES: i64 = 30;
+const MAX_ZONE_EVENTS: usize = 10;
+
+// recorded locations for a map, played back from a cursor
+struct Replay {
+    cursor: DateTime<Utc>,
+    frames: Vec<RealtimeUserData>,
+    from: DateTime<Utc>,
+    map_id: i32,
+    playing: bool,
+    speed: u32,
+    to: DateTime<Utc>,
+}
+
+impl Replay {
+    // the latest location of every user at the cursor
+    fn users(&self) -> Vec<RealtimeUserData> {
+        let mut latest = BTreeMap::new();
+        for frame in self.frames.iter().take_while(|f| f.last_active <= self.cursor) {
+            latest.insert(frame.addr, frame);
+        }
+        latest.into_iter().map(|(_addr, frame)| frame.clone()).collect()
+    }
+
+    fn duration_secs(&self) -> i64 {
+        (self.to - self.from).num_seconds()
+    }
+
+    fn cursor_secs(&self) -> i64 {
+        (self.cursor - self.from).num_seconds()
+    }
+
+    fn page_url(&self, cursor: Option<ReplayCursor>) -> String {
+        let url = format!(
+            "{}?from={}&to={}",
+            map_replay_url(&self.map_id.to_string()),
+            format_query_timestamp(&self.from),
+            format_query_timestamp(&self.to),
+        );
+        match cursor {
+            // the cursor is kept to the microsecond, locations recorded in the same second are split
+            // across pages
+            Some(cursor) => format!("{}&after={}&after_id={}", url, cursor.after.format("%Y-%m-%dT%H:%M:%S%.6fZ"), cursor.after_id),
+            None => url,
+        }
+    }
+}
 
 pub enum Msg {
     CheckImage,
     ChooseMap(i32),
     Ignore,
+    MotionAlertChanged(MotionAlert),
     ToggleGrid,
     ViewDistance(ShortAddress),
 
+    InputReplayFrom(String),
+    InputReplayTo(String),
+    ReplayPlayPause,
+    ReplaySeek(String),
+    ReplaySpeed(u32),
+    ReplayTick,
+    ToggleReplay,
+
+    PushConnect,
+    PushReceived(PushResponse),
+    PushStatus(WebSocketStatus),
+
     RequestGetBeaconsForMap(i32),
     RequestGetMap(i32),
     RequestGetMaps,
+    RequestGetMotionAlerts,
     RequestRealtimeUser,
+    RequestReplay,
+    RequestGetZones(i32),
 
     ResponseGetBeaconsForMap(JsonResponse<Vec<Beacon>>),
     ResponseGetMap(JsonResponse<Map>),
     ResponseGetMaps(JsonResponse<Vec<Map>>),
+    ResponseGetMotionAlerts(JsonResponse<Vec<MotionAlert>>),
     ResponseRealtimeUser(JsonResponse<Vec<RealtimeUserData>>),
+    ResponseReplay(JsonResponse<ReplayPage>),
+    ResponseGetZones(JsonResponse<Vec<Zone>>),
 }
 
 pub struct MapViewComponent {
@@ -37,28 +112,47 @@ pub struct MapViewComponent {
     emergency: bool,
     fetch_service: FetchService,
     fetch_task_beacons: Option<FetchTask>,
+    fetch_task_motion_alerts: Option<FetchTask>,
     fetch_task_realtime_users: Option<FetchTask>,
+    fetch_task_replay: Option<FetchTask>,
+    fetch_task_zones: Option<FetchTask>,
     get_fetch_task: Option<FetchTask>,
     get_many_fetch_task: Option<FetchTask>,
     interval_service: IntervalService,
     interval_service_task_user: Option<IntervalTask>,
     interval_service_task_beacon: Option<IntervalTask>,
     interval_service_task_blueprint: Option<IntervalTask>,
+    interval_service_task_replay: Option<IntervalTask>,
     legend_canvas: Canvas,
     map_img: Option<ImageElement>,
     maps: Vec<Map>,
+    motion_alerts: Vec<MotionAlert>,
+    push: PushChannel,
     realtime_users: Vec<RealtimeUserData>,
+    replay: Option<Replay>,
+    replay_from: String,
+    replay_mode: bool,
+    replay_to: String,
     self_link: ComponentLink<MapViewComponent>,
     show_distance: Option<ShortAddress>,
     user_msg: UserMessage<Self>,
     user_type: WebUserType,
     show_grid: bool,
+    zones: Vec<Zone>,
+    // the most recent zone entries and exits, newest first
+    zone_events: VecDeque<ZoneEvent>,
 }
 
 impl JsonResponseHandler for MapViewComponent {}
 
 impl MapViewComponent {
     fn start_service(&mut self) {
+        // users and beacons are pushed while the socket is up
+        if self.push.is_connected() {
+            self.end_service();
+            return;
+        }
+
         if let Some(map) = &self.current_map {
             let id = map.id;
             self.interval_service_task_beacon = Some(
@@ -78,15 +172,69 @@ impl MapViewComponent {
     fn render(&mut self) {
         if let Some(map) = &self.current_map {
             self.canvas.reset(map, &self.map_img, self.show_grid);
+            self.canvas.draw_zones(map, &self.zones.iter().collect());
 
-            self.canvas.draw_users(map, &self.realtime_users, self.show_distance);
-            if self.user_type == WebUserType::Admin {
+            let now = match &self.replay {
+                Some(replay) if self.replay_mode => replay.cursor.timestamp_millis() as f64,
+                _ => Date::now(),
+            };
+            self.canvas.draw_users(map, &self.realtime_users, self.show_distance, now);
+            if !self.replay_mode {
+                let alerts = self.motion_alerts.iter().filter(|a| a.map_id == Some(map.id)).collect();
+                self.canvas.draw_motion_alerts(map, &alerts);
+            }
+            // the beacons may have been moved since the replay was recorded, the ranges drawn with
+            // the users are where they were
+            if self.user_type == WebUserType::Admin && !self.replay_mode {
                 self.canvas.draw_beacons(map, &self.beacons.iter().collect());
             }
             self.legend_canvas.legend(80, map.bounds.y as u32, self.user_type);
         }
     }
 
+    fn end_replay(&mut self) {
+        self.interval_service_task_replay = None;
+        self.fetch_task_replay = None;
+        self.replay = None;
+        self.realtime_users = Vec::new();
+    }
+
+    fn set_playing(&mut self, playing: bool) {
+        if let Some(replay) = &mut self.replay {
+            replay.playing = playing;
+            if playing {
+                // restart from the beginning once the end has been reached
+                if replay.cursor >= replay.to {
+                    replay.cursor = replay.from;
+                }
+                self.interval_service_task_replay = Some(
+                    self.interval_service.spawn(REPLAY_TICK_RATE, self.self_link.send_back(|_| Msg::ReplayTick))
+                );
+            } else {
+                self.interval_service_task_replay = None;
+            }
+        }
+    }
+
+    fn request_replay_page(&mut self, cursor: Option<ReplayCursor>) {
+        let url = match &self.replay {
+            Some(replay) => replay.page_url(cursor),
+            None => return,
+        };
+        self.fetch_task_replay = get_request!(
+            self.fetch_service,
+            &url,
+            self.self_link,
+            Msg::ResponseReplay
+        );
+    }
+
+    fn replay_users(&mut self) {
+        if let Some(replay) = &self.replay {
+            self.realtime_users = replay.users();
+        }
+    }
+
     fn load_img(&mut self) {
         if let Some(map) = &self.current_map {
             let img = ImageElement::new();
@@ -116,6 +264,10 @@ impl Component for MapViewComponent {
             link.send_self(Msg::RequestGetBeaconsForMap(id));
         }
         link.send_self(Msg::RequestGetMaps);
+        link.send_self(Msg::RequestGetMotionAlerts);
+        link.send_self(Msg::PushConnect);
+        // chrono cannot read the clock in the browser
+        let now = Utc.timestamp_millis(Date::now() as i64);
         let click_callback = link.send_back(|_event| Msg::Ignore);
 
         let mut result = MapViewComponent {
@@ -125,22 +277,38 @@ impl Component for MapViewComponent {
             emergency: props.emergency,
             fetch_service: FetchService::new(),
             fetch_task_beacons: None,
+            fetch_task_motion_alerts: None,
             fetch_task_realtime_users: None,
+            fetch_task_replay: None,
+            fetch_task_zones: None,
             get_fetch_task: None,
             get_many_fetch_task: None,
             interval_service: IntervalService::new(),
             interval_service_task_user: None,
             interval_service_task_beacon: None,
             interval_service_task_blueprint: None,
+            interval_service_task_replay: None,
             legend_canvas: Canvas::new("legend_canvas", click_callback),
             map_img: None,
             maps: Vec::new(),
+            motion_alerts: Vec::new(),
+            // nothing is pushed until a map is chosen
+            push: PushChannel::new(PushSubscription {
+                diagnostics: false,
+                map_ids: Some(Vec::new()),
+            }),
             realtime_users: Vec::new(),
+            replay: None,
+            replay_from: format_local_input(&(now - chrono::Duration::minutes(DEFAULT_REPLAY_MINUTES))),
+            replay_mode: false,
+            replay_to: format_local_input(&now),
             self_link: link,
             show_distance: None,
             user_msg: UserMessage::new(),
             user_type: props.user_type,
             show_grid: false,
+            zones: Vec::new(),
+            zone_events: VecDeque::new(),
         };
 
         if props.emergency {
@@ -199,12 +367,151 @@ impl Component for MapViewComponent {
                 if let Some(map) = &self.current_map {
                     self.self_link.send_self(Msg::RequestGetMap(map.id));
                 }
+                // the server also pushes users leaving the subscribed map, so they are removed
+                self.push.subscribe(PushSubscription {
+                    diagnostics: false,
+                    map_ids: Some(self.current_map.iter().map(|m| m.id).collect()),
+                });
 
-                if self.emergency {
+                if self.replay_mode {
+                    // a replay only covers a single map
+                    self.end_replay();
+                } else if self.emergency {
                     self.start_service();
                 }
             },
+            Msg::ToggleReplay => {
+                self.replay_mode = !self.replay_mode;
+                self.end_replay();
+                if self.replay_mode {
+                    self.end_service();
+                } else if self.emergency {
+                    self.start_service();
+                }
+            },
+            Msg::PushConnect => {
+                self.push.connect(
+                    self.self_link.send_back(Msg::PushReceived),
+                    self.self_link.send_back(Msg::PushStatus),
+                );
+            },
+            Msg::PushStatus(status) => {
+                if self.push.status(status, self.self_link.send_back(|_| Msg::PushConnect)) {
+                    // alerts may have changed while the socket was down
+                    self.self_link.send_self(Msg::RequestGetMotionAlerts);
+                }
+                if self.emergency && !self.replay_mode {
+                    self.start_service();
+                }
+            },
+            Msg::PushReceived(Json(response)) => {
+                match response {
+                    Ok(PushMessage::UserMoved(user)) if self.emergency && !self.replay_mode => {
+                        let current_mid = self.current_map.as_ref().map(|m| m.id);
+                        let existing = self.realtime_users.iter().position(|u| u.id == user.id);
+                        match existing {
+                            Some(index) if user.map_id != current_mid => {
+                                self.realtime_users.remove(index);
+                            },
+                            Some(index) => {
+                                self.realtime_users[index] = user;
+                            },
+                            None if user.map_id == current_mid => {
+                                self.realtime_users.push(user);
+                            },
+                            None => {
+                                return false;
+                            },
+                        }
+                    },
+                    Ok(PushMessage::MotionAlert(alert)) => {
+                        motion::merge_alert(&mut self.motion_alerts, alert);
+                    },
+                    Ok(PushMessage::ZoneEvent(event)) => {
+                        self.zone_events.push_front(event);
+                        self.zone_events.truncate(MAX_ZONE_EVENTS);
+                    },
+                    Ok(PushMessage::BeaconChanged(realtime_beacon)) => {
+                        match self.beacons.iter_mut().find(|b| b.id == realtime_beacon.id) {
+                            Some(beacon) => beacon.merge(realtime_beacon),
+                            None => return false,
+                        }
+                    },
+                    Ok(_) => {
+                        return false;
+                    },
+                    Err(e) => {
+                        Log!("failed to parse push message, {}", e);
+                        return false;
+                    },
+                }
+            },
+            Msg::InputReplayFrom(value) => {
+                self.replay_from = value;
+            },
+            Msg::InputReplayTo(value) => {
+                self.replay_to = value;
+            },
+            Msg::ReplayPlayPause => {
+                let playing = self.replay.as_ref().map(|r| r.playing).unwrap_or(false);
+                self.set_playing(!playing);
+            },
+            Msg::ReplaySeek(value) => {
+                if let (Some(replay), Ok(secs)) = (&mut self.replay, value.parse::<i64>()) {
+                    replay.cursor = replay.from + chrono::Duration::seconds(secs);
+                }
+                self.replay_users();
+            },
+            Msg::ReplaySpeed(speed) => {
+                if let Some(replay) = &mut self.replay {
+                    replay.speed = speed;
+                }
+            },
+            Msg::ReplayTick => {
+                let mut finished = false;
+                if let Some(replay) = &mut self.replay {
+                    let step = REPLAY_TICK_RATE.as_millis() as i64 * replay.speed as i64;
+                    replay.cursor = replay.cursor + chrono::Duration::milliseconds(step);
+                    if replay.cursor >= replay.to {
+                        replay.cursor = replay.to;
+                        finished = true;
+                    }
+                }
+                if finished {
+                    self.set_playing(false);
+                }
+                self.replay_users();
+            },
+            Msg::RequestReplay => {
+                self.user_msg.reset();
+                let from = parse_local_input(&self.replay_from);
+                let to = parse_local_input(&self.replay_to);
+                match (&self.current_map, from, to) {
+                    (Some(map), Some(from), Some(to)) if from < to => {
+                        self.end_replay();
+                        self.replay = Some(Replay {
+                            cursor: from,
+                            frames: Vec::new(),
+                            from,
+                            map_id: map.id,
+                            playing: false,
+                            speed: REPLAY_SPEEDS[0],
+                            to,
+                        });
+                        self.request_replay_page(None);
+                    },
+                    (None, _, _) => {
+                        self.user_msg.error_messages.push("choose a map to replay".to_string());
+                    },
+                    _ => {
+                        self.user_msg.error_messages.push("the replay start must be a valid time before the end".to_string());
+                    },
+                }
+            },
             Msg::RequestRealtimeUser => {
+                if self.replay_mode {
+                    return false;
+                }
                 self.user_msg.reset();
                 self.fetch_task_realtime_users = get_request!(
                     self.fetch_service,
@@ -231,6 +538,25 @@ impl Component for MapViewComponent {
                     Msg::ResponseGetMap
                 );
             },
+            Msg::RequestGetZones(id) => {
+                self.fetch_task_zones = get_request!(
+                    self.fetch_service,
+                    &zones_for_map_url(&id.to_string()),
+                    self.self_link,
+                    Msg::ResponseGetZones
+                );
+            },
+            Msg::MotionAlertChanged(alert) => {
+                motion::merge_alert(&mut self.motion_alerts, alert);
+            },
+            Msg::RequestGetMotionAlerts => {
+                self.fetch_task_motion_alerts = get_request!(
+                    self.fetch_service,
+                    &motion_alerts_url(),
+                    self.self_link,
+                    Msg::ResponseGetMotionAlerts
+                );
+            },
             Msg::RequestGetMaps => {
                 self.user_msg.reset();
                 self.get_many_fetch_task = get_request!(
@@ -252,6 +578,24 @@ impl Component for MapViewComponent {
                     },
                 );
             },
+            Msg::ResponseReplay(response) => {
+                self.handle_response(
+                    response,
+                    |s, page| {
+                        // the frames so far can be played while the rest are fetched
+                        if let Some(replay) = &mut s.replay {
+                            replay.frames.extend(page.frames);
+                        }
+                        s.replay_users();
+                        if page.next.is_some() {
+                            s.request_replay_page(page.next);
+                        }
+                    },
+                    |s, e| {
+                        s.user_msg.error_messages.push(format!("failed to get replay, reason: {}", e));
+                    },
+                );
+            },
             Msg::ResponseGetBeaconsForMap(response) => {
                 self.handle_response(
                     response,
@@ -267,18 +611,42 @@ impl Component for MapViewComponent {
                 self.handle_response(
                     response,
                     |s, map| {
+                        let map_id = map.id;
                         s.current_map = Some(map.clone());
                         s.maps.iter_mut().find(|m| m.id == map.id).and_then(|m| {
                             *m = map;
                             Some(())
                         });
                         s.load_img();
+                        s.self_link.send_self(Msg::RequestGetZones(map_id));
                     },
                     |s, e| {
                         s.user_msg.error_messages.push(format!("failed to get map, reason: {}", e));
                     },
                 );
             },
+            Msg::ResponseGetZones(response) => {
+                self.handle_response(
+                    response,
+                    |s, zones| {
+                        s.zones = zones;
+                    },
+                    |s, e| {
+                        s.user_msg.error_messages.push(format!("failed to get zones, reason: {}", e));
+                    },
+                );
+            },
+            Msg::ResponseGetMotionAlerts(response) => {
+                self.handle_response(
+                    response,
+                    |s, alerts| {
+                        s.motion_alerts = alerts;
+                    },
+                    |s, e| {
+                        s.user_msg.error_messages.push(format!("failed to get motion alerts, reason: {}", e));
+                    },
+                );
+            },
             Msg::ResponseGetMaps(response) => {
                 self.handle_response(
                     response,
@@ -305,18 +673,103 @@ impl Component for MapViewComponent {
         // do not overwrite the canvas or context.
         self.emergency = props.emergency;
 
-        if self.emergency {
-            self.end_service();
-            self.start_service();
-        } else {
-            self.end_service();
-            self.realtime_users = Vec::new();
+        // a replay is independent of the current emergency state
+        if !self.replay_mode {
+            if self.emergency {
+                self.end_service();
+                self.start_service();
+            } else {
+                self.end_service();
+                self.realtime_users = Vec::new();
+            }
         }
         self.render();
         true
     }
 }
 
+impl MapViewComponent {
+    fn render_replay(&self) -> Html<Self> {
+        if !self.replay_mode {
+            return html! {};
+        }
+
+        let controls = match &self.replay {
+            Some(replay) => {
+                let current_speed = replay.speed;
+                let mut speeds = REPLAY_SPEEDS.iter().map(|speed| {
+                    let speed = *speed;
+                    html! {
+                        <ValueButton<u32>
+                            on_click=|value: u32| Msg::ReplaySpeed(value),
+                            border=speed == current_speed,
+                            value={speed},
+                            style={ if speed == current_speed {"btn-sm btn-primary"} else {"btn-sm btn-outline-primary"} },
+                            display=Some(format!("{}x", speed)),
+                        />
+                    }
+                });
+
+                html! {
+                    <div>
+                        <button
+                            class="btn btn-sm btn-primary mx-1",
+                            onclick=|_| Msg::ReplayPlayPause,
+                        >
+                            { if replay.playing { "Pause" } else { "Play" } }
+                        </button>
+                        { for speeds }
+                        <input
+                            type="range",
+                            class="custom-range",
+                            min="0",
+                            max=replay.duration_secs().to_string(),
+                            value=replay.cursor_secs().to_string(),
+                            oninput=|e| Msg::ReplaySeek(e.value),
+                        />
+                        <p>{ format!("{} ({} recorded locations)", format_timestamp(&replay.cursor), replay.frames.len()) }</p>
+                    </div>
+                }
+            },
+            None => html! {},
+        };
+
+        html! {
+            <div>
+                <table>
+                    <tr>
+                        <td class="formLabel">{ "From:" }</td>
+                        <td>
+                            <input
+                                type="datetime-local",
+                                value=&self.replay_from,
+                                oninput=|e| Msg::InputReplayFrom(e.value),
+                            />
+                        </td>
+                        <td class="formLabel">{ "To:" }</td>
+                        <td>
+                            <input
+                                type="datetime-local",
+                                value=&self.replay_to,
+                                oninput=|e| Msg::InputReplayTo(e.value),
+                            />
+                        </td>
+                        <td>
+                            <button
+                                class="btn btn-sm btn-success mx-1",
+                                onclick=|_| Msg::RequestReplay,
+                            >
+                                { "Load" }
+                            </button>
+                        </td>
+                    </tr>
+                </table>
+                { controls }
+            </div>
+        }
+    }
+}
+
 impl Renderable<MapViewComponent> for MapViewComponent {
     fn view(&self) -> Html<Self> {
         let current_map_id = match &self.current_map {
@@ -369,6 +822,17 @@ impl Renderable<MapViewComponent> for MapViewComponent {
             }
         });
 
+        let mut zone_events = self.zone_events.iter().map(|event| {
+            html! {
+                <tr>
+                    <td>{ format_timestamp(&event.timestamp) }</td>
+                    <td>{ &event.user_name }</td>
+                    <td>{ event.kind.to_string() }</td>
+                    <td>{ format!("{} ({})", event.zone_name, event.zone_kind) }</td>
+                </tr>
+            }
+        });
+
         html! {
             <>
                 { self.user_msg.view() }
@@ -390,10 +854,26 @@ impl Renderable<MapViewComponent> for MapViewComponent {
                                 />
                                 <label class="checkmark m-1" for="grid1">{ "Show Gridlines" }</label>
                             </div>
+                            <div class="form-check">
+                                <input
+                                    type="checkbox",
+                                    class="form-check-input",
+                                    id="replay1",
+                                    checked = self.replay_mode,
+                                    value=&self.replay_mode,
+                                    onclick=|_| Msg::ToggleReplay,
+                                />
+                                <label class="checkmark m-1" for="replay1">{ "Replay" }</label>
+                            </div>
                         </div>
+                        { self.render_replay() }
                         { VNode::VRef(Node::from(self.legend_canvas.canvas.to_owned()).to_owned()) }
                         { VNode::VRef(Node::from(self.canvas.canvas.to_owned()).to_owned()) }
                         <div class="tinyBoxForm align-top">
+                            <MotionAlerts
+                                alerts={self.motion_alerts.clone()},
+                                on_change=|alert| Msg::MotionAlertChanged(alert),
+                            />
                             <h4>{"User Status"}</h4>
                             <table class="table table-sm table-striped">
                                 <tr>
@@ -411,6 +891,16 @@ impl Renderable<MapViewComponent> for MapViewComponent {
                                 </tr>
                                 { for realtime_users }
                             </table>
+                            <h4>{"Zone Events"}</h4>
+                            <table class="table table-sm table-striped">
+                                <tr>
+                                    <th>{"Time"}</th>
+                                    <th>{"Name"}</th>
+                                    <th>{"Event"}</th>
+                                    <th>{"Zone"}</th>
+                                </tr>
+                                { for zone_events }
+                            </table>
                         </div>
                     </div>
                 </div>
@@ -1,12 +1,16 @@
 
 pub mod beacon_addupdate;
+pub mod beacon_discovery;
 pub mod beacon_list;
 pub mod diagnostics;
 pub mod emergency_buttons;
+pub mod emergency_history;
 pub mod login;
 pub mod map_addupdate;
 pub mod map_list;
 pub mod map_view;
+pub mod motion_alerts;
+pub mod muster;
 pub mod root;
 pub mod status;
 pub mod system_settings;
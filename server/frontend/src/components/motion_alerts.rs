@@ -0,0 +1,142 @@
+use common::*;
+use common::motion::MotionAlert;
+use crate::util::*;
+use stdweb::web::Date;
+use super::user_message::UserMessage;
+use super::value_button::DisplayButton;
+use yew::prelude::*;
+use yew::services::fetch::{ FetchService, FetchTask, };
+
+pub enum Msg {
+    RequestAcknowledge(u64),
+
+    ResponseAcknowledge(JsonResponse<MotionAlert>),
+}
+
+// users reported for not moving or for losing their tag, shared by the status and map pages
+pub struct MotionAlerts {
+    alerts: Vec<MotionAlert>,
+    fetch_service: FetchService,
+    fetch_task: Option<FetchTask>,
+    on_change: Callback<MotionAlert>,
+    self_link: ComponentLink<Self>,
+    user_msg: UserMessage<Self>,
+}
+
+impl JsonResponseHandler for MotionAlerts {}
+
+#[derive(Properties)]
+pub struct MotionAlertsProps {
+    pub alerts: Vec<MotionAlert>,
+    // an acknowledged alert, in case it was not pushed
+    #[props(required)]
+    pub on_change: Callback<MotionAlert>,
+}
+
+impl Component for MotionAlerts {
+    type Message = Msg;
+    type Properties = MotionAlertsProps;
+
+    fn create(props: Self::Properties, link: ComponentLink<Self>) -> Self {
+        MotionAlerts {
+            alerts: props.alerts,
+            fetch_service: FetchService::new(),
+            fetch_task: None,
+            on_change: props.on_change,
+            self_link: link,
+            user_msg: UserMessage::new(),
+        }
+    }
+
+    fn update(&mut self, msg: Self::Message) -> ShouldRender {
+        match msg {
+            Msg::RequestAcknowledge(id) => {
+                self.user_msg.reset();
+                self.fetch_task = post_request!(
+                    self.fetch_service,
+                    &motion_alert_acknowledge_url(&id.to_string()),
+                    (),
+                    self.self_link,
+                    Msg::ResponseAcknowledge
+                );
+            },
+            Msg::ResponseAcknowledge(response) => {
+                self.handle_response(
+                    response,
+                    |s, alert| {
+                        s.on_change.emit(alert);
+                    },
+                    |s, e| {
+                        s.user_msg.error_messages.push(format!("failed to acknowledge alert, reason: {}", e));
+                    },
+                );
+            },
+        }
+        true
+    }
+
+    fn change(&mut self, props: Self::Properties) -> ShouldRender {
+        self.alerts = props.alerts;
+        self.on_change = props.on_change;
+        true
+    }
+}
+
+impl Renderable<MotionAlerts> for MotionAlerts {
+    fn view(&self) -> Html<Self> {
+        if self.alerts.is_empty() {
+            return html! { <>{ self.user_msg.view() }</> };
+        }
+
+        // chrono cannot read the clock in the browser
+        let now = Utc.timestamp_millis(Date::now() as i64);
+        let mut rows = self.alerts.iter().map(|alert| {
+            let state = match (&alert.cleared_at, &alert.acknowledged_by) {
+                (None, None) => "Ongoing".to_string(),
+                (Some(_), None) => "Cleared".to_string(),
+                (_, Some(account)) => format!("Acknowledged by {}", account),
+            };
+            let acknowledge = match alert.acknowledged_at {
+                Some(_) => html! {},
+                None => html! {
+                    <DisplayButton<u64>
+                        display="Acknowledge".to_owned(),
+                        on_click=|id| Msg::RequestAcknowledge(id),
+                        border=false,
+                        value=alert.id,
+                        icon="fa fa-check",
+                        style="btn btn-sm btn-danger",
+                    />
+                },
+            };
+            html! {
+                <tr class={ if alert.acknowledged_at.is_none() { "table-danger" } else { "" } },>
+                    <td>{ &alert.user_name }</td>
+                    <td>{ alert.kind.to_string() }</td>
+                    <td>{ format!("{:.1},{:.1}", alert.coordinates.x, alert.coordinates.y) }</td>
+                    <td>{ format_duration(now - alert.last_active) }</td>
+                    <td>{ state }</td>
+                    <td>{ acknowledge }</td>
+                </tr>
+            }
+        });
+
+        html! {
+            <>
+                { self.user_msg.view() }
+                <h4>{ format!("Motion Alerts ({})", self.alerts.len()) }</h4>
+                <table class="table table-sm">
+                    <tr>
+                        <th>{ "Name" }</th>
+                        <th>{ "Alert" }</th>
+                        <th>{ "Location" }</th>
+                        <th>{ "Last Seen" }</th>
+                        <th>{ "State" }</th>
+                        <th>{ "" }</th>
+                    </tr>
+                    { for rows }
+                </table>
+            </>
+        }
+    }
+}
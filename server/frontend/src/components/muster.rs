@@ -0,0 +1,224 @@
+use common::*;
+use common::muster::{ MusterReport, MusterStatus, };
+use crate::push::{ PushChannel, PushResponse, };
+use crate::util::*;
+use std::time::Duration;
+use super::user_message::UserMessage;
+use yew::format::Json;
+use yew::prelude::*;
+use yew::services::fetch::{ FetchService, FetchTask, };
+use yew::services::interval::{ IntervalService, IntervalTask, };
+use yew::services::websocket::WebSocketStatus;
+
+// the report is refetched at most this often, and only when something was pushed since, unless
+// the socket is down
+const MUSTER_REFRESH_RATE: Duration = Duration::from_millis(1000);
+
+pub enum Msg {
+    PushConnect,
+    PushReceived(PushResponse),
+    PushStatus(WebSocketStatus),
+
+    Refresh,
+    RequestGetReport,
+
+    ResponseGetReport(JsonResponse<MusterReport>),
+}
+
+pub struct Muster {
+    fetch_service: FetchService,
+    fetch_task: Option<FetchTask>,
+    interval_service: IntervalService,
+    interval_service_task: Option<IntervalTask>,
+    push: PushChannel,
+    report: Option<MusterReport>,
+    self_link: ComponentLink<Self>,
+    // a user moved, or the emergency changed, since the report was fetched
+    stale: bool,
+    user_msg: UserMessage<Self>,
+}
+
+impl JsonResponseHandler for Muster {}
+
+#[derive(Properties)]
+pub struct MusterProps {
+    pub emergency: bool,
+}
+
+fn status_class(status: MusterStatus) -> &'static str {
+    match status {
+        MusterStatus::Missing => "table-danger",
+        MusterStatus::NeverSeen => "table-warning",
+        MusterStatus::Mustered => "table-success",
+    }
+}
+
+impl Component for Muster {
+    type Message = Msg;
+    type Properties = MusterProps;
+
+    fn create(_props: Self::Properties, mut link: ComponentLink<Self>) -> Self {
+        link.send_self(Msg::RequestGetReport);
+        link.send_self(Msg::PushConnect);
+        let mut interval_service = IntervalService::new();
+        let interval_service_task = Some(
+            interval_service.spawn(MUSTER_REFRESH_RATE, link.send_back(|_| Msg::Refresh))
+        );
+        Muster {
+            fetch_service: FetchService::new(),
+            fetch_task: None,
+            interval_service,
+            interval_service_task,
+            push: PushChannel::new(PushSubscription::new()),
+            report: None,
+            self_link: link,
+            stale: false,
+            user_msg: UserMessage::new(),
+        }
+    }
+
+    fn update(&mut self, msg: Self::Message) -> ShouldRender {
+        match msg {
+            Msg::PushConnect => {
+                self.push.connect(
+                    self.self_link.send_back(Msg::PushReceived),
+                    self.self_link.send_back(Msg::PushStatus),
+                );
+                return false;
+            },
+            Msg::PushStatus(status) => {
+                if self.push.status(status, self.self_link.send_back(|_| Msg::PushConnect)) {
+                    // users may have moved while the socket was down
+                    self.stale = true;
+                }
+                return false;
+            },
+            Msg::PushReceived(Json(response)) => {
+                match response {
+                    Ok(PushMessage::UserMoved(_)) | Ok(PushMessage::ZoneEvent(_)) | Ok(PushMessage::Emergency(_)) => {
+                        self.stale = true;
+                    },
+                    Ok(_) => {},
+                    Err(e) => {
+                        Log!("failed to parse push message, {}", e);
+                    },
+                }
+                return false;
+            },
+            Msg::Refresh => {
+                if self.stale || !self.push.is_connected() {
+                    self.self_link.send_self(Msg::RequestGetReport);
+                }
+                return false;
+            },
+            Msg::RequestGetReport => {
+                self.stale = false;
+                self.fetch_task = get_request!(
+                    self.fetch_service,
+                    &emergency_muster_url(),
+                    self.self_link,
+                    Msg::ResponseGetReport
+                );
+            },
+            Msg::ResponseGetReport(response) => {
+                self.handle_response(
+                    response,
+                    |s, report| {
+                        s.user_msg.reset();
+                        s.report = Some(report);
+                    },
+                    |s, e| {
+                        s.user_msg.reset();
+                        s.user_msg.error_messages.push(format!("failed to obtain muster report, reason: {}", e));
+                    },
+                );
+            },
+        }
+        true
+    }
+
+    fn change(&mut self, _props: Self::Properties) -> ShouldRender {
+        self.self_link.send_self(Msg::RequestGetReport);
+        true
+    }
+}
+
+impl Renderable<Muster> for Muster {
+    fn view(&self) -> Html<Self> {
+        let report = match &self.report {
+            Some(report) => report,
+            None => return html! { <>{ self.user_msg.view() }</> },
+        };
+
+        let mut rows = report.entries.iter().map(|entry| {
+            let location = match (&entry.map_name, entry.coordinates) {
+                (Some(map_name), Some(point)) => format!("{} ({:.1}, {:.1})", map_name, point.x, point.y),
+                _ => String::new(),
+            };
+            html! {
+                <tr class=status_class(entry.status),>
+                    <td>{ &entry.name }</td>
+                    <td>{ entry.employee_id.clone().unwrap_or(String::new()) }</td>
+                    <td>{ entry.status.to_string() }</td>
+                    <td>{ location }</td>
+                    <td>{ entry.last_seen.map_or(String::new(), |stamp| format_duration(report.generated_at - stamp)) }</td>
+                    <td>{ entry.muster_point.clone().unwrap_or(String::new()) }</td>
+                </tr>
+            }
+        });
+
+        let title = match (report.emergency.active, report.emergency.started_at) {
+            (true, Some(started_at)) => format!("Muster Report, emergency since {}", format_timestamp(&started_at)),
+            _ => "Muster Report".to_string(),
+        };
+
+        html! {
+            <>
+                { self.user_msg.view() }
+                <div class="content-wrapper">
+                    <div class="boxedForm">
+                        <div class="d-flex justify-content-between">
+                            <h2>{ title }</h2>
+                            <a
+                                class="btn btn-primary my-1",
+                                href=emergency_muster_export_url(),
+                                download="",
+                            >
+                                { "Export" }
+                            </a>
+                        </div>
+                        <table>
+                            <tr>
+                                <td class="formLabel">{ "Mustered: " }</td>
+                                <td>{ report.mustered }</td>
+                            </tr>
+                            <tr>
+                                <td class="formLabel">{ "Last Seen Inside: " }</td>
+                                <td>{ report.missing }</td>
+                            </tr>
+                            <tr>
+                                <td class="formLabel">{ "Never Seen: " }</td>
+                                <td>{ report.never_seen }</td>
+                            </tr>
+                        </table>
+                        <table class="table">
+                            <thead>
+                                <tr>
+                                    <th>{ "Name" }</th>
+                                    <th>{ "Employee ID" }</th>
+                                    <th>{ "Status" }</th>
+                                    <th>{ "Location" }</th>
+                                    <th>{ "Last Seen" }</th>
+                                    <th>{ "Muster Point" }</th>
+                                </tr>
+                            </thead>
+                            <tbody>
+                                { for rows }
+                            </tbody>
+                        </table>
+                    </div>
+                </div>
+            </>
+        }
+    }
+}
@@ -1,33 +1,42 @@
 use common::*;
+use crate::push::{ PushChannel, PushResponse, };
 use crate::util::*;
 use std::time::Duration;
 use super::beacon_addupdate::BeaconAddUpdate;
+use super::beacon_discovery::BeaconDiscovery;
 use super::beacon_list::BeaconList;
 use super::diagnostics::Diagnostics;
-use super::emergency_buttons::EmergencyButtons;
+use super::emergency_buttons::{ EmergencyButtons, EmergencyRequest, };
+use super::emergency_history::EmergencyHistory;
 use super::login::{ self, Login, };
 use super::map_addupdate::MapAddUpdate;
 use super::map_list::MapList;
 use super::map_view::MapViewComponent;
+use super::muster::Muster;
 use super::status::{ self, Status, };
 use super::system_settings::SystemSettings;
 use super::user_addupdate::UserAddUpdate;
 use super::user_list::UserList;
+use yew::format::Json;
 use yew::prelude::*;
 use yew::services::fetch::{ FetchService, FetchTask, };
 use yew::services::interval::{ IntervalService, IntervalTask, };
+use yew::services::websocket::WebSocketStatus;
 
 const POLL_RATE: Duration = Duration::from_millis(2000);
 
 #[derive(PartialEq)]
 pub enum Page {
     BeaconAddUpdate(Option<i32>),
+    BeaconDiscovery,
     BeaconList,
     Diagnostics,
+    EmergencyHistory,
     Login(login::AutoAction),
     MapAddUpdate(Option<i32>),
     MapList,
     MapView(Option<i32>),
+    Muster,
     Status(status::PageState),
     SystemSettings,
     UserAddUpdate(Option<i32>),
@@ -39,12 +48,15 @@ pub struct RootComponent {
     user_type: WebUserType,
     current_page: Page,
     emergency: bool,
+    emergency_status: EmergencyStatus,
     fetch_service: FetchService,
     fetch_task: Option<FetchTask>,
     link: ComponentLink<RootComponent>,
 
     interval_service: IntervalService,
     interval_ping_task: Option<IntervalTask>,
+
+    push: PushChannel,
 }
 
 impl JsonResponseHandler for RootComponent {}
@@ -55,14 +67,18 @@ pub enum Msg {
     ChangeWebUserType(WebUserType),
 
     // requests
-    RequestPostEmergency(bool),
+    RequestPostEmergency(bool, EmergencyRequest),
     RequestGetEmergency,
     RequestGetPing,
 
     // responses
     ResponsePostEmergency(JsonResponse<bool>),
-    ResponseGetEmergency(JsonResponse<bool>),
+    ResponseGetEmergency(JsonResponse<EmergencyStatus>),
     ResponseGetPing(JsonResponse<()>),
+
+    PushConnect,
+    PushReceived(PushResponse),
+    PushStatus(WebSocketStatus),
 }
 
 impl Component for RootComponent {
@@ -71,14 +87,21 @@ impl Component for RootComponent {
 
     fn create(_: Self::Properties, mut link: ComponentLink<Self>) -> Self {
         link.send_self(Msg::RequestGetEmergency);
+        link.send_self(Msg::PushConnect);
         let root = RootComponent {
             current_page: Page::Login(login::AutoAction::Login),
             emergency: false,
+            emergency_status: EmergencyStatus::new(),
             fetch_service: FetchService::new(),
             fetch_task: None,
             interval_ping_task: None,
             interval_service: IntervalService::new(),
             link: link,
+            // only the emergency state is needed here
+            push: PushChannel::new(PushSubscription {
+                diagnostics: false,
+                map_ids: Some(Vec::new()),
+            }),
             user_type: WebUserType::Responder,
         };
         root
@@ -103,11 +126,14 @@ impl Component for RootComponent {
             },
 
             // requests
-            Msg::RequestPostEmergency(is_emergency) => {
+            Msg::RequestPostEmergency(is_emergency, request) => {
+                let mut command = SystemCommandResponse::new(is_emergency);
+                command.reason = request.reason;
+                command.map_ids = request.map_ids;
                 self.fetch_task = post_request!(
                     self.fetch_service,
                     &system_emergency_url(),
-                    SystemCommandResponse::new(is_emergency),
+                    command,
                     self.link,
                     Msg::ResponsePostEmergency
                 );
@@ -115,7 +141,7 @@ impl Component for RootComponent {
             Msg::RequestGetEmergency => {
                 self.fetch_task = get_request!(
                     self.fetch_service,
-                    &system_emergency_url(),
+                    &system_emergency_status_url(),
                     self.link,
                     Msg::ResponseGetEmergency
                 );
@@ -128,6 +154,34 @@ impl Component for RootComponent {
                     Msg::ResponseGetPing
                 );
             },
+            Msg::PushConnect => {
+                self.push.connect(
+                    self.link.send_back(Msg::PushReceived),
+                    self.link.send_back(Msg::PushStatus),
+                );
+            },
+            Msg::PushStatus(status) => {
+                if self.push.status(status, self.link.send_back(|_| Msg::PushConnect)) {
+                    // the emergency may have changed while the socket was down
+                    self.link.send_self(Msg::RequestGetEmergency);
+                }
+                return false;
+            },
+            Msg::PushReceived(Json(response)) => {
+                match response {
+                    Ok(PushMessage::Emergency(status)) => {
+                        self.emergency = status.active;
+                        self.emergency_status = status;
+                    },
+                    Ok(_) => {
+                        return false;
+                    },
+                    Err(e) => {
+                        Log!("failed to parse push message, {}", e);
+                        return false;
+                    },
+                }
+            },
             // responses
             Msg::ResponsePostEmergency(response) => {
                 self.handle_response(
@@ -139,12 +193,15 @@ impl Component for RootComponent {
                         Log!("response - failed to post start emergency, {}", e);
                     },
                 );
+                // the scope may have changed even when the emergency did not start or end
+                self.link.send_self(Msg::RequestGetEmergency);
             },
             Msg::ResponseGetEmergency(response) => {
                 self.handle_response(
                     response,
-                    |s, resp| {
-                        s.emergency = resp;
+                    |s, status| {
+                        s.emergency = status.active;
+                        s.emergency_status = status;
                     },
                     |_s, e| {
                         Log!("response - failed to request emergency status, {}", e);
@@ -178,9 +235,9 @@ impl Renderable<RootComponent> for RootComponent {
                         { self.navigation() }
                         <div class="container-fluid">
                             <EmergencyButtons
-                                is_emergency={self.emergency},
-                                on_emergency=|_| Msg::RequestPostEmergency(true),
-                                on_end_emergency=|_| Msg::RequestPostEmergency(false),
+                                status=self.emergency_status.clone(),
+                                on_emergency=|request| Msg::RequestPostEmergency(true, request),
+                                on_end_emergency=|request| Msg::RequestPostEmergency(false, request),
                             />
                             <Diagnostics
                                 emergency={self.emergency}
@@ -195,9 +252,9 @@ impl Renderable<RootComponent> for RootComponent {
                         { self.navigation() }
                         <div class="container-fluid">
                             <EmergencyButtons
-                                is_emergency={self.emergency},
-                                on_emergency=|_| Msg::RequestPostEmergency(true),
-                                on_end_emergency=|_| Msg::RequestPostEmergency(false),
+                                status=self.emergency_status.clone(),
+                                on_emergency=|request| Msg::RequestPostEmergency(true, request),
+                                on_end_emergency=|request| Msg::RequestPostEmergency(false, request),
                             />
                             <Status
                                 change_page=|page| Msg::ChangePage(page),
@@ -208,6 +265,30 @@ impl Renderable<RootComponent> for RootComponent {
                     </div>
                 }
             },
+            Page::EmergencyHistory => {
+                html! {
+                    <div>
+                        { self.navigation() }
+                        <div class="container-fluid">
+                            <EmergencyHistory
+                                emergency={self.emergency},
+                            />
+                        </div>
+                    </div>
+                }
+            },
+            Page::Muster => {
+                html! {
+                    <div>
+                        { self.navigation() }
+                        <div class="container-fluid">
+                            <Muster
+                                emergency={self.emergency},
+                            />
+                        </div>
+                    </div>
+                }
+            },
             Page::Login(auto_action) => {
                 html! {
                     <div>
@@ -225,9 +306,9 @@ impl Renderable<RootComponent> for RootComponent {
                         { self.navigation() }
                         <div class="container-fluid">
                             <EmergencyButtons
-                                is_emergency={self.emergency},
-                                on_emergency=|_| Msg::RequestPostEmergency(true),
-                                on_end_emergency=|_| Msg::RequestPostEmergency(false),
+                                status=self.emergency_status.clone(),
+                                on_emergency=|request| Msg::RequestPostEmergency(true, request),
+                                on_end_emergency=|request| Msg::RequestPostEmergency(false, request),
                             />
                         </div>
                         <div class="container-fluid">
@@ -252,6 +333,18 @@ impl Renderable<RootComponent> for RootComponent {
                     </div>
                 }
             },
+            Page::BeaconDiscovery => {
+               html! {
+                    <div>
+                        { self.navigation() }
+                        <div class="container-fluid">
+                            <BeaconDiscovery
+                                change_page=|page| Msg::ChangePage(page),
+                            />
+                        </div>
+                    </div>
+                }
+            },
             Page::BeaconAddUpdate(id) => {
                html! {
                     <div>
@@ -377,6 +470,8 @@ impl RootComponent {
                 <a
                     class = match self.current_page {
                         Page::Status {..} => {"nav-link navBarText active"},
+                        Page::EmergencyHistory => {"nav-link navBarText active"},
+                        Page::Muster => {"nav-link navBarText active"},
                         _ => {"nav-link navBarText"},
                     }
                     id="navbarDropdown",
@@ -410,6 +505,20 @@ impl RootComponent {
                     >
                         { "Beacon Status" }
                     </a>
+                    <a
+                        class="dropdown-item navBarText",
+                        onclick=|_| Msg::ChangePage(Page::EmergencyHistory),
+                        disabled={self.current_page == Page::EmergencyHistory},
+                    >
+                        { "Emergency History" }
+                    </a>
+                    <a
+                        class="dropdown-item navBarText",
+                        onclick=|_| Msg::ChangePage(Page::Muster),
+                        disabled={self.current_page == Page::Muster},
+                    >
+                        { "Muster Report" }
+                    </a>
                 </div>
             </>
         };
@@ -462,6 +571,7 @@ impl RootComponent {
                         class = match self.current_page {
                             Page::BeaconList => {"nav-link dropdown navBarText active"},
                             Page::BeaconAddUpdate{..} => {"nav-link dropdown navBarText active"},
+                            Page::BeaconDiscovery => {"nav-link dropdown navBarText active"},
                             _ => {"nav-link dropdown navBarText"},
                         }
                         role="button",
@@ -491,6 +601,13 @@ impl RootComponent {
                             >
                                 { "Add Beacon" }
                             </a>
+                            <a
+                                class="dropdown-item navBarText",
+                                onclick=|_| Msg::ChangePage(Page::BeaconDiscovery),
+                                disabled={self.current_page == Page::BeaconDiscovery},
+                            >
+                                { "Discovered Beacons" }
+                            </a>
                     </div>
                 </>
             },
@@ -595,8 +712,8 @@ impl RootComponent {
             WebUserType::Responder => html! {
                 <>
                     <button
-                        class="btn btn-success btn-sm nav-link logoutPlacement ml-auto",
-                        onclick=|_| Msg::ChangePage(Page::Login(login::AutoAction::Nothing)),
+                        class="btn btn-danger btn-sm nav-link logoutPlacement ml-auto",
+                        onclick=|_| Msg::ChangePage(Page::Login(login::AutoAction::Logout)),
                         disabled={
                             match self.current_page {
                                 Page::Login{..} => true,
@@ -604,9 +721,9 @@ impl RootComponent {
                             }
                         },
                     >
-                        <i class="fa fa-sign-in" aria-hidden="true"></i>
+                        { "Logout" }
                         { space }
-                        { "Login" }
+                        <i class="fa fa-sign-out" aria-hidden="true"></i>
                     </button>
                     <a class="loginTypeHeader">{ "FIRST RESPONDER" }</a>
                 </>
@@ -1,13 +1,17 @@
 use common::*;
+use common::motion::{ self, MotionAlert, };
+use crate::push::{ PushChannel, PushResponse, };
 use crate::util::*;
 use std::collections::HashMap;
 use std::time::Duration;
+use super::motion_alerts::MotionAlerts;
 use super::root;
 use super::value_button::{ ValueButton, DisplayButton, };
 use yew::format::Json;
 use yew::prelude::*;
 use yew::services::fetch::{ FetchService, FetchTask, };
 use yew::services::interval::{ IntervalTask, IntervalService, };
+use yew::services::websocket::WebSocketStatus;
 use super::user_message::UserMessage;
 
 const POLL_RATE: Duration = Duration::from_millis(1000);
@@ -21,12 +25,18 @@ pub enum PageState {
 pub enum Msg {
     ChangeRootPage(root::Page),
     ChangeStatus(PageState),
+    MotionAlertChanged(MotionAlert),
+
+    PushConnect,
+    PushReceived(PushResponse),
+    PushStatus(WebSocketStatus),
 
     RequestCommandBeacon(BeaconRequest),
     RequestGetBeacons,
     RequestGetBeaconsStatus,
     RequestGetMap(i32),
     RequestGetMaps,
+    RequestGetMotionAlerts,
     RequestGetUser(i32),
     RequestGetUsers,
     RequestGetUsersStatus,
@@ -36,6 +46,7 @@ pub enum Msg {
     ResponseGetBeaconsStatus(JsonResponse<Vec<RealtimeBeacon>>),
     ResponseGetMap(JsonResponse<Map>),
     ResponseGetMaps(JsonResponse<Vec<Map>>),
+    ResponseGetMotionAlerts(JsonResponse<Vec<MotionAlert>>),
     ResponseGetUser(JsonResponse<TrackedUser>),
     ResponseGetUsers(JsonResponse<Vec<TrackedUser>>),
     ResponseGetUsersStatus(JsonResponse<Vec<RealtimeUserData>>),
@@ -48,6 +59,8 @@ pub struct Status {
     interval_service: IntervalService,
     interval_service_task: Option<IntervalTask>,
     maps: HashMap<i32, Map>,
+    motion_alerts: Vec<MotionAlert>,
+    push: PushChannel,
     self_link: ComponentLink<Self>,
     state: PageState,
     user_msg: UserMessage<Self>,
@@ -63,12 +76,19 @@ pub struct Status {
     fetch_user_status: Option<FetchTask>,
     fetch_maps: Option<FetchTask>,
     fetch_map: Option<FetchTask>,
+    fetch_motion_alerts: Option<FetchTask>,
 }
 
 impl JsonResponseHandler for Status {}
 
 impl Status {
     fn restart_service(&mut self) {
+        // realtime data is pushed while the socket is up
+        if self.push.is_connected() {
+            self.interval_service_task = None;
+            return;
+        }
+
         let callback = match self.state {
             PageState::UserStatus => self.self_link.send_back(|_| Msg::RequestGetUsersStatus),
             PageState::BeaconStatus => self.self_link.send_back(|_| Msg::RequestGetBeaconsStatus),
@@ -95,6 +115,8 @@ impl Component for Status {
         link.send_self(Msg::RequestGetBeacons);
         link.send_self(Msg::RequestGetUsers);
         link.send_self(Msg::RequestGetMaps);
+        link.send_self(Msg::RequestGetMotionAlerts);
+        link.send_self(Msg::PushConnect);
         let mut result = Status {
             beacons: HashMap::new(),
             change_page: props.change_page,
@@ -102,6 +124,8 @@ impl Component for Status {
             interval_service: IntervalService::new(),
             interval_service_task: None,
             maps: HashMap::new(),
+            motion_alerts: Vec::new(),
+            push: PushChannel::new(PushSubscription::new()),
             self_link: link,
             state: props.state,
             user_msg: UserMessage::new(),
@@ -113,6 +137,7 @@ impl Component for Status {
             fetch_beacons_status: None,
             fetch_map: None,
             fetch_maps: None,
+            fetch_motion_alerts: None,
             fetch_user: None,
             fetch_user_status: None,
             fetch_users: None,
@@ -131,6 +156,47 @@ impl Component for Status {
             Msg::ChangeRootPage(page) => {
                 self.change_page.emit(page);
             }
+            Msg::MotionAlertChanged(alert) => {
+                motion::merge_alert(&mut self.motion_alerts, alert);
+            }
+            Msg::PushConnect => {
+                self.push.connect(
+                    self.self_link.send_back(Msg::PushReceived),
+                    self.self_link.send_back(Msg::PushStatus),
+                );
+            },
+            Msg::PushStatus(status) => {
+                if self.push.status(status, self.self_link.send_back(|_| Msg::PushConnect)) {
+                    // alerts may have changed while the socket was down
+                    self.self_link.send_self(Msg::RequestGetMotionAlerts);
+                }
+                self.restart_service();
+            },
+            Msg::PushReceived(Json(response)) => {
+                match response {
+                    Ok(PushMessage::UserMoved(ru)) => {
+                        // users that have not been fetched yet are dropped, like the polled data
+                        if let Some(u) = self.users.get_mut(&ru.id) {
+                            u.merge(ru);
+                        }
+                    },
+                    Ok(PushMessage::MotionAlert(alert)) => {
+                        motion::merge_alert(&mut self.motion_alerts, alert);
+                    },
+                    Ok(PushMessage::BeaconChanged(rb)) => {
+                        if let Some(b) = self.beacons.get_mut(&rb.id) {
+                            b.merge(rb);
+                        }
+                    },
+                    Ok(_) => {
+                        return false;
+                    },
+                    Err(e) => {
+                        Log!("failed to parse push message, {}", e);
+                        return false;
+                    },
+                }
+            },
             Msg::RequestGetMaps => {
                 self.user_msg.reset();
                 self.fetch_maps = get_request!(
@@ -140,6 +206,14 @@ impl Component for Status {
                     Msg::ResponseGetMaps
                 );
             },
+            Msg::RequestGetMotionAlerts => {
+                self.fetch_motion_alerts = get_request!(
+                    self.fetch_service,
+                    &motion_alerts_url(),
+                    self.self_link,
+                    Msg::ResponseGetMotionAlerts
+                );
+            },
             Msg::RequestGetMap(id) => {
                 self.user_msg.reset();
                 self.fetch_map = get_request!(
@@ -217,6 +291,17 @@ impl Component for Status {
                     },
                 );
             },
+            Msg::ResponseGetMotionAlerts(response) => {
+                self.handle_response(
+                    response,
+                    |s, alerts| {
+                        s.motion_alerts = alerts;
+                    },
+                    |s, e| {
+                        s.user_msg.error_messages.push(format!("failed to obtain motion alerts, reason: {}", e));
+                    },
+                );
+            },
             Msg::ResponseGetMap(response) => {
                 self.handle_response(
                     response,
@@ -515,6 +600,10 @@ impl Status {
                 <div class="content-wrapper">
                     <div class="boxedForm">
                         <h2>{ "User Status" }</h2>
+                        <MotionAlerts
+                            alerts={self.motion_alerts.clone()},
+                            on_change=|alert| Msg::MotionAlertChanged(alert),
+                        />
                         <table class="table table-striped">
                             <thead>
                                 <tr>
@@ -2,6 +2,7 @@ use common::*;
 use crate::util::*;
 use std::net::Ipv4Addr;
 use stdweb::web;
+use super::login;
 use super::root;
 use super::user_message::UserMessage;
 use super::value_button::DisplayButton;
@@ -14,10 +15,14 @@ pub enum Msg {
     ChangeRootPage(root::Page),
     InputIp(String),
 
+    RequestLogoutAll,
     RequestRestart(SystemCommand),
+    RequestRotateKey,
     RequestSetIp,
 
+    ResponseLogoutAll(JsonResponse<u64>),
     ResponseRestart(JsonResponse<()>),
+    ResponseRotateKey(JsonResponse<()>),
     ResponseSetIp(JsonResponse<()>),
 }
 
@@ -86,6 +91,28 @@ impl Component for SystemSettings {
                     );
                 }
             },
+            Msg::RequestLogoutAll => {
+                if web::window().confirm("Are you sure you wish to log out every account, including your own?") {
+                    self.fetch_task = post_request! (
+                        self.fetch_service,
+                        &system_sessions_logout_url(),
+                        (),
+                        self.self_link,
+                        Msg::ResponseLogoutAll
+                    );
+                }
+            },
+            Msg::RequestRotateKey => {
+                if web::window().confirm("Are you sure you wish to replace the session key? Every account will be logged out and the server will restart.") {
+                    self.fetch_task = post_request! (
+                        self.fetch_service,
+                        &system_session_key_url(),
+                        (),
+                        self.self_link,
+                        Msg::ResponseRotateKey
+                    );
+                }
+            },
             Msg::InputIp(ip) => {
                 self.ip_raw = ip;
             }
@@ -118,6 +145,28 @@ impl Component for SystemSettings {
                     self.user_msg.error_messages.push("failed to send restart command".to_string());
                 }
             },
+            Msg::ResponseLogoutAll(response) => {
+                self.handle_response(
+                    response,
+                    |s, _count| {
+                        s.self_link.send_self(Msg::ChangeRootPage(root::Page::Login(login::AutoAction::Login)));
+                    },
+                    |s, e| {
+                        s.user_msg.error_messages.push(format!("failed to log out sessions, reason: {}", e));
+                    },
+                );
+            },
+            Msg::ResponseRotateKey(response) => {
+                self.handle_response(
+                    response,
+                    |s, _| {
+                        s.self_link.send_self(Msg::ChangeRootPage(root::Page::Restarting));
+                    },
+                    |s, e| {
+                        s.user_msg.error_messages.push(format!("failed to rotate the session key, reason: {}", e));
+                    },
+                );
+            },
             Msg::ResponseSetIp(response) => {
                 self.handle_response(
                     response,
@@ -174,6 +223,23 @@ impl Renderable<SystemSettings> for SystemSettings {
                             />
                         </div>
 
+                        <div class="d-flex">
+                            <DisplayButton<()>
+                                value=(),
+                                style="btn btn-lg btn-info mr-3 my-auto",
+                                on_click=|_| Msg::RequestLogoutAll,
+                                icon="fa fa-sign-out",
+                                display="Log Out All Sessions",
+                            />
+                            <DisplayButton<()>
+                                value=(),
+                                style="btn btn-lg btn-info ml-3 my-auto",
+                                on_click=|_| Msg::RequestRotateKey,
+                                icon="fa fa-key",
+                                display="Rotate Session Key",
+                            />
+                        </div>
+
                         <div class="d-flex justify-content-start">
                             <DisplayButton<()>
                                 value=(),
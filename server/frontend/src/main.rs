@@ -18,6 +18,7 @@ extern crate failure;
 mod util;
 mod canvas;
 mod components;
+mod push;
 
 use components::root::RootComponent;
 
@@ -0,0 +1,86 @@
+// Connection to the server's push channel. Pages subscribe to the changes they display and only
+// poll the REST endpoints while the socket is down.
+
+use common::*;
+use failure::Fallible;
+use std::time::Duration;
+use yew::callback::Callback;
+use yew::format::Json;
+use yew::services::timeout::{ TimeoutService, TimeoutTask, };
+use yew::services::websocket::{ WebSocketService, WebSocketStatus, WebSocketTask, };
+
+// how long to wait before trying to reconnect a dropped socket
+const RECONNECT_INTERVAL: Duration = Duration::from_millis(5000);
+
+pub type PushResponse = Json<Fallible<PushMessage>>;
+
+fn websocket_url(path: &str) -> String {
+    let base: String = js! {
+        return (location.protocol === "https:" ? "wss://" : "ws://") + location.host;
+    }.into_string().unwrap_or_default();
+    format!("{}{}", base, path)
+}
+
+pub struct PushChannel {
+    connected: bool,
+    reconnect_service: TimeoutService,
+    reconnect_task: Option<TimeoutTask>,
+    service: WebSocketService,
+    subscription: PushSubscription,
+    task: Option<WebSocketTask>,
+}
+
+impl PushChannel {
+    pub fn new(subscription: PushSubscription) -> PushChannel {
+        PushChannel {
+            connected: false,
+            reconnect_service: TimeoutService::new(),
+            reconnect_task: None,
+            service: WebSocketService::new(),
+            subscription,
+            task: None,
+        }
+    }
+
+    pub fn connect(&mut self, messages: Callback<PushResponse>, status: Callback<WebSocketStatus>) {
+        self.connected = false;
+        self.reconnect_task = None;
+        self.task = Some(self.service.connect(&websocket_url(&push_url()), messages, status));
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.connected
+    }
+
+    // must be given every status the socket reports. returns true when the socket opened, otherwise
+    // reconnect is called after a while, and should connect again.
+    pub fn status(&mut self, status: WebSocketStatus, reconnect: Callback<()>) -> bool {
+        match status {
+            WebSocketStatus::Opened => {
+                self.connected = true;
+                self.send_subscription();
+                true
+            },
+            _ => {
+                // the socket is refused until the user logs in, so keep retrying
+                self.connected = false;
+                self.task = None;
+                self.reconnect_task = Some(self.reconnect_service.spawn(RECONNECT_INTERVAL, reconnect));
+                false
+            },
+        }
+    }
+
+    pub fn subscribe(&mut self, subscription: PushSubscription) {
+        self.subscription = subscription;
+        self.send_subscription();
+    }
+
+    fn send_subscription(&mut self) {
+        if self.connected {
+            if let Some(task) = &mut self.task {
+                task.send(Json(&self.subscription));
+            }
+        }
+    }
+}
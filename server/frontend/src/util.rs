@@ -1,5 +1,5 @@
-use chrono::offset::FixedOffset;
-use chrono::{ DateTime, Utc, format::DelayedFormat, format::StrftimeItems, };
+use chrono::offset::{ FixedOffset, TimeZone, };
+use chrono::{ DateTime, NaiveDateTime, Utc, format::DelayedFormat, format::StrftimeItems, };
 use common::*;
 use failure::Fallible;
 use stdweb::web::Date;
@@ -15,6 +15,15 @@ pub enum WebUserType {
     Responder,
 }
 
+impl From<AccountRole> for WebUserType {
+    fn from(role: AccountRole) -> Self {
+        match role {
+            AccountRole::Admin => WebUserType::Admin,
+            AccountRole::Responder => WebUserType::Responder,
+        }
+    }
+}
+
 pub fn format_timestamp<'a>(stamp: &DateTime<Utc>) -> DelayedFormat<StrftimeItems<'a>> {
     let offset_minutes = Date::new().get_timezone_offset();
     let off = FixedOffset::west(offset_minutes * 60);
@@ -22,6 +31,35 @@ pub fn format_timestamp<'a>(stamp: &DateTime<Utc>) -> DelayedFormat<StrftimeItem
     zoned_stamp.format("%c")
 }
 
+// hours, minutes and seconds, eg 1h 02m 03s
+pub fn format_duration(duration: chrono::Duration) -> String {
+    let seconds = duration.num_seconds().max(0);
+    format!("{}h {:02}m {:02}s", seconds / 3600, (seconds / 60) % 60, seconds % 60)
+}
+
+fn local_offset() -> FixedOffset {
+    let offset_minutes = Date::new().get_timezone_offset();
+    FixedOffset::west(offset_minutes * 60)
+}
+
+// format a timestamp for a datetime-local input
+pub fn format_local_input(stamp: &DateTime<Utc>) -> String {
+    stamp.with_timezone(&local_offset()).format("%Y-%m-%dT%H:%M").to_string()
+}
+
+// parse the value of a datetime-local input
+pub fn parse_local_input(value: &str) -> Option<DateTime<Utc>> {
+    NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M")
+        .ok()
+        .and_then(|naive| local_offset().from_local_datetime(&naive).single())
+        .map(|stamp| stamp.with_timezone(&Utc))
+}
+
+// format a timestamp for use in a url query
+pub fn format_query_timestamp(stamp: &DateTime<Utc>) -> String {
+    stamp.format("%Y-%m-%dT%H:%M:%SZ").to_string()
+}
+
 macro_rules! Log {
     ($($arg:tt)*) => (
         {
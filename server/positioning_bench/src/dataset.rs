@@ -0,0 +1,206 @@
+// The ranges to replay and where their tags really were. A dataset is either recorded, as json,
+// or simulated from a toml scenario of beacons and tags, which is seeded so that every run of the
+// benchmark replays the same ranges.
+
+use chrono::Duration;
+use common::*;
+use common::tag_filter::FilterConfig;
+use na;
+use rand::SeedableRng;
+use rand::rngs::SmallRng;
+use serde_derive::{ Deserialize, Serialize, };
+use serde_json;
+use simulator::{ Scenario, ScriptedTag, Simulator, SimulatorConfig, };
+use std::fs;
+use std::net::{ IpAddr, Ipv4Addr, };
+use std::path::Path;
+use toml;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Dataset {
+    pub name: String,
+    #[serde(default)]
+    pub filter: FilterConfig,
+    pub beacons: Vec<Beacon>,
+    // in the order they reached the beacon manager
+    pub ranges: Vec<TagData>,
+    pub truth: Vec<GroundTruth>,
+}
+
+impl Dataset {
+    // toml files are simulated scenarios, anything else is a recorded dataset
+    pub fn from_file(file: &str) -> Result<Dataset, String> {
+        let contents = fs::read_to_string(file)
+            .map_err(|e| format!("failed to read dataset {}: {}", file, e))?;
+        if Path::new(file).extension().map_or(false, |extension| extension == "toml") {
+            SimulatedScenario::from_toml(&contents)
+                .map(|scenario| scenario.simulate())
+                .map_err(|e| format!("{}: {}", file, e))
+        } else {
+            let mut dataset: Dataset = serde_json::from_str(&contents)
+                .map_err(|e| format!("invalid dataset {}: {}", file, e))?;
+            dataset.ranges.sort_by_key(|range| range.timestamp);
+            Ok(dataset)
+        }
+    }
+}
+
+// a beacon of a simulated scenario, the rest of the beacon is made up
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PlacedBeacon {
+    pub map_id: i32,
+    pub coordinates: [f64; 2],
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SimulatedScenario {
+    pub name: String,
+    // how long the tags are simulated for
+    pub seconds: u64,
+    #[serde(default)]
+    pub filter: FilterConfig,
+    // the simulator is seeded with 0 when no seed is given
+    #[serde(default)]
+    pub simulator: SimulatorConfig,
+    pub beacons: Vec<PlacedBeacon>,
+    #[serde(default)]
+    pub tags: Vec<ScriptedTag>,
+    // tags that walk randomly between the beacons, as well as the scripted tags
+    #[serde(default)]
+    pub random_tags: u8,
+}
+
+impl SimulatedScenario {
+    pub fn from_toml(contents: &str) -> Result<SimulatedScenario, String> {
+        let scenario: SimulatedScenario = toml::from_str(contents).map_err(|e| format!("invalid scenario: {}", e))?;
+        if scenario.seconds == 0 {
+            return Err("seconds must be greater than 0".to_string());
+        }
+        if scenario.beacons.len() > 255 {
+            return Err("a scenario can have at most 255 beacons".to_string());
+        }
+        if scenario.simulator.scenario_file.is_some() || scenario.simulator.ground_truth_file.is_some() {
+            return Err("simulator.scenario_file and simulator.ground_truth_file are not used by the benchmark".to_string());
+        }
+        scenario.simulator.validate()?;
+        Scenario { tags: scenario.tags.clone() }.validate()?;
+        Ok(scenario)
+    }
+
+    // the random walkers are numbered from 10:01
+    fn tag_macs(&self) -> Vec<ShortAddress> {
+        let mut macs: Vec<ShortAddress> = self.tags.iter().map(|tag| tag.mac).collect();
+        macs.extend((1..=self.random_tags).map(|n| ShortAddress::new([0x10, n])));
+        macs.sort();
+        macs.dedup();
+        macs
+    }
+
+    fn beacons(&self) -> Vec<Beacon> {
+        self.beacons.iter().enumerate().map(|(index, placed)| {
+            let number = index as u8 + 1;
+            let mut beacon = Beacon::new();
+            beacon.id = number as i32;
+            beacon.coordinates = na::Vector2::new(placed.coordinates[0], placed.coordinates[1]);
+            beacon.ip = IpAddr::V4(Ipv4Addr::new(10, 0, 0, number));
+            beacon.mac_address = MacAddress8::from_bytes(&[0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff, 0x00, number]).unwrap();
+            beacon.map_id = Some(placed.map_id);
+            beacon.name = format!("beacon {}", number);
+            beacon
+        }).collect()
+    }
+
+    pub fn simulate(&self) -> Dataset {
+        let beacons = self.beacons();
+        let tag_macs = self.tag_macs();
+        let rng = SmallRng::seed_from_u64(self.simulator.seed.unwrap_or(0));
+        let mut simulator = Simulator::new(self.simulator.clone(), Scenario { tags: self.tags.clone() }, rng);
+
+        let interval = self.simulator.interval_ms as i64;
+        let start = Utc.ymd(2020, 1, 1).and_hms(0, 0, 0);
+        let mut ranges = Vec::new();
+        let mut truth = Vec::new();
+        for step in 1..=(self.seconds as i64 * 1000 / interval) {
+            let now = start + Duration::milliseconds(step * interval);
+            let tick = simulator.tick(&tag_macs, &beacons, interval as f64 / 1000.0, now);
+            for range in tick.ranges {
+                // stamped when it arrives, like the ranges from the dummy beacons
+                ranges.push(TagData {
+                    beacon_mac: range.beacon_mac,
+                    tag_distance: range.distance,
+                    tag_mac: range.tag_mac,
+                    timestamp: now + Duration::from_std(range.delay).unwrap_or_else(|_e| Duration::zero()),
+                });
+            }
+            truth.extend(tick.truth);
+        }
+        ranges.sort_by_key(|range| range.timestamp);
+
+        Dataset {
+            name: self.name.clone(),
+            filter: self.filter.clone(),
+            beacons,
+            ranges,
+            truth,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCENARIO: &str = "
+        name = \"square\"
+        seconds = 20
+        random_tags = 1
+
+        [simulator]
+        seed = 4
+
+        [[beacons]]
+        map_id = 1
+        coordinates = [0.0, 0.0]
+        [[beacons]]
+        map_id = 1
+        coordinates = [10.0, 0.0]
+        [[beacons]]
+        map_id = 1
+        coordinates = [10.0, 10.0]
+
+        [[tags]]
+        mac = \"00:01\"
+        map_id = 1
+        waypoints = [[2.0, 2.0], [8.0, 2.0]]
+    ";
+
+    #[test]
+    fn simulate() {
+        let scenario = SimulatedScenario::from_toml(SCENARIO).unwrap();
+        let dataset = scenario.simulate();
+        assert_eq!(dataset.beacons.len(), 3);
+        // a truth for each tag every second
+        assert_eq!(dataset.truth.len(), 40);
+        assert!(!dataset.ranges.is_empty());
+        assert!(dataset.ranges.windows(2).all(|pair| pair[0].timestamp <= pair[1].timestamp));
+        assert!(dataset.ranges.iter().any(|range| range.tag_mac == ShortAddress::new([0x10, 1])));
+
+        // the same seed replays the same ranges
+        let again = scenario.simulate();
+        assert_eq!(
+            serde_json::to_string(&dataset).unwrap(),
+            serde_json::to_string(&again).unwrap(),
+        );
+    }
+
+    #[test]
+    fn scenario_errors() {
+        assert!(SimulatedScenario::from_toml(&SCENARIO.replace("seconds = 20", "seconds = 0")).is_err());
+        assert!(SimulatedScenario::from_toml(&SCENARIO.replace("seed = 4", "seed = 4\ndropout_chance = 2.0")).is_err());
+        assert!(SimulatedScenario::from_toml(&SCENARIO.replace("waypoints = [[2.0, 2.0], [8.0, 2.0]]", "waypoints = []")).is_err());
+        assert!(SimulatedScenario::from_toml(&SCENARIO.replace("random_tags = 1", "random_tags = 1\nunknown = 1")).is_err());
+    }
+}
@@ -0,0 +1,18 @@
+// Measures how well tags are located, so a change to the positioning can be compared against
+// the commit before it. Datasets of ranges with the true location of their tags, recorded or
+// simulated, are replayed through the same steps the data processor takes, without a database,
+// and the locations are scored against the truth.
+
+extern crate chrono;
+extern crate common;
+extern crate nalgebra as na;
+extern crate rand;
+extern crate serde_derive;
+extern crate serde_json;
+extern crate simulator;
+extern crate toml;
+
+pub mod dataset;
+pub mod metrics;
+pub mod replay;
+pub mod report;
@@ -0,0 +1,77 @@
+// Replays datasets through the positioning and reports how accurate it was, ie
+//
+//     positioning_bench scenarios/*.toml --output report.json
+//
+// then again after a change, and diff the two reports. toml files are simulated scenarios, see
+// scenarios/ for examples, and json files are recorded datasets.
+
+extern crate clap;
+extern crate positioning_bench;
+
+use clap::{ App, Arg, };
+use common::tag_filter::FilterType;
+use positioning_bench::dataset::Dataset;
+use positioning_bench::report::Report;
+use std::fs;
+use std::process;
+
+fn cli<'a, 'b>() -> App<'a, 'b> {
+    App::new("positioning_bench")
+        .about("Scores the positioning of Akriveia against datasets with known tag locations")
+        .arg(Arg::with_name("datasets")
+            .value_name("DATASET")
+            .help("simulated scenario toml, or recorded dataset json")
+            .required(true)
+            .multiple(true))
+        .arg(Arg::with_name("output")
+            .long("output")
+            .takes_value(true)
+            .help("where the json report is written, it is printed when not given"))
+        .arg(Arg::with_name("filter")
+            .long("filter")
+            .takes_value(true)
+            .possible_values(&["passthrough", "kalman"])
+            .help("filter every dataset with this filter, instead of their own"))
+}
+
+fn exit(message: String) -> ! {
+    eprintln!("{}", message);
+    process::exit(1);
+}
+
+fn main() {
+    let matches = cli().get_matches();
+    let filter_type = match matches.value_of("filter") {
+        Some("passthrough") => Some(FilterType::Passthrough),
+        Some(_kalman) => Some(FilterType::Kalman),
+        None => None,
+    };
+
+    let mut report = Report::new();
+    for file in matches.values_of("datasets").into_iter().flatten() {
+        let mut dataset = Dataset::from_file(file).unwrap_or_else(|e| exit(e));
+        if let Some(filter_type) = filter_type {
+            dataset.filter.filter_type = filter_type;
+        }
+        let scenario = report.run(&dataset);
+        let meters = |value: Option<f64>| value.map_or("-".to_string(), |v| format!("{:.3}m", v));
+        eprintln!(
+            "{}: cep50 {} cep95 {} rmse {} latency {} dropout {}",
+            scenario.name,
+            meters(scenario.metrics.cep50),
+            meters(scenario.metrics.cep95),
+            meters(scenario.metrics.rmse),
+            scenario.metrics.latency_mean_ms.map_or("-".to_string(), |v| format!("{:.0}ms", v)),
+            scenario.metrics.dropout_rate.map_or("-".to_string(), |v| format!("{:.1}%", v * 100.0)),
+        );
+    }
+
+    match matches.value_of("output") {
+        Some(output) => {
+            if let Err(e) = fs::write(output, report.to_json()) {
+                exit(format!("failed to write report {}: {}", output, e));
+            }
+        },
+        None => print!("{}", report.to_json()),
+    }
+}
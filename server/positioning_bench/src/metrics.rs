@@ -0,0 +1,194 @@
+// Scores the replayed locations against where the tags really were. Every location is compared
+// to the true location at the same time, between the two nearest samples of the truth, so the
+// error includes how far the tag moved while its ranges were on the way.
+
+use chrono::Duration;
+use common::*;
+use crate::replay::Estimate;
+use na;
+use serde_derive::{ Deserialize, Serialize, };
+use std::collections::BTreeMap;
+
+// values that are None had nothing to be calculated from, ie no locations on the right floor
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Metrics {
+    pub estimates: usize,
+    pub truth_samples: usize,
+    // the radius around the true location that half, and 95 percent of the locations on the
+    // right floor are within, in meters
+    pub cep50: Option<f64>,
+    pub cep95: Option<f64>,
+    pub rmse: Option<f64>,
+    // from when a tag was somewhere until the first location after it, in milliseconds
+    pub latency_mean_ms: Option<f64>,
+    pub latency_p95_ms: Option<f64>,
+    // fraction of true locations that were not followed by a location before the next one
+    pub dropout_rate: Option<f64>,
+    // fraction of locations on the floor the tag was on
+    pub floor_accuracy: Option<f64>,
+}
+
+// reports are compared as text, more digits than a millimeter or a millisecond are just noise
+fn round(value: f64) -> f64 {
+    (value * 1000.0).round() / 1000.0
+}
+
+// nearest rank, the values must be sorted
+fn percentile(sorted: &[f64], fraction: f64) -> Option<f64> {
+    if sorted.is_empty() {
+        return None;
+    }
+    let rank = (fraction * sorted.len() as f64).ceil() as usize;
+    Some(sorted[rank.max(1) - 1])
+}
+
+fn mean(values: &[f64]) -> Option<f64> {
+    if values.is_empty() {
+        None
+    } else {
+        Some(values.iter().sum::<f64>() / values.len() as f64)
+    }
+}
+
+fn milliseconds(duration: Duration) -> f64 {
+    duration.num_microseconds().unwrap_or(0) as f64 / 1000.0
+}
+
+// where the tag was at the time, None before the first sample. the samples must be sorted.
+fn truth_at(samples: &[&GroundTruth], timestamp: DateTime<Utc>) -> Option<(i32, na::Vector2<f64>)> {
+    let next = samples.iter().position(|sample| sample.timestamp > timestamp).unwrap_or(samples.len());
+    let previous = samples.get(next.checked_sub(1)?)?;
+    match samples.get(next) {
+        // a tag that changed floors is only somewhere in between on one of them
+        Some(following) if following.map_id == previous.map_id => {
+            let span = milliseconds(following.timestamp - previous.timestamp);
+            let fraction = milliseconds(timestamp - previous.timestamp) / span;
+            Some((previous.map_id, previous.coordinates + (following.coordinates - previous.coordinates) * fraction))
+        },
+        _ => Some((previous.map_id, previous.coordinates)),
+    }
+}
+
+pub fn score(truth: &[GroundTruth], estimates: &[Estimate]) -> Metrics {
+    let mut by_tag: BTreeMap<ShortAddress, Vec<&GroundTruth>> = BTreeMap::new();
+    for sample in truth {
+        by_tag.entry(sample.tag_mac).or_default().push(sample);
+    }
+    for samples in by_tag.values_mut() {
+        samples.sort_by_key(|sample| sample.timestamp);
+    }
+
+    let mut errors = Vec::new();
+    let mut scored = 0;
+    for estimate in estimates {
+        let samples = match by_tag.get(&estimate.tag_mac) {
+            Some(samples) => samples,
+            None => continue,
+        };
+        if let Some((map_id, coordinates)) = truth_at(samples, estimate.timestamp) {
+            scored += 1;
+            if map_id == estimate.map_id {
+                errors.push((estimate.coordinates - coordinates).norm());
+            }
+        }
+    }
+
+    let mut latencies = Vec::new();
+    let mut dropouts = 0;
+    for (tag_mac, samples) in by_tag.iter() {
+        let tag_estimates: Vec<&Estimate> = estimates.iter().filter(|e| e.tag_mac == *tag_mac).collect();
+        for (index, sample) in samples.iter().enumerate() {
+            let until = samples.get(index + 1).map(|following| following.timestamp);
+            let first = tag_estimates
+                .iter()
+                .filter(|e| e.timestamp >= sample.timestamp && until.map_or(true, |until| e.timestamp < until))
+                .map(|e| e.timestamp)
+                .min();
+            match first {
+                Some(timestamp) => latencies.push(milliseconds(timestamp - sample.timestamp)),
+                None => dropouts += 1,
+            }
+        }
+    }
+
+    errors.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    latencies.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let squared: Vec<f64> = errors.iter().map(|e| e * e).collect();
+    let fraction = |count: usize, total: usize| if total > 0 { Some(count as f64 / total as f64) } else { None };
+
+    Metrics {
+        estimates: estimates.len(),
+        truth_samples: truth.len(),
+        cep50: percentile(&errors, 0.5).map(round),
+        cep95: percentile(&errors, 0.95).map(round),
+        rmse: mean(&squared).map(|m| round(m.sqrt())),
+        latency_mean_ms: mean(&latencies).map(round),
+        latency_p95_ms: percentile(&latencies, 0.95).map(round),
+        dropout_rate: fraction(dropouts, truth.len()).map(round),
+        floor_accuracy: fraction(errors.len(), scored).map(round),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(seconds: i64, millis: i64) -> DateTime<Utc> {
+        Utc.timestamp(1000 + seconds, 0) + Duration::milliseconds(millis)
+    }
+
+    fn truth(seconds: i64, map_id: i32, x: f64) -> GroundTruth {
+        GroundTruth {
+            coordinates: na::Vector2::new(x, 0.0),
+            map_id,
+            tag_mac: ShortAddress::new([0, 1]),
+            timestamp: at(seconds, 0),
+        }
+    }
+
+    fn estimate(timestamp: DateTime<Utc>, map_id: i32, x: f64) -> Estimate {
+        Estimate {
+            coordinates: na::Vector2::new(x, 0.0),
+            map_id,
+            tag_mac: ShortAddress::new([0, 1]),
+            timestamp,
+        }
+    }
+
+    #[test]
+    fn percentiles() {
+        let values: Vec<f64> = (1..=20).map(|v| v as f64).collect();
+        assert_eq!(percentile(&values, 0.5), Some(10.0));
+        assert_eq!(percentile(&values, 0.95), Some(19.0));
+        assert_eq!(percentile(&[3.0], 0.0), Some(3.0));
+        assert_eq!(percentile(&[], 0.5), None);
+    }
+
+    #[test]
+    fn scores() {
+        let truth = vec![truth(0, 1, 0.0), truth(1, 1, 1.0), truth(2, 1, 2.0), truth(3, 2, 0.0)];
+        let estimates = vec![
+            // halfway between the first two samples, a meter off
+            estimate(at(0, 500), 1, 1.5),
+            // nothing for the second sample, then late for the third
+            estimate(at(2, 200), 1, 2.2),
+            // on the wrong floor
+            estimate(at(3, 100), 1, 2.0),
+        ];
+
+        let metrics = score(&truth, &estimates);
+        assert_eq!(metrics.estimates, 3);
+        assert_eq!(metrics.truth_samples, 4);
+        assert_eq!(metrics.cep50, Some(0.2));
+        assert_eq!(metrics.cep95, Some(1.0));
+        assert_eq!(metrics.rmse, Some(round(0.52f64.sqrt())));
+        assert_eq!(metrics.latency_mean_ms, Some(round(800.0 / 3.0)));
+        assert_eq!(metrics.latency_p95_ms, Some(500.0));
+        assert_eq!(metrics.dropout_rate, Some(0.25));
+        assert_eq!(metrics.floor_accuracy, Some(round(2.0 / 3.0)));
+
+        let empty = score(&truth, &[]);
+        assert_eq!(empty.cep50, None);
+        assert_eq!(empty.dropout_rate, Some(1.0));
+    }
+}
@@ -0,0 +1,134 @@
+// Runs the ranges of a dataset through the steps the data processor takes for every range it
+// receives: the range is added to the tag's history, the history is averaged, the tag is located
+// on the most likely floor and the location is smoothed by the tag's filter.
+
+use common::*;
+use common::positioning::{ self, TagRanges, };
+use common::tag_filter::TagFilter;
+use crate::dataset::Dataset;
+use na;
+use std::collections::BTreeMap;
+
+// a location as the data processor would have stored it
+#[derive(Debug, Clone)]
+pub struct Estimate {
+    pub coordinates: na::Vector2<f64>,
+    pub map_id: i32,
+    pub tag_mac: ShortAddress,
+    pub timestamp: DateTime<Utc>,
+}
+
+struct TagState {
+    filter: TagFilter,
+    map_id: Option<i32>,
+    ranges: TagRanges,
+}
+
+// the first range of a tag only starts its history, the same as the first range of a user that
+// the data processor has not loaded yet.
+pub fn replay(dataset: &Dataset) -> Vec<Estimate> {
+    let mut tags: BTreeMap<ShortAddress, TagState> = BTreeMap::new();
+    let mut estimates = Vec::new();
+    for range in &dataset.ranges {
+        let state = match tags.get_mut(&range.tag_mac) {
+            Some(state) => state,
+            None => {
+                let mut ranges = TagRanges::new();
+                ranges.append(range);
+                tags.insert(range.tag_mac, TagState {
+                    filter: TagFilter::new(dataset.filter.clone()),
+                    map_id: None,
+                    ranges,
+                });
+                continue;
+            },
+        };
+
+        state.ranges.append(range);
+        let averages = match state.ranges.averages(range.tag_mac, dataset.filter.filter_type, range.timestamp) {
+            Some(averages) => averages,
+            None => continue,
+        };
+        // the beacons the data processor would select by mac
+        let beacons: Vec<Beacon> = dataset.beacons
+            .iter()
+            .filter(|beacon| averages.iter().any(|(data, _weight)| data.beacon_mac == beacon.mac_address))
+            .cloned()
+            .collect();
+        let fix = match positioning::locate(beacons, averages, state.map_id) {
+            Ok(fix) => fix,
+            Err(_e) => continue,
+        };
+
+        if state.map_id != Some(fix.map_id) {
+            state.filter.reset();
+        }
+        let filtered = state.filter.update(&fix.solution, fix.timestamp);
+        state.map_id = Some(fix.map_id);
+        estimates.push(Estimate {
+            coordinates: filtered.coordinates,
+            map_id: fix.map_id,
+            tag_mac: range.tag_mac,
+            timestamp: fix.timestamp,
+        });
+    }
+    estimates
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dataset::SimulatedScenario;
+
+    #[test]
+    fn perfect_ranges() {
+        let scenario = SimulatedScenario::from_toml("
+            name = \"perfect\"
+            seconds = 30
+
+            [filter]
+            filter_type = \"Passthrough\"
+            process_noise = 0.5
+            measurement_noise = 0.05
+            initial_velocity_variance = 4.0
+            reset_timeout = 10.0
+
+            [simulator]
+            range_noise = 0.0
+            nlos_chance = 0.0
+            dropout_chance = 0.0
+            latency_ms = 0
+            latency_jitter_ms = 0
+
+            [[beacons]]
+            map_id = 1
+            coordinates = [0.0, 0.0]
+            [[beacons]]
+            map_id = 1
+            coordinates = [10.0, 0.0]
+            [[beacons]]
+            map_id = 1
+            coordinates = [10.0, 10.0]
+            [[beacons]]
+            map_id = 1
+            coordinates = [0.0, 10.0]
+
+            [[tags]]
+            mac = \"00:01\"
+            map_id = 1
+            speed = 0.1
+            waypoints = [[3.0, 4.0], [6.0, 4.0]]
+        ").unwrap();
+        let dataset = scenario.simulate();
+        let estimates = replay(&dataset);
+
+        // the first few ranges only fill the history
+        assert!(estimates.len() > 100);
+        for estimate in estimates {
+            assert_eq!(estimate.map_id, 1);
+            let truth = dataset.truth.iter().find(|truth| truth.timestamp == estimate.timestamp).unwrap();
+            // the average over the history lags behind a little
+            assert!((estimate.coordinates - truth.coordinates).norm() < 0.5);
+        }
+    }
+}
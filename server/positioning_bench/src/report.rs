@@ -0,0 +1,44 @@
+// The result of a benchmark run, written as json so that the reports of two commits can be
+// diffed. Scenarios are reported in the order they were given and every value is rounded, so
+// the same code and datasets always give the same report.
+
+use crate::dataset::Dataset;
+use crate::metrics::{ self, Metrics, };
+use crate::replay;
+use common::tag_filter::FilterType;
+use serde_derive::{ Deserialize, Serialize, };
+use serde_json;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScenarioReport {
+    pub name: String,
+    pub filter_type: FilterType,
+    #[serde(flatten)]
+    pub metrics: Metrics,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Report {
+    pub scenarios: Vec<ScenarioReport>,
+}
+
+impl Report {
+    pub fn new() -> Report {
+        Report::default()
+    }
+
+    pub fn run(&mut self, dataset: &Dataset) -> &ScenarioReport {
+        let estimates = replay::replay(dataset);
+        self.scenarios.push(ScenarioReport {
+            name: dataset.name.clone(),
+            filter_type: dataset.filter.filter_type,
+            metrics: metrics::score(&dataset.truth, &estimates),
+        });
+        self.scenarios.last().unwrap()
+    }
+
+    pub fn to_json(&self) -> String {
+        // nothing in a report can fail to serialize
+        serde_json::to_string_pretty(self).unwrap() + "\n"
+    }
+}
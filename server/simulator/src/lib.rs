@@ -0,0 +1,495 @@
+// Virtual tags for the dummy beacons and the benchmark. Each tag walks a scripted path, or
+// wanders randomly around the area covered by the beacons on its map, and is ranged by those
+// beacons the way real hardware would range it: with noise, with a bias when the signal bounces
+// around an obstacle, with ranges that never arrive, and with ranges that arrive late.
+
+use common::*;
+use nalgebra as na;
+use rand::Rng;
+use rand::rngs::SmallRng;
+use serde_derive::{ Deserialize, Serialize, };
+use std::collections::BTreeMap;
+use std::fs;
+use std::net::IpAddr;
+use std::time::Duration;
+
+// random walks stay this far inside the beacons, tags outside of them cannot be located well
+const WALK_MARGIN: f64 = 0.5;
+
+// the tags moved around by the dummy beacons, and how badly they are ranged
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SimulatorConfig {
+    pub interval_ms: u64,
+    // the same seed and scenario give the same paths and ranges, random when not set
+    pub seed: Option<u64>,
+    // walking speed of tags without a scripted speed, in meters per second
+    pub speed: f64,
+    // standard deviation of the error added to every range, in meters
+    pub range_noise: f64,
+    // chance of a range bouncing off something on the way, which makes it up to 1.5 times
+    // the bias too long
+    pub nlos_chance: f64,
+    pub nlos_bias: f64,
+    // chance of a range never reaching the manager
+    pub dropout_chance: f64,
+    // how long ranges take to arrive, plus up to the jitter
+    pub latency_ms: u64,
+    pub latency_jitter_ms: u64,
+    // toml file of scripted tag paths, the other tags walk randomly
+    pub scenario_file: Option<String>,
+    // where the true location of every tag is appended, one json object per line
+    pub ground_truth_file: Option<String>,
+}
+
+impl Default for SimulatorConfig {
+    fn default() -> SimulatorConfig {
+        SimulatorConfig {
+            interval_ms: 1000,
+            seed: None,
+            speed: 1.2,
+            range_noise: 0.15,
+            nlos_chance: 0.1,
+            nlos_bias: 1.0,
+            dropout_chance: 0.05,
+            latency_ms: 20,
+            latency_jitter_ms: 30,
+            scenario_file: None,
+            ground_truth_file: None,
+        }
+    }
+}
+
+impl SimulatorConfig {
+    pub fn interval(&self) -> Duration {
+        Duration::from_millis(self.interval_ms)
+    }
+
+    // errors name the field relative to the simulator section, ie simulator.speed
+    pub fn validate(&self) -> Result<(), String> {
+        if self.interval_ms == 0 {
+            return Err("simulator.interval_ms must be greater than 0".to_string());
+        }
+        if !self.speed.is_finite() || self.speed <= 0.0 {
+            return Err(format!("simulator.speed must be a positive speed, not {}", self.speed));
+        }
+        let distances = [
+            ("simulator.range_noise", self.range_noise),
+            ("simulator.nlos_bias", self.nlos_bias),
+        ];
+        for (name, distance) in distances.iter() {
+            if !distance.is_finite() || *distance < 0.0 {
+                return Err(format!("{} must not be negative, not {}", name, distance));
+            }
+        }
+        let chances = [
+            ("simulator.nlos_chance", self.nlos_chance),
+            ("simulator.dropout_chance", self.dropout_chance),
+        ];
+        for (name, chance) in chances.iter() {
+            if !(0.0..=1.0).contains(chance) {
+                return Err(format!("{} must be between 0 and 1, not {}", name, chance));
+            }
+        }
+        if let Some(file) = &self.scenario_file {
+            Scenario::from_file(file)?;
+        }
+        Ok(())
+    }
+}
+
+// a tag that follows the same path every time, ie to repeat an accuracy test
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScriptedTag {
+    pub mac: ShortAddress,
+    pub map_id: i32,
+    // meters per second, the configured speed when not given
+    pub speed: Option<f64>,
+    pub waypoints: Vec<[f64; 2]>,
+    // start over at the first waypoint after the last one, otherwise stop there
+    #[serde(default = "default_looped")]
+    pub looped: bool,
+}
+
+fn default_looped() -> bool {
+    true
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Scenario {
+    // tags that are not listed walk randomly
+    pub tags: Vec<ScriptedTag>,
+}
+
+impl Scenario {
+    pub fn from_toml(contents: &str) -> Result<Scenario, String> {
+        let scenario: Scenario = toml::from_str(contents).map_err(|e| format!("invalid scenario: {}", e))?;
+        scenario.validate()?;
+        Ok(scenario)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        for tag in &self.tags {
+            if tag.waypoints.is_empty() {
+                return Err(format!("scripted tag {} has no waypoints", tag.mac));
+            }
+            if tag.speed.map_or(false, |speed| speed.is_nan() || speed <= 0.0) {
+                return Err(format!("scripted tag {} must have a positive speed", tag.mac));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn from_file(file: &str) -> Result<Scenario, String> {
+        let contents = fs::read_to_string(file)
+            .map_err(|e| format!("failed to read scenario file {}: {}", file, e))?;
+        Scenario::from_toml(&contents)
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Path {
+    Scripted { waypoints: Vec<na::Vector2<f64>>, next: usize, looped: bool },
+    RandomWalk { target: na::Vector2<f64> },
+}
+
+#[derive(Debug, Clone)]
+struct SimulatedTag {
+    map_id: i32,
+    path: Path,
+    position: na::Vector2<f64>,
+    speed: f64,
+}
+
+// a range as a beacon would report it
+#[derive(Debug, Clone)]
+pub struct SimulatedRange {
+    pub beacon_ip: IpAddr,
+    pub beacon_mac: MacAddress8,
+    // how long until the range reaches the manager
+    pub delay: Duration,
+    pub distance: f64,
+    pub tag_mac: ShortAddress,
+}
+
+#[derive(Debug, Default)]
+pub struct Tick {
+    pub ranges: Vec<SimulatedRange>,
+    pub truth: Vec<GroundTruth>,
+}
+
+// the area a random walk stays in, None when there are too few beacons to locate a tag
+fn walk_bounds(beacons: &[&Beacon]) -> Option<(na::Vector2<f64>, na::Vector2<f64>)> {
+    if beacons.len() < 3 {
+        return None;
+    }
+    let first = beacons[0].coordinates;
+    let (mut min, mut max) = (first, first);
+    for beacon in beacons {
+        min = na::Vector2::new(min.x.min(beacon.coordinates.x), min.y.min(beacon.coordinates.y));
+        max = na::Vector2::new(max.x.max(beacon.coordinates.x), max.y.max(beacon.coordinates.y));
+    }
+    // beacons that are closer together than twice the margin are walked between anyway
+    let margin = |low: f64, high: f64| if high - low > 2.0 * WALK_MARGIN { (low + WALK_MARGIN, high - WALK_MARGIN) } else { (low, high) };
+    let (min_x, max_x) = margin(min.x, max.x);
+    let (min_y, max_y) = margin(min.y, max.y);
+    Some((na::Vector2::new(min_x, min_y), na::Vector2::new(max_x, max_y)))
+}
+
+fn random_point<R: Rng>(rng: &mut R, (min, max): (na::Vector2<f64>, na::Vector2<f64>)) -> na::Vector2<f64> {
+    let pick = |rng: &mut R, low: f64, high: f64| if low < high { rng.gen_range(low, high) } else { low };
+    na::Vector2::new(pick(rng, min.x, max.x), pick(rng, min.y, max.y))
+}
+
+// normally distributed with a mean of 0 and a standard deviation of 1
+fn standard_normal<R: Rng>(rng: &mut R) -> f64 {
+    let u1: f64 = rng.gen_range(std::f64::EPSILON, 1.0);
+    let u2: f64 = rng.gen();
+    (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos()
+}
+
+pub struct Simulator {
+    config: SimulatorConfig,
+    rng: SmallRng,
+    scenario: Scenario,
+    tags: BTreeMap<ShortAddress, SimulatedTag>,
+}
+
+impl Simulator {
+    pub fn new(config: SimulatorConfig, scenario: Scenario, rng: SmallRng) -> Simulator {
+        Simulator {
+            config,
+            rng,
+            scenario,
+            tags: BTreeMap::new(),
+        }
+    }
+
+    // the tag starts at the beginning of its script, or somewhere random between the beacons
+    fn place(&mut self, mac: ShortAddress, beacons: &[Beacon]) -> Option<SimulatedTag> {
+        if let Some(script) = self.scenario.tags.iter().find(|t| t.mac == mac) {
+            let waypoints: Vec<na::Vector2<f64>> = script.waypoints.iter().map(|p| na::Vector2::new(p[0], p[1])).collect();
+            return Some(SimulatedTag {
+                map_id: script.map_id,
+                position: waypoints[0],
+                path: Path::Scripted { waypoints, next: 1, looped: script.looped },
+                speed: script.speed.unwrap_or(self.config.speed),
+            });
+        }
+
+        let mut maps: Vec<i32> = beacons.iter().filter_map(|b| b.map_id).collect();
+        maps.sort();
+        maps.dedup();
+        maps.retain(|map_id| walk_bounds(&beacons.iter().filter(|b| b.map_id == Some(*map_id)).collect::<Vec<_>>()).is_some());
+        if maps.is_empty() {
+            return None;
+        }
+        let map_id = maps[self.rng.gen_range(0, maps.len())];
+        let bounds = walk_bounds(&beacons.iter().filter(|b| b.map_id == Some(map_id)).collect::<Vec<_>>())?;
+        Some(SimulatedTag {
+            map_id,
+            position: random_point(&mut self.rng, bounds),
+            path: Path::RandomWalk { target: random_point(&mut self.rng, bounds) },
+            speed: self.config.speed,
+        })
+    }
+
+    fn walk(&mut self, tag: &mut SimulatedTag, beacons: &[&Beacon], seconds: f64) {
+        let mut remaining = tag.speed * seconds;
+        // waypoints in the same place are passed through, unless there is nowhere else to go
+        let mut empty_legs = 0;
+        let max_empty_legs = match &tag.path {
+            Path::Scripted { waypoints, .. } => waypoints.len(),
+            Path::RandomWalk { .. } => 1,
+        };
+        // a short leg can be finished with distance to spare, keep going along the next one
+        while remaining > 0.0 && empty_legs <= max_empty_legs {
+            let target = match &tag.path {
+                Path::Scripted { waypoints, next, .. } => match waypoints.get(*next) {
+                    Some(target) => *target,
+                    None => return,
+                },
+                Path::RandomWalk { target } => *target,
+            };
+            let leg = target - tag.position;
+            let length = leg.norm();
+            if length > remaining {
+                tag.position += leg * (remaining / length);
+                return;
+            }
+            tag.position = target;
+            remaining -= length;
+            empty_legs = if length > 0.0 { 0 } else { empty_legs + 1 };
+
+            match &mut tag.path {
+                Path::Scripted { waypoints, next, looped } => {
+                    *next += 1;
+                    if *next >= waypoints.len() && *looped {
+                        *next = 0;
+                    }
+                },
+                Path::RandomWalk { target } => match walk_bounds(beacons) {
+                    Some(bounds) => *target = random_point(&mut self.rng, bounds),
+                    None => return,
+                },
+            }
+        }
+    }
+
+    // what a beacon would report for the tag, None when the range never arrives
+    fn measure(&mut self, tag: &SimulatedTag, beacon: &Beacon) -> Option<(f64, Duration)> {
+        if self.rng.gen_bool(self.config.dropout_chance) {
+            return None;
+        }
+        let mut distance = (beacon.coordinates - tag.position).norm();
+        distance += standard_normal(&mut self.rng) * self.config.range_noise;
+        if self.rng.gen_bool(self.config.nlos_chance) {
+            // a reflected signal always travels further than the direct path
+            distance += self.config.nlos_bias * self.rng.gen_range(0.5, 1.5);
+        }
+
+        let jitter = if self.config.latency_jitter_ms > 0 {
+            self.rng.gen_range(0, self.config.latency_jitter_ms + 1)
+        } else {
+            0
+        };
+        Some((distance.max(0.0), Duration::from_millis(self.config.latency_ms + jitter)))
+    }
+
+    // moves every tag by the given number of seconds, and ranges it from each beacon on its map.
+    // tags follow the users that have them, users without a tag are not simulated.
+    pub fn tick(&mut self, tag_macs: &[ShortAddress], beacons: &[Beacon], seconds: f64, now: DateTime<Utc>) -> Tick {
+        self.tags.retain(|mac, _tag| tag_macs.contains(mac));
+        for mac in tag_macs {
+            if !self.tags.contains_key(mac) {
+                if let Some(tag) = self.place(*mac, beacons) {
+                    self.tags.insert(*mac, tag);
+                }
+            }
+        }
+
+        let mut tick = Tick::default();
+        let macs: Vec<ShortAddress> = self.tags.keys().cloned().collect();
+        for mac in macs {
+            let mut tag = self.tags.remove(&mac).unwrap();
+            let on_map: Vec<&Beacon> = beacons.iter().filter(|b| b.map_id == Some(tag.map_id)).collect();
+            self.walk(&mut tag, &on_map, seconds);
+
+            tick.truth.push(GroundTruth {
+                coordinates: tag.position,
+                map_id: tag.map_id,
+                tag_mac: mac,
+                timestamp: now,
+            });
+            for beacon in on_map {
+                if let Some((distance, delay)) = self.measure(&tag, beacon) {
+                    tick.ranges.push(SimulatedRange {
+                        beacon_ip: beacon.ip,
+                        beacon_mac: beacon.mac_address,
+                        delay,
+                        distance,
+                        tag_mac: mac,
+                    });
+                }
+            }
+            self.tags.insert(mac, tag);
+        }
+        tick
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use std::net::Ipv4Addr;
+
+    fn beacon(index: u8, map_id: i32, x: f64, y: f64) -> Beacon {
+        let mut beacon = Beacon::new();
+        beacon.id = index as i32;
+        beacon.coordinates = na::Vector2::new(x, y);
+        beacon.ip = IpAddr::V4(Ipv4Addr::new(10, 0, 0, index));
+        beacon.mac_address = MacAddress8::from_bytes(&[0, 0, 0, 0, 0, 0, 0, index]).unwrap();
+        beacon.map_id = Some(map_id);
+        beacon
+    }
+
+    fn square() -> Vec<Beacon> {
+        vec![
+            beacon(1, 1, 0.0, 0.0),
+            beacon(2, 1, 10.0, 0.0),
+            beacon(3, 1, 10.0, 10.0),
+            beacon(4, 1, 0.0, 10.0),
+        ]
+    }
+
+    fn perfect() -> SimulatorConfig {
+        let mut config = SimulatorConfig::default();
+        config.range_noise = 0.0;
+        config.nlos_chance = 0.0;
+        config.dropout_chance = 0.0;
+        config.latency_ms = 0;
+        config.latency_jitter_ms = 0;
+        config.speed = 1.0;
+        config
+    }
+
+    fn mac(s: &str) -> ShortAddress {
+        ShortAddress::parse_str(s).unwrap()
+    }
+
+    fn scripted() -> Scenario {
+        Scenario::from_toml("
+            [[tags]]
+            mac = \"00:01\"
+            map_id = 1
+            waypoints = [[2.0, 2.0], [5.0, 2.0], [5.0, 6.0]]
+            looped = false
+        ").unwrap()
+    }
+
+    #[test]
+    fn scripted_path() {
+        let mut sim = Simulator::new(perfect(), scripted(), SmallRng::seed_from_u64(1));
+        let now = Utc.timestamp(1000, 0);
+        let beacons = square();
+
+        let tick = sim.tick(&[mac("00:01")], &beacons, 2.0, now);
+        assert_eq!(tick.truth.len(), 1);
+        assert_eq!(tick.truth[0].coordinates, na::Vector2::new(4.0, 2.0));
+
+        // turns the corner at the second waypoint
+        let tick = sim.tick(&[mac("00:01")], &beacons, 2.0, now);
+        assert_eq!(tick.truth[0].coordinates, na::Vector2::new(5.0, 3.0));
+
+        // and stops at the last
+        let tick = sim.tick(&[mac("00:01")], &beacons, 60.0, now);
+        assert_eq!(tick.truth[0].coordinates, na::Vector2::new(5.0, 6.0));
+
+        // without noise the ranges are the true distances
+        assert_eq!(tick.ranges.len(), 4);
+        let to_origin = tick.ranges.iter().find(|r| r.beacon_ip == beacons[0].ip).unwrap();
+        assert!((to_origin.distance - (25.0f64 + 36.0).sqrt()).abs() < 1e-9);
+        assert_eq!(to_origin.delay, Duration::from_millis(0));
+    }
+
+    #[test]
+    fn random_walk_stays_between_beacons() {
+        let mut sim = Simulator::new(perfect(), Scenario::default(), SmallRng::seed_from_u64(2));
+        let mut beacons = square();
+        // beacons on another floor, and a floor with too few beacons to walk on
+        beacons.push(beacon(5, 2, 0.0, 0.0));
+        let now = Utc.timestamp(1000, 0);
+        for _ in 0..200 {
+            let tick = sim.tick(&[mac("00:02")], &beacons, 1.0, now);
+            let truth = &tick.truth[0];
+            assert_eq!(truth.map_id, 1);
+            assert!(truth.coordinates.x >= WALK_MARGIN && truth.coordinates.x <= 10.0 - WALK_MARGIN);
+            assert!(truth.coordinates.y >= WALK_MARGIN && truth.coordinates.y <= 10.0 - WALK_MARGIN);
+            assert_eq!(tick.ranges.len(), 4);
+        }
+
+        // users that no longer have the tag stop being simulated
+        assert!(sim.tick(&[], &beacons, 1.0, now).truth.is_empty());
+    }
+
+    #[test]
+    fn impairments() {
+        let mut config = perfect();
+        config.range_noise = 0.2;
+        config.nlos_chance = 0.5;
+        config.nlos_bias = 2.0;
+        config.dropout_chance = 0.25;
+        config.latency_ms = 10;
+        config.latency_jitter_ms = 20;
+        let mut sim = Simulator::new(config, scripted(), SmallRng::seed_from_u64(3));
+        let beacons = square();
+        let now = Utc.timestamp(1000, 0);
+
+        let mut errors = Vec::new();
+        let mut received = 0;
+        for _ in 0..500 {
+            let tick = sim.tick(&[mac("00:01")], &beacons, 0.0, now);
+            for range in tick.ranges {
+                let beacon = beacons.iter().find(|b| b.ip == range.beacon_ip).unwrap();
+                errors.push(range.distance - (beacon.coordinates - na::Vector2::new(2.0, 2.0)).norm());
+                assert!(range.delay >= Duration::from_millis(10) && range.delay <= Duration::from_millis(30));
+                received += 1;
+            }
+        }
+        // about a quarter of the ranges are dropped
+        assert!(received > 1300 && received < 1700);
+        // reflections bias the ranges long, by about half the chance times the bias
+        let mean = errors.iter().sum::<f64>() / errors.len() as f64;
+        assert!(mean > 0.7 && mean < 1.3);
+    }
+
+    #[test]
+    fn scenario_errors() {
+        assert!(Scenario::from_toml("[[tags]]\nmac = \"00:01\"\nmap_id = 1\nwaypoints = []").is_err());
+        assert!(Scenario::from_toml("[[tags]]\nmac = \"00:01\"\nmap_id = 1\nspeed = 0.0\nwaypoints = [[0.0, 0.0]]").is_err());
+        assert!(Scenario::from_toml("[[tags]]\nmac = \"00:01\"\nmap_id = 1\nwaypoints = [[0.0, 0.0]]\nunknown = 1").is_err());
+    }
+}